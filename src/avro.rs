@@ -0,0 +1,114 @@
+//! Count tables as Avro object container files
+//!
+//! The file embeds its schema, `SCHEMA`, so Kafka and other Avro consumers
+//! read the records without a separate schema registry entry. Records are
+//! ordered from most to least abundant.
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use apache_avro::{from_value, Reader, Schema, Writer};
+use serde::{Deserialize, Serialize};
+
+use crate::create_output;
+
+/// Schema of each record, a kmer and its count
+pub const SCHEMA: &str = r#"{
+    "type": "record",
+    "name": "KmerCount",
+    "fields": [
+        {"name": "kmer", "type": "string"},
+        {"name": "count", "type": "long"}
+    ]
+}"#;
+
+#[derive(Serialize, Deserialize)]
+struct AvroRecord {
+    kmer: String,
+    count: i64,
+}
+
+/// Save `kmer_count` at `output_path` as an Avro container file
+///
+/// Fails if a count exceeds the range of an Avro `long`.
+pub(crate) fn save_avro_kmer_count(kmer_count: crate::KmerCount, output_path: &Path) -> Result<()> {
+    let schema = Schema::parse_str(SCHEMA)?;
+    let mut writer = Writer::new(&schema, BufWriter::new(create_output(output_path)?));
+    for kmer in kmer_count {
+        let count = i64::try_from(kmer.count)
+            .map_err(|_| anyhow!("Count of {} is too large for an Avro long", kmer.seq))?;
+        writer.append_ser(AvroRecord {
+            kmer: kmer.seq.into_owned(),
+            count,
+        })?;
+    }
+    writer.into_inner()?.flush()?;
+    Ok(())
+}
+
+/// Read the kmers and counts of the Avro container file at `path`, in file
+/// order
+pub fn read_avro_table(path: &Path) -> Result<Vec<(String, u64)>> {
+    let file = File::open(path).with_context(|| format!("Could not open {:?}", path))?;
+    let reader = Reader::new(BufReader::new(file))
+        .with_context(|| format!("Invalid Avro count table {:?}", path))?;
+    let mut rows = Vec::new();
+    for value in reader {
+        let record: AvroRecord = from_value(&value?)?;
+        let count = u64::try_from(record.count)
+            .map_err(|_| anyhow!("Negative count of {} in {:?}", record.kmer, path))?;
+        rows.push((record.kmer, count));
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{run_fasta_kmer_count, CountOptions, KmerRecord, OutputFormat};
+    use std::borrow::Cow;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_avro_roundtrip() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("seq.fasta");
+        fs::write(&fasta_path, ">a\nATCGGATCG\n")?;
+
+        let output_path = dir.path().join("seq_kmer.avro");
+        let options = CountOptions {
+            format: OutputFormat::Avro,
+            ..CountOptions::new(3)
+        };
+        run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
+
+        let reader = Reader::new(File::open(&output_path)?)?;
+        assert_eq!(reader.writer_schema(), &Schema::parse_str(SCHEMA)?);
+        assert_eq!(
+            read_avro_table(&output_path)?,
+            [
+                ("ATC".to_owned(), 2),
+                ("TCG".to_owned(), 2),
+                ("CGG".to_owned(), 1),
+                ("GAT".to_owned(), 1),
+                ("GGA".to_owned(), 1),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_avro_count_out_of_range() {
+        let dir = tempdir().unwrap();
+        let kmer_count = vec![KmerRecord {
+            seq: Cow::from("AC"),
+            count: u64::MAX,
+        }];
+        let err = save_avro_kmer_count(kmer_count, &dir.path().join("big_kmer.avro"));
+        assert!(err.unwrap_err().to_string().contains("too large"));
+    }
+}
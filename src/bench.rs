@@ -0,0 +1,173 @@
+//! Throughput measurement of the counting pipeline on real inputs
+//!
+//! The pipeline is run repeatedly on one fasta file with phase timings
+//! enabled, and each measurement is summarized by its mean and sample
+//! standard deviation over the runs.
+
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+
+use crate::metadata::RunMetadata;
+use crate::timing::PhaseTimings;
+use crate::{run_fasta_kmer_count_timed, CountOptions};
+
+/// Measurements of one pipeline run
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchRun {
+    pub wall: Duration,
+    pub timings: PhaseTimings,
+}
+
+impl BenchRun {
+    pub fn bases_per_second(&self) -> f64 {
+        self.timings.bases as f64 / self.wall.as_secs_f64()
+    }
+}
+
+/// Run the counting pipeline on `fasta_path` `repeat` times
+///
+/// Counts are saved at `output_path`, or discarded if it is `None`.
+pub fn bench_fasta(
+    fasta_path: &Path,
+    options: &CountOptions,
+    output_path: Option<&Path>,
+    repeat: usize,
+) -> Result<Vec<BenchRun>> {
+    if repeat == 0 {
+        return Err(anyhow!("repeat must be at least 1"));
+    }
+    let metadata = if options.metadata_header {
+        Some(RunMetadata::for_count(options, fasta_path))
+    } else {
+        None
+    };
+    (0..repeat)
+        .map(|_| {
+            let mut timings = PhaseTimings::enabled();
+            let start = Instant::now();
+            run_fasta_kmer_count_timed(
+                fasta_path,
+                options,
+                metadata.as_ref(),
+                output_path,
+                &mut timings,
+            )?;
+            Ok(BenchRun {
+                wall: start.elapsed(),
+                timings,
+            })
+        })
+        .collect()
+}
+
+/// Named measurement extracted from a run
+type Metric = (&'static str, fn(&BenchRun) -> f64);
+
+/// Mean and sample standard deviation of `values`; the deviation of a single value is 0
+pub fn mean_sd(values: &[f64]) -> (f64, f64) {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    if values.len() < 2 {
+        return (mean, 0.0);
+    }
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    (mean, variance.sqrt())
+}
+
+/// Write a report of `runs` as `metric\tmean\tsd` rows after a summary of the input
+pub fn write_report<W: Write>(runs: &[BenchRun], mut out: W) -> Result<()> {
+    let first = runs.first().ok_or_else(|| anyhow!("no runs to report"))?;
+    writeln!(out, "runs\t{}", runs.len())?;
+    writeln!(out, "records\t{}", first.timings.records)?;
+    writeln!(out, "bases\t{}", first.timings.bases)?;
+    writeln!(
+        out,
+        "peak_counter_size\t{}",
+        first.timings.peak_counter_size
+    )?;
+
+    let metrics: [Metric; 6] = [
+        ("wall_s", |r| r.wall.as_secs_f64()),
+        ("bases_per_s", BenchRun::bases_per_second),
+        ("parse_s", |r| r.timings.parse.as_secs_f64()),
+        ("count_s", |r| r.timings.count.as_secs_f64()),
+        ("sort_s", |r| r.timings.sort.as_secs_f64()),
+        ("write_s", |r| r.timings.write.as_secs_f64()),
+    ];
+    writeln!(out, "metric\tmean\tsd")?;
+    for (name, metric) in &metrics {
+        let values: Vec<f64> = runs.iter().map(metric).collect();
+        let (mean, sd) = mean_sd(&values);
+        writeln!(out, "{}\t{:.6}\t{:.6}", name, mean, sd)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::random_sequence;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_mean_sd() {
+        assert_eq!(mean_sd(&[2.0]), (2.0, 0.0));
+        let (mean, sd) = mean_sd(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]);
+        assert_eq!(mean, 5.0);
+        assert!((sd - (32.0f64 / 7.0).sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_bench_report() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("tiny.fasta");
+        let sequence = String::from_utf8(random_sequence(2000, 3))?;
+        std::fs::write(&fasta_path, format!(">a\n{}\n>b\n{}\n", sequence, sequence))?;
+        let output_path = dir.path().join("tiny_kmer.txt");
+
+        let runs = bench_fasta(&fasta_path, &CountOptions::new(5), Some(&output_path), 2)?;
+        assert_eq!(runs.len(), 2);
+        for run in &runs {
+            assert_eq!(run.timings.records, 2);
+            assert_eq!(run.timings.bases, 4000);
+            assert!(run.timings.peak_counter_size > 0);
+            for phase in &[
+                run.timings.parse,
+                run.timings.count,
+                run.timings.sort,
+                run.timings.write,
+            ] {
+                assert!(*phase > Duration::default());
+            }
+        }
+
+        let mut out = Vec::new();
+        write_report(&runs, &mut out)?;
+        let report = String::from_utf8(out)?;
+        let keys: Vec<&str> = report
+            .lines()
+            .map(|line| line.split('\t').next().unwrap())
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                "runs",
+                "records",
+                "bases",
+                "peak_counter_size",
+                "metric",
+                "wall_s",
+                "bases_per_s",
+                "parse_s",
+                "count_s",
+                "sort_s",
+                "write_s"
+            ]
+        );
+        assert!(report.starts_with("runs\t2\nrecords\t2\nbases\t4000\n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+//! Strand symmetry of kmer counts (Chargaff's second parity rule)
+//!
+//! Within a single strand of most genomes, a kmer occurs about as often as
+//! its reverse complement. Each kmer is paired with its reverse complement,
+//! and the pair's ratio is the smaller count over the larger, 1 for perfect
+//! parity. Pairs below a minimum ratio are flagged. The whole table is
+//! summarized by its parity, `1 - sum |a - b| / sum (a + b)` over all pairs.
+//! Palindromic kmers, their own reverse complement, and kmers with bases
+//! other than ACGT are left out.
+
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::Result;
+use log::info;
+
+use crate::metadata::{write_header, RunMetadata};
+use crate::{create_output, fasta_kmer_counter, reverse_complement, CountOptions};
+
+/// Counts of a kmer and its reverse complement
+#[derive(Debug, Clone, PartialEq)]
+pub struct StrandPair {
+    /// the lexicographically smaller of the two
+    pub kmer: String,
+    pub reverse_complement: String,
+    pub count: u64,
+    pub reverse_count: u64,
+}
+
+impl StrandPair {
+    /// Smaller count over larger count, from 0 to 1
+    pub fn ratio(&self) -> f64 {
+        let (low, high) = if self.count < self.reverse_count {
+            (self.count, self.reverse_count)
+        } else {
+            (self.reverse_count, self.count)
+        };
+        if high == 0 {
+            1.0
+        } else {
+            low as f64 / high as f64
+        }
+    }
+}
+
+/// Pairs of the kmers of `counter` with their reverse complements, sorted by
+/// kmer
+///
+/// A kmer whose reverse complement was not counted is paired with a count of 0.
+pub fn strand_pairs(counter: &HashMap<String, u64>) -> Vec<StrandPair> {
+    let mut pairs = Vec::new();
+    for (kmer, &count) in counter {
+        if !kmer
+            .bytes()
+            .all(|base| b"ACGT".contains(&base.to_ascii_uppercase()))
+        {
+            continue;
+        }
+        let reverse = String::from_utf8(reverse_complement(kmer.as_bytes()))
+            .expect("reverse complement of ASCII is ASCII");
+        let reverse_count = counter.get(&reverse).copied().unwrap_or(0);
+        // each pair is built once, from its smaller kmer, or from the larger
+        // one when the smaller was not counted
+        if *kmer < reverse || (*kmer > reverse && reverse_count == 0) {
+            let (kmer, reverse, count, reverse_count) = if *kmer < reverse {
+                (kmer.clone(), reverse, count, reverse_count)
+            } else {
+                (reverse, kmer.clone(), reverse_count, count)
+            };
+            pairs.push(StrandPair {
+                kmer,
+                reverse_complement: reverse,
+                count,
+                reverse_count,
+            });
+        }
+    }
+    pairs.sort_by(|a, b| a.kmer.cmp(&b.kmer));
+    pairs
+}
+
+/// Overall strand symmetry of `pairs`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParitySummary {
+    pub pairs: usize,
+    /// pairs with a ratio below the minimum
+    pub flagged: usize,
+    /// `1 - sum |a - b| / sum (a + b)`, 1 when every pair is balanced
+    pub parity: f64,
+}
+
+/// Summarize `pairs`, flagging those with a ratio below `min_ratio`
+pub fn parity_summary(pairs: &[StrandPair], min_ratio: f64) -> ParitySummary {
+    let mut difference = 0u128;
+    let mut total = 0u128;
+    for pair in pairs {
+        difference += pair.count.abs_diff(pair.reverse_count) as u128;
+        total += pair.count as u128 + pair.reverse_count as u128;
+    }
+    ParitySummary {
+        pairs: pairs.len(),
+        flagged: pairs.iter().filter(|pair| pair.ratio() < min_ratio).count(),
+        parity: if total == 0 {
+            1.0
+        } else {
+            1.0 - difference as f64 / total as f64
+        },
+    }
+}
+
+/// Write `pairs` as a tab-separated table, with a `flagged` column of 1 for
+/// ratios below `min_ratio`
+pub fn write_strand_pairs<W: Write>(
+    pairs: &[StrandPair],
+    min_ratio: f64,
+    mut out: W,
+) -> Result<()> {
+    writeln!(
+        out,
+        "kmer\treverse_complement\tcount\treverse_count\tratio\tflagged"
+    )?;
+    for pair in pairs {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{:.6}\t{}",
+            pair.kmer,
+            pair.reverse_complement,
+            pair.count,
+            pair.reverse_count,
+            pair.ratio(),
+            u8::from(pair.ratio() < min_ratio)
+        )?;
+    }
+    Ok(())
+}
+
+/// Save the strand pairs of the kmers of the fasta file at `fasta_path`,
+/// counted as configured by `options`, at `output_path` after the `metadata`
+/// header if given, which also records the parity summary
+///
+/// Kmers outside the GC window of `options` are left out.
+pub fn run_fasta_chargaff(
+    fasta_path: &Path,
+    options: &CountOptions,
+    min_ratio: f64,
+    metadata: Option<&RunMetadata>,
+    output_path: &Path,
+) -> Result<ParitySummary> {
+    let mut counter = fasta_kmer_counter(fasta_path, options)?;
+    counter.retain(|kmer, _| options.in_gc_window(kmer));
+    let pairs = strand_pairs(&counter);
+    let summary = parity_summary(&pairs, min_ratio);
+    info!(
+        "{:?}: strand parity {:.6}, {} of {} kmer pairs below a ratio of {}",
+        fasta_path, summary.parity, summary.flagged, summary.pairs, min_ratio
+    );
+
+    let metadata = metadata.map(|metadata| {
+        metadata
+            .clone()
+            .with("chargaff_min_ratio", min_ratio)
+            .with("chargaff_pairs", summary.pairs)
+            .with("chargaff_flagged", summary.flagged)
+            .with("chargaff_parity", format!("{:.6}", summary.parity))
+    });
+    let mut out = BufWriter::new(create_output(output_path)?);
+    write_header(metadata.as_ref(), &mut out)?;
+    write_strand_pairs(&pairs, min_ratio, &mut out)?;
+    out.flush()?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_strand_pairs() {
+        let counter: HashMap<String, u64> = [("AAC", 3), ("GTT", 1), ("ACG", 2), ("TTT", 4)]
+            .iter()
+            .map(|&(kmer, count)| (kmer.to_owned(), count))
+            .chain([("ANC".to_owned(), 7), ("AT".to_owned(), 2)])
+            .collect();
+        let pairs = strand_pairs(&counter);
+        let rows: Vec<(&str, &str, u64, u64)> = pairs
+            .iter()
+            .map(|p| {
+                (
+                    p.kmer.as_str(),
+                    p.reverse_complement.as_str(),
+                    p.count,
+                    p.reverse_count,
+                )
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ("AAA", "TTT", 0, 4),
+                ("AAC", "GTT", 3, 1),
+                ("ACG", "CGT", 2, 0),
+            ]
+        );
+        assert_eq!(pairs[1].ratio(), 1.0 / 3.0);
+
+        let summary = parity_summary(&pairs, 0.5);
+        assert_eq!(summary.pairs, 3);
+        assert_eq!(summary.flagged, 3);
+        // |0 - 4| + |3 - 1| + |2 - 0| over 4 + 4 + 2
+        assert!((summary.parity - (1.0 - 8.0 / 10.0)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_run_fasta_chargaff() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("seq.fasta");
+        // AACGTT is its own reverse complement, so every pair is balanced
+        // within it; the trailing A adds an unpaired TTA
+        fs::write(&fasta_path, ">a\nAACGTTA\n")?;
+        let output_path = dir.path().join("seq_chargaff.txt");
+        let summary =
+            run_fasta_chargaff(&fasta_path, &CountOptions::new(3), 0.5, None, &output_path)?;
+        assert_eq!(
+            fs::read_to_string(&output_path)?,
+            "kmer\treverse_complement\tcount\treverse_count\tratio\tflagged\n\
+             AAC\tGTT\t1\t1\t1.000000\t0\n\
+             ACG\tCGT\t1\t1\t1.000000\t0\n\
+             TAA\tTTA\t0\t1\t0.000000\t1\n"
+        );
+        assert_eq!(summary.pairs, 3);
+        assert_eq!(summary.flagged, 1);
+        assert!((summary.parity - (1.0 - 1.0 / 5.0)).abs() < 1e-12);
+        Ok(())
+    }
+}
@@ -0,0 +1,629 @@
+//! Resumable counting of a single large fasta file
+//!
+//! While counting, the counts accumulated, with the tallies of the records
+//! counted, the number of records consumed, and the byte offset at which the
+//! next record begins are periodically saved to a checkpoint file. A
+//! restarted run reloads them, seeks the input to that offset, and continues;
+//! a gzipped input, which cannot be seeked, is decompressed up to it without
+//! parsing. Checkpoints are only taken between records. Records are counted as
+//! by `run_fasta_kmer_count_timed`, so the table, the warnings, and the
+//! `records_*` header entries of a resumed run are those of an uninterrupted
+//! one. The records seen by `dedup_records` are not saved, so it cannot be
+//! checkpointed, and `deadline` is not honored.
+//!
+//! Each checkpoint carries a fingerprint of the count settings and the input
+//! contents; a checkpoint whose fingerprint does not match is ignored. The
+//! file is written to a temporary path and renamed into place, so a crash
+//! while checkpointing leaves the previous checkpoint intact.
+//!
+//! Layout: the magic bytes `KMCK2`, then varints for the fingerprint, the
+//! number of records consumed, the offset of the next record, the records
+//! processed, too short, failed, with a repeated id, and with suspect bases,
+//! and the windows skipped as ambiguous and as masked. Then come the record
+//! ids seen, the suspect bytes, and the kmers, each as a varint number of
+//! entries followed by the entries: an id is a varint byte length and its
+//! bytes, a suspect byte is the byte and a varint count, and a kmer is a
+//! varint byte length, the kmer bytes, and a varint count.
+
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+
+use crate::inputs::content_hash;
+use crate::metadata::RunMetadata;
+use crate::seqio::{OffsetReader, SequenceReader};
+use crate::sketch::hash_bytes;
+use crate::timing::PhaseTimings;
+use crate::variants::VariantMask;
+use crate::varint::{read_varint, write_varint};
+use crate::{create_file, is_fastq, is_gzipped, open_input, CountOptions, InputCounts};
+
+/// Leading bytes identifying a checkpoint file
+pub const MAGIC: &[u8; 5] = b"KMCK2";
+
+/// When checkpoints are taken
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckpointConfig {
+    pub path: PathBuf,
+    /// checkpoint once this many bases have been counted since the last one
+    pub every_bases: Option<u64>,
+    /// checkpoint once this much time has passed since the last one
+    pub every: Option<Duration>,
+}
+
+impl CheckpointConfig {
+    /// Checkpoints for the table at `output_path`, saved beside it with `.ckpt` appended
+    pub fn for_output(output_path: &Path) -> Self {
+        let mut path = OsString::from(output_path.as_os_str());
+        path.push(".ckpt");
+        CheckpointConfig {
+            path: PathBuf::from(path),
+            every_bases: None,
+            every: None,
+        }
+    }
+}
+
+/// Saved counting progress
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    pub fingerprint: u64,
+    /// records fully counted
+    pub records: u64,
+    /// byte offset of the next record in the decompressed input
+    pub offset: u64,
+    pub counts: InputCounts,
+}
+
+impl Checkpoint {
+    /// Save atomically to `path`
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut tmp = OsString::from(path.as_os_str());
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+
+        let counts = &self.counts;
+        let mut out = BufWriter::new(create_file(&tmp)?);
+        out.write_all(MAGIC)?;
+        for value in [
+            self.fingerprint,
+            self.records,
+            self.offset,
+            counts.tally.processed,
+            counts.tally.too_short,
+            counts.tally.failed,
+            counts.ids.duplicates,
+            counts.suspect.records,
+            counts.skipped_ambiguous,
+            counts.masked,
+        ] {
+            write_varint(value, &mut out)?;
+        }
+        write_varint(counts.ids.seen.len() as u64, &mut out)?;
+        for id in &counts.ids.seen {
+            write_bytes(id.as_bytes(), &mut out)?;
+        }
+        write_varint(counts.suspect.counts.len() as u64, &mut out)?;
+        for (&base, &count) in &counts.suspect.counts {
+            out.write_all(&[base])?;
+            write_varint(count, &mut out)?;
+        }
+        write_varint(counts.counter.len() as u64, &mut out)?;
+        for (kmer, &count) in &counts.counter {
+            write_bytes(kmer.as_bytes(), &mut out)?;
+            write_varint(count, &mut out)?;
+        }
+        out.into_inner()?.sync_all()?;
+        fs::rename(&tmp, path)
+            .with_context(|| format!("Could not move checkpoint into place at {:?}", path))
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Could not open {:?}", path))?;
+        let mut input = BufReader::new(file);
+
+        let mut magic = [0u8; 5];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(anyhow!("{:?} is not a checkpoint", path));
+        }
+        let mut values = [0u64; 10];
+        for value in values.iter_mut() {
+            *value = read_varint(&mut input)?;
+        }
+        let [fingerprint, records, offset, processed, too_short, failed, duplicates, suspect_records, skipped_ambiguous, masked] =
+            values;
+        let mut counts = InputCounts {
+            skipped_ambiguous,
+            masked,
+            ..InputCounts::default()
+        };
+        counts.tally.processed = processed;
+        counts.tally.too_short = too_short;
+        counts.tally.failed = failed;
+        counts.ids.duplicates = duplicates;
+        counts.suspect.records = suspect_records;
+        for _ in 0..read_varint(&mut input)? {
+            counts.ids.seen.insert(read_string(&mut input)?);
+        }
+        for _ in 0..read_varint(&mut input)? {
+            let mut base = [0u8];
+            input.read_exact(&mut base)?;
+            counts
+                .suspect
+                .counts
+                .insert(base[0], read_varint(&mut input)?);
+        }
+        for _ in 0..read_varint(&mut input)? {
+            let kmer = read_string(&mut input)?;
+            counts.counter.insert(kmer, read_varint(&mut input)?);
+        }
+        Ok(Checkpoint {
+            fingerprint,
+            records,
+            offset,
+            counts,
+        })
+    }
+}
+
+fn write_bytes<W: Write>(bytes: &[u8], out: &mut W) -> Result<()> {
+    write_varint(bytes.len() as u64, out)?;
+    out.write_all(bytes)?;
+    Ok(())
+}
+
+fn read_string<R: Read>(input: &mut R) -> Result<String> {
+    let mut bytes = vec![0; read_varint(input)? as usize];
+    input.read_exact(&mut bytes)?;
+    Ok(String::from_utf8(bytes)?)
+}
+
+/// Fingerprint of counting the fasta file at `fasta_path` with `options`
+///
+/// Every field of `options` that changes the counts is part of it; the
+/// others are named as ignored, so a new field must be placed in one or the
+/// other.
+pub fn fingerprint(fasta_path: &Path, options: &CountOptions) -> Result<u64> {
+    let CountOptions {
+        k,
+        mismatches,
+        library_type,
+        translate,
+        stop_codons,
+        canonical,
+        canonical_rule,
+        case,
+        skip_ambiguous,
+        orf_min_len,
+        duplicate_ids,
+        dedup_records,
+        dedup_threshold,
+        variant_mask,
+        // these change only how the counts are filtered and saved, or when
+        // counting stops
+        indexed_output: _,
+        log_tf: _,
+        varint_output: _,
+        format: _,
+        metadata_header: _,
+        gc_min: _,
+        gc_max: _,
+        top_n: _,
+        min_count: _,
+        empty_inputs: _,
+        deadline: _,
+        split: _,
+        minimizer_shards: _,
+        large_k: _,
+        tm: _,
+        revcomp_column: _,
+        sink: _,
+    } = options;
+    let settings = format!(
+        "{} {} {:?} {} {:?} {} {:?} {:?} {} {:?} {:?} {} {:?} {:?}",
+        k,
+        mismatches,
+        library_type,
+        translate,
+        stop_codons,
+        canonical,
+        canonical_rule,
+        case,
+        skip_ambiguous,
+        orf_min_len,
+        duplicate_ids,
+        dedup_records,
+        dedup_threshold,
+        variant_mask.as_ref().map(VariantMask::by_record)
+    );
+    Ok(hash_bytes(settings.as_bytes(), content_hash(fasta_path)?))
+}
+
+/// Open the input at `fasta_path` at byte `offset` of its decompressed bytes
+fn open_input_at(fasta_path: &Path, offset: u64) -> Result<Box<dyn Read + Send>> {
+    if is_gzipped(fasta_path) {
+        let mut input = open_input(fasta_path)?;
+        io::copy(&mut (&mut input).take(offset), &mut io::sink())?;
+        Ok(input)
+    } else {
+        let mut file =
+            File::open(fasta_path).with_context(|| format!("Could not open {:?}", fasta_path))?;
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(Box::new(file))
+    }
+}
+
+/// Count every record of `fasta_path`, checkpointing as configured by
+/// `config`
+///
+/// With `resume`, counting continues from a matching checkpoint at
+/// `config.path`. After each checkpoint, `interrupt` is called with the number
+/// of records counted so far; if it returns true, counting stops and `None` is
+/// returned.
+pub fn count_fasta_checkpointed<F>(
+    fasta_path: &Path,
+    options: &CountOptions,
+    config: &CheckpointConfig,
+    resume: bool,
+    mut interrupt: F,
+) -> Result<Option<InputCounts>>
+where
+    F: FnMut(u64) -> bool,
+{
+    if options.dedup_records {
+        return Err(anyhow!(
+            "Collapsing repeated records cannot be checkpointed"
+        ));
+    }
+    let fingerprint = fingerprint(fasta_path, options)?;
+    let mut state = Checkpoint {
+        fingerprint,
+        records: 0,
+        offset: 0,
+        counts: InputCounts::new(options),
+    };
+    if resume && config.path.exists() {
+        match Checkpoint::load(&config.path) {
+            Ok(checkpoint) if checkpoint.fingerprint == fingerprint => {
+                info!(
+                    "Resuming {:?} after {} records, at byte {}",
+                    fasta_path, checkpoint.records, checkpoint.offset
+                );
+                state = checkpoint;
+            }
+            Ok(_) => warn!(
+                "Ignoring checkpoint {:?}: input or settings changed",
+                config.path
+            ),
+            Err(err) => warn!("Ignoring checkpoint {:?}: {}", config.path, err),
+        }
+    }
+
+    let fastq = is_fastq(fasta_path);
+    let input = BufReader::new(open_input_at(fasta_path, state.offset)?);
+    let reader = OffsetReader::new(input, fastq, state.offset);
+    let next_record = reader.next_record();
+    let mut timings = PhaseTimings::disabled();
+    let mut bases_since = 0u64;
+    let mut last = Instant::now();
+    for record in SequenceReader::new(reader, fastq).records() {
+        let record = record?;
+        state.counts.add_record(&record, options, &mut timings)?;
+        state.records += 1;
+        bases_since += record.seq().len() as u64;
+
+        let due = config.every_bases.is_some_and(|n| bases_since >= n)
+            || config.every.is_some_and(|d| last.elapsed() >= d);
+        if due {
+            state.offset = next_record.load(Ordering::Relaxed);
+            state.save(&config.path)?;
+            bases_since = 0;
+            last = Instant::now();
+            if interrupt(state.records) {
+                return Ok(None);
+            }
+        }
+    }
+    Ok(Some(state.counts))
+}
+
+/// Save the kmer counts of all records of `fasta_path` at `output_path`,
+/// after the `metadata` header if given, as `run_fasta_kmer_count_timed`
+/// does, checkpointing as configured by `config`
+///
+/// The checkpoint is removed once the table is saved.
+pub fn run_fasta_kmer_count_checkpointed(
+    fasta_path: &Path,
+    options: &CountOptions,
+    config: &CheckpointConfig,
+    resume: bool,
+    metadata: Option<&RunMetadata>,
+    output_path: &Path,
+) -> Result<()> {
+    let counts = count_fasta_checkpointed(fasta_path, options, config, resume, |_| false)?
+        .expect("counting is never interrupted");
+    counts.save(
+        fasta_path,
+        options,
+        metadata,
+        Some(output_path),
+        false,
+        &mut PhaseTimings::disabled(),
+    )?;
+    if config.path.exists() {
+        fs::remove_file(&config.path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::read_kmer_table;
+    use crate::testutil::random_sequence;
+    use crate::{CaseHandling, DuplicateIds};
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use tempfile::tempdir;
+
+    fn fixture(dir: &Path) -> Result<PathBuf> {
+        let path = dir.join("genome.fasta");
+        let mut fasta = String::new();
+        for seed in 1..=5 {
+            let seq = String::from_utf8(random_sequence(300, seed))?;
+            fasta.push_str(&format!(">chr{}\n{}\n", seed, seq));
+        }
+        fs::write(&path, fasta)?;
+        Ok(path)
+    }
+
+    #[test]
+    fn test_checkpoint_roundtrip() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("c.ckpt");
+        let mut counts = InputCounts {
+            counter: vec![("ACG".to_owned(), 4), ("NNN".to_owned(), 1)]
+                .into_iter()
+                .collect(),
+            ..InputCounts::default()
+        };
+        counts.tally.processed = 2;
+        counts.tally.too_short = 1;
+        counts.ids.resolve("chr1", DuplicateIds::Merge)?;
+        counts.ids.resolve("chr1", DuplicateIds::Merge)?;
+        counts.suspect.check("chr1", b"ACNNR", CaseHandling::Upper);
+        counts.skipped_ambiguous = 5;
+        let checkpoint = Checkpoint {
+            fingerprint: 99,
+            records: 3,
+            offset: 1234,
+            counts,
+        };
+        checkpoint.save(&path)?;
+
+        let loaded = Checkpoint::load(&path)?;
+        assert_eq!(
+            (loaded.fingerprint, loaded.records, loaded.offset),
+            (99, 3, 1234)
+        );
+        assert_eq!(loaded.counts.counter, checkpoint.counts.counter);
+        assert_eq!(loaded.counts.tally, checkpoint.counts.tally);
+        assert_eq!(loaded.counts.ids.seen, checkpoint.counts.ids.seen);
+        assert_eq!(loaded.counts.ids.duplicates, 1);
+        assert_eq!(loaded.counts.suspect, checkpoint.counts.suspect);
+        assert_eq!(loaded.counts.skipped_ambiguous, 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resume_matches_uninterrupted_run() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = fixture(dir.path())?;
+        let fasta = fs::read_to_string(&fasta_path)?;
+        let gzipped_path = dir.path().join("genome.fasta.gz");
+        let mut encoder = GzEncoder::new(create_file(&gzipped_path)?, Compression::default());
+        encoder.write_all(fasta.as_bytes())?;
+        encoder.finish()?;
+        let options = CountOptions::new(4);
+
+        for path in [&fasta_path, &gzipped_path] {
+            let config = CheckpointConfig {
+                every_bases: Some(600),
+                ..CheckpointConfig::for_output(&dir.path().join("genome_kmer.txt"))
+            };
+            let full = count_fasta_checkpointed(path, &options, &config, false, |_| false)?
+                .map(|counts| counts.counter);
+
+            // stop right after the first checkpoint, two records in, which
+            // resumes at the third record
+            let interrupted = count_fasta_checkpointed(path, &options, &config, false, |_| true)?;
+            assert!(interrupted.is_none());
+            let checkpoint = Checkpoint::load(&config.path)?;
+            assert_eq!(checkpoint.records, 2);
+            assert_eq!(checkpoint.offset, fasta.find(">chr3").unwrap() as u64);
+
+            let resumed = count_fasta_checkpointed(path, &options, &config, true, |_| false)?
+                .map(|counts| counts.counter);
+            assert_eq!(resumed, full);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_changed_settings_invalidate_checkpoint() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = fixture(dir.path())?;
+        let config = CheckpointConfig {
+            every_bases: Some(600),
+            ..CheckpointConfig::for_output(&dir.path().join("genome_kmer.txt"))
+        };
+        let uncheckpointed = CheckpointConfig::for_output(&dir.path().join("other_kmer.txt"));
+        let options = CountOptions::new(5);
+        let full =
+            count_fasta_checkpointed(&fasta_path, &options, &uncheckpointed, false, |_| false)?;
+
+        // a checkpoint of k = 4 counts must not leak into a k = 5 run
+        count_fasta_checkpointed(&fasta_path, &CountOptions::new(4), &config, false, |_| true)?;
+        let resumed = count_fasta_checkpointed(&fasta_path, &options, &config, true, |_| false)?;
+        assert_eq!(
+            resumed.map(|counts| counts.counter),
+            full.map(|counts| counts.counter)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_changed_case_invalidates_checkpoint() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("masked.fasta");
+        fs::write(
+            &fasta_path,
+            ">a\nACGTacgtAC\n>b\nacgtACGTac\n>c\nACGTACGT\n",
+        )?;
+        let config = CheckpointConfig {
+            every_bases: Some(10),
+            ..CheckpointConfig::for_output(&dir.path().join("masked_kmer.txt"))
+        };
+        let uncheckpointed = CheckpointConfig::for_output(&dir.path().join("other_kmer.txt"));
+        let sensitive = CountOptions {
+            case: CaseHandling::Sensitive,
+            ..CountOptions::new(3)
+        };
+        let full =
+            count_fasta_checkpointed(&fasta_path, &sensitive, &uncheckpointed, false, |_| false)?;
+
+        // a checkpoint of uppercased counts must not leak into a case-sensitive run
+        count_fasta_checkpointed(&fasta_path, &CountOptions::new(3), &config, false, |_| true)?;
+        assert_ne!(
+            fingerprint(&fasta_path, &CountOptions::new(3))?,
+            fingerprint(&fasta_path, &sensitive)?
+        );
+        let resumed = count_fasta_checkpointed(&fasta_path, &sensitive, &config, true, |_| false)?;
+        assert_eq!(
+            resumed.map(|counts| counts.counter),
+            full.map(|counts| counts.counter)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_fingerprint_covers_counting_options() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = fixture(dir.path())?;
+        let options = CountOptions::new(4);
+        let base = fingerprint(&fasta_path, &options)?;
+        let skipping = CountOptions {
+            skip_ambiguous: true,
+            ..CountOptions::new(4)
+        };
+        assert_ne!(fingerprint(&fasta_path, &skipping)?, base);
+        let deduplicating = CountOptions {
+            dedup_records: true,
+            ..CountOptions::new(4)
+        };
+        assert_ne!(fingerprint(&fasta_path, &deduplicating)?, base);
+        // filters applied when saving leave the counts, and so a checkpoint, valid
+        let filtered = CountOptions {
+            min_count: 2,
+            top_n: Some(10),
+            ..CountOptions::new(4)
+        };
+        assert_eq!(fingerprint(&fasta_path, &filtered)?, base);
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_checkpointed_removes_checkpoint() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = fixture(dir.path())?;
+        let output_path = dir.path().join("genome_kmer.txt");
+        let config = CheckpointConfig {
+            every_bases: Some(600),
+            ..CheckpointConfig::for_output(&output_path)
+        };
+        run_fasta_kmer_count_checkpointed(
+            &fasta_path,
+            &CountOptions::new(4),
+            &config,
+            true,
+            None,
+            &output_path,
+        )?;
+
+        assert!(!config.path.exists());
+        let total: u64 = read_kmer_table(&output_path)?.iter().map(|(_, c)| c).sum();
+        assert_eq!(total, 5 * (300 - 4 + 1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_resumed_table_matches_uncheckpointed_run() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("genome.fasta");
+        // a repeated id, a record too short, and suspect bases on both sides
+        // of the checkpoint
+        fs::write(
+            &fasta_path,
+            ">a\nACGTNACGTA\n>b\nAC\n>a\nTTGCARACGT\n>c\nGGGCCCATAN\n",
+        )?;
+        let options = CountOptions {
+            duplicate_ids: DuplicateIds::Suffix,
+            skip_ambiguous: true,
+            ..CountOptions::new(3)
+        };
+        let metadata = RunMetadata::for_count(&options, &fasta_path);
+
+        let expected_path = dir.path().join("expected_kmer.txt");
+        crate::run_fasta_kmer_count_timed(
+            &fasta_path,
+            &options,
+            Some(&metadata),
+            Some(&expected_path),
+            &mut PhaseTimings::disabled(),
+        )?;
+
+        let output_path = dir.path().join("genome_kmer.txt");
+        let config = CheckpointConfig {
+            every_bases: Some(12),
+            ..CheckpointConfig::for_output(&output_path)
+        };
+        count_fasta_checkpointed(&fasta_path, &options, &config, false, |_| true)?;
+        assert_eq!(Checkpoint::load(&config.path)?.records, 2);
+        run_fasta_kmer_count_checkpointed(
+            &fasta_path,
+            &options,
+            &config,
+            true,
+            Some(&metadata),
+            &output_path,
+        )?;
+
+        let table = fs::read_to_string(&output_path)?;
+        assert!(table.contains("# records_duplicate_ids: 1\n"));
+        assert!(table.contains("# records_skipped_too_short: 1\n"));
+        assert_eq!(table, fs::read_to_string(&expected_path)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dedup_records_cannot_be_checkpointed() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = fixture(dir.path())?;
+        let config = CheckpointConfig::for_output(&dir.path().join("genome_kmer.txt"));
+        let options = CountOptions {
+            dedup_records: true,
+            ..CountOptions::new(4)
+        };
+        assert!(
+            count_fasta_checkpointed(&fasta_path, &options, &config, false, |_| false).is_err()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,370 @@
+//! Pairwise kmer distances between samples and UPGMA clustering
+//!
+//! Each sample is counted in memory into a kmer profile, optionally weighted
+//! by `log_tf`. Profiles are compared pairwise by cosine or Bray-Curtis
+//! distance, or by the D2S or D2* dissimilarity of raw counts (see `d2`), and
+//! the distance matrix can be clustered into a rooted tree by UPGMA (average
+//! linkage), written in Newick format.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+
+use crate::d2::d2_statistics_with_backgrounds;
+use crate::sketch::Sketch;
+use crate::{fasta_kmer_counter, log_tf, CountOptions};
+
+/// Kmer weights of one sample
+pub type Profile = HashMap<String, f64>;
+
+/// Distance between two kmer profiles
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DistanceMetric {
+    /// one minus the cosine similarity of the weight vectors
+    Cosine,
+    /// summed absolute differences over summed weights
+    BrayCurtis,
+    /// normalized D2S of counts centered on each sample's background
+    D2S,
+    /// normalized D2* of counts centered on each sample's background
+    D2Star,
+}
+
+impl DistanceMetric {
+    pub const VARIANTS: &'static [&'static str] = &["cosine", "bray-curtis", "d2s", "d2star"];
+
+    /// Distance between profiles `a` and `b`, from 0 (identical) to 1
+    pub fn distance(self, a: &Profile, b: &Profile) -> f64 {
+        match self {
+            DistanceMetric::Cosine => {
+                let dot: f64 = a
+                    .iter()
+                    .filter_map(|(kmer, x)| b.get(kmer).map(|y| x * y))
+                    .sum();
+                let norm = |p: &Profile| p.values().map(|x| x * x).sum::<f64>().sqrt();
+                let norms = norm(a) * norm(b);
+                if norms == 0.0 {
+                    return if a.is_empty() && b.is_empty() {
+                        0.0
+                    } else {
+                        1.0
+                    };
+                }
+                (1.0 - dot / norms).max(0.0)
+            }
+            DistanceMetric::BrayCurtis => {
+                let shared: f64 = a
+                    .iter()
+                    .filter_map(|(kmer, x)| b.get(kmer).map(|y| x.min(*y)))
+                    .sum();
+                let total: f64 = a.values().chain(b.values()).sum();
+                if total == 0.0 {
+                    return 0.0;
+                }
+                1.0 - 2.0 * shared / total
+            }
+            DistanceMetric::D2S => d2_statistics_with_backgrounds(a, b).d2s_dissimilarity,
+            DistanceMetric::D2Star => d2_statistics_with_backgrounds(a, b).d2_star_dissimilarity,
+        }
+    }
+}
+
+impl FromStr for DistanceMetric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "cosine" => Ok(DistanceMetric::Cosine),
+            "bray-curtis" | "braycurtis" => Ok(DistanceMetric::BrayCurtis),
+            "d2s" => Ok(DistanceMetric::D2S),
+            "d2star" | "d2*" => Ok(DistanceMetric::D2Star),
+            _ => Err(format!("Unknown distance metric: {}", s)),
+        }
+    }
+}
+
+/// Kmer profile of `counter`, with counts scaled by `log_tf` if
+/// `options.log_tf` is set
+pub fn counter_profile(counter: HashMap<String, u64>, options: &CountOptions) -> Profile {
+    counter
+        .into_iter()
+        .map(|(kmer, count)| {
+            let weight = if options.log_tf {
+                log_tf(count)
+            } else {
+                count as f64
+            };
+            (kmer, weight)
+        })
+        .collect()
+}
+
+/// Kmer profile of all records of the fasta file at `fasta_path`, with counts
+/// scaled by `log_tf` if `options.log_tf` is set
+pub fn fasta_profile(fasta_path: &Path, options: &CountOptions) -> Result<Profile> {
+    Ok(counter_profile(
+        fasta_kmer_counter(fasta_path, options)?,
+        options,
+    ))
+}
+
+/// Symmetric matrix of pairwise distances between `profiles`
+pub fn distance_matrix(profiles: &[Profile], metric: DistanceMetric) -> Vec<Vec<f64>> {
+    let n = profiles.len();
+    let mut matrix = vec![vec![0.0; n]; n];
+    for i in 0..n {
+        for j in i + 1..n {
+            let d = metric.distance(&profiles[i], &profiles[j]);
+            matrix[i][j] = d;
+            matrix[j][i] = d;
+        }
+    }
+    matrix
+}
+
+/// Symmetric matrix of one minus the estimated Jaccard similarity between
+/// `sketches`
+///
+/// Fails if two sketches differ in `k` or seed.
+pub fn sketch_distance_matrix(sketches: &[Sketch]) -> Result<Vec<Vec<f64>>> {
+    let n = sketches.len();
+    let mut matrix = vec![vec![0.0; n]; n];
+    for i in 0..n {
+        for j in i + 1..n {
+            let (a, b) = (&sketches[i], &sketches[j]);
+            if !a.is_comparable(b) {
+                bail!(
+                    "Cannot compare sketches with k = {} and seed {} to k = {} and seed {}",
+                    a.k,
+                    a.seed,
+                    b.k,
+                    b.seed
+                );
+            }
+            let d = 1.0 - a.jaccard(b);
+            matrix[i][j] = d;
+            matrix[j][i] = d;
+        }
+    }
+    Ok(matrix)
+}
+
+/// Write `matrix` as a tab-separated table with a row and column per label
+pub fn write_matrix<W: Write>(labels: &[String], matrix: &[Vec<f64>], mut out: W) -> Result<()> {
+    writeln!(out, "sample\t{}", labels.join("\t"))?;
+    for (label, row) in labels.iter().zip(matrix) {
+        let row: Vec<String> = row.iter().map(|d| format!("{:.6}", d)).collect();
+        writeln!(out, "{}\t{}", label, row.join("\t"))?;
+    }
+    Ok(())
+}
+
+/// Rooted ultrametric tree over the samples of a distance matrix
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tree {
+    /// sample, as an index into the distance matrix
+    Leaf(usize),
+    /// merge of two subtrees at `height`, half the distance between them
+    Node {
+        left: Box<Tree>,
+        right: Box<Tree>,
+        height: f64,
+    },
+}
+
+impl Tree {
+    pub fn height(&self) -> f64 {
+        match self {
+            Tree::Leaf(_) => 0.0,
+            Tree::Node { height, .. } => *height,
+        }
+    }
+
+    /// Newick representation with branch lengths, leaves named by `labels`
+    pub fn newick(&self, labels: &[String]) -> String {
+        fn write(tree: &Tree, labels: &[String], parent_height: Option<f64>, out: &mut String) {
+            match tree {
+                Tree::Leaf(i) => out.push_str(&newick_name(&labels[*i])),
+                Tree::Node { left, right, .. } => {
+                    out.push('(');
+                    write(left, labels, Some(tree.height()), out);
+                    out.push(',');
+                    write(right, labels, Some(tree.height()), out);
+                    out.push(')');
+                }
+            }
+            if let Some(parent_height) = parent_height {
+                out.push_str(&format!(":{:.6}", parent_height - tree.height()));
+            }
+        }
+
+        let mut out = String::new();
+        write(self, labels, None, &mut out);
+        out.push(';');
+        out
+    }
+}
+
+/// `name` quoted for Newick if it contains characters with a meaning there
+fn newick_name(name: &str) -> String {
+    if name
+        .chars()
+        .any(|c| c.is_whitespace() || "()[]':;,".contains(c))
+    {
+        format!("'{}'", name.replace('\'', "''"))
+    } else {
+        name.to_owned()
+    }
+}
+
+/// Cluster the samples of the distance `matrix` by UPGMA
+///
+/// The closest pair of clusters is merged until one remains; the distance
+/// from a merged cluster to any other is the size-weighted mean of its
+/// parts' distances. Ties are broken by the lowest sample indices. Returns
+/// `None` for an empty matrix.
+pub fn upgma(matrix: &[Vec<f64>]) -> Option<Tree> {
+    let mut clusters: Vec<(Tree, usize)> = (0..matrix.len()).map(|i| (Tree::Leaf(i), 1)).collect();
+    let mut distances: Vec<Vec<f64>> = matrix.to_vec();
+
+    while clusters.len() > 1 {
+        let mut closest = (0, 1);
+        for i in 0..clusters.len() {
+            for j in i + 1..clusters.len() {
+                if distances[i][j] < distances[closest.0][closest.1] {
+                    closest = (i, j);
+                }
+            }
+        }
+        let (i, j) = closest;
+        let d = distances[i][j];
+        let (right, right_size) = clusters.remove(j);
+        let (left, left_size) = clusters.remove(i);
+        let right_distances = distances.remove(j);
+        let left_distances = distances.remove(i);
+        for row in distances.iter_mut() {
+            row.remove(j);
+            row.remove(i);
+        }
+
+        let size = left_size + right_size;
+        let mut merged: Vec<f64> = (0..clusters.len())
+            .map(|m| {
+                // indices of the remaining clusters before the two removals
+                let old = if m >= i { m + 1 } else { m };
+                let old = if old >= j { old + 1 } else { old };
+                (left_distances[old] * left_size as f64 + right_distances[old] * right_size as f64)
+                    / size as f64
+            })
+            .collect();
+        for (row, &d) in distances.iter_mut().zip(&merged) {
+            row.insert(i, d);
+        }
+        merged.insert(i, 0.0);
+        distances.insert(i, merged);
+        clusters.insert(
+            i,
+            (
+                Tree::Node {
+                    left: Box::new(left),
+                    right: Box::new(right),
+                    height: d / 2.0,
+                },
+                size,
+            ),
+        );
+    }
+    clusters.pop().map(|(tree, _)| tree)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::random_sequence;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn profile(weights: &[(&str, f64)]) -> Profile {
+        weights.iter().map(|&(k, w)| (k.to_owned(), w)).collect()
+    }
+
+    fn labels(names: &[&str]) -> Vec<String> {
+        names.iter().map(|&n| n.to_owned()).collect()
+    }
+
+    #[test]
+    fn test_distances() {
+        let a = profile(&[("AA", 1.0), ("CC", 1.0)]);
+        let b = profile(&[("AA", 1.0), ("GG", 1.0)]);
+        let c = profile(&[("TT", 4.0)]);
+
+        assert!((DistanceMetric::Cosine.distance(&a, &b) - 0.5).abs() < 1e-12);
+        assert_eq!(DistanceMetric::Cosine.distance(&a, &c), 1.0);
+        assert!(DistanceMetric::Cosine.distance(&a, &a).abs() < 1e-12);
+
+        assert_eq!(DistanceMetric::BrayCurtis.distance(&a, &b), 0.5);
+        assert_eq!(DistanceMetric::BrayCurtis.distance(&a, &c), 1.0);
+        assert_eq!(DistanceMetric::BrayCurtis.distance(&a, &a), 0.0);
+    }
+
+    #[test]
+    fn test_upgma_newick() {
+        // a and b join at 0.1, then c at the mean of its distances to them
+        let matrix = vec![
+            vec![0.0, 0.2, 0.6, 0.9],
+            vec![0.2, 0.0, 0.8, 0.9],
+            vec![0.6, 0.8, 0.0, 0.9],
+            vec![0.9, 0.9, 0.9, 0.0],
+        ];
+        let tree = upgma(&matrix).unwrap();
+        assert_eq!(
+            tree.newick(&labels(&["a", "b", "c", "d e"])),
+            "(((a:0.100000,b:0.100000):0.250000,c:0.350000):0.100000,'d e':0.450000);"
+        );
+        assert_eq!(upgma(&[]), None);
+        assert_eq!(upgma(&[vec![0.0]]), Some(Tree::Leaf(0)));
+    }
+
+    #[test]
+    fn test_cluster_fasta_fixtures() -> Result<()> {
+        let dir = tempdir()?;
+        let a = random_sequence(2000, 1);
+        let mut a_variant = a.clone();
+        for i in (0..a_variant.len()).step_by(100) {
+            a_variant[i] = if a_variant[i] == b'A' { b'C' } else { b'A' };
+        }
+        let b = random_sequence(2000, 2);
+
+        let mut profiles = Vec::new();
+        for (name, seq) in &[("a", &a), ("b", &b), ("a_variant", &a_variant)] {
+            let path = dir.path().join(format!("{}.fasta", name));
+            fs::write(&path, format!(">{}\n{}\n", name, std::str::from_utf8(seq)?))?;
+            profiles.push(fasta_profile(&path, &CountOptions::new(6))?);
+        }
+
+        for &metric in &[
+            DistanceMetric::Cosine,
+            DistanceMetric::BrayCurtis,
+            DistanceMetric::D2S,
+            DistanceMetric::D2Star,
+        ] {
+            let tree = upgma(&distance_matrix(&profiles, metric)).unwrap();
+            match tree {
+                Tree::Node { left, right, .. } => {
+                    assert!(matches!(*right, Tree::Leaf(1)));
+                    match *left {
+                        Tree::Node { left, right, .. } => {
+                            assert_eq!((*left, *right), (Tree::Leaf(0), Tree::Leaf(2)))
+                        }
+                        leaf => panic!("expected a and a_variant to pair, got {:?}", leaf),
+                    }
+                }
+                leaf => panic!("expected a tree, got {:?}", leaf),
+            }
+        }
+        Ok(())
+    }
+}
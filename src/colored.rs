@@ -0,0 +1,215 @@
+//! Kmers colored by the samples containing them
+//!
+//! For interop with colored de Bruijn graph tools, every sample of a run is
+//! given a color, its index in input order, and each kmer is listed with the
+//! colors of the samples containing it and its count summed over them. Colors
+//! are written as a bit string, the first character for color 0.
+
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::metadata::{write_header, RunMetadata};
+use crate::{create_output, fasta_kmer_counter, CountOptions, KmerError};
+
+/// File name of the colored kmer table in the output root
+pub const COLORED_FILE_NAME: &str = "colored_kmers.tsv";
+
+/// File name of the table of colors and their samples in the output root
+pub const COLORS_FILE_NAME: &str = "colors.tsv";
+
+/// Colors and total count of one kmer
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ColoredKmer {
+    /// bits of the colors containing the kmer, 64 colors per word
+    colors: Vec<u64>,
+    pub count: u64,
+}
+
+impl ColoredKmer {
+    /// Whether the sample of `color` contains the kmer
+    pub fn has_color(&self, color: usize) -> bool {
+        self.colors
+            .get(color / 64)
+            .is_some_and(|word| word & (1 << (color % 64)) != 0)
+    }
+
+    /// Colors as a string of `samples` bits, `1` where the sample contains
+    /// the kmer
+    pub fn bits(&self, samples: usize) -> String {
+        (0..samples)
+            .map(|color| if self.has_color(color) { '1' } else { '0' })
+            .collect()
+    }
+}
+
+/// Kmers of all samples of a run with their colors
+#[derive(Debug, Clone, Default)]
+pub struct ColoredKmers {
+    samples: Vec<String>,
+    kmers: HashMap<String, ColoredKmer>,
+}
+
+impl ColoredKmers {
+    pub fn new() -> Self {
+        ColoredKmers::default()
+    }
+
+    /// Names of the samples, indexed by color
+    pub fn samples(&self) -> &[String] {
+        &self.samples
+    }
+
+    pub fn get(&self, kmer: &str) -> Option<&ColoredKmer> {
+        self.kmers.get(kmer)
+    }
+
+    /// Add the kmer counts of the sample `name` under the next color
+    ///
+    /// Fails if a total count would exceed `u64::MAX`.
+    pub fn add_sample(&mut self, name: &str, counter: HashMap<String, u64>) -> Result<()> {
+        let color = self.samples.len();
+        self.samples.push(name.to_owned());
+        for (kmer, count) in counter {
+            let total = self
+                .kmers
+                .get(&kmer)
+                .map_or(0, |colored| colored.count)
+                .checked_add(count)
+                .ok_or_else(|| KmerError::CountOverflow { kmer: kmer.clone() })?;
+            let colored = self.kmers.entry(kmer).or_default();
+            colored.count = total;
+            colored.colors.resize(color / 64 + 1, 0);
+            colored.colors[color / 64] |= 1 << (color % 64);
+        }
+        Ok(())
+    }
+
+    /// Write `kmer\tcolors\tcount` rows sorted by kmer, after the `metadata`
+    /// header if given
+    pub fn write<W: Write>(&self, metadata: Option<&RunMetadata>, mut out: W) -> Result<()> {
+        write_header(metadata, &mut out)?;
+        writeln!(out, "kmer\tcolors\tcount")?;
+        let mut kmers: Vec<(&String, &ColoredKmer)> = self.kmers.iter().collect();
+        kmers.sort_by(|a, b| a.0.cmp(b.0));
+        for (kmer, colored) in kmers {
+            writeln!(
+                out,
+                "{}\t{}\t{}",
+                kmer,
+                colored.bits(self.samples.len()),
+                colored.count
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write `color\tsample` rows, one per sample
+    pub fn write_colors<W: Write>(&self, mut out: W) -> Result<()> {
+        writeln!(out, "color\tsample")?;
+        for (color, sample) in self.samples.iter().enumerate() {
+            writeln!(out, "{}\t{}", color, sample)?;
+        }
+        Ok(())
+    }
+
+    /// Save the colored kmer table and the colors table in `output_root`
+    pub fn save(&self, metadata: Option<&RunMetadata>, output_root: &Path) -> Result<()> {
+        let mut out = BufWriter::new(create_output(&output_root.join(COLORED_FILE_NAME))?);
+        self.write(metadata, &mut out)?;
+        out.flush()?;
+        let mut out = BufWriter::new(create_output(&output_root.join(COLORS_FILE_NAME))?);
+        self.write_colors(&mut out)?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// Count the kmers of each of `samples`, named fasta files, as configured by
+/// `options`, and save them colored by sample in `output_root` after the
+/// `metadata` header if given
+///
+/// Kmers outside the GC window of `options` are left out.
+pub fn run_colored_kmer_count(
+    samples: &[(String, PathBuf)],
+    options: &CountOptions,
+    metadata: Option<&RunMetadata>,
+    output_root: &Path,
+) -> Result<ColoredKmers> {
+    let mut colored = ColoredKmers::new();
+    for (name, fasta_path) in samples {
+        let mut counter = fasta_kmer_counter(fasta_path, options)
+            .with_context(|| format!("Could not count {:?}", fasta_path))?;
+        counter.retain(|kmer, _| options.in_gc_window(kmer));
+        colored.add_sample(name, counter)?;
+    }
+    colored.save(metadata, output_root)?;
+    Ok(colored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn counter(counts: &[(&str, u64)]) -> HashMap<String, u64> {
+        counts.iter().map(|&(k, c)| (k.to_owned(), c)).collect()
+    }
+
+    #[test]
+    fn test_colors_past_one_word() -> Result<()> {
+        let mut colored = ColoredKmers::new();
+        for n in 0..70 {
+            let counts = if n == 0 || n == 65 {
+                &[("AC", 1)][..]
+            } else {
+                &[]
+            };
+            colored.add_sample(&n.to_string(), counter(counts))?;
+        }
+        let kmer = colored.get("AC").unwrap();
+        assert!(kmer.has_color(0) && kmer.has_color(65));
+        assert!(!kmer.has_color(64) && !kmer.has_color(69) && !kmer.has_color(200));
+        assert_eq!(kmer.count, 2);
+        assert_eq!(kmer.bits(70).matches('1').count(), 2);
+
+        let mut colored = ColoredKmers::new();
+        colored.add_sample("a", counter(&[("AC", u64::MAX)]))?;
+        assert!(colored.add_sample("b", counter(&[("AC", 1)])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_colored_kmer_count() -> Result<()> {
+        let dir = tempdir()?;
+        let a = dir.path().join("a.fasta");
+        let b = dir.path().join("b.fasta");
+        fs::write(&a, ">a\nAACGT\n")?;
+        fs::write(&b, ">b\nACGTT\n")?;
+        let samples = vec![("a".to_owned(), a), ("b".to_owned(), b)];
+
+        let output_root = dir.path().join("out");
+        let colored = run_colored_kmer_count(&samples, &CountOptions::new(3), None, &output_root)?;
+        assert_eq!(colored.samples(), ["a", "b"]);
+        let acg = colored.get("ACG").unwrap();
+        assert!(acg.has_color(0) && acg.has_color(1));
+        assert!(!colored.get("AAC").unwrap().has_color(1));
+        assert!(!colored.get("GTT").unwrap().has_color(0));
+        assert_eq!(
+            fs::read_to_string(output_root.join(COLORED_FILE_NAME))?,
+            "kmer\tcolors\tcount\n\
+             AAC\t10\t1\n\
+             ACG\t11\t2\n\
+             CGT\t11\t2\n\
+             GTT\t01\t1\n"
+        );
+        assert_eq!(
+            fs::read_to_string(output_root.join(COLORS_FILE_NAME))?,
+            "color\tsample\n0\ta\n1\tb\n"
+        );
+        Ok(())
+    }
+}
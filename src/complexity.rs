@@ -0,0 +1,233 @@
+//! Per-record sequence complexity scored by kmer diversity
+//!
+//! Only summary statistics are retained for each record, so memory use is
+//! independent of the size of the per-record count tables.
+//!
+//! The linguistic complexity of a sequence of length `L` over word sizes
+//! `i = m..=n` is `sum U_i / sum M_i`, where `U_i` is the number of distinct
+//! substrings of length `i` and `M_i = min(4^i, L - i + 1)` the most there
+//! can be, bounded both by the ACGT alphabet and by the number of windows.
+//! Over a single word size it is the kmer diversity `U_k / M_k`.
+
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use log::warn;
+use serde::Serialize;
+
+use crate::{count_kmers, kmer_counter, open_fasta, KmerCount, OutputFormat};
+
+/// Complexity summary for a single sequence record
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RecordComplexity {
+    pub id: String,
+    pub length: usize,
+    /// number of distinct kmers observed, summed over the word sizes
+    pub distinct: usize,
+    /// maximum number of distinct kmers possible for a sequence of this
+    /// length, summed over the word sizes
+    pub expected: usize,
+    /// linguistic complexity, `distinct / expected`
+    pub complexity: f64,
+    /// Shannon entropy (bits) of the frequency distribution of the kmers of
+    /// the largest word size
+    pub entropy: f64,
+}
+
+/// Score every record in the fasta file at `fasta_path` by kmer diversity
+///
+/// Records shorter than `k` are skipped with a warning. Records are returned
+/// in file order.
+pub fn fasta_complexity(fasta_path: &Path, k: usize) -> Result<Vec<RecordComplexity>> {
+    fasta_linguistic_complexity(fasta_path, k..=k)
+}
+
+/// Score every record in the fasta file at `fasta_path` by linguistic
+/// complexity over `word_sizes`
+///
+/// Records shorter than the largest word size are skipped with a warning.
+/// Records are returned in file order.
+pub fn fasta_linguistic_complexity(
+    fasta_path: &Path,
+    word_sizes: RangeInclusive<usize>,
+) -> Result<Vec<RecordComplexity>> {
+    let reader = open_fasta(fasta_path)?;
+
+    let mut scores = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        match sequence_linguistic_complexity(record.id(), record.seq(), word_sizes.clone()) {
+            Ok(score) => scores.push(score),
+            Err(err) => warn!("Skipping record {}: {}", record.id(), err),
+        }
+    }
+    Ok(scores)
+}
+
+/// Score a single sequence by kmer diversity
+pub fn sequence_complexity(id: &str, sequence: &[u8], k: usize) -> Result<RecordComplexity> {
+    sequence_linguistic_complexity(id, sequence, k..=k)
+}
+
+/// Score a single sequence by linguistic complexity over `word_sizes`
+pub fn sequence_linguistic_complexity(
+    id: &str,
+    sequence: &[u8],
+    word_sizes: RangeInclusive<usize>,
+) -> Result<RecordComplexity> {
+    let (min_k, k) = word_sizes.into_inner();
+    if min_k == 0 || min_k > k {
+        bail!("Invalid word sizes {}..={}", min_k, k);
+    }
+    let kmer_count = count_kmers(sequence, k, false)?;
+
+    let entropy = kmer_entropy(&kmer_count);
+
+    let mut distinct = kmer_count.len();
+    let mut expected = expected_distinct_kmers(sequence.len(), k);
+    for word_size in min_k..k {
+        distinct += kmer_counter(sequence, word_size, false)?.len();
+        expected += expected_distinct_kmers(sequence.len(), word_size);
+    }
+    Ok(RecordComplexity {
+        id: id.to_owned(),
+        length: sequence.len(),
+        distinct,
+        expected,
+        complexity: distinct as f64 / expected as f64,
+        entropy,
+    })
+}
+
+/// Shannon entropy, in bits, of the kmer frequency distribution of `kmer_count`
+pub(crate) fn kmer_entropy(kmer_count: &KmerCount) -> f64 {
+    let total: u128 = kmer_count.iter().map(|r| r.count as u128).sum();
+    kmer_count
+        .iter()
+        .map(|r| {
+            let p = r.count as f64 / total as f64;
+            -p * p.log2()
+        })
+        .fold(0.0, |acc, h| acc + h)
+}
+
+/// Maximum possible number of distinct kmers of length `k` in a sequence of length `seq_len`
+///
+/// Bounded both by the number of windows and by the size of the ATCG kmer space.
+pub fn expected_distinct_kmers(seq_len: usize, k: usize) -> usize {
+    let windows = (seq_len + 1).saturating_sub(k);
+    let space = 4usize.checked_pow(k as u32).unwrap_or(usize::MAX);
+    windows.min(space)
+}
+
+/// Order scores from least to most complex, breaking ties by record id
+pub fn sort_by_complexity(scores: &mut [RecordComplexity]) {
+    scores.sort_by(|a, b| {
+        a.complexity
+            .partial_cmp(&b.complexity)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.id.cmp(&b.id))
+    });
+}
+
+/// Write complexity scores to `out` in the requested `format`
+pub fn write_complexity<W: Write>(
+    scores: &[RecordComplexity],
+    format: OutputFormat,
+    mut out: W,
+) -> Result<()> {
+    match format {
+        OutputFormat::Tsv => {
+            writeln!(out, "id\tlength\tdistinct\texpected\tcomplexity\tentropy")?;
+            for s in scores {
+                writeln!(
+                    out,
+                    "{}\t{}\t{}\t{}\t{:.6}\t{:.6}",
+                    s.id, s.length, s.distinct, s.expected, s.complexity, s.entropy
+                )?;
+            }
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, scores)?;
+            writeln!(out)?;
+        }
+        other => return Err(other.unsupported_report()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::random_sequence;
+    use crate::KmerRecord;
+
+    #[test]
+    fn test_expected_distinct_kmers() {
+        assert_eq!(expected_distinct_kmers(10, 3), 8);
+        assert_eq!(expected_distinct_kmers(1000, 2), 16);
+        assert_eq!(expected_distinct_kmers(2, 3), 0);
+    }
+
+    #[test]
+    fn test_homopolymer_near_floor() {
+        let score = sequence_complexity("polyA", &[b'A'; 200], 5).unwrap();
+        assert_eq!(score.distinct, 1);
+        assert_eq!(score.expected, 196);
+        assert!(score.complexity < 0.01);
+        assert_eq!(score.entropy, 0.0);
+    }
+
+    #[test]
+    fn test_random_near_one() {
+        let score = sequence_complexity("random", &random_sequence(200, 0), 8).unwrap();
+        assert!(score.complexity > 0.95, "complexity {}", score.complexity);
+        assert!(score.entropy > 7.0);
+    }
+
+    #[test]
+    fn test_entropy_of_counts_near_max() {
+        let kmer_count = vec![
+            KmerRecord {
+                seq: "AAA".into(),
+                count: u64::MAX,
+            },
+            KmerRecord {
+                seq: "CCC".into(),
+                count: u64::MAX,
+            },
+        ];
+        assert_eq!(kmer_entropy(&kmer_count), 1.0);
+    }
+
+    #[test]
+    fn test_linguistic_complexity() {
+        // ACGTAC has 4, 4, 4, and 3 distinct words of sizes 1 to 4, of at
+        // most 4, 5, 4, and 3
+        let score = sequence_linguistic_complexity("a", b"ACGTAC", 1..=4).unwrap();
+        assert_eq!((score.distinct, score.expected), (15, 16));
+        assert_eq!(score.complexity, 15.0 / 16.0);
+
+        let polya = sequence_linguistic_complexity("polyA", &[b'A'; 200], 1..=8).unwrap();
+        let random =
+            sequence_linguistic_complexity("random", &random_sequence(200, 0), 1..=8).unwrap();
+        assert_eq!(polya.distinct, 8);
+        assert!(polya.complexity < 0.01, "complexity {}", polya.complexity);
+        assert!(random.complexity > 0.9, "complexity {}", random.complexity);
+        assert!(sequence_linguistic_complexity("a", b"ACGT", 0..=2).is_err());
+        assert!(sequence_linguistic_complexity("a", b"ACG", 1..=4).is_err());
+    }
+
+    #[test]
+    fn test_sort_by_complexity() {
+        let mut scores = vec![
+            sequence_complexity("random", &random_sequence(200, 0), 5).unwrap(),
+            sequence_complexity("polyA", &[b'A'; 200], 5).unwrap(),
+        ];
+        sort_by_complexity(&mut scores);
+        assert_eq!(scores[0].id, "polyA");
+        assert_eq!(scores[1].id, "random");
+    }
+}
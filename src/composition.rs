@@ -0,0 +1,117 @@
+//! Per-record composition statistics
+//!
+//! Bundles length, GC content, kmer diversity, and kmer entropy into one row
+//! per record for comparative genomics.
+
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::Result;
+use log::warn;
+
+use crate::complexity::kmer_entropy;
+use crate::metadata::{write_header, RunMetadata};
+use crate::{count_kmers, create_output, gc_content, open_fasta};
+
+/// Composition summary for a single sequence record
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordComposition {
+    pub id: String,
+    pub length: usize,
+    /// fraction of A, T, C, G bases that are G or C
+    pub gc: f64,
+    /// distinct kmers over total kmers
+    pub diversity: f64,
+    /// Shannon entropy (bits) of the kmer frequency distribution
+    pub entropy: f64,
+}
+
+/// Summarize the composition of a single sequence using kmers of length `k`
+pub fn record_composition(id: &str, sequence: &[u8], k: usize) -> Result<RecordComposition> {
+    let kmer_count = count_kmers(sequence, k, false)?;
+    let total: u128 = kmer_count.iter().map(|r| r.count as u128).sum();
+
+    Ok(RecordComposition {
+        id: id.to_owned(),
+        length: sequence.len(),
+        gc: gc_content(sequence),
+        diversity: kmer_count.len() as f64 / total as f64,
+        entropy: kmer_entropy(&kmer_count),
+    })
+}
+
+/// Summarize every record of the fasta file at `fasta_path`, in file order
+///
+/// Records shorter than `k` are skipped with a warning.
+pub fn fasta_composition(fasta_path: &Path, k: usize) -> Result<Vec<RecordComposition>> {
+    let mut rows = Vec::new();
+    for record in open_fasta(fasta_path)?.records() {
+        let record = record?;
+        match record_composition(record.id(), record.seq(), k) {
+            Ok(row) => rows.push(row),
+            Err(err) => warn!("Skipping record {}: {}", record.id(), err),
+        }
+    }
+    Ok(rows)
+}
+
+/// Write composition rows as a tab-separated table
+pub fn write_composition<W: Write>(rows: &[RecordComposition], mut out: W) -> Result<()> {
+    writeln!(out, "record_id\tlength\tgc\tdiversity\tentropy")?;
+    for row in rows {
+        writeln!(
+            out,
+            "{}\t{}\t{:.6}\t{:.6}\t{:.6}",
+            row.id, row.length, row.gc, row.diversity, row.entropy
+        )?;
+    }
+    Ok(())
+}
+
+/// Save the composition report for the fasta file at `fasta_path` at
+/// `output_path`, after the `metadata` header if given
+pub fn run_fasta_composition_report(
+    fasta_path: &Path,
+    k: usize,
+    metadata: Option<&RunMetadata>,
+    output_path: &Path,
+) -> Result<()> {
+    let rows = fasta_composition(fasta_path, k)?;
+    let mut out = BufWriter::new(create_output(output_path)?);
+    write_header(metadata, &mut out)?;
+    write_composition(&rows, &mut out)?;
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_fasta_composition_report() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("multi.fasta");
+        let mut fasta = File::create(&fasta_path)?;
+        writeln!(fasta, ">polyA\nAAAAAAAA\n>mixed desc\nATCGGATCG\n>short\nA")?;
+
+        let output_path = dir.path().join("multi_composition.txt");
+        run_fasta_composition_report(&fasta_path, 3, None, &output_path)?;
+
+        // mixed: 5 of 9 bases are G/C; kmers ATC:2 TCG:2 CGG GGA GAT, so
+        // diversity 5/7 and entropy 2 * (2/7) log2(7/2) + 3 * (1/7) log2(7)
+        let entropy = 4.0 / 7.0 * (3.5f64).log2() + 3.0 / 7.0 * (7.0f64).log2();
+        assert_eq!(
+            std::fs::read_to_string(&output_path)?,
+            format!(
+                "record_id\tlength\tgc\tdiversity\tentropy\n\
+                 polyA\t8\t0.000000\t0.166667\t0.000000\n\
+                 mixed\t9\t0.555556\t0.714286\t{:.6}\n",
+                entropy
+            )
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,172 @@
+//! Kmer pairs that co-occur within records
+//!
+//! For every record, the set of kmers present is noted and each unordered
+//! pair from that set is counted once. Across records, a pair's count is the
+//! number of records containing both kmers. The number of pairs grows with
+//! the square of a record's distinct kmers, so only the most abundant kmers of
+//! each record are considered.
+
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::Result;
+use log::warn;
+
+use crate::metadata::{write_header, RunMetadata};
+use crate::{count_kmers, create_output, open_fasta};
+
+/// Default cap on the distinct kmers of a record that are paired
+pub const DEFAULT_MAX_RECORD_KMERS: usize = 200;
+
+/// Number of records containing each pair of kmers, keyed with the smaller kmer first
+pub type PairCounts = HashMap<(String, String), u64>;
+
+/// Kmer pair and the number of records containing both kmers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PairCount {
+    pub a: String,
+    pub b: String,
+    pub records: u64,
+}
+
+/// Add the kmer pairs present in `sequence` to `pairs`
+///
+/// At most `max_record_kmers` distinct kmers of the record are paired, taken
+/// from most to least abundant with ties in kmer order.
+pub fn add_record_pairs(
+    pairs: &mut PairCounts,
+    sequence: &[u8],
+    k: usize,
+    max_record_kmers: usize,
+) -> Result<()> {
+    let kmer_count = count_kmers(sequence, k, false)?;
+    if kmer_count.len() > max_record_kmers {
+        warn!(
+            "Pairing only the {} most abundant of {} distinct kmers",
+            max_record_kmers,
+            kmer_count.len()
+        );
+    }
+
+    let mut present: Vec<&str> = kmer_count
+        .iter()
+        .take(max_record_kmers)
+        .map(|record| record.seq.as_ref())
+        .collect();
+    present.sort_unstable();
+    for (i, a) in present.iter().enumerate() {
+        for b in &present[i + 1..] {
+            *pairs.entry(((*a).to_owned(), (*b).to_owned())).or_insert(0) += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Count co-occurring kmer pairs over all records of the fasta file at `fasta_path`
+///
+/// Records shorter than `k` are skipped with a warning.
+pub fn fasta_pair_counts(
+    fasta_path: &Path,
+    k: usize,
+    max_record_kmers: usize,
+) -> Result<PairCounts> {
+    let mut pairs = PairCounts::new();
+    for record in open_fasta(fasta_path)?.records() {
+        let record = record?;
+        if let Err(err) = add_record_pairs(&mut pairs, record.seq(), k, max_record_kmers) {
+            warn!("Skipping record {}: {}", record.id(), err);
+        }
+    }
+    Ok(pairs)
+}
+
+/// The `n` pairs found in the most records, ties in kmer order
+pub fn top_pairs(pairs: PairCounts, n: usize) -> Vec<PairCount> {
+    let mut ranked: Vec<PairCount> = pairs
+        .into_iter()
+        .map(|((a, b), records)| PairCount { a, b, records })
+        .collect();
+    ranked.sort_by(|x, y| {
+        y.records
+            .cmp(&x.records)
+            .then_with(|| (&x.a, &x.b).cmp(&(&y.a, &y.b)))
+    });
+    ranked.truncate(n);
+    ranked
+}
+
+/// Write pairs as a tab-separated table
+pub fn write_pairs<W: Write>(pairs: &[PairCount], mut out: W) -> Result<()> {
+    writeln!(out, "kmer_a\tkmer_b\trecords")?;
+    for pair in pairs {
+        writeln!(out, "{}\t{}\t{}", pair.a, pair.b, pair.records)?;
+    }
+    Ok(())
+}
+
+/// Save the `top` most frequently co-occurring kmer pairs of the fasta file at
+/// `fasta_path` at `output_path`, after the `metadata` header if given
+pub fn run_fasta_cooccurrence(
+    fasta_path: &Path,
+    k: usize,
+    top: usize,
+    max_record_kmers: usize,
+    metadata: Option<&RunMetadata>,
+    output_path: &Path,
+) -> Result<()> {
+    let pairs = fasta_pair_counts(fasta_path, k, max_record_kmers)?;
+    let mut out = BufWriter::new(create_output(output_path)?);
+    write_header(metadata, &mut out)?;
+    write_pairs(&top_pairs(pairs, top), &mut out)?;
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_shared_pair_reported() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("pairs.fasta");
+        // AAAA and CCCC occur in both records, everything else in one
+        std::fs::write(&fasta_path, ">r1\nAAAAGTCCCC\n>r2\nCCCCTGAAAA\n")?;
+
+        let output_path = dir.path().join("pairs_cooccurrence.txt");
+        run_fasta_cooccurrence(
+            &fasta_path,
+            4,
+            2,
+            DEFAULT_MAX_RECORD_KMERS,
+            None,
+            &output_path,
+        )?;
+
+        let report = std::fs::read_to_string(&output_path)?;
+        let mut lines = report.lines();
+        assert_eq!(lines.next(), Some("kmer_a\tkmer_b\trecords"));
+        assert_eq!(lines.next(), Some("AAAA\tCCCC\t2"));
+        assert_eq!(lines.next().map(|l| l.ends_with("\t1")), Some(true));
+        assert_eq!(lines.next(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_record_kmers() -> Result<()> {
+        let mut pairs = PairCounts::new();
+        // AAA:3 is kept ahead of the single-count kmers, then AAC in kmer order
+        add_record_pairs(&mut pairs, b"AAAAACGT", 3, 2)?;
+        assert_eq!(
+            top_pairs(pairs, 10),
+            [PairCount {
+                a: "AAA".to_owned(),
+                b: "AAC".to_owned(),
+                records: 1,
+            }]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+//! Per-base coverage estimated from kmer counts
+//!
+//! The coverage of a base is the mean count, over the whole input, of the
+//! kmers of its record that overlap it. Interior bases are overlapped by `k`
+//! kmers, while the `k - 1` bases at either end of a record are overlapped by
+//! fewer, down to a single kmer at the first and last base; averaging over the
+//! kmers actually overlapping each base keeps this ramp out of the track.
+
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::Result;
+use log::warn;
+
+use crate::metadata::{write_header, RunMetadata};
+use crate::{create_output, fasta_kmer_counter, kmers, open_fasta, CountOptions, KmerError};
+
+/// Mean count of the kmers overlapping each base of `sequence`, in order,
+/// given the count of each kmer by `count`
+pub(crate) fn coverage_track<F>(
+    sequence: &[u8],
+    k: usize,
+    mut count: F,
+) -> Result<Vec<f64>, KmerError>
+where
+    F: FnMut(&str) -> u64,
+{
+    // prefix sums of the counts of the kmers starting at each position
+    let mut prefix = vec![0u128];
+    for kmer in kmers(sequence, k)? {
+        prefix.push(prefix[prefix.len() - 1] + count(kmer) as u128);
+    }
+    let last_start = prefix.len() - 2;
+    Ok((0..sequence.len())
+        .map(|position| {
+            let first = position.saturating_sub(k - 1);
+            let last = position.min(last_start);
+            let sum = prefix[last + 1] - prefix[first];
+            sum as f64 / (last + 1 - first) as f64
+        })
+        .collect())
+}
+
+/// Save the coverage track of every record of the fasta file at `fasta_path`,
+/// from kmers counted over the whole file as configured by `options`, at
+/// `output_path` after the `metadata` header if given
+///
+/// Positions are 1-based. Records shorter than `k` are skipped with a warning.
+pub fn run_fasta_coverage_track(
+    fasta_path: &Path,
+    options: &CountOptions,
+    metadata: Option<&RunMetadata>,
+    output_path: &Path,
+) -> Result<()> {
+    let counter = fasta_kmer_counter(fasta_path, options)?;
+    let count = |kmer: &str| {
+        let found = if options.canonical {
+            counter.get(&options.count_key(kmer)).copied()
+        } else {
+            counter.get(kmer).copied()
+        };
+        found.unwrap_or(0)
+    };
+
+    let mut out = BufWriter::new(create_output(output_path)?);
+    write_header(metadata, &mut out)?;
+    writeln!(out, "record_id\tposition\tcoverage")?;
+    for record in open_fasta(fasta_path)?.records() {
+        let record = record?;
+        match coverage_track(record.seq(), options.k, &count) {
+            Ok(track) => {
+                for (position, coverage) in track.iter().enumerate() {
+                    writeln!(out, "{}\t{}\t{:.6}", record.id(), position + 1, coverage)?;
+                }
+            }
+            Err(err) => warn!("Skipping record {}: {}", record.id(), err),
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_coverage_track() {
+        // counts 1, 2, 3, 4 for the kmers starting at 0, 1, 2, 3
+        let counts = [("AAC", 1), ("ACG", 2), ("CGT", 3), ("GTT", 4)];
+        let count = |kmer: &str| counts.iter().find(|(k, _)| *k == kmer).unwrap().1;
+        let track = coverage_track(b"AACGTT", 3, count).unwrap();
+        assert_eq!(track, [1.0, 1.5, 2.0, 3.0, 3.5, 4.0]);
+
+        assert_eq!(coverage_track(b"ACG", 3, |_| 7).unwrap(), [7.0; 3]);
+        assert!(coverage_track(b"AC", 3, |_| 7).is_err());
+    }
+
+    #[test]
+    fn test_run_fasta_coverage_track() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("repeat.fasta");
+        // ACG occurs twice, in both records
+        fs::write(&fasta_path, ">a\nACGTT\n>b\nACG\n>short\nAC\n")?;
+
+        let output_path = dir.path().join("repeat_coverage.txt");
+        run_fasta_coverage_track(&fasta_path, &CountOptions::new(3), None, &output_path)?;
+        // a: ACG 2, CGT 1, GTT 1
+        assert_eq!(
+            fs::read_to_string(&output_path)?,
+            "record_id\tposition\tcoverage\n\
+             a\t1\t2.000000\n\
+             a\t2\t1.500000\n\
+             a\t3\t1.333333\n\
+             a\t4\t1.000000\n\
+             a\t5\t1.000000\n\
+             b\t1\t2.000000\n\
+             b\t2\t2.000000\n\
+             b\t3\t2.000000\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,351 @@
+//! D2, D2S, and D2* alignment-free comparison statistics
+//!
+//! For kmer counts `X_w` and `Y_w` of two samples, `D2 = sum X_w Y_w`. D2S
+//! and D2* (Reinert et al. 2009, Wan et al. 2010) first center each count on
+//! its expectation under the sample's background nucleotide frequencies,
+//! `X~_w = X_w - n_X p_X(w)`, where `n_X` is the sample's total count and
+//! `p_X(w)` the product of the background frequencies of the bases of `w`:
+//!
+//! - `D2S = sum X~_w Y~_w / sqrt(X~_w^2 + Y~_w^2)`
+//! - `D2* = sum X~_w Y~_w / sqrt(n_X p_X(w) n_Y p_Y(w))`
+//!
+//! Each is normalized into a dissimilarity from 0 to 1 as
+//! `(1 - D / sqrt(A B)) / 2`, where `A` and `B` are the same sums with
+//! `X~_w^2` and `Y~_w^2` in place of `X~_w Y~_w`: 0 for proportional centered
+//! counts, about 1/2 for uncorrelated ones, and 1 for opposite ones. The
+//! backgrounds can be given or recomputed from the kmer counts by
+//! `background`.
+//!
+//! The sums run over all `4^k` ACGT words, including those absent from both
+//! samples, whose centered counts are not 0. Absent words are summed by base
+//! composition, since `p(w)` depends only on how many of each base `w` holds:
+//! the terms of all words are the terms of each of the `(k + 1)(k + 2)(k + 3)
+//! / 6` compositions weighted by its multinomial number of words, computed
+//! through logarithms so they stay finite for any `k`; the terms the counted
+//! words would have had as absent words are then swapped for their actual
+//! terms. Counts are converted to `f64` exactly up to `2^53`; D2 is summed in
+//! `f64` products of counts, so it is exact only while the products are. Kmers
+//! with bases other than ACGT are left out.
+
+use std::collections::HashSet;
+
+use crate::base_index;
+use crate::cluster::Profile;
+
+/// Frequencies of A, C, G, and T, summing to 1
+pub type Background = [f64; 4];
+
+/// Number of each base in `kmer`, or `None` if it has a base other than ACGT
+fn composition(kmer: &str) -> Option<[i32; 4]> {
+    let mut bases = [0; 4];
+    for base in kmer.bytes() {
+        bases[base_index(base)?] += 1;
+    }
+    Some(bases)
+}
+
+/// Background nucleotide frequencies recomputed from the kmers of `profile`,
+/// each base weighted by its kmer's count
+///
+/// Every base of a sequence but the `k - 1` at either end lies in `k` kmers,
+/// so this is the base composition of the counted sequence up to its ends.
+/// Without any ACGT base, the background is uniform.
+pub fn background(profile: &Profile) -> Background {
+    let mut totals = [0.0; 4];
+    for (kmer, &count) in profile {
+        if let Some(bases) = composition(kmer) {
+            for (total, n) in totals.iter_mut().zip(&bases) {
+                *total += *n as f64 * count;
+            }
+        }
+    }
+    let sum: f64 = totals.iter().sum();
+    if sum == 0.0 {
+        return [0.25; 4];
+    }
+    totals.map(|total| total / sum)
+}
+
+/// Probability of a word with `bases` of each base under `background`
+fn word_probability(bases: &[i32; 4], background: &Background) -> f64 {
+    bases
+        .iter()
+        .zip(background)
+        .map(|(&n, &p)| p.powi(n))
+        .product()
+}
+
+/// D2 statistics of two samples and the dissimilarities derived from them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct D2Statistics {
+    pub d2: f64,
+    pub d2s: f64,
+    pub d2_star: f64,
+    /// D2S normalized to a dissimilarity from 0 to 1
+    pub d2s_dissimilarity: f64,
+    /// D2* normalized to a dissimilarity from 0 to 1
+    pub d2_star_dissimilarity: f64,
+}
+
+/// Per-word terms of the D2S and D2* sums and their normalizers
+#[derive(Debug, Clone, Copy, Default)]
+struct Terms {
+    d2s: f64,
+    d2s_x: f64,
+    d2s_y: f64,
+    d2_star: f64,
+    d2_star_x: f64,
+    d2_star_y: f64,
+}
+
+impl Terms {
+    /// Terms of a word counted `x` and `y` times, expected `expected_x` and
+    /// `expected_y` times; terms with a zero denominator are 0
+    fn of_word(x: f64, y: f64, expected_x: f64, expected_y: f64) -> Self {
+        let (x, y) = (x - expected_x, y - expected_y);
+        let ratio = |numerator: f64, denominator: f64| {
+            if denominator > 0.0 {
+                numerator / denominator
+            } else {
+                0.0
+            }
+        };
+        let length = (x * x + y * y).sqrt();
+        Terms {
+            d2s: ratio(x * y, length),
+            d2s_x: ratio(x * x, length),
+            d2s_y: ratio(y * y, length),
+            d2_star: ratio(x * y, (expected_x * expected_y).sqrt()),
+            d2_star_x: ratio(x * x, expected_x),
+            d2_star_y: ratio(y * y, expected_y),
+        }
+    }
+
+    fn add(&mut self, other: Terms, weight: f64) {
+        self.d2s += weight * other.d2s;
+        self.d2s_x += weight * other.d2s_x;
+        self.d2s_y += weight * other.d2s_y;
+        self.d2_star += weight * other.d2_star;
+        self.d2_star_x += weight * other.d2_star_x;
+        self.d2_star_y += weight * other.d2_star_y;
+    }
+}
+
+/// `(1 - statistic / sqrt(norm_x norm_y)) / 2`, or 0 for identical samples
+/// whose counts all match their expectations
+fn dissimilarity(statistic: f64, norm_x: f64, norm_y: f64) -> f64 {
+    let norms = (norm_x * norm_y).sqrt();
+    if norms == 0.0 {
+        return 0.0;
+    }
+    ((1.0 - statistic / norms) / 2.0).clamp(0.0, 1.0)
+}
+
+/// D2, D2S, and D2* between the kmer counts `x` and `y`, with background
+/// nucleotide frequencies `background_x` and `background_y`
+///
+/// Kmers of a length other than that of the first ACGT kmer are left out.
+pub fn d2_statistics(
+    x: &Profile,
+    background_x: &Background,
+    y: &Profile,
+    background_y: &Background,
+) -> D2Statistics {
+    let acgt = |kmer: &&String| composition(kmer).is_some();
+    let k = match x.keys().chain(y.keys()).find(acgt) {
+        Some(kmer) => kmer.len(),
+        None => {
+            return D2Statistics {
+                d2: 0.0,
+                d2s: 0.0,
+                d2_star: 0.0,
+                d2s_dissimilarity: 0.0,
+                d2_star_dissimilarity: 0.0,
+            }
+        }
+    };
+    let words: HashSet<&String> = x
+        .keys()
+        .chain(y.keys())
+        .filter(acgt)
+        .filter(|kmer| kmer.len() == k)
+        .collect();
+    let total =
+        |profile: &Profile| -> f64 { words.iter().filter_map(|&kmer| profile.get(kmer)).sum() };
+    let (n_x, n_y) = (total(x), total(y));
+
+    // every word as if absent from both samples, by base composition
+    let ln_factorial: Vec<f64> = (0..=k)
+        .scan(0.0, |sum, i| {
+            if i > 0 {
+                *sum += (i as f64).ln();
+            }
+            Some(*sum)
+        })
+        .collect();
+    let mut terms = Terms::default();
+    for a in 0..=k {
+        for c in 0..=k - a {
+            for g in 0..=k - a - c {
+                let t = k - a - c - g;
+                let ln_words = ln_factorial[k]
+                    - ln_factorial[a]
+                    - ln_factorial[c]
+                    - ln_factorial[g]
+                    - ln_factorial[t];
+                let bases = [a as i32, c as i32, g as i32, t as i32];
+                let expected_x = n_x * word_probability(&bases, background_x);
+                let expected_y = n_y * word_probability(&bases, background_y);
+                terms.add(
+                    Terms::of_word(0.0, 0.0, expected_x, expected_y),
+                    ln_words.exp(),
+                );
+            }
+        }
+    }
+
+    // swap the absent terms of the counted words for their actual terms
+    let mut d2 = 0.0;
+    for kmer in words {
+        let bases = composition(kmer).expect("ACGT kmer");
+        let expected_x = n_x * word_probability(&bases, background_x);
+        let expected_y = n_y * word_probability(&bases, background_y);
+        let count_x = x.get(kmer).copied().unwrap_or(0.0);
+        let count_y = y.get(kmer).copied().unwrap_or(0.0);
+        d2 += count_x * count_y;
+        terms.add(Terms::of_word(0.0, 0.0, expected_x, expected_y), -1.0);
+        terms.add(
+            Terms::of_word(count_x, count_y, expected_x, expected_y),
+            1.0,
+        );
+    }
+
+    D2Statistics {
+        d2,
+        d2s: terms.d2s,
+        d2_star: terms.d2_star,
+        d2s_dissimilarity: dissimilarity(terms.d2s, terms.d2s_x, terms.d2s_y),
+        d2_star_dissimilarity: dissimilarity(terms.d2_star, terms.d2_star_x, terms.d2_star_y),
+    }
+}
+
+/// D2 statistics between `x` and `y` with backgrounds recomputed from each
+pub fn d2_statistics_with_backgrounds(x: &Profile, y: &Profile) -> D2Statistics {
+    d2_statistics(x, &background(x), y, &background(y))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(counts: &[(&str, f64)]) -> Profile {
+        counts.iter().map(|&(k, c)| (k.to_owned(), c)).collect()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9 * expected.abs().max(1.0),
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_background() {
+        let bg = background(&profile(&[("AC", 2.0), ("CG", 1.0), ("NA", 5.0)]));
+        assert_eq!(bg, [2.0 / 6.0, 3.0 / 6.0, 1.0 / 6.0, 0.0]);
+        assert_eq!(background(&Profile::new()), [0.25; 4]);
+    }
+
+    #[test]
+    fn test_d2_statistics_by_hand() {
+        // n_x = 3 and n_y = 2, so with uniform backgrounds each of the 16
+        // words is expected 3/16 and 2/16 times
+        let x = profile(&[("AA", 2.0), ("AC", 1.0)]);
+        let y = profile(&[("AA", 1.0), ("CA", 1.0)]);
+        let uniform = [0.25; 4];
+        let stats = d2_statistics(&x, &uniform, &y, &uniform);
+        assert_eq!(stats.d2, 2.0);
+
+        // centered counts of AA, AC, CA, and the 13 other words
+        let (ex, ey) = (3.0 / 16.0, 2.0 / 16.0);
+        let centered = [
+            (2.0 - ex, 1.0 - ey, 1.0),
+            (1.0 - ex, -ey, 1.0),
+            (-ex, 1.0 - ey, 1.0),
+            (-ex, -ey, 13.0),
+        ];
+        let sum = |f: &dyn Fn(f64, f64) -> f64| -> f64 {
+            centered.iter().map(|&(x, y, n)| n * f(x, y)).sum()
+        };
+        // sum of products is 2 - 2 * 3 * 2 / 16 + 16 * 3 * 2 / 256 = 1.625
+        assert_close(sum(&|x, y| x * y), 1.625);
+        assert_close(stats.d2_star, 1.625 / (ex * ey).sqrt());
+        let d2s = sum(&|x, y| x * y / (x * x + y * y).sqrt());
+        assert_close(stats.d2s, d2s);
+
+        let d2s_x = sum(&|x, y| x * x / (x * x + y * y).sqrt());
+        let d2s_y = sum(&|x, y| y * y / (x * x + y * y).sqrt());
+        assert_close(
+            stats.d2s_dissimilarity,
+            (1.0 - d2s / (d2s_x * d2s_y).sqrt()) / 2.0,
+        );
+        let star_x = sum(&|x, _| x * x / ex);
+        let star_y = sum(&|_, y| y * y / ey);
+        assert_close(
+            stats.d2_star_dissimilarity,
+            (1.0 - stats.d2_star / (star_x * star_y).sqrt()) / 2.0,
+        );
+    }
+
+    #[test]
+    fn test_d2_statistics_match_enumeration() {
+        // skewed backgrounds, checked against a sum over all 64 words
+        let x = profile(&[("AAC", 40.0), ("ACG", 3.0), ("TTT", 7.0), ("GGA", 1.0)]);
+        let y = profile(&[("AAC", 5.0), ("CCC", 9.0), ("TTT", 2.0), ("ANA", 4.0)]);
+        let (bx, by) = ([0.4, 0.1, 0.2, 0.3], [0.1, 0.5, 0.15, 0.25]);
+        let stats = d2_statistics(&x, &bx, &y, &by);
+
+        let (n_x, n_y) = (51.0, 16.0);
+        let mut expected = Terms::default();
+        for word in 0..64 {
+            let kmer: String = (0..3)
+                .map(|i| ['A', 'C', 'G', 'T'][(word >> (2 * i)) & 3])
+                .collect();
+            let bases = composition(&kmer).unwrap();
+            let count = |p: &Profile| p.get(&kmer).copied().unwrap_or(0.0);
+            expected.add(
+                Terms::of_word(
+                    count(&x),
+                    count(&y),
+                    n_x * word_probability(&bases, &bx),
+                    n_y * word_probability(&bases, &by),
+                ),
+                1.0,
+            );
+        }
+        assert_eq!(stats.d2, 40.0 * 5.0 + 7.0 * 2.0);
+        assert_close(stats.d2s, expected.d2s);
+        assert_close(stats.d2_star, expected.d2_star);
+        assert_close(
+            stats.d2s_dissimilarity,
+            dissimilarity(expected.d2s, expected.d2s_x, expected.d2s_y),
+        );
+    }
+
+    #[test]
+    fn test_d2_dissimilarity_bounds() {
+        let x = profile(&[("ACGTA", 1e12), ("CCGTA", 3e11), ("TTTTT", 5.0)]);
+        let same = d2_statistics_with_backgrounds(&x, &x);
+        assert!(same.d2s_dissimilarity.abs() < 1e-9);
+        assert!(same.d2_star_dissimilarity.abs() < 1e-9);
+
+        // uncorrelated samples are about 0.5 apart
+        let y = profile(&[("GGGGG", 1e12), ("GGGGC", 2.0)]);
+        let apart = d2_statistics_with_backgrounds(&x, &y);
+        for d in [apart.d2s_dissimilarity, apart.d2_star_dissimilarity] {
+            assert!(d.is_finite() && d > 0.4 && d <= 1.0, "{:?}", apart);
+        }
+    }
+}
@@ -0,0 +1,421 @@
+//! Near-duplicate record detection by kmer profile
+//!
+//! Every record is reduced to a MinHash sketch of its canonical kmers, so
+//! reverse-complemented copies are recognized. Only record pairs sharing at
+//! least one sketch hash are compared, found by bucketing records on their
+//! hash values. Pairs whose similarity meets the threshold are joined into
+//! clusters, each represented by its longest record.
+//!
+//! While counting, `SeenRecords` applies the same comparison one record at a
+//! time, so a record is skipped if it repeats one counted before it; the first
+//! copy in file order is the one counted.
+
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::Result;
+use bio::io::fasta;
+use serde::Serialize;
+
+use crate::sketch::{hash_bytes, Sketch};
+use crate::{create_output, open_fasta, OutputFormat};
+
+/// Seed for record sketches
+const SEED: u64 = 42;
+
+/// Kmer length of the sketches compared by `SeenRecords`, independent of the
+/// counted `k` so that near-duplicates are judged the same way at any `k`
+pub const SEEN_SKETCH_K: usize = 21;
+
+/// Number of MinHash values kept per record by `SeenRecords`
+pub const SEEN_SKETCH_SIZE: usize = 1000;
+
+/// Similarity between two records' kmer sets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimilarityMetric {
+    /// shared kmers over all kmers of either record
+    Jaccard,
+    /// shared kmers over the kmers of the smaller record
+    Containment,
+}
+
+impl SimilarityMetric {
+    pub const VARIANTS: &'static [&'static str] = &["jaccard", "containment"];
+
+    fn similarity(self, a: &Sketch, b: &Sketch) -> f64 {
+        match self {
+            SimilarityMetric::Jaccard => a.jaccard(b),
+            SimilarityMetric::Containment => a.containment(b),
+        }
+    }
+}
+
+impl FromStr for SimilarityMetric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "jaccard" => Ok(SimilarityMetric::Jaccard),
+            "containment" => Ok(SimilarityMetric::Containment),
+            _ => Err(format!("Unknown similarity metric: {}", s)),
+        }
+    }
+}
+
+/// Sketch of a single record
+#[derive(Debug, Clone)]
+pub struct RecordSketch {
+    pub id: String,
+    pub length: usize,
+    pub sketch: Sketch,
+}
+
+/// Group of near-duplicate records, as indices into the sketched records
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cluster {
+    /// members in file order
+    pub members: Vec<usize>,
+    /// longest member, the first in file order among equals
+    pub representative: usize,
+}
+
+/// Sketch every record of the fasta file at `fasta_path`
+pub fn sketch_fasta(fasta_path: &Path, k: usize, sketch_size: usize) -> Result<Vec<RecordSketch>> {
+    let mut sketches = Vec::new();
+    for record in open_fasta(fasta_path)?.records() {
+        let record = record?;
+        sketches.push(RecordSketch {
+            id: record.id().to_owned(),
+            length: record.seq().len(),
+            sketch: Sketch::from_sequence(record.seq(), k, sketch_size, SEED),
+        });
+    }
+    Ok(sketches)
+}
+
+/// Cluster records whose pairwise similarity is at least `threshold`
+///
+/// Clustering is transitive: records linked through a chain of similar pairs
+/// share a cluster. Only clusters with two or more members are returned,
+/// ordered by their first member.
+pub fn find_clusters(
+    records: &[RecordSketch],
+    metric: SimilarityMetric,
+    threshold: f64,
+) -> Vec<Cluster> {
+    // candidate pairs share at least one sketch hash
+    let mut buckets: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (i, record) in records.iter().enumerate() {
+        for &hash in record.sketch.hashes() {
+            buckets.entry(hash).or_default().push(i);
+        }
+    }
+    let mut candidates = HashSet::new();
+    for members in buckets.values() {
+        for (n, &a) in members.iter().enumerate() {
+            for &b in &members[n + 1..] {
+                candidates.insert((a, b));
+            }
+        }
+    }
+
+    let mut parent: Vec<usize> = (0..records.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    for (a, b) in candidates {
+        if metric.similarity(&records[a].sketch, &records[b].sketch) >= threshold {
+            let (ra, rb) = (root(&mut parent, a), root(&mut parent, b));
+            parent[ra.max(rb)] = ra.min(rb);
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for i in 0..records.len() {
+        groups.entry(root(&mut parent, i)).or_default().push(i);
+    }
+    let mut clusters: Vec<Cluster> = groups
+        .into_values()
+        .filter(|members| members.len() > 1)
+        .map(|members| {
+            let representative = members.iter().cloned().fold(members[0], |best, i| {
+                if records[i].length > records[best].length {
+                    i
+                } else {
+                    best
+                }
+            });
+            Cluster {
+                members,
+                representative,
+            }
+        })
+        .collect();
+    clusters.sort_by_key(|cluster| cluster.members[0]);
+    clusters
+}
+
+/// Records already counted from one input, for skipping later copies
+///
+/// Exact copies are recognized by a 64-bit hash of their sequence. With a
+/// threshold, a record is also a copy if the Jaccard similarity of its sketch
+/// to that of a record kept earlier is at least the threshold; as in
+/// `find_clusters`, only kept records sharing a sketch hash are compared, and
+/// reverse complements are alike.
+#[derive(Debug, Clone, Default)]
+pub struct SeenRecords {
+    threshold: Option<f64>,
+    exact: HashSet<u64>,
+    sketches: Vec<Sketch>,
+    buckets: HashMap<u64, Vec<usize>>,
+    /// records recognized as copies
+    pub collapsed: u64,
+}
+
+impl SeenRecords {
+    /// Records compared exactly, and by sketch if `threshold` is given
+    pub fn new(threshold: Option<f64>) -> Self {
+        SeenRecords {
+            threshold,
+            ..SeenRecords::default()
+        }
+    }
+
+    /// Whether `sequence` repeats a record seen before; if not, it is
+    /// remembered for the records after it
+    pub fn is_duplicate(&mut self, sequence: &[u8]) -> bool {
+        let duplicate = self.check(sequence);
+        if duplicate {
+            self.collapsed += 1;
+        }
+        duplicate
+    }
+
+    fn check(&mut self, sequence: &[u8]) -> bool {
+        if !self.exact.insert(hash_bytes(sequence, SEED)) {
+            return true;
+        }
+        let threshold = match self.threshold {
+            Some(threshold) => threshold,
+            None => return false,
+        };
+
+        let sketch = Sketch::from_sequence(sequence, SEEN_SKETCH_K, SEEN_SKETCH_SIZE, SEED);
+        let candidates: HashSet<usize> = sketch
+            .hashes()
+            .iter()
+            .filter_map(|hash| self.buckets.get(hash))
+            .flatten()
+            .cloned()
+            .collect();
+        if candidates
+            .into_iter()
+            .any(|i| self.sketches[i].jaccard(&sketch) >= threshold)
+        {
+            return true;
+        }
+
+        let index = self.sketches.len();
+        for &hash in sketch.hashes() {
+            self.buckets.entry(hash).or_default().push(index);
+        }
+        self.sketches.push(sketch);
+        false
+    }
+}
+
+#[derive(Serialize)]
+struct ClusterRow<'a> {
+    cluster: usize,
+    record: &'a str,
+    length: usize,
+    representative: bool,
+}
+
+/// Write one row per clustered record to `out` in the requested `format`
+pub fn write_clusters<W: Write>(
+    records: &[RecordSketch],
+    clusters: &[Cluster],
+    format: OutputFormat,
+    mut out: W,
+) -> Result<()> {
+    let rows: Vec<ClusterRow> = clusters
+        .iter()
+        .enumerate()
+        .flat_map(|(n, cluster)| {
+            cluster.members.iter().map(move |&i| ClusterRow {
+                cluster: n + 1,
+                record: &records[i].id,
+                length: records[i].length,
+                representative: i == cluster.representative,
+            })
+        })
+        .collect();
+
+    match format {
+        OutputFormat::Tsv => {
+            writeln!(out, "cluster\trecord\tlength\trepresentative")?;
+            for row in rows {
+                writeln!(
+                    out,
+                    "{}\t{}\t{}\t{}",
+                    row.cluster, row.record, row.length, row.representative
+                )?;
+            }
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, &rows)?;
+            writeln!(out)?;
+        }
+        other => return Err(other.unsupported_report()),
+    }
+    Ok(())
+}
+
+/// Copy the records of `fasta_path` to `output_path`, keeping only cluster
+/// representatives and unclustered records
+///
+/// Returns the number of records written.
+pub fn write_deduped(fasta_path: &Path, clusters: &[Cluster], output_path: &Path) -> Result<usize> {
+    let removed: HashSet<usize> = clusters
+        .iter()
+        .flat_map(|c| {
+            c.members
+                .iter()
+                .cloned()
+                .filter(move |&i| i != c.representative)
+        })
+        .collect();
+
+    let mut writer = fasta::Writer::new(create_output(output_path)?);
+    let mut written = 0;
+    for (i, record) in open_fasta(fasta_path)?.records().enumerate() {
+        let record = record?;
+        if !removed.contains(&i) {
+            writer.write_record(&record)?;
+            written += 1;
+        }
+    }
+    writer.flush()?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reverse_complement;
+    use crate::testutil::random_sequence;
+    use std::fs::File;
+    use tempfile::tempdir;
+
+    fn fixture(dir: &Path) -> Result<std::path::PathBuf> {
+        let a = random_sequence(800, 1);
+        let longer_a = [a.clone(), random_sequence(10, 3)].concat();
+        let b = random_sequence(800, 2);
+
+        let path = dir.join("assembly.fasta");
+        let mut file = File::create(&path)?;
+        for (id, seq) in &[
+            ("a", a.clone()),
+            ("b", b),
+            ("a_copy", a.clone()),
+            ("a_rc", reverse_complement(&a)),
+            ("a_longer", longer_a),
+            ("c", random_sequence(300, 4)),
+        ] {
+            writeln!(file, ">{}\n{}", id, std::str::from_utf8(seq)?)?;
+        }
+        Ok(path)
+    }
+
+    #[test]
+    fn test_find_clusters() -> Result<()> {
+        let dir = tempdir()?;
+        let path = fixture(dir.path())?;
+        let records = sketch_fasta(&path, 15, 1000)?;
+
+        let clusters = find_clusters(&records, SimilarityMetric::Jaccard, 0.95);
+        assert_eq!(
+            clusters,
+            [Cluster {
+                members: vec![0, 2, 3, 4],
+                representative: 4,
+            }]
+        );
+
+        // a_longer adds only a few kmers, so it joins even at a strict threshold
+        // under containment, but not under Jaccard
+        let strict = find_clusters(&records, SimilarityMetric::Jaccard, 1.0);
+        assert_eq!(strict[0].members, [0, 2, 3]);
+        let strict = find_clusters(&records, SimilarityMetric::Containment, 1.0);
+        assert_eq!(strict[0].members, [0, 2, 3, 4]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_deduped() -> Result<()> {
+        let dir = tempdir()?;
+        let path = fixture(dir.path())?;
+        let records = sketch_fasta(&path, 15, 1000)?;
+        let clusters = find_clusters(&records, SimilarityMetric::Jaccard, 0.95);
+
+        let output_path = dir.path().join("deduped.fasta");
+        assert_eq!(write_deduped(&path, &clusters, &output_path)?, 3);
+
+        let ids: Vec<String> = open_fasta(&output_path)?
+            .records()
+            .map(|r| r.map(|r| r.id().to_owned()))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(ids, ["b", "a_longer", "c"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_clusters() -> Result<()> {
+        let dir = tempdir()?;
+        let path = fixture(dir.path())?;
+        let records = sketch_fasta(&path, 15, 1000)?;
+        let clusters = find_clusters(&records, SimilarityMetric::Jaccard, 0.95);
+
+        let mut out = Vec::new();
+        write_clusters(&records, &clusters, OutputFormat::Tsv, &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "cluster\trecord\tlength\trepresentative\n\
+             1\ta\t800\tfalse\n\
+             1\ta_copy\t800\tfalse\n\
+             1\ta_rc\t800\tfalse\n\
+             1\ta_longer\t810\ttrue\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_seen_records() {
+        let a = random_sequence(800, 1);
+        let longer_a = [a.clone(), random_sequence(10, 3)].concat();
+        let b = random_sequence(800, 2);
+
+        let mut exact = SeenRecords::new(None);
+        assert!(!exact.is_duplicate(&a));
+        assert!(!exact.is_duplicate(&b));
+        assert!(exact.is_duplicate(&a));
+        assert!(!exact.is_duplicate(&reverse_complement(&a)));
+        assert!(!exact.is_duplicate(&longer_a));
+        assert_eq!(exact.collapsed, 1);
+
+        let mut near = SeenRecords::new(Some(0.95));
+        assert!(!near.is_duplicate(&a));
+        assert!(!near.is_duplicate(&b));
+        assert!(near.is_duplicate(&reverse_complement(&a)));
+        assert!(near.is_duplicate(&longer_a));
+        assert!(!near.is_duplicate(&random_sequence(300, 4)));
+        assert_eq!(near.collapsed, 2);
+    }
+}
@@ -0,0 +1,209 @@
+//! Dinucleotide relative abundances, the genomic signature of an input
+//!
+//! The odds ratio of a dinucleotide `XY` is `f(XY) / (f(X) f(Y))`, its
+//! frequency among all dinucleotides over the product of the frequencies of
+//! its bases among all bases. It is near 1 for a dinucleotide as common as
+//! its bases predict, and well below 1 for one avoided, such as `CG` in
+//! vertebrate genomes. The 16 ratios vary little along a genome but differ
+//! between organisms, so they serve to compare them. Only ACGT bases are
+//! counted, and dinucleotides with any other base are left out. Lowercase
+//! bases follow `--case`: counted as upper case by default, and otherwise
+//! left out like any other base. The one-row table has no `# rows` trailer.
+
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::Result;
+use log::warn;
+
+use crate::metadata::{write_header, RunMetadata};
+use crate::{base_index, create_output, open_fasta, CaseHandling, CountOptions};
+
+/// Number of dinucleotides, the length of a signature
+pub const SIGNATURE_LEN: usize = 16;
+
+/// The dinucleotides in signature order, `AA`, `AC`, ..., `TT`
+pub fn dinucleotides() -> Vec<String> {
+    (0..SIGNATURE_LEN)
+        .map(|index| {
+            let pair = [b"ACGT"[index >> 2], b"ACGT"[index & 3]];
+            String::from_utf8(pair.to_vec()).expect("ACGT is ASCII")
+        })
+        .collect()
+}
+
+/// Counts of mononucleotides and dinucleotides
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct NucleotideCounts {
+    /// counts of A, C, G, and T
+    pub mononucleotides: [u64; 4],
+    /// counts of the dinucleotides, in signature order
+    pub dinucleotides: [u64; SIGNATURE_LEN],
+}
+
+impl NucleotideCounts {
+    /// Add the bases and dinucleotides of `sequence`, as it is counted under
+    /// `case`
+    pub fn add(&mut self, sequence: &[u8], case: CaseHandling) {
+        let sequence = case.normalize(sequence);
+        let mut previous: Option<usize> = None;
+        for &base in sequence.iter() {
+            // lowercase bases left after normalizing are masked, or distinct
+            // from ACGT
+            let index = if base.is_ascii_lowercase() {
+                None
+            } else {
+                base_index(base)
+            };
+            if let Some(index) = index {
+                self.mononucleotides[index] += 1;
+                if let Some(previous) = previous {
+                    self.dinucleotides[previous << 2 | index] += 1;
+                }
+            }
+            previous = index;
+        }
+    }
+
+    /// Odds ratios of the dinucleotides, in signature order, or `None` if no
+    /// dinucleotide was counted
+    ///
+    /// A dinucleotide with a base never seen has no ratio and gets `NaN`.
+    pub fn odds_ratios(&self) -> Option<Vec<f64>> {
+        let bases: u64 = self.mononucleotides.iter().sum();
+        let pairs: u64 = self.dinucleotides.iter().sum();
+        if pairs == 0 {
+            return None;
+        }
+        let frequency = |index: usize| self.mononucleotides[index] as f64 / bases as f64;
+        Some(
+            self.dinucleotides
+                .iter()
+                .enumerate()
+                .map(|(index, &count)| {
+                    let expected = frequency(index >> 2) * frequency(index & 3);
+                    if expected == 0.0 {
+                        f64::NAN
+                    } else {
+                        count as f64 / pairs as f64 / expected
+                    }
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Save the dinucleotide signature of the fasta file at `fasta_path`, over
+/// all its records with lowercase bases handled as `options.case`, at
+/// `output_path`, after the `metadata` header if given,
+/// as a one-row tab-separated table with a column per dinucleotide
+///
+/// Ratios that cannot be computed are written as `NA`; an input without any
+/// dinucleotide gets `NA` throughout, with a warning.
+pub fn run_fasta_dinucleotide_signature(
+    fasta_path: &Path,
+    options: &CountOptions,
+    metadata: Option<&RunMetadata>,
+    output_path: &Path,
+) -> Result<()> {
+    let mut counts = NucleotideCounts::default();
+    for record in open_fasta(fasta_path)?.records() {
+        counts.add(record?.seq(), options.case);
+    }
+    let ratios = counts.odds_ratios().unwrap_or_else(|| {
+        warn!(
+            "No dinucleotides counted in {:?}; its signature is NA",
+            fasta_path
+        );
+        vec![f64::NAN; SIGNATURE_LEN]
+    });
+    let row: Vec<String> = ratios
+        .iter()
+        .map(|ratio| {
+            if ratio.is_nan() {
+                "NA".to_owned()
+            } else {
+                format!("{:.6}", ratio)
+            }
+        })
+        .collect();
+    let mut out = BufWriter::new(create_output(output_path)?);
+    write_header(metadata, &mut out)?;
+    writeln!(out, "{}", dinucleotides().join("\t"))?;
+    writeln!(out, "{}", row.join("\t"))?;
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_dinucleotides() {
+        let dinucleotides = dinucleotides();
+        assert_eq!(dinucleotides.len(), SIGNATURE_LEN);
+        assert_eq!(dinucleotides[..3], ["AA", "AC", "AG"]);
+        assert_eq!(dinucleotides[6], "CG");
+        assert_eq!(dinucleotides[15], "TT");
+    }
+
+    #[test]
+    fn test_odds_ratios() {
+        let mut counts = NucleotideCounts::default();
+        // the N breaks the run: AC, CG, GT, and TA, but not TN or NA
+        counts.add(b"acgtaNacgt", CaseHandling::Upper);
+        counts.add(b"C", CaseHandling::Upper);
+        assert_eq!(counts.mononucleotides, [3, 3, 2, 2]);
+        assert_eq!(counts.dinucleotides.iter().sum::<u64>(), 7);
+        let ratios = counts.odds_ratios().unwrap();
+        // CG: 2 of 7 dinucleotides, from C at 3/10 and G at 2/10
+        assert!((ratios[6] - (2.0 / 7.0) / (0.3 * 0.2)).abs() < 1e-12);
+        assert_eq!(ratios[0], 0.0);
+
+        let mut counts = NucleotideCounts::default();
+        counts.add(b"AAAA", CaseHandling::Upper);
+        let ratios = counts.odds_ratios().unwrap();
+        assert_eq!(ratios[0], 1.0);
+        assert!(ratios[1].is_nan());
+
+        // masked or case-sensitive, the lowercase run breaks AC and GT apart
+        for case in [CaseHandling::SkipMasked, CaseHandling::Sensitive] {
+            let mut counts = NucleotideCounts::default();
+            counts.add(b"ACgtGT", case);
+            assert_eq!(counts.mononucleotides, [1, 1, 1, 1]);
+            assert_eq!(counts.dinucleotides.iter().sum::<u64>(), 2);
+        }
+        assert_eq!(NucleotideCounts::default().odds_ratios(), None);
+    }
+
+    #[test]
+    fn test_run_fasta_dinucleotide_signature() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("genome.fasta");
+        fs::write(&fasta_path, ">a\nACGTACGT\n>b\nAAAA\n")?;
+        let output_path = dir.path().join("genome_dinucleotide_signature.txt");
+        let options = CountOptions::new(2);
+        run_fasta_dinucleotide_signature(&fasta_path, &options, None, &output_path)?;
+
+        let contents = fs::read_to_string(&output_path)?;
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("AA\tAC\tAG\tAT\tCA\tCC\tCG\t"));
+        let row: Vec<&str> = lines[1].split('\t').collect();
+        assert_eq!(row.len(), SIGNATURE_LEN);
+        // CG is 2 of 10 dinucleotides; C is 2 and G 2 of 12 bases
+        assert_eq!(row[6], "7.200000");
+
+        fs::write(&fasta_path, ">short\nA\n")?;
+        run_fasta_dinucleotide_signature(&fasta_path, &options, None, &output_path)?;
+        let contents = fs::read_to_string(&output_path)?;
+        assert_eq!(
+            contents.lines().nth(1),
+            Some(vec!["NA"; 16].join("\t").as_str())
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+//! Entropy rate of an input under a Markov model of a fixed order
+//!
+//! The entropy rate of order `n` is the conditional entropy
+//! `H(X_k | X_1..X_{k-1})` of a base given the `n = k - 1` bases before it,
+//! estimated from the counts of kmers and of their `(k-1)`-mer contexts:
+//!
+//! `H = sum over kmers w of p(w) log2(count(context of w) / count(w))`
+//!
+//! where `p(w)` is the kmer's share of all kmers and the context of `w` is
+//! its first `k - 1` bases. Context counts are those of the `(k-1)`-mers
+//! followed by a base, summed from the kmer counts, so the two always agree
+//! at record ends. The rate is in bits per base: near 2 for random ACGT
+//! sequence and near 0 for a sequence its recent bases predict, such as a
+//! tandem repeat, which makes it a proxy for compressibility.
+
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::Result;
+use log::warn;
+
+use crate::metadata::{write_header, RunMetadata};
+use crate::{create_output, fasta_kmer_counter, CountOptions};
+
+/// Entropy rate of one input
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntropyRate {
+    /// bases of context, `k - 1`
+    pub order: usize,
+    /// kmers counted
+    pub kmers: u64,
+    /// distinct contexts seen
+    pub contexts: usize,
+    /// conditional entropy, in bits per base
+    pub entropy_rate: f64,
+}
+
+/// Entropy rate of the Markov model of order `k - 1` fitted to `counter`, a
+/// count of kmers of length `k`, or `None` if it is empty
+pub fn entropy_rate(counter: &HashMap<String, u64>, k: usize) -> Option<EntropyRate> {
+    let mut contexts: HashMap<&str, u64> = HashMap::new();
+    for (kmer, &count) in counter {
+        *contexts.entry(&kmer[..kmer.len() - 1]).or_default() += count;
+    }
+    let kmers: u64 = contexts.values().sum();
+    if kmers == 0 {
+        return None;
+    }
+    let entropy_rate = counter
+        .iter()
+        .filter(|(_, &count)| count > 0)
+        .map(|(kmer, &count)| {
+            let context = contexts[&kmer[..kmer.len() - 1]];
+            count as f64 / kmers as f64 * (context as f64 / count as f64).log2()
+        })
+        .sum();
+    Some(EntropyRate {
+        order: k - 1,
+        kmers,
+        contexts: contexts.len(),
+        entropy_rate,
+    })
+}
+
+/// Save the entropy rate of order `options.k - 1` of the fasta file at
+/// `fasta_path` at `output_path`, after the `metadata` header if given, as a
+/// one-row tab-separated table
+///
+/// An input without any kmer gets `NA` for its rate, with a warning.
+pub fn run_fasta_entropy_rate(
+    fasta_path: &Path,
+    options: &CountOptions,
+    metadata: Option<&RunMetadata>,
+    output_path: &Path,
+) -> Result<()> {
+    let counter = fasta_kmer_counter(fasta_path, options)?;
+    let mut out = BufWriter::new(create_output(output_path)?);
+    write_header(metadata, &mut out)?;
+    writeln!(out, "order\tkmers\tcontexts\tentropy_rate")?;
+    match entropy_rate(&counter, options.k) {
+        Some(rate) => writeln!(
+            out,
+            "{}\t{}\t{}\t{:.6}",
+            rate.order, rate.kmers, rate.contexts, rate.entropy_rate
+        )?,
+        None => {
+            warn!(
+                "No kmers counted in {:?}; its entropy rate is NA",
+                fasta_path
+            );
+            writeln!(out, "{}\t0\t0\tNA", options.k - 1)?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::random_sequence;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn rate(sequence: &[u8], order: usize) -> f64 {
+        let mut counter = HashMap::new();
+        for window in sequence.windows(order + 1) {
+            *counter
+                .entry(String::from_utf8(window.to_vec()).unwrap())
+                .or_default() += 1;
+        }
+        entropy_rate(&counter, order + 1).unwrap().entropy_rate
+    }
+
+    #[test]
+    fn test_entropy_rate() {
+        let repeat = b"ACGT".repeat(300);
+        let random = random_sequence(20_000, 3);
+        for order in 1..=3 {
+            // each base of the repeat follows from the one before it
+            assert!(rate(&repeat, order).abs() < 1e-9);
+            assert!(rate(&random, order) > 1.9, "{}", rate(&random, order));
+        }
+        // with no context, the rate is the entropy of the base composition
+        assert!((rate(&repeat, 0) - 2.0).abs() < 1e-9);
+        assert!((rate(b"AAAC", 0) - (0.75 * (4f64 / 3.0).log2() + 0.5)).abs() < 1e-9);
+        // after a T, T and G are equally likely, and T always follows G: 2/3 bit
+        // per base
+        assert!((rate(b"TTGTTGTTGTTGTTGTTG", 1) - 2.0 / 3.0).abs() < 0.1);
+        assert_eq!(entropy_rate(&HashMap::new(), 3), None);
+    }
+
+    #[test]
+    fn test_run_fasta_entropy_rate() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("repeat.fasta");
+        fs::write(&fasta_path, ">a\nACACACAC\n>b\nGGGG\n")?;
+        let output_path = dir.path().join("repeat_entropy_rate.txt");
+        run_fasta_entropy_rate(&fasta_path, &CountOptions::new(3), None, &output_path)?;
+        // contexts AC, CA, and GG each predict their next base
+        assert_eq!(
+            fs::read_to_string(&output_path)?,
+            "order\tkmers\tcontexts\tentropy_rate\n2\t8\t3\t0.000000\n"
+        );
+
+        fs::write(&fasta_path, ">short\nAC\n")?;
+        run_fasta_entropy_rate(&fasta_path, &CountOptions::new(3), None, &output_path)?;
+        assert!(fs::read_to_string(&output_path)?.ends_with("2\t0\t0\tNA\n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,172 @@
+//! Sequencing error rate estimation from the kmer spectrum
+//!
+//! A simplified form of the model fitted by GenomeScope-style tools. A kmer
+//! is erroneous if any of its `k` bases is, so with a per-base error rate `e`
+//! a fraction `1 - (1 - e)^k` of all kmer occurrences are erroneous. Nearly
+//! all of these are unique to one read and fall in the error peak below the
+//! valley of the spectrum (see `genomesize`). The error kmer mass is the mass
+//! below the valley less the low tail expected of the genomic kmers, a
+//! Poisson distribution around the coverage peak, and inverting the relation
+//! above gives `e`.
+
+use std::io::Write;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::genomesize::{estimate_genome_size, SpectrumError};
+use crate::histogram::Histogram;
+use crate::OutputFormat;
+
+/// Error rate estimate and the spectrum quantities it was derived from
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ErrorRateEstimate {
+    pub k: usize,
+    /// abundance separating error kmers from genomic kmers
+    pub valley: u64,
+    /// abundance of the genomic kmer peak, i.e. the haploid kmer coverage
+    pub peak: u64,
+    /// kmers (with multiplicity) attributed to sequencing errors
+    pub error_kmer_mass: u128,
+    /// all kmers (with multiplicity) of the spectrum
+    pub total_kmer_mass: u128,
+    /// estimated per-base error rate
+    pub error_rate: f64,
+}
+
+/// Estimate the per-base error rate of the reads behind a spectrum of
+/// length `k` kmers
+///
+/// Fails like `estimate_genome_size` when the spectrum has no clear error
+/// peak, valley, and coverage peak, and also when it rises from abundance 1,
+/// leaving no error component to measure.
+pub fn estimate_error_rate(
+    histogram: &Histogram,
+    k: usize,
+) -> Result<ErrorRateEstimate, SpectrumError> {
+    let genome = estimate_genome_size(histogram)?;
+    if genome.valley <= 1 {
+        return Err(SpectrumError::NoErrorComponent);
+    }
+
+    let mass = |(&abundance, &frequency): (&u64, &u64)| abundance as u128 * frequency as u128;
+    let total_kmer_mass: u128 = histogram.iter().map(mass).sum();
+    let low_mass: u128 = histogram.range(..genome.valley).map(mass).sum();
+    let genomic_low_mass =
+        genome.genome_size as f64 * poisson_mass_below(genome.peak as f64, genome.valley);
+    let error_kmer_mass = (low_mass as f64 - genomic_low_mass).max(0.0).round() as u128;
+
+    let error_fraction = error_kmer_mass as f64 / total_kmer_mass as f64;
+    let error_rate = 1.0 - (1.0 - error_fraction).powf(1.0 / k as f64);
+    Ok(ErrorRateEstimate {
+        k,
+        valley: genome.valley,
+        peak: genome.peak,
+        error_kmer_mass,
+        total_kmer_mass,
+        error_rate,
+    })
+}
+
+/// Mean abundance contributed by abundances `1..valley` of a Poisson
+/// distribution of mean `lambda`, `sum a * P(a)`
+fn poisson_mass_below(lambda: f64, valley: u64) -> f64 {
+    let mut probability = (-lambda).exp();
+    let mut mass = 0.0;
+    for abundance in 1..valley {
+        probability *= lambda / abundance as f64;
+        mass += abundance as f64 * probability;
+    }
+    mass
+}
+
+/// Write the error rate estimate to `out` in the requested `format`
+pub fn write_error_rate<W: Write>(
+    estimate: &ErrorRateEstimate,
+    format: OutputFormat,
+    mut out: W,
+) -> Result<()> {
+    match format {
+        OutputFormat::Tsv => {
+            writeln!(out, "k\t{}", estimate.k)?;
+            writeln!(out, "valley\t{}", estimate.valley)?;
+            writeln!(out, "peak\t{}", estimate.peak)?;
+            writeln!(out, "error_kmer_mass\t{}", estimate.error_kmer_mass)?;
+            writeln!(out, "total_kmer_mass\t{}", estimate.total_kmer_mass)?;
+            writeln!(out, "error_rate\t{:.6}", estimate.error_rate)?;
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, estimate)?;
+            writeln!(out)?;
+        }
+        other => return Err(other.unsupported_report()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Spectrum of `genome` distinct kmers sequenced to a kmer coverage of
+    /// `coverage` with a per-base error rate of `error_rate`, the error kmers
+    /// seen once, twice, or three times
+    fn synthetic_spectrum(genome: f64, coverage: f64, error_rate: f64, k: usize) -> Histogram {
+        let correct = (1.0 - error_rate).powi(k as i32);
+        let lambda = coverage * correct;
+        let mut histogram = Histogram::new();
+        let mut probability = (-lambda).exp();
+        for abundance in 1..=(4.0 * lambda) as u64 {
+            probability *= lambda / abundance as f64;
+            histogram.insert(abundance, (genome * probability).round() as u64);
+        }
+        let error_mass = genome * coverage * (1.0 - correct);
+        for (abundance, share) in [(1, 0.8), (2, 0.15), (3, 0.05)] {
+            *histogram.get_mut(&abundance).unwrap() +=
+                (error_mass * share / abundance as f64).round() as u64;
+        }
+        histogram
+    }
+
+    #[test]
+    fn test_estimate_error_rate_synthetic() {
+        for &(coverage, error_rate) in &[(30.0, 0.01), (50.0, 0.002), (20.0, 0.005)] {
+            let spectrum = synthetic_spectrum(1e6, coverage, error_rate, 21);
+            let estimate = estimate_error_rate(&spectrum, 21).unwrap();
+            assert!(
+                (estimate.error_rate - error_rate).abs() < 0.05 * error_rate,
+                "{:?} for an error rate of {}",
+                estimate,
+                error_rate
+            );
+            assert!(estimate.valley > 3 && estimate.valley < estimate.peak);
+        }
+    }
+
+    #[test]
+    fn test_poisson_mass_below() {
+        // sum a * P(a) over 1..valley is lambda * P(X <= valley - 2)
+        let lambda: f64 = 4.0;
+        let below_two = (-lambda).exp() * (1.0 + lambda + lambda * lambda / 2.0);
+        assert!((poisson_mass_below(lambda, 4) - lambda * below_two).abs() < 1e-12);
+        assert_eq!(poisson_mass_below(lambda, 1), 0.0);
+        assert!((poisson_mass_below(lambda, 100) - lambda).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_estimate_error_rate_needs_bimodal_spectrum() {
+        // errors only, no coverage peak
+        let spectrum = Histogram::from([(1, 100), (2, 50), (3, 20), (4, 5)]);
+        assert_eq!(
+            estimate_error_rate(&spectrum, 21),
+            Err(SpectrumError::NoValley)
+        );
+
+        // a coverage peak without error kmers, as from corrected reads
+        let spectrum = Histogram::from([(1, 5), (2, 50), (3, 100), (4, 50), (5, 5)]);
+        assert_eq!(
+            estimate_error_rate(&spectrum, 21),
+            Err(SpectrumError::NoErrorComponent)
+        );
+    }
+}
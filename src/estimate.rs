@@ -0,0 +1,370 @@
+//! Pre-run resource estimates from a sample of each input
+//!
+//! The leading records of a file, up to a budget of bases, are counted and
+//! their distinct kmers tallied in a HyperLogLog. The file's total bases are
+//! extrapolated from its size, and its distinct kmers from how fast distinct
+//! kmers grew between the first half of the sample and the whole sample.
+//! Peak memory, output size, and runtime follow from simple per-kmer and
+//! per-base cost models. `ASSUMPTIONS` states the model for reports.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::seqio::{detect_line_width, DEFAULT_LINE_WIDTH};
+use crate::sketch::hash_bytes;
+use crate::strand::{self, Mate};
+use crate::timing::PhaseTimings;
+use crate::{count_sequence, open_fasta, CountOptions};
+
+/// Bases sampled from each input by default
+pub const DEFAULT_SAMPLE_BASES: u64 = 10_000_000;
+
+/// Assumptions of the extrapolation model, one per line
+pub const ASSUMPTIONS: &[&str] = &[
+    "the sample is the leading records of the file, up to the base budget, and is representative of the rest",
+    "total bases scale with file size at the sample's ratio of bases to bytes",
+    "distinct kmers grow as a power of the bases counted, with the exponent measured between the first half of the sample and the whole sample",
+    "distinct kmers are counted by a HyperLogLog with about 1.6% standard error",
+    "peak memory is one hash map entry plus one sorted row per distinct kmer, without allocator overhead",
+    "runtime scales linearly with bases from the time spent counting the sample",
+];
+
+/// Register index bits of the HyperLogLog; 2^12 registers
+const HLL_PRECISION: u32 = 12;
+
+/// Seed for kmer hashes
+const SEED: u64 = 7;
+
+/// HyperLogLog cardinality estimator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        HyperLogLog {
+            registers: vec![0; 1 << HLL_PRECISION],
+        }
+    }
+}
+
+impl HyperLogLog {
+    pub fn new() -> Self {
+        HyperLogLog::default()
+    }
+
+    pub fn insert(&mut self, item: &[u8]) {
+        let hash = hash_bytes(item, SEED);
+        let index = (hash >> (64 - HLL_PRECISION)) as usize;
+        let rank = ((hash << HLL_PRECISION) | (1 << (HLL_PRECISION - 1))).leading_zeros() + 1;
+        self.registers[index] = self.registers[index].max(rank as u8);
+    }
+
+    /// Estimated number of distinct items inserted
+    pub fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+        let raw = alpha * m * m / sum;
+
+        // linear counting is more accurate while many registers are empty
+        let empty = self.registers.iter().filter(|&&r| r == 0).count();
+        if raw <= 2.5 * m && empty > 0 {
+            m * (m / empty as f64).ln()
+        } else {
+            raw
+        }
+    }
+}
+
+/// Estimated resources needed to count one input
+#[derive(Debug, Clone, PartialEq)]
+pub struct Estimate {
+    pub input: PathBuf,
+    pub sampled_bases: u64,
+    /// the sample covered the whole file
+    pub complete: bool,
+    pub bases: u64,
+    pub kmers: u64,
+    pub distinct_kmers: u64,
+    pub peak_memory_bytes: u64,
+    pub output_bytes: u64,
+    pub runtime: Duration,
+}
+
+/// Running totals over the sampled bases
+#[derive(Default)]
+struct Sample {
+    hll: HyperLogLog,
+    /// HyperLogLog and bases counted when the first half of the budget was reached
+    half: Option<(HyperLogLog, u64)>,
+    bases: u64,
+    bytes: u64,
+    kmers: u64,
+    counting: Duration,
+}
+
+impl Sample {
+    fn add(&mut self, sequence: &[u8], options: &CountOptions) {
+        let start = Instant::now();
+        if let Ok(kmer_count) = count_sequence(sequence, options, &mut PhaseTimings::disabled()) {
+            for kmer in kmer_count {
+                self.hll.insert(kmer.seq.as_bytes());
+                self.kmers += kmer.count;
+            }
+        }
+        self.counting += start.elapsed();
+    }
+}
+
+/// Estimate the resources needed to count the fasta file at `fasta_path` as
+/// configured by `options`, from its leading `sample_bases` bases
+pub fn estimate_fasta(
+    fasta_path: &Path,
+    options: &CountOptions,
+    sample_bases: u64,
+) -> Result<Estimate> {
+    let file_bytes = fs::metadata(fasta_path)?.len();
+    let line_width = detect_line_width(fasta_path)?.unwrap_or(DEFAULT_LINE_WIDTH) as u64;
+    let half_budget = sample_bases / 2;
+    let overlap = options.k.saturating_sub(1);
+
+    let mut sample = Sample::default();
+    let mut complete = true;
+    for record in open_fasta(fasta_path)?.records() {
+        if sample.bases >= sample_bases {
+            complete = false;
+            break;
+        }
+        let record = record?;
+        let mate = Mate::from_header(record.id(), record.desc());
+        let sequence = strand::orient(record.seq(), mate, options.library_type);
+        let take = sequence.len().min((sample_bases - sample.bases) as usize);
+        if take < sequence.len() {
+            complete = false;
+        }
+        let sequence = &sequence[..take];
+
+        // split at the half budget so the growth of distinct kmers can be measured
+        if sample.half.is_none() && sample.bases + take as u64 >= half_budget {
+            let at = (half_budget - sample.bases) as usize;
+            sample.add(&sequence[..at], options);
+            sample.half = Some((sample.hll.clone(), half_budget));
+            sample.add(&sequence[at.saturating_sub(overlap)..], options);
+        } else {
+            sample.add(sequence, options);
+        }
+
+        let header = 2 + record.id().len() + record.desc().map_or(0, |d| d.len() + 1);
+        let lines = (record.seq().len() as u64).div_ceil(line_width);
+        sample.bases += take as u64;
+        sample.bytes +=
+            header as u64 + take as u64 + lines * take as u64 / record.seq().len().max(1) as u64;
+    }
+
+    let bases = if complete || sample.bytes == 0 {
+        sample.bases
+    } else {
+        let per_byte = sample.bases as f64 / sample.bytes as f64;
+        ((file_bytes as f64 * per_byte) as u64).max(sample.bases)
+    };
+    let scale = if sample.bases == 0 {
+        1.0
+    } else {
+        bases as f64 / sample.bases as f64
+    };
+
+    let distinct_sample = sample.hll.estimate();
+    let exponent = match &sample.half {
+        Some((half, half_bases)) if !complete && *half_bases > 0 && half.estimate() > 0.0 => {
+            let growth = (distinct_sample / half.estimate()).ln();
+            let span = (sample.bases as f64 / *half_bases as f64).ln();
+            (growth / span).clamp(0.0, 1.0)
+        }
+        _ => 1.0,
+    };
+    let kmers = (sample.kmers as f64 * scale).round() as u64;
+    let mut distinct = distinct_sample * scale.powf(exponent);
+    distinct = distinct.min(kmers as f64);
+    if !options.translate && options.k < 32 {
+        distinct = distinct.min(4f64.powi(options.k as i32));
+    }
+    let distinct_kmers = distinct.round() as u64;
+
+    Ok(Estimate {
+        input: fasta_path.to_owned(),
+        sampled_bases: sample.bases,
+        complete,
+        bases,
+        kmers,
+        distinct_kmers,
+        peak_memory_bytes: distinct_kmers * entry_bytes(options.k),
+        output_bytes: distinct_kmers * row_bytes(options, kmers, distinct_kmers),
+        runtime: sample.counting.mul_f64(scale),
+    })
+}
+
+/// Bytes held per distinct kmer: a `String` key and `u64` count in a hash map
+/// at 7/8 load with one control byte, plus a row of the sorted table
+fn entry_bytes(k: usize) -> u64 {
+    let key = 24 + (k as u64).div_ceil(8) * 8;
+    (key + 8 + 1) * 8 / 7 + 32
+}
+
+/// Average bytes per row of the table written for `options`
+fn row_bytes(options: &CountOptions, kmers: u64, distinct: u64) -> u64 {
+    let mean_count = kmers.checked_div(distinct).unwrap_or(1).max(1);
+    if options.varint_output {
+        let gap = (4f64.powi(options.k.min(32) as i32) / distinct.max(1) as f64).max(1.0);
+        varint_len(gap as u64) + varint_len(mean_count)
+    } else if options.log_tf {
+        options.k as u64 + 10
+    } else {
+        options.k as u64 + 2 + mean_count.to_string().len() as u64
+    }
+}
+
+/// Bytes of the LEB128 varint encoding of `n`
+fn varint_len(n: u64) -> u64 {
+    (64 - n.leading_zeros() as u64).max(1).div_ceil(7)
+}
+
+/// Write `estimates` as a tab-separated report, after the model's assumptions
+/// as `#` comment lines
+pub fn write_estimates<W: Write>(estimates: &[Estimate], mut out: W) -> Result<()> {
+    writeln!(out, "# Assumptions:")?;
+    for assumption in ASSUMPTIONS {
+        writeln!(out, "# - {}", assumption)?;
+    }
+    writeln!(
+        out,
+        "input\tsampled_bases\tcomplete\testimated_bases\testimated_distinct_kmers\t\
+         estimated_peak_memory_mb\testimated_output_mb\testimated_runtime_s"
+    )?;
+    for estimate in estimates {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\t{:.1}\t{:.1}\t{:.1}",
+            estimate.input.display(),
+            estimate.sampled_bases,
+            estimate.complete,
+            estimate.bases,
+            estimate.distinct_kmers,
+            estimate.peak_memory_bytes as f64 / 1e6,
+            estimate.output_bytes as f64 / 1e6,
+            estimate.runtime.as_secs_f64()
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fasta_kmer_counter;
+    use crate::testutil::random_sequence;
+    use tempfile::tempdir;
+
+    fn write_fasta(path: &Path, records: &[Vec<u8>]) -> Result<()> {
+        let mut fasta = String::new();
+        for (i, seq) in records.iter().enumerate() {
+            fasta.push_str(&format!(">r{}\n", i));
+            for line in seq.chunks(60) {
+                fasta.push_str(std::str::from_utf8(line)?);
+                fasta.push('\n');
+            }
+        }
+        fs::write(path, fasta)?;
+        Ok(())
+    }
+
+    fn assert_within(estimate: u64, actual: usize, tolerance: f64) {
+        let ratio = estimate as f64 / actual as f64;
+        assert!(
+            (1.0 - tolerance..=1.0 + tolerance).contains(&ratio),
+            "estimate {} vs actual {}",
+            estimate,
+            actual
+        );
+    }
+
+    #[test]
+    fn test_hyperloglog() {
+        let mut hll = HyperLogLog::new();
+        assert_eq!(hll.estimate(), 0.0);
+        for i in 0..50_000u32 {
+            hll.insert(&i.to_le_bytes());
+            hll.insert(&i.to_le_bytes());
+        }
+        assert_within(hll.estimate().round() as u64, 50_000, 0.05);
+    }
+
+    #[test]
+    fn test_estimate_diverse_input() -> Result<()> {
+        // random sequence: almost every 12-mer is new, so distinct kmers grow linearly
+        let dir = tempdir()?;
+        let path = dir.path().join("diverse.fasta");
+        let records: Vec<Vec<u8>> = (0..20).map(|seed| random_sequence(10_000, seed)).collect();
+        write_fasta(&path, &records)?;
+        let options = CountOptions::new(12);
+
+        let estimate = estimate_fasta(&path, &options, 50_000)?;
+        assert!(!estimate.complete);
+        assert_eq!(estimate.sampled_bases, 50_000);
+        assert_within(estimate.bases, 200_000, 0.02);
+        assert_within(
+            estimate.distinct_kmers,
+            fasta_kmer_counter(&path, &options)?.len(),
+            0.15,
+        );
+        assert!(estimate.peak_memory_bytes > estimate.distinct_kmers * 32);
+        assert!(estimate.output_bytes >= estimate.distinct_kmers * 15);
+        Ok(())
+    }
+
+    #[test]
+    fn test_estimate_repetitive_input() -> Result<()> {
+        // one unit repeated: distinct kmers stop growing once the unit is seen
+        let dir = tempdir()?;
+        let path = dir.path().join("repeats.fasta");
+        let unit = random_sequence(1_000, 3);
+        write_fasta(&path, &[unit.repeat(200)])?;
+        let options = CountOptions::new(12);
+
+        let estimate = estimate_fasta(&path, &options, 20_000)?;
+        assert_within(estimate.bases, 200_000, 0.02);
+        assert_within(
+            estimate.distinct_kmers,
+            fasta_kmer_counter(&path, &options)?.len(),
+            0.15,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_estimate_complete_sample() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("small.fasta");
+        write_fasta(
+            &path,
+            &[random_sequence(3_000, 5), random_sequence(2_000, 6)],
+        )?;
+        let options = CountOptions::new(10);
+
+        let estimate = estimate_fasta(&path, &options, DEFAULT_SAMPLE_BASES)?;
+        assert!(estimate.complete);
+        assert_eq!(estimate.bases, 5_000);
+        assert_eq!(estimate.kmers, 5_000 - 2 * 9);
+        assert_within(
+            estimate.distinct_kmers,
+            fasta_kmer_counter(&path, &options)?.len(),
+            0.05,
+        );
+        Ok(())
+    }
+}
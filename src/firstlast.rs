@@ -0,0 +1,119 @@
+//! First versus last base of counted kmers
+//!
+//! A 4x4 table, rows by the first base of each kmer and columns by its last
+//! base, of the total count of kmers with each pair of ends. Departures from
+//! the product of the margins hint at dependencies between bases `k - 1`
+//! apart. Kmers whose first or last base is not ACGT are left out.
+
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::metadata::{write_header, RunMetadata};
+use crate::{base_index, create_output, fasta_kmer_counter, CountOptions, KmerError};
+
+/// Row and column order of the matrix
+pub const BASES: [u8; 4] = *b"ACGT";
+
+/// Counts indexed by first base, then last base, both in `BASES` order
+pub type FirstLastMatrix = [[u64; 4]; 4];
+
+/// Tally the first and last bases of `kmer_counts`, weighted by count
+///
+/// Fails if a cell would exceed `u64::MAX`.
+pub fn first_last_matrix<'a, I>(kmer_counts: I) -> Result<FirstLastMatrix>
+where
+    I: IntoIterator<Item = (&'a str, u64)>,
+{
+    let mut matrix = [[0u64; 4]; 4];
+    for (kmer, count) in kmer_counts {
+        let bases = kmer.as_bytes();
+        let first = bases.first().and_then(|&base| base_index(base));
+        let last = bases.last().and_then(|&base| base_index(base));
+        if let (Some(first), Some(last)) = (first, last) {
+            let cell = &mut matrix[first][last];
+            *cell = cell
+                .checked_add(count)
+                .ok_or_else(|| KmerError::CountOverflow {
+                    kmer: kmer.to_owned(),
+                })?;
+        }
+    }
+    Ok(matrix)
+}
+
+/// Write `matrix` as a tab-separated table with `ACGT` row and column labels
+pub fn write_first_last_matrix<W: Write>(matrix: &FirstLastMatrix, mut out: W) -> Result<()> {
+    writeln!(out, "first\\last\tA\tC\tG\tT")?;
+    for (&first, row) in BASES.iter().zip(matrix) {
+        let row: Vec<String> = row.iter().map(u64::to_string).collect();
+        writeln!(out, "{}\t{}", first as char, row.join("\t"))?;
+    }
+    Ok(())
+}
+
+/// Save the first versus last base matrix of the kmers of the fasta file at
+/// `fasta_path`, counted as configured by `options`, at `output_path` after
+/// the `metadata` header if given
+///
+/// Kmers outside the GC window of `options` are left out.
+pub fn run_fasta_first_last_matrix(
+    fasta_path: &Path,
+    options: &CountOptions,
+    metadata: Option<&RunMetadata>,
+    output_path: &Path,
+) -> Result<()> {
+    let counter = fasta_kmer_counter(fasta_path, options)?;
+    let matrix = first_last_matrix(
+        counter
+            .iter()
+            .filter(|(kmer, _)| options.in_gc_window(kmer))
+            .map(|(kmer, &count)| (kmer.as_str(), count)),
+    )?;
+    let mut out = BufWriter::new(create_output(output_path)?);
+    write_header(metadata, &mut out)?;
+    write_first_last_matrix(&matrix, &mut out)?;
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_first_last_matrix() {
+        let matrix = first_last_matrix(vec![("ATC", 2), ("acg", 1), ("NAA", 5), ("G", 3)]).unwrap();
+        assert_eq!(matrix[0][1], 2);
+        assert_eq!(matrix[0][2], 1);
+        assert_eq!(matrix[2][2], 3);
+        assert_eq!(matrix.iter().flatten().sum::<u64>(), 6);
+
+        let matrix = first_last_matrix(vec![("ACA", u64::MAX - 1), ("AGA", 1)]).unwrap();
+        assert_eq!(matrix[0][0], u64::MAX);
+        assert!(first_last_matrix(vec![("ACA", u64::MAX), ("AGA", 1)]).is_err());
+    }
+
+    #[test]
+    fn test_run_fasta_first_last_matrix() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("fixed.fasta");
+        fs::write(&fasta_path, ">seq\nATCGGATCG\n")?;
+
+        // ATC 2, TCG 2, CGG 1, GAT 1, GGA 1
+        let output_path = dir.path().join("fixed_first_last.txt");
+        run_fasta_first_last_matrix(&fasta_path, &CountOptions::new(3), None, &output_path)?;
+        assert_eq!(
+            fs::read_to_string(&output_path)?,
+            "first\\last\tA\tC\tG\tT\n\
+             A\t0\t2\t0\t0\n\
+             C\t0\t0\t1\t0\n\
+             G\t1\t0\t0\t1\n\
+             T\t0\t0\t2\t0\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+//! Fragment counting of interleaved paired-end reads
+//!
+//! Each mate pair is joined into one fragment: the first mate, a gap, and the
+//! reverse complement of the second mate, so both mates read along the same
+//! strand. Kmers are counted within each mate and never across the gap, and
+//! a fragment is counted only if both of its mates can be. Mates are paired
+//! by their headers (see `strand::Mate`); a record without a mate is counted
+//! alone.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Result;
+use log::warn;
+
+use crate::metadata::RunMetadata;
+use crate::strand::{LibraryType, Mate};
+use crate::timing::PhaseTimings;
+use crate::{
+    add_count, add_sequence_counts, open_fasta, ordered_kmer_count, reverse_complement,
+    save_counts, CountOptions, KmerError,
+};
+
+/// Separates the mates of a fragment; no kmer spans it
+pub const MATE_GAP: u8 = b'|';
+
+/// Fragment of the mates `first` and `second`, either of which may be missing,
+/// oriented onto the transcribed strand for stranded `library_type`s
+pub fn fragment_sequence(
+    first: Option<&[u8]>,
+    second: Option<&[u8]>,
+    library_type: LibraryType,
+) -> Vec<u8> {
+    let mut fragment = first.map(<[u8]>::to_vec).unwrap_or_default();
+    if let Some(second) = second {
+        if first.is_some() {
+            fragment.push(MATE_GAP);
+        }
+        fragment.extend(reverse_complement(second));
+    }
+    if library_type == LibraryType::Rf {
+        // the second mate is on the transcribed strand
+        reverse_complement(&fragment)
+    } else {
+        fragment
+    }
+}
+
+/// Add the kmers of each mate of `fragment` to `counter`
+///
+/// A mate shorter than the kmer length is skipped if the other mate is
+/// counted. On error, nothing of the fragment is added.
+pub(crate) fn add_fragment_counts(
+    counter: &mut HashMap<String, u64>,
+    fragment: &[u8],
+    options: &CountOptions,
+    timings: &mut PhaseTimings,
+) -> Result<(), KmerError> {
+    let mut fragment_counter = HashMap::new();
+    let mut too_short = None;
+    for mate in fragment.split(|&base| base == MATE_GAP) {
+        match add_sequence_counts(&mut fragment_counter, mate, options, timings) {
+            Ok(()) => {}
+            Err(err @ KmerError::KmerLengthTooLong { .. }) => too_short = Some(err),
+            Err(err) => return Err(err),
+        }
+    }
+    match too_short {
+        Some(err) if fragment_counter.is_empty() => return Err(err),
+        _ => {}
+    }
+    for (kmer, count) in fragment_counter {
+        add_count(counter, kmer, count)?;
+    }
+    Ok(())
+}
+
+/// Count kmers over the mate pairs of the interleaved fasta file at
+/// `fasta_path`
+///
+/// Fragments that cannot be counted are skipped with a warning, as is any
+/// record without a mate.
+pub fn fasta_fragment_counter(
+    fasta_path: &Path,
+    options: &CountOptions,
+) -> Result<HashMap<String, u64>> {
+    let mut counter = HashMap::new();
+    let mut timings = PhaseTimings::disabled();
+    let mut add = |id: &str, first: Option<&[u8]>, second: Option<&[u8]>| {
+        let fragment = fragment_sequence(first, second, options.library_type);
+        match add_fragment_counts(&mut counter, &fragment, options, &mut timings) {
+            Err(err) if err.is_fatal() => Err(err),
+            Err(err) => {
+                warn!("Skipping fragment {}: {}", id, err);
+                Ok(())
+            }
+            Ok(()) => Ok(()),
+        }
+    };
+
+    let mut unpaired = 0;
+    let mut pending: Option<(String, Vec<u8>)> = None;
+    for record in open_fasta(fasta_path)?.records() {
+        let record = record?;
+        match Mate::from_header(record.id(), record.desc()) {
+            Mate::First => {
+                let first = (record.id().to_owned(), record.seq().to_vec());
+                if let Some((id, seq)) = pending.replace(first) {
+                    unpaired += 1;
+                    add(&id, Some(&seq), None)?;
+                }
+            }
+            Mate::Second => match pending.take() {
+                Some((id, seq)) => add(&id, Some(&seq), Some(record.seq()))?,
+                None => {
+                    unpaired += 1;
+                    add(record.id(), None, Some(record.seq()))?;
+                }
+            },
+        }
+    }
+    if let Some((id, seq)) = pending {
+        unpaired += 1;
+        add(&id, Some(&seq), None)?;
+    }
+
+    if unpaired > 0 {
+        warn!(
+            "{} record(s) of {:?} had no mate and were counted alone",
+            unpaired, fasta_path
+        );
+    }
+    Ok(counter)
+}
+
+/// Save the fragment kmer counts of the interleaved fasta file at
+/// `fasta_path` at `output_path`
+pub fn run_fasta_fragment_kmer_count(
+    fasta_path: &Path,
+    options: &CountOptions,
+    metadata: Option<&RunMetadata>,
+    output_path: &Path,
+) -> Result<()> {
+    let counter = fasta_fragment_counter(fasta_path, options)?;
+    save_counts(ordered_kmer_count(counter), options, metadata, output_path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::read_kmer_table;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_fragment_sequence() {
+        let fragment = |library_type| fragment_sequence(Some(b"AAAC"), Some(b"GGTT"), library_type);
+        assert_eq!(fragment(LibraryType::Unstranded), b"AAAC|AACC");
+        assert_eq!(fragment(LibraryType::Fr), b"AAAC|AACC");
+        assert_eq!(fragment(LibraryType::Rf), b"GGTT|GTTT");
+        assert_eq!(
+            fragment_sequence(None, Some(b"GGTT"), LibraryType::Unstranded),
+            b"AACC"
+        );
+        assert_eq!(
+            fragment_sequence(Some(b"AAAC"), None, LibraryType::Unstranded),
+            b"AAAC"
+        );
+    }
+
+    #[test]
+    fn test_add_fragment_counts() {
+        let options = CountOptions::new(3);
+        let mut timings = PhaseTimings::disabled();
+        let mut counter = HashMap::new();
+        add_fragment_counts(&mut counter, b"AAAC|AC", &options, &mut timings).unwrap();
+        assert_eq!(counter.len(), 2);
+        assert_eq!(counter["AAA"], 1);
+        assert_eq!(counter["AAC"], 1);
+
+        let err = add_fragment_counts(&mut counter, b"AC|AC", &options, &mut timings);
+        assert!(matches!(err, Err(KmerError::KmerLengthTooLong { .. })));
+        let err = add_fragment_counts(&mut counter, b"GGG|\xffGG", &options, &mut timings);
+        assert!(matches!(err, Err(KmerError::NonAsciiBase { .. })));
+        assert_eq!(counter.len(), 2);
+    }
+
+    #[test]
+    fn test_run_fasta_fragment_kmer_count() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("pairs.fasta");
+        fs::write(
+            &fasta_path,
+            ">frag1/1\nAAAAC\n>frag1/2\nGGGTT\n>frag2/1\nTTTTT\n>frag2/2\nCCCCC\n",
+        )?;
+
+        let output_path = dir.path().join("pairs_kmer.txt");
+        run_fasta_fragment_kmer_count(&fasta_path, &CountOptions::new(3), None, &output_path)?;
+        let table: HashMap<String, u64> = read_kmer_table(&output_path)?.into_iter().collect();
+
+        // frag1 is AAAAC|AACCC and frag2 TTTTT|GGGGG
+        let expected: HashMap<String, u64> = [
+            ("AAA", 2),
+            ("AAC", 2),
+            ("ACC", 1),
+            ("CCC", 1),
+            ("TTT", 3),
+            ("GGG", 3),
+        ]
+        .iter()
+        .map(|&(kmer, count)| (kmer.to_owned(), count))
+        .collect();
+        assert_eq!(table, expected);
+
+        // windows across the junction of frag1 would be ACA and CAA
+        assert!(!table.contains_key("ACA") && !table.contains_key("CAA"));
+        Ok(())
+    }
+}
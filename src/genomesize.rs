@@ -0,0 +1,227 @@
+//! Genome size estimation from the kmer spectrum
+//!
+//! Uses the standard kmer-spectrum model: low-abundance kmers produced by
+//! sequencing errors form a peak at abundance 1 that decays into a valley,
+//! followed by a peak of genomic kmers centered on the haploid kmer coverage.
+//! The genome size is estimated as the number of kmers at or above the valley
+//! divided by that coverage.
+
+use std::io::Write;
+
+use anyhow::Result;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::histogram::Histogram;
+use crate::OutputFormat;
+
+/// Largest abundance considered when searching for the valley and coverage peak
+pub const MAX_DETECTION_ABUNDANCE: u64 = 10_000;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum SpectrumError {
+    #[error("Kmer spectrum is empty")]
+    Empty,
+
+    #[error("No valley found. The kmer spectrum never rises after the error peak")]
+    NoValley,
+
+    #[error("No coverage peak found above the valley at abundance {valley:?}")]
+    NoPeak { valley: u64 },
+
+    #[error("No error kmers found. The kmer spectrum rises from abundance 1, as for error-corrected reads")]
+    NoErrorComponent,
+}
+
+/// Genome size estimate and the spectrum features it was derived from
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GenomeSizeEstimate {
+    /// abundance separating error kmers from genomic kmers
+    pub valley: u64,
+    /// abundance of the genomic kmer peak, i.e. the haploid kmer coverage
+    pub peak: u64,
+    /// total number of kmers (with multiplicity) at or above the valley
+    pub kmers_above_valley: u128,
+    /// estimated genome size in bases
+    pub genome_size: u64,
+}
+
+/// Estimate genome size from a kmer spectrum
+///
+/// The spectrum is smoothed with a 3-point moving average before searching for
+/// the valley and peak, so isolated noisy bins do not produce spurious features.
+pub fn estimate_genome_size(histogram: &Histogram) -> Result<GenomeSizeEstimate, SpectrumError> {
+    let max_abundance = match histogram.keys().next_back() {
+        Some(&max) if max > 0 => max.min(MAX_DETECTION_ABUNDANCE),
+        _ => return Err(SpectrumError::Empty),
+    };
+    let smoothed = smooth(histogram, max_abundance);
+    let at = |abundance: u64| smoothed[abundance as usize - 1];
+
+    // the first rise after the initial decline marks the error/genomic boundary
+    let first_rise = (1..max_abundance)
+        .find(|&a| at(a + 1) > at(a))
+        .ok_or(SpectrumError::NoValley)?;
+
+    // first maximum wins ties so the choice is deterministic
+    let peak = (first_rise + 1..=max_abundance).fold(first_rise + 1, |best, a| {
+        if at(a) > at(best) {
+            a
+        } else {
+            best
+        }
+    });
+    let valley = (first_rise..=peak).fold(
+        first_rise,
+        |best, a| {
+            if at(a) < at(best) {
+                a
+            } else {
+                best
+            }
+        },
+    );
+    // a genomic peak spans neighboring abundances; an isolated bin is a repeat or noise
+    let supported = (peak - 1..=peak + 1).all(|a| histogram.get(&a).is_some_and(|&f| f > 0));
+    if at(peak) <= at(valley) || !supported {
+        return Err(SpectrumError::NoPeak { valley });
+    }
+
+    let kmers_above_valley: u128 = histogram
+        .range(valley..)
+        .map(|(&abundance, &frequency)| abundance as u128 * frequency as u128)
+        .sum();
+    let genome_size = (kmers_above_valley as f64 / peak as f64).round() as u64;
+
+    Ok(GenomeSizeEstimate {
+        valley,
+        peak,
+        kmers_above_valley,
+        genome_size,
+    })
+}
+
+/// 3-point moving average of the histogram over abundances `1..=max_abundance`
+///
+/// Abundance 1 is averaged with its right neighbor only; abundances past the
+/// end of the histogram count as empty.
+fn smooth(histogram: &Histogram, max_abundance: u64) -> Vec<f64> {
+    let dense: Vec<f64> = (1..=max_abundance + 1)
+        .map(|a| *histogram.get(&a).unwrap_or(&0) as f64)
+        .collect();
+
+    (0..dense.len() - 1)
+        .map(|i| {
+            let window = &dense[i.saturating_sub(1)..i + 2];
+            window.iter().sum::<f64>() / window.len() as f64
+        })
+        .collect()
+}
+
+/// Write the genome size estimate for kmer length `k` to `out` in the requested `format`
+pub fn write_estimate<W: Write>(
+    estimate: &GenomeSizeEstimate,
+    k: Option<usize>,
+    format: OutputFormat,
+    mut out: W,
+) -> Result<()> {
+    match format {
+        OutputFormat::Tsv => {
+            if let Some(k) = k {
+                writeln!(out, "k\t{}", k)?;
+            }
+            writeln!(out, "valley\t{}", estimate.valley)?;
+            writeln!(out, "peak\t{}", estimate.peak)?;
+            writeln!(out, "kmers_above_valley\t{}", estimate.kmers_above_valley)?;
+            writeln!(out, "genome_size\t{}", estimate.genome_size)?;
+        }
+        OutputFormat::Json => {
+            let mut value = serde_json::to_value(estimate)?;
+            if let Some(k) = k {
+                value["k"] = k.into();
+            }
+            serde_json::to_writer_pretty(&mut out, &value)?;
+            writeln!(out)?;
+        }
+        other => return Err(other.unsupported_report()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn histogram(bins: &[(u64, u64)]) -> Histogram {
+        bins.iter().cloned().collect()
+    }
+
+    #[test]
+    fn test_estimate_genome_size() {
+        // error kmers decaying from abundance 1, then 100,000 genomic kmers
+        // distributed symmetrically around a coverage of 20
+        let spectrum = histogram(&[
+            (1, 50_000),
+            (2, 8_000),
+            (3, 1_000),
+            (4, 200),
+            (5, 60),
+            (6, 40),
+            (7, 20),
+            (8, 15),
+            (9, 10),
+            (10, 12),
+            (11, 40),
+            (12, 150),
+            (18, 10_000),
+            (19, 20_000),
+            (20, 40_000),
+            (21, 20_000),
+            (22, 10_000),
+        ]);
+
+        let estimate = estimate_genome_size(&spectrum).unwrap();
+        assert_eq!(estimate.peak, 20);
+        // the small bump at 11-12 is passed over for the true minimum before the peak
+        assert_eq!(estimate.valley, 14);
+        assert_eq!(estimate.kmers_above_valley, 2_000_000);
+        assert_eq!(estimate.genome_size, 100_000);
+    }
+
+    #[test]
+    fn test_estimate_genome_size_isolated_peak() {
+        // a single high-abundance kmer is not a coverage peak
+        let spectrum = histogram(&[(1, 100), (2, 20), (3, 5), (18, 1)]);
+        assert_eq!(
+            estimate_genome_size(&spectrum),
+            Err(SpectrumError::NoPeak { valley: 16 })
+        );
+    }
+
+    #[test]
+    fn test_estimate_genome_size_empty() {
+        assert_eq!(
+            estimate_genome_size(&Histogram::new()),
+            Err(SpectrumError::Empty)
+        );
+    }
+
+    #[test]
+    fn test_estimate_genome_size_no_valley() {
+        let spectrum = histogram(&[(1, 100), (2, 50), (3, 20), (4, 5)]);
+        assert_eq!(
+            estimate_genome_size(&spectrum),
+            Err(SpectrumError::NoValley)
+        );
+    }
+
+    #[test]
+    fn test_estimate_genome_size_noise_smoothed() {
+        // a single noisy bin is not a real rise once smoothed
+        let spectrum = histogram(&[(1, 10), (2, 3), (3, 4)]);
+        assert_eq!(
+            estimate_genome_size(&spectrum),
+            Err(SpectrumError::NoValley)
+        );
+    }
+}
@@ -0,0 +1,318 @@
+//! Approximate counts of the most abundant kmers in bounded memory
+//!
+//! The Space-Saving algorithm (Metwally, Agrawal and El Abbadi, 2005)
+//! monitors at most `capacity` kmers. A monitored kmer has its count raised as
+//! it recurs; an unmonitored kmer evicts the monitored kmer with the smallest
+//! count and takes over that count, recording it as its error. Every kmer
+//! occurring more than `total / capacity` times is kept, and the count of a
+//! kept kmer overestimates its true count by at most its error. Kmers
+//! monitored from their first occurrence have an error of 0 and exact counts.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::metadata::RunMetadata;
+use crate::strand::{self, Mate};
+use crate::timing::PhaseTimings;
+use crate::{
+    add_sequence_counts, open_fasta, ordered_kmer_count, save_counts, CountOptions, KmerError,
+    RecordTally,
+};
+
+/// A monitored kmer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Counter {
+    pub kmer: String,
+    /// upper bound of the true count
+    pub count: u64,
+    /// most by which `count` may exceed the true count
+    pub error: u64,
+}
+
+/// Space-Saving summary of a stream of kmers
+#[derive(Debug, Clone)]
+pub struct SpaceSaving {
+    capacity: usize,
+    /// min-heap of the monitored kmers by count
+    heap: Vec<Counter>,
+    /// position of each monitored kmer in `heap`
+    positions: HashMap<String, usize>,
+}
+
+impl SpaceSaving {
+    /// Summary monitoring at most `capacity` kmers, at least 1
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "Space-Saving needs at least one counter");
+        SpaceSaving {
+            capacity,
+            heap: Vec::with_capacity(capacity),
+            positions: HashMap::with_capacity(capacity),
+        }
+    }
+
+    /// Number of monitored kmers
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    pub fn get(&self, kmer: &str) -> Option<&Counter> {
+        self.positions
+            .get(kmer)
+            .map(|&position| &self.heap[position])
+    }
+
+    /// Add `weight` occurrences of `kmer`
+    ///
+    /// Fails if a count would exceed `u64::MAX`.
+    pub fn add(&mut self, kmer: &str, weight: u64) -> Result<()> {
+        let overflow = || KmerError::CountOverflow {
+            kmer: kmer.to_owned(),
+        };
+        if let Some(&position) = self.positions.get(kmer) {
+            let counter = &mut self.heap[position];
+            counter.count = counter.count.checked_add(weight).ok_or_else(overflow)?;
+            self.sift_down(position);
+        } else if self.heap.len() < self.capacity {
+            self.positions.insert(kmer.to_owned(), self.heap.len());
+            self.heap.push(Counter {
+                kmer: kmer.to_owned(),
+                count: weight,
+                error: 0,
+            });
+            self.sift_up(self.heap.len() - 1);
+        } else {
+            let min = self.heap[0].count;
+            let evicted = std::mem::replace(
+                &mut self.heap[0],
+                Counter {
+                    kmer: kmer.to_owned(),
+                    count: min.checked_add(weight).ok_or_else(overflow)?,
+                    error: min,
+                },
+            );
+            self.positions.remove(&evicted.kmer);
+            self.positions.insert(kmer.to_owned(), 0);
+            self.sift_down(0);
+        }
+        Ok(())
+    }
+
+    /// Largest error of a monitored kmer
+    pub fn max_error(&self) -> u64 {
+        self.heap
+            .iter()
+            .map(|counter| counter.error)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Monitored kmers from most to least abundant, ties by kmer
+    pub fn into_counters(self) -> Vec<Counter> {
+        let mut counters = self.heap;
+        counters.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.kmer.cmp(&b.kmer)));
+        counters
+    }
+
+    fn swap(&mut self, a: usize, b: usize) {
+        self.heap.swap(a, b);
+        *self.positions.get_mut(&self.heap[a].kmer).unwrap() = a;
+        *self.positions.get_mut(&self.heap[b].kmer).unwrap() = b;
+    }
+
+    fn sift_up(&mut self, mut position: usize) {
+        while position > 0 {
+            let parent = (position - 1) / 2;
+            if self.heap[parent].count <= self.heap[position].count {
+                break;
+            }
+            self.swap(parent, position);
+            position = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut position: usize) {
+        loop {
+            let mut smallest = position;
+            for child in [2 * position + 1, 2 * position + 2] {
+                if child < self.heap.len() && self.heap[child].count < self.heap[smallest].count {
+                    smallest = child;
+                }
+            }
+            if smallest == position {
+                break;
+            }
+            self.swap(smallest, position);
+            position = smallest;
+        }
+    }
+}
+
+/// Summarize the kmers of the fasta file at `fasta_path`, counted as
+/// configured by `options`, in at most `capacity` counters
+///
+/// Each record is counted on its own before its kmers are added in sorted
+/// order, so memory stays within `capacity` counters plus one record's kmers.
+/// Kmers outside the GC window of `options` are left out, and records shorter
+/// than `k` are skipped and reported in a single warning.
+pub fn fasta_heavy_hitters(
+    fasta_path: &Path,
+    options: &CountOptions,
+    capacity: usize,
+) -> Result<SpaceSaving> {
+    let mut summary = SpaceSaving::new(capacity);
+    let mut tally = RecordTally::default();
+    for record in open_fasta(fasta_path)?.records() {
+        let record = record?;
+        let mate = Mate::from_header(record.id(), record.desc());
+        let sequence = strand::orient(record.seq(), mate, options.library_type);
+        let mut counter = HashMap::new();
+        let mut timings = PhaseTimings::disabled();
+        match add_sequence_counts(&mut counter, &sequence, options, &mut timings) {
+            Err(err) if err.is_fatal() => return Err(err.into()),
+            result => tally.add(record.id(), result),
+        }
+        let mut kmers: Vec<(String, u64)> = counter
+            .into_iter()
+            .filter(|(kmer, _)| options.in_gc_window(kmer))
+            .collect();
+        kmers.sort_unstable();
+        for (kmer, count) in kmers {
+            summary.add(&kmer, count)?;
+        }
+    }
+    tally.warn_too_short(fasta_path, options.k);
+    Ok(summary)
+}
+
+/// Save the approximate counts of the `capacity` most abundant kmers of the
+/// fasta file at `fasta_path` at `output_path`, after the `metadata` header
+/// if given, which also records the largest overestimate of a count
+pub fn run_fasta_heavy_hitters(
+    fasta_path: &Path,
+    options: &CountOptions,
+    capacity: usize,
+    metadata: Option<&RunMetadata>,
+    output_path: &Path,
+) -> Result<()> {
+    let summary = fasta_heavy_hitters(fasta_path, options, capacity)?;
+    let metadata = metadata.map(|metadata| {
+        metadata
+            .clone()
+            .with("heavy_hitters_max_error", summary.max_error())
+    });
+    let counter: HashMap<String, u64> = summary
+        .into_counters()
+        .into_iter()
+        .map(|counter| (counter.kmer, counter.count))
+        .collect();
+    save_counts(
+        ordered_kmer_count(counter),
+        options,
+        metadata.as_ref(),
+        output_path,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::read_kmer_table;
+    use crate::testutil::random_sequence;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_space_saving_exact_within_capacity() -> Result<()> {
+        let mut summary = SpaceSaving::new(3);
+        for kmer in ["AC", "GT", "AC", "TT", "AC", "GT"] {
+            summary.add(kmer, 1)?;
+        }
+        let counters = summary.into_counters();
+        let counts: Vec<(&str, u64, u64)> = counters
+            .iter()
+            .map(|c| (c.kmer.as_str(), c.count, c.error))
+            .collect();
+        assert_eq!(counts, [("AC", 3, 0), ("GT", 2, 0), ("TT", 1, 0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_space_saving_evicts_smallest() -> Result<()> {
+        let mut summary = SpaceSaving::new(2);
+        summary.add("AA", 5)?;
+        summary.add("CC", 1)?;
+        summary.add("GG", 2)?;
+        assert!(summary.get("CC").is_none());
+        assert_eq!(
+            summary.get("GG"),
+            Some(&Counter {
+                kmer: "GG".to_owned(),
+                count: 3,
+                error: 1,
+            })
+        );
+        assert_eq!(summary.max_error(), 1);
+        assert_eq!(summary.len(), 2);
+
+        summary.add("AA", u64::MAX - 5)?;
+        assert!(summary.add("AA", 1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fasta_heavy_hitters_keeps_frequent_kmers() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("noisy.fasta");
+        // 200 records of random sequence, every tenth also carrying one of
+        // three repeats, so the repeat kmers are far above the noise
+        let repeats = ["ACGTACGTAC", "GGGGCCCCGG", "TTAATTAATT"];
+        let mut fasta = String::new();
+        for n in 0..200 {
+            let mut seq = String::from_utf8(random_sequence(100, n))?;
+            seq.push_str(repeats[n as usize % 3]);
+            fasta.push_str(&format!(">r{}\n{}\n", n, seq));
+        }
+        fs::write(&fasta_path, fasta)?;
+
+        let options = CountOptions::new(8);
+        let exact = crate::fasta_kmer_counter(&fasta_path, &options)?;
+        let total: u64 = exact.values().sum();
+        let capacity = 500;
+        let summary = fasta_heavy_hitters(&fasta_path, &options, capacity)?;
+        assert_eq!(summary.len(), capacity);
+
+        let bound = total / capacity as u64;
+        for repeat in &repeats {
+            for kmer in crate::kmers(repeat.as_bytes(), 8)? {
+                let true_count = exact[kmer];
+                let counter = summary.get(kmer).expect("frequent kmer evicted");
+                assert!(counter.count >= true_count);
+                assert!(counter.count - counter.error <= true_count);
+                assert!(counter.error <= bound);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_fasta_heavy_hitters() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("repeat.fasta");
+        fs::write(&fasta_path, ">a\nAAAAAAC\n")?;
+        let output_path = dir.path().join("repeat_kmer.txt");
+        run_fasta_heavy_hitters(&fasta_path, &CountOptions::new(3), 1, None, &output_path)?;
+        // AAA is seen 4 times before AAC evicts it with an error of 4
+        let table = read_kmer_table(&output_path)?;
+        assert_eq!(
+            table.into_iter().collect::<Vec<_>>(),
+            [("AAC".to_owned(), 5)]
+        );
+        Ok(())
+    }
+}
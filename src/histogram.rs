@@ -0,0 +1,175 @@
+//! Kmer abundance histograms (kmer spectra)
+//!
+//! A histogram maps each kmer abundance to the number of distinct kmers seen
+//! that many times. The text format is the two-column `abundance frequency`
+//! layout written by `jellyfish histo`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+
+use crate::{kmers, open_fasta};
+
+/// Number of distinct kmers (value) observed at each abundance (key)
+pub type Histogram = BTreeMap<u64, u64>;
+
+/// Build a histogram from kmer counts
+pub fn histogram_from_counts<I>(counts: I) -> Histogram
+where
+    I: IntoIterator<Item = u64>,
+{
+    let mut histogram = Histogram::new();
+    for count in counts {
+        *histogram.entry(count).or_insert(0) += 1;
+    }
+    histogram
+}
+
+/// Build the spectrum of length `k` kmers over all records of the fasta file at `fasta_path`
+///
+/// Records shorter than `k` are skipped with a warning.
+pub fn fasta_kmer_histogram(fasta_path: &Path, k: usize) -> Result<Histogram> {
+    let reader = open_fasta(fasta_path)?;
+
+    let mut counter: HashMap<String, u64> = HashMap::new();
+    for record in reader.records() {
+        let record = record?;
+        match kmers(record.seq(), k) {
+            Ok(kmers) => {
+                for kmer in kmers {
+                    *counter.entry(kmer.to_owned()).or_insert(0) += 1;
+                }
+            }
+            Err(err) => warn!("Skipping record {}: {}", record.id(), err),
+        };
+    }
+    Ok(histogram_from_counts(counter.into_values()))
+}
+
+/// Read a two-column `abundance frequency` histogram from the file at `path`
+///
+/// Columns may be separated by any whitespace. Blank lines, `#` comments, and a
+/// non-numeric header line are ignored.
+pub fn read_histogram(path: &Path) -> Result<Histogram> {
+    let file = File::open(path).with_context(|| format!("Could not open {:?}", path))?;
+
+    let mut histogram = Histogram::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let parsed = match fields.as_slice() {
+            [abundance, frequency] => abundance
+                .parse::<u64>()
+                .and_then(|a| frequency.parse::<u64>().map(|f| (a, f))),
+            _ => return Err(anyhow!("{:?}:{}: expected two columns", path, i + 1)),
+        };
+        match parsed {
+            Ok((abundance, frequency)) => {
+                let total = histogram.entry(abundance).or_insert(0);
+                *total = total.checked_add(frequency).ok_or_else(|| {
+                    anyhow!(
+                        "{:?}:{}: frequency of abundance {} exceeds {}",
+                        path,
+                        i + 1,
+                        abundance,
+                        u64::MAX
+                    )
+                })?;
+            }
+            Err(_) if i == 0 => continue, // header line
+            Err(err) => return Err(anyhow!("{:?}:{}: {}", path, i + 1, err)),
+        }
+    }
+    Ok(histogram)
+}
+
+/// Write `histogram` as tab-separated `abundance frequency` lines
+pub fn write_histogram<W: Write>(histogram: &Histogram, mut out: W) -> Result<()> {
+    for (abundance, frequency) in histogram {
+        writeln!(out, "{}\t{}", abundance, frequency)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_histogram_from_counts() {
+        let histogram = histogram_from_counts(vec![1, 1, 3, 1, 3, 7]);
+        assert_eq!(
+            histogram.into_iter().collect::<Vec<_>>(),
+            [(1, 3), (3, 2), (7, 1)]
+        );
+    }
+
+    #[test]
+    fn test_read_histogram() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("histo.txt");
+        let mut file = File::create(&path)?;
+        writeln!(file, "abundance\tfrequency")?;
+        writeln!(file, "1 100")?;
+        writeln!(file, "# comment")?;
+        writeln!(file, "2\t5")?;
+
+        let histogram = read_histogram(&path)?;
+        assert_eq!(
+            histogram.into_iter().collect::<Vec<_>>(),
+            [(1, 100), (2, 5)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_histogram_bad_line() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("histo.txt");
+        let mut file = File::create(&path)?;
+        writeln!(file, "1 100")?;
+        writeln!(file, "2 x")?;
+
+        assert!(read_histogram(&path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_histogram_overflow() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("histo.txt");
+        let mut file = File::create(&path)?;
+        writeln!(file, "5 {}", u64::MAX)?;
+        writeln!(file, "5 1")?;
+
+        let err = read_histogram(&path).unwrap_err();
+        assert!(err.to_string().contains("exceeds"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fasta_kmer_histogram() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("seqs.fasta");
+        let mut file = File::create(&path)?;
+        writeln!(file, ">a\nATCGATCG\n>b\nATC")?;
+
+        // ATC x3, TCG x2, CGA x1, GAT x1
+        let histogram = fasta_kmer_histogram(&path, 3)?;
+        assert_eq!(
+            histogram.into_iter().collect::<Vec<_>>(),
+            [(1, 2), (2, 1), (3, 1)]
+        );
+        Ok(())
+    }
+}
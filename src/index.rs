@@ -0,0 +1,221 @@
+//! Lexicographically sorted count tables with a prefix index
+//!
+//! The table is the usual `kmer\tcount` TSV, sorted by kmer. A sidecar file
+//! (the table path with `.idx` appended) maps each kmer prefix of up to
+//! `PREFIX_LEN` bases to the byte offset of its first row, so lookups only
+//! read the rows sharing the query's prefix.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::metadata::{write_header, RunMetadata};
+use crate::validate::write_trailer;
+use crate::{create_output, KmerCount};
+
+/// Number of leading bases used as the index key
+pub const PREFIX_LEN: usize = 2;
+
+/// Byte offset of the first row for each kmer prefix
+pub type PrefixIndex = BTreeMap<String, u64>;
+
+/// Path of the prefix index sidecar for the table at `table_path`
+pub fn index_path(table_path: &Path) -> PathBuf {
+    let mut path = OsString::from(table_path.as_os_str());
+    path.push(".idx");
+    PathBuf::from(path)
+}
+
+/// First `PREFIX_LEN` characters of `kmer`, cut on a character boundary so
+/// a query with a multibyte character does not panic
+fn prefix(kmer: &str) -> &str {
+    let end = kmer
+        .char_indices()
+        .nth(PREFIX_LEN)
+        .map_or(kmer.len(), |(end, _)| end);
+    &kmer[..end]
+}
+
+/// Save `kmer_count` sorted by kmer to `output_path`, after the `metadata`
+/// header if given, with its prefix index alongside
+pub(crate) fn save_indexed_kmer_count(
+    mut kmer_count: KmerCount,
+    metadata: Option<&RunMetadata>,
+    output_path: &Path,
+) -> Result<()> {
+    kmer_count.sort_by(|a, b| a.seq.cmp(&b.seq));
+
+    let mut file = BufWriter::new(create_output(output_path)?);
+    let mut index = PrefixIndex::new();
+
+    let mut header = Vec::new();
+    write_header(metadata, &mut header)?;
+    header.extend_from_slice(b"kmer\tcount\n");
+    file.write_all(&header)?;
+    let mut offset = header.len() as u64;
+    for kmer in &kmer_count {
+        index.entry(prefix(&kmer.seq).to_owned()).or_insert(offset);
+
+        let row = format!("{}\t{}\n", kmer.seq, kmer.count);
+        file.write_all(row.as_bytes())?;
+        offset += row.len() as u64;
+    }
+    write_trailer(metadata, kmer_count.len(), &mut file)?;
+    file.flush()?;
+
+    let mut index_file = BufWriter::new(File::create(index_path(output_path))?);
+    for (prefix, offset) in &index {
+        writeln!(index_file, "{}\t{}", prefix, offset)?;
+    }
+    index_file.flush()?;
+    Ok(())
+}
+
+/// Read the prefix index sidecar for the table at `table_path`
+pub fn read_index(table_path: &Path) -> Result<PrefixIndex> {
+    let path = index_path(table_path);
+    let file = File::open(&path).with_context(|| format!("Could not open index {:?}", path))?;
+
+    let mut index = PrefixIndex::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        let (prefix, offset) = line
+            .split_once('\t')
+            .ok_or_else(|| anyhow!("{:?}:{}: expected two columns", path, i + 1))?;
+        let offset = offset
+            .parse()
+            .with_context(|| format!("{:?}:{}: bad offset", path, i + 1))?;
+        index.insert(prefix.to_owned(), offset);
+    }
+    Ok(index)
+}
+
+/// Return all rows of the indexed table at `table_path` whose kmer starts with `query`
+///
+/// A full-length kmer as `query` returns at most its own row. Only the rows
+/// sharing the query's index prefix are read.
+pub fn lookup(table_path: &Path, query: &str) -> Result<Vec<(String, u64)>> {
+    let index = read_index(table_path)?;
+
+    // queries shorter than the prefix span several consecutive index entries
+    let start = match index.range(prefix(query).to_owned()..).next() {
+        Some((key, &offset)) if key.starts_with(prefix(query)) => offset,
+        _ => return Ok(Vec::new()),
+    };
+
+    let mut reader = BufReader::new(File::open(table_path)?);
+    reader.seek(SeekFrom::Start(start))?;
+
+    let mut rows = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.starts_with('#') {
+            // the row count trailer
+            break;
+        }
+        let (kmer, count) = line
+            .split_once('\t')
+            .ok_or_else(|| anyhow!("Malformed row in {:?}: {:?}", table_path, line))?;
+
+        if kmer.starts_with(query) {
+            rows.push((kmer.to_owned(), count.parse()?));
+        } else if kmer > query {
+            // sorted, so no later row can match
+            break;
+        }
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KmerRecord;
+    use tempfile::tempdir;
+
+    fn indexed_table(dir: &Path) -> Result<PathBuf> {
+        let kmer_count = vec![
+            ("TTA", 1),
+            ("ATC", 2),
+            ("GAT", 4),
+            ("ATG", 1),
+            ("CAT", 3),
+            ("ACT", 5),
+        ]
+        .into_iter()
+        .map(|(seq, count)| KmerRecord {
+            seq: seq.into(),
+            count,
+        })
+        .collect();
+
+        let path = dir.join("table_kmer.txt");
+        save_indexed_kmer_count(kmer_count, None, &path)?;
+        Ok(path)
+    }
+
+    #[test]
+    fn test_indexed_table_sorted() -> Result<()> {
+        let dir = tempdir()?;
+        let path = indexed_table(dir.path())?;
+
+        let contents = std::fs::read_to_string(&path)?;
+        assert_eq!(
+            contents,
+            "kmer\tcount\nACT\t5\nATC\t2\nATG\t1\nCAT\t3\nGAT\t4\nTTA\t1\n"
+        );
+
+        let index = read_index(&path)?;
+        assert_eq!(index["AC"], 11);
+        assert_eq!(index["AT"], 17);
+        assert_eq!(index.len(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lookup_prefix() -> Result<()> {
+        let dir = tempdir()?;
+        let path = indexed_table(dir.path())?;
+
+        assert_eq!(
+            lookup(&path, "AT")?,
+            [("ATC".to_owned(), 2), ("ATG".to_owned(), 1)]
+        );
+        assert_eq!(lookup(&path, "GAT")?, [("GAT".to_owned(), 4)]);
+        assert_eq!(
+            lookup(&path, "A")?,
+            [
+                ("ACT".to_owned(), 5),
+                ("ATC".to_owned(), 2),
+                ("ATG".to_owned(), 1)
+            ]
+        );
+        assert!(lookup(&path, "GG")?.is_empty());
+        assert!(lookup(&path, "ATA")?.is_empty());
+        // multibyte characters match nothing rather than splitting a byte
+        assert!(lookup(&path, "ÄT")?.is_empty());
+        assert!(lookup(&path, "AÄ")?.is_empty());
+        assert!(lookup(&path, "Ä")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_lookup_after_metadata_header() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("table_kmer.txt");
+        let kmer_count = vec![KmerRecord {
+            seq: "ACT".into(),
+            count: 5,
+        }];
+        let metadata = RunMetadata::new().with("k", 3);
+        save_indexed_kmer_count(kmer_count, Some(&metadata), &path)?;
+
+        assert!(std::fs::read_to_string(&path)?.starts_with("# k: 3\nkmer\tcount\n"));
+        assert_eq!(lookup(&path, "AC")?, [("ACT".to_owned(), 5)]);
+        Ok(())
+    }
+}
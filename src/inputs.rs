@@ -0,0 +1,333 @@
+//! Detection of input files with identical contents, and input order
+//!
+//! Contents are compared as read through `open_input`, decompressed, so a
+//! gzipped file is a duplicate of an uncompressed file with its contents.
+//! Candidate duplicates are found cheaply by file size, for pairs of
+//! uncompressed files, then by a streaming content hash, and finally
+//! confirmed byte for byte so a hash collision can never merge different
+//! inputs.
+//!
+//! Inputs are processed in an order that does not depend on the file system,
+//! by path unless another `InputOrder` is chosen.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::Hasher;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+
+use crate::{is_gzipped, open_input};
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Input files sharing the same contents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputGroup {
+    /// the file that is processed
+    pub primary: PathBuf,
+    /// identical files that reuse the primary's results
+    pub aliases: Vec<PathBuf>,
+}
+
+impl InputGroup {
+    pub fn single(path: PathBuf) -> Self {
+        InputGroup {
+            primary: path,
+            aliases: Vec::new(),
+        }
+    }
+}
+
+/// Order in which inputs are processed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputOrder {
+    /// lexicographically by path
+    Name,
+    /// largest first, which balances parallel runs
+    Size,
+    /// least recently modified first
+    Mtime,
+}
+
+impl InputOrder {
+    pub const VARIANTS: &'static [&'static str] = &["name", "size", "mtime"];
+}
+
+impl FromStr for InputOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "name" => Ok(InputOrder::Name),
+            "size" => Ok(InputOrder::Size),
+            "mtime" => Ok(InputOrder::Mtime),
+            _ => Err(format!("Unknown input order: {}", s)),
+        }
+    }
+}
+
+/// Sort `paths` into `order`, breaking ties by path
+pub fn sort_inputs(paths: &mut [PathBuf], order: InputOrder) -> Result<()> {
+    paths.sort();
+    let metadata = |path: &Path| {
+        fs::metadata(path).with_context(|| format!("Could not read metadata of {:?}", path))
+    };
+    match order {
+        InputOrder::Name => {}
+        InputOrder::Size => {
+            let mut sizes = HashMap::new();
+            for path in paths.iter() {
+                sizes.insert(path.clone(), metadata(path)?.len());
+            }
+            paths.sort_by_key(|path| std::cmp::Reverse(sizes[path]));
+        }
+        InputOrder::Mtime => {
+            let mut mtimes: HashMap<PathBuf, SystemTime> = HashMap::new();
+            for path in paths.iter() {
+                mtimes.insert(path.clone(), metadata(path)?.modified()?);
+            }
+            paths.sort_by_key(|path| mtimes[path]);
+        }
+    }
+    Ok(())
+}
+
+/// Group `paths` by identical contents
+///
+/// Groups are ordered by their primary, which is the first of its files in
+/// `paths`; aliases keep their order in `paths`.
+pub fn group_identical_files(paths: &[PathBuf]) -> Result<Vec<InputGroup>> {
+    let mut groups: Vec<InputGroup> = Vec::new();
+    // candidate groups sharing a file size, then a content hash; the size of
+    // a gzipped file says nothing of its contents, so gzipped primaries are
+    // candidates for every file, and every primary for gzipped files
+    let mut by_size: HashMap<u64, Vec<usize>> = HashMap::new();
+    let mut gzipped: Vec<usize> = Vec::new();
+    let mut hashes: HashMap<PathBuf, u64> = HashMap::new();
+
+    for path in paths {
+        let size = if is_gzipped(path) {
+            None
+        } else {
+            let metadata = fs::metadata(path)
+                .with_context(|| format!("Could not read metadata of {:?}", path))?;
+            Some(metadata.len())
+        };
+        let candidates: Vec<usize> = match size {
+            Some(size) => {
+                let mut candidates = by_size.get(&size).cloned().unwrap_or_default();
+                candidates.extend(&gzipped);
+                candidates.sort_unstable();
+                candidates
+            }
+            None => (0..groups.len()).collect(),
+        };
+
+        let mut matched = None;
+        for g in candidates {
+            let primary = &groups[g].primary;
+            if cached_hash(&mut hashes, primary)? == cached_hash(&mut hashes, path)?
+                && same_contents(primary, path)?
+            {
+                matched = Some(g);
+                break;
+            }
+        }
+
+        match matched {
+            Some(g) => groups[g].aliases.push(path.clone()),
+            None => {
+                match size {
+                    Some(size) => by_size.entry(size).or_default().push(groups.len()),
+                    None => gzipped.push(groups.len()),
+                }
+                groups.push(InputGroup::single(path.clone()));
+            }
+        }
+    }
+    Ok(groups)
+}
+
+fn cached_hash(hashes: &mut HashMap<PathBuf, u64>, path: &Path) -> Result<u64> {
+    if let Some(&hash) = hashes.get(path) {
+        return Ok(hash);
+    }
+    let hash = content_hash(path)?;
+    hashes.insert(path.to_owned(), hash);
+    Ok(hash)
+}
+
+/// Hash of the contents of the file at `path`, decompressed as by
+/// `open_input`, read in fixed-size chunks
+pub fn content_hash(path: &Path) -> Result<u64> {
+    let mut reader = BufReader::new(open_input(path)?);
+    let mut hasher = DefaultHasher::new();
+    let mut buffer = vec![0; BUFFER_SIZE];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            return Ok(hasher.finish());
+        }
+        hasher.write(&buffer[..n]);
+    }
+}
+
+/// Whether the files at `a` and `b` have identical contents, decompressed
+/// as by `open_input`
+pub fn same_contents(a: &Path, b: &Path) -> Result<bool> {
+    let mut a = BufReader::new(open_input(a)?);
+    let mut b = BufReader::new(open_input(b)?);
+    let (mut buf_a, mut buf_b) = (vec![0; BUFFER_SIZE], vec![0; BUFFER_SIZE]);
+    loop {
+        let n = read_full(&mut a, &mut buf_a)?;
+        if n != read_full(&mut b, &mut buf_b)? || buf_a[..n] != buf_b[..n] {
+            return Ok(false);
+        }
+        if n == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Fill `buffer` as far as possible, returning the number of bytes read
+fn read_full<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        let n = reader.read(&mut buffer[filled..])?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::fs::File;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    fn write_file(dir: &Path, name: &str, contents: &str) -> Result<PathBuf> {
+        let path = dir.join(name);
+        File::create(&path)?.write_all(contents.as_bytes())?;
+        Ok(path)
+    }
+
+    #[test]
+    fn test_group_identical_files() -> Result<()> {
+        let dir = tempdir()?;
+        let a = write_file(dir.path(), "a.fasta", ">x\nACGTACGT\n")?;
+        let b = write_file(dir.path(), "b.fasta", ">y\nTTTT\n")?;
+        let a_copy = write_file(dir.path(), "a_copy.fasta", ">x\nACGTACGT\n")?;
+        // same size as a, one base different
+        let near = write_file(dir.path(), "near.fasta", ">x\nACGTACGA\n")?;
+        let a_again = write_file(dir.path(), "a_again.fasta", ">x\nACGTACGT\n")?;
+
+        let groups = group_identical_files(&[
+            a.clone(),
+            b.clone(),
+            a_copy.clone(),
+            near.clone(),
+            a_again.clone(),
+        ])?;
+        assert_eq!(
+            groups,
+            [
+                InputGroup {
+                    primary: a,
+                    aliases: vec![a_copy, a_again],
+                },
+                InputGroup::single(b),
+                InputGroup::single(near),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_gzipped_duplicates() -> Result<()> {
+        let dir = tempdir()?;
+        let contents = ">x\nACGTACGT\n";
+        let plain = write_file(dir.path(), "x.fa", contents)?;
+        let gzipped = dir.path().join("x.fa.gz");
+        let mut encoder = GzEncoder::new(File::create(&gzipped)?, Compression::default());
+        encoder.write_all(contents.as_bytes())?;
+        encoder.finish()?;
+        let other = dir.path().join("y.fa.gz");
+        let mut encoder = GzEncoder::new(File::create(&other)?, Compression::default());
+        encoder.write_all(b">x\nACGTACGA\n")?;
+        encoder.finish()?;
+
+        assert!(same_contents(&plain, &gzipped)?);
+        assert_eq!(content_hash(&plain)?, content_hash(&gzipped)?);
+        // the gzipped copy is found whether it comes before or after the plain file
+        for paths in [
+            [plain.clone(), other.clone(), gzipped.clone()],
+            [gzipped.clone(), other.clone(), plain.clone()],
+        ] {
+            let groups = group_identical_files(&paths)?;
+            assert_eq!(
+                groups,
+                [
+                    InputGroup {
+                        primary: paths[0].clone(),
+                        aliases: vec![paths[2].clone()],
+                    },
+                    InputGroup::single(other.clone()),
+                ]
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_same_contents() -> Result<()> {
+        let dir = tempdir()?;
+        let long = "ACGT".repeat(BUFFER_SIZE);
+        let a = write_file(dir.path(), "a", &long)?;
+        let b = write_file(dir.path(), "b", &long)?;
+        let c = write_file(dir.path(), "c", &format!("{}A", long))?;
+
+        assert!(same_contents(&a, &b)?);
+        assert!(!same_contents(&a, &c)?);
+        assert_eq!(content_hash(&a)?, content_hash(&b)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_inputs() -> Result<()> {
+        let dir = tempdir()?;
+        let now = SystemTime::now();
+        let b = write_file(dir.path(), "b.fasta", ">x\nACGTACGTACGT\n")?;
+        let c = write_file(dir.path(), "c.fasta", ">x\nACGT\n")?;
+        let a = write_file(dir.path(), "a.fasta", ">x\nACGT\n")?;
+        let age = |path: &Path, seconds| -> Result<()> {
+            let time = now - std::time::Duration::from_secs(seconds);
+            Ok(File::options().write(true).open(path)?.set_modified(time)?)
+        };
+        age(&a, 10)?;
+        age(&b, 20)?;
+        age(&c, 30)?;
+
+        let scrambled = vec![c.clone(), a.clone(), b.clone()];
+        let sorted = |order| -> Result<Vec<PathBuf>> {
+            let mut paths = scrambled.clone();
+            sort_inputs(&mut paths, order)?;
+            Ok(paths)
+        };
+        assert_eq!(sorted(InputOrder::Name)?, [a.clone(), b.clone(), c.clone()]);
+        // a and c tie on size and keep their order by name
+        assert_eq!(sorted(InputOrder::Size)?, [b.clone(), a.clone(), c.clone()]);
+        assert_eq!(sorted(InputOrder::Mtime)?, [c, b, a]);
+        Ok(())
+    }
+}
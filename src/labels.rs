@@ -0,0 +1,295 @@
+//! Sample labels built from input path components
+//!
+//! A template such as `{-3}/{-2}` is filled in with components of each input
+//! path: `{-1}` is the file name, `{-2}` its directory, and so on, while
+//! `{0}` is the first component after any root. `{{` and `}}` are literal
+//! braces. Both `/` and `\` separate components, so Windows paths are split
+//! the same way on every platform; empty and `.` components are dropped.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum LabelError {
+    #[error("Unclosed `{{` in label template {0:?}")]
+    Unclosed(String),
+
+    #[error("Unmatched `}}` in label template {0:?}")]
+    Unmatched(String),
+
+    #[error("Invalid path component index {0:?} in label template")]
+    BadIndex(String),
+
+    #[error("Path {path:?} has no component {index}")]
+    MissingComponent { index: isize, path: String },
+
+    #[error("Labels are not unique: {0}")]
+    Collisions(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Component(isize),
+}
+
+/// Parsed label template
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelTemplate {
+    parts: Vec<Part>,
+}
+
+impl FromStr for LabelTemplate {
+    type Err = LabelError;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut index = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => index.push(c),
+                            None => return Err(LabelError::Unclosed(template.to_owned())),
+                        }
+                    }
+                    let index = index
+                        .trim()
+                        .parse()
+                        .map_err(|_| LabelError::BadIndex(index))?;
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Component(index));
+                }
+                '}' => return Err(LabelError::Unmatched(template.to_owned())),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(LabelTemplate { parts })
+    }
+}
+
+impl LabelTemplate {
+    /// Label of the input at `path`
+    pub fn label(&self, path: &str) -> Result<String, LabelError> {
+        let components = path_components(path);
+        let mut label = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => label.push_str(text),
+                Part::Component(index) => {
+                    let component = component(&components, *index).ok_or_else(|| {
+                        LabelError::MissingComponent {
+                            index: *index,
+                            path: path.to_owned(),
+                        }
+                    })?;
+                    label.push_str(component);
+                }
+            }
+        }
+        Ok(label)
+    }
+
+    /// Labels of every input in `paths`, in order
+    ///
+    /// Fails if a path lacks a requested component, or if two paths share a
+    /// label, listing every collision.
+    pub fn unique_labels(&self, paths: &[PathBuf]) -> Result<Vec<String>, LabelError> {
+        let labels = paths
+            .iter()
+            .map(|path| self.label(&path.to_string_lossy()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut by_label: BTreeMap<&str, Vec<&Path>> = BTreeMap::new();
+        for (label, path) in labels.iter().zip(paths) {
+            by_label.entry(label).or_default().push(path);
+        }
+        let collisions: Vec<String> = by_label
+            .iter()
+            .filter(|(_, paths)| paths.len() > 1)
+            .map(|(label, paths)| {
+                let paths: Vec<String> = paths.iter().map(|p| format!("{:?}", p)).collect();
+                format!("{:?} from {}", label, paths.join(", "))
+            })
+            .collect();
+        if !collisions.is_empty() {
+            return Err(LabelError::Collisions(collisions.join("; ")));
+        }
+        Ok(labels)
+    }
+}
+
+/// Components of `path`, split on `/` and `\`
+pub fn path_components(path: &str) -> Vec<&str> {
+    path.split(['/', '\\'])
+        .filter(|c| !c.is_empty() && *c != ".")
+        .collect()
+}
+
+/// Component `index` of `components`, counting from the end when negative
+fn component<'a>(components: &[&'a str], index: isize) -> Option<&'a str> {
+    let i = if index < 0 {
+        components.len().checked_sub(index.unsigned_abs())?
+    } else {
+        index as usize
+    };
+    components.get(i).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn label(template: &str, path: &str) -> Result<String, LabelError> {
+        template.parse::<LabelTemplate>()?.label(path)
+    }
+
+    #[test]
+    fn test_path_components() {
+        assert_eq!(
+            path_components("/data/proj/s1/rep1.fasta"),
+            ["data", "proj", "s1", "rep1.fasta"]
+        );
+        assert_eq!(
+            path_components("./data//proj/rep1.fasta"),
+            ["data", "proj", "rep1.fasta"]
+        );
+        assert_eq!(
+            path_components(r"C:\data\proj\rep1.fasta"),
+            ["C:", "data", "proj", "rep1.fasta"]
+        );
+        assert_eq!(
+            path_components(r"\\server\share\s1/rep1.fasta"),
+            ["server", "share", "s1", "rep1.fasta"]
+        );
+        assert_eq!(path_components("rep1.fasta"), ["rep1.fasta"]);
+        assert!(path_components("/").is_empty());
+    }
+
+    #[test]
+    fn test_label_absolute() {
+        let path = "/data/proj/s1/rep1.fasta";
+        assert_eq!(label("{-3}/{-2}", path).unwrap(), "proj/s1");
+        assert_eq!(label("{-1}", path).unwrap(), "rep1.fasta");
+        assert_eq!(label("{-4}", path).unwrap(), "data");
+        assert_eq!(label("{0}_{1}", path).unwrap(), "data_proj");
+        assert_eq!(label("{ -2 }", path).unwrap(), "s1");
+    }
+
+    #[test]
+    fn test_label_relative() {
+        assert_eq!(label("{-3}/{-2}", "proj/s1/rep1.fasta").unwrap(), "proj/s1");
+        assert_eq!(label("{-3}/{-2}", "./proj/s1/r.fasta").unwrap(), "proj/s1");
+        assert_eq!(label("{-3}/{-2}", "../proj/s1/r.fasta").unwrap(), "proj/s1");
+        assert_eq!(label("{-4}", "../proj/s1/r.fasta").unwrap(), "..");
+    }
+
+    #[test]
+    fn test_label_windows() {
+        assert_eq!(
+            label("{-3}/{-2}", r"C:\data\proj\s1\rep1.fasta").unwrap(),
+            "proj/s1"
+        );
+        assert_eq!(
+            label("{-2}-{-1}", r"proj\s1/rep1.fasta").unwrap(),
+            "s1-rep1.fasta"
+        );
+        assert_eq!(label("{0}", r"C:\data\r.fasta").unwrap(), "C:");
+    }
+
+    #[test]
+    fn test_label_literals() {
+        let path = "/data/proj/s1/rep1.fasta";
+        assert_eq!(label("sample", path).unwrap(), "sample");
+        assert_eq!(label("{{{-2}}}", path).unwrap(), "{s1}");
+        assert_eq!(label("x{-2}y", path).unwrap(), "xs1y");
+        assert_eq!(label("", path).unwrap(), "");
+    }
+
+    #[test]
+    fn test_label_missing_component() {
+        assert_eq!(
+            label("{-3}/{-2}", "s1/rep1.fasta"),
+            Err(LabelError::MissingComponent {
+                index: -3,
+                path: "s1/rep1.fasta".to_owned()
+            })
+        );
+        assert!(label("{4}", "/data/proj/s1/rep1.fasta").is_err());
+        assert!(label("{-1}", "/").is_err());
+    }
+
+    #[test]
+    fn test_bad_templates() {
+        assert_eq!(
+            "{-3".parse::<LabelTemplate>(),
+            Err(LabelError::Unclosed("{-3".to_owned()))
+        );
+        assert_eq!(
+            "-3}".parse::<LabelTemplate>(),
+            Err(LabelError::Unmatched("-3}".to_owned()))
+        );
+        assert_eq!(
+            "{x}".parse::<LabelTemplate>(),
+            Err(LabelError::BadIndex("x".to_owned()))
+        );
+        assert_eq!(
+            "{}".parse::<LabelTemplate>(),
+            Err(LabelError::BadIndex(String::new()))
+        );
+    }
+
+    #[test]
+    fn test_unique_labels() {
+        let template: LabelTemplate = "{-3}/{-2}".parse().unwrap();
+        let paths = [
+            PathBuf::from("/data/p1/s1/rep1.fasta"),
+            PathBuf::from("/data/p1/s2/rep1.fasta"),
+            PathBuf::from("/data/p2/s1/rep1.fasta"),
+        ];
+        assert_eq!(
+            template.unique_labels(&paths).unwrap(),
+            ["p1/s1", "p1/s2", "p2/s1"]
+        );
+    }
+
+    #[test]
+    fn test_unique_labels_collisions() {
+        let template: LabelTemplate = "{-2}".parse().unwrap();
+        let paths = [
+            PathBuf::from("/data/p1/s1/rep1.fasta"),
+            PathBuf::from("/data/p1/s2/rep1.fasta"),
+            PathBuf::from("/data/p1/s1/rep2.fasta"),
+            PathBuf::from("/data/p2/s2/rep1.fasta"),
+            PathBuf::from("/data/p2/s3/rep1.fasta"),
+        ];
+        assert_eq!(
+            template.unique_labels(&paths),
+            Err(LabelError::Collisions(
+                "\"s1\" from \"/data/p1/s1/rep1.fasta\", \"/data/p1/s1/rep2.fasta\"; \
+                 \"s2\" from \"/data/p1/s2/rep1.fasta\", \"/data/p2/s2/rep1.fasta\""
+                    .to_owned()
+            ))
+        );
+    }
+}
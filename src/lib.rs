@@ -1,17 +1,100 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsStr;
 use std::io::Write;
 use std::fs;
 use std::fs::File;
-use std::path::{Path, PathBuf};
+use std::hash::Hash;
+use std::io::{BufReader, BufWriter, Read};
+use std::path::{Component, Path, PathBuf, Prefix};
 use std::str;
+use std::str::FromStr;
+use std::time::Instant;
 
 use bio::io::fasta;
+use flate2::read::MultiGzDecoder;
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use dedup::SeenRecords;
+use split::{KmerSplit, Split};
+use strand::{LibraryType, Mate};
+use metadata::RunMetadata;
+use minimizer::MinimizerShards;
+use timing::{Phase, PhaseTimings};
+use translate::StopCodons;
+
+#[cfg(feature = "avro")]
+pub mod avro;
+pub mod bench;
+pub mod chargaff;
+pub mod checkpoint;
+pub mod cluster;
+pub mod colored;
+pub mod complexity;
+pub mod composition;
+pub mod cooccurrence;
+pub mod coverage;
+pub mod d2;
+pub mod dedup;
+pub mod dinucleotide;
+pub mod entropy;
+pub mod errorrate;
+pub mod estimate;
+pub mod firstlast;
+pub mod fragment;
+pub mod genomesize;
+pub mod heavy;
+pub mod histogram;
+pub mod index;
+pub mod inputs;
+pub mod labels;
+pub mod manifest;
+pub mod mask;
+pub mod metadata;
+pub mod minimizer;
+pub mod mismatch;
+pub mod npy;
+pub mod orf;
+pub mod perrecord;
+pub mod pool;
+pub mod presence;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
+pub mod qv;
+#[cfg(feature = "http")]
+pub mod remote;
+pub mod screen;
+pub mod search;
+pub mod selfcheck;
+pub mod seqio;
+pub mod shell;
+pub mod sink;
+pub mod sketch;
+pub mod specificity;
+pub mod split;
+pub mod strand;
+pub mod strandbias;
+pub mod table;
+pub mod timing;
+pub mod tm;
+pub mod tnf;
+pub mod translate;
+pub mod validate;
+pub mod variants;
+pub mod varint;
+pub mod verify;
+pub mod workbook;
+
+#[cfg(test)]
+mod testutil;
+
+/// Errors counting the kmers of a sequence
 #[derive(Error, Debug, PartialEq)]
-enum KmerError {
+pub enum KmerError {
     #[error("No valid kmers. kmer length is {k:?}, but must be 1 or greater")]
     KmerLengthTooSmall { k: usize },
 
@@ -20,247 +103,3428 @@ enum KmerError {
     )]
     KmerLengthTooLong { k: usize, seq_len: usize },
 
-    #[error("Suspect base(s) found: {bases:?}. Use only ATCG bases")]
-    IncorrectBases { bases: String },
-}
+    #[error("Suspect base(s) found: {}. Use only ATCG bases", format_base_counts(.bases))]
+    IncorrectBases { bases: Vec<(u8, u64)> },
+
+    #[error("Non-ASCII byte {byte:#04x} at position {position:?}. Sequences must be ASCII")]
+    NonAsciiBase { byte: u8, position: usize },
+
+    #[error(
+        "Mismatch neighborhood of {size:?} kmers for k = {k:?}, d = {d:?} exceeds the limit of {limit:?}"
+    )]
+    NeighborhoodTooLarge {
+        k: usize,
+        d: usize,
+        size: u128,
+        limit: u128,
+    },
+
+    #[error("Count of kmer {kmer:?} exceeds the maximum of {max}", max = u64::MAX)]
+    CountOverflow { kmer: String },
+
+    #[error("kmer length {k:?} exceeds {limit:?}, the limit of {backend}")]
+    KmerLengthAboveLimit {
+        k: usize,
+        limit: usize,
+        backend: &'static str,
+    },
+}
+
+impl KmerError {
+    /// Whether the error spoils the counts of the whole input rather than
+    /// only those of one record
+    pub(crate) fn is_fatal(&self) -> bool {
+        matches!(self, KmerError::CountOverflow { .. })
+    }
+}
+
+/// A kmer and its count, borrowing the kmer from the counted sequence where
+/// it can
+///
+/// Serializes as `{"kmer": ..., "count": ...}`.
+#[derive(Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct KmerRecord<'b> {
+    #[serde(rename = "kmer")]
+    seq: Cow<'b, str>,
+    count: u64,
+}
+
+impl KmerRecord<'_> {
+    /// The kmer
+    pub fn kmer(&self) -> &str {
+        &self.seq
+    }
+
+    /// Occurrences of the kmer
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The record, no longer borrowing its kmer
+    pub fn into_owned(self) -> KmerRecord<'static> {
+        KmerRecord {
+            seq: Cow::Owned(self.seq.into_owned()),
+            count: self.count,
+        }
+    }
+}
+
+/// Aggregate count of all Kmers
+pub type KmerCount<'a> = Vec<KmerRecord<'a>>;
+
+/// Serialization format for tabular outputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Tsv,
+    Json,
+    /// one JSON object per line; count tables only
+    Ndjson,
+    /// length-delimited protobuf messages; count tables only
+    Protobuf,
+    /// an Avro container file with an embedded schema; count tables only
+    Avro,
+    /// a NumPy `.npy` array with a labels file (see `npy`); count tables only
+    Npy,
+}
+
+impl OutputFormat {
+    pub const VARIANTS: &'static [&'static str] =
+        &["tsv", "json", "ndjson", "protobuf", "avro", "npy"];
+
+    /// Formats of the reports written by the subcommands, as opposed to count
+    /// tables
+    pub const REPORT_VARIANTS: &'static [&'static str] = &["tsv", "json"];
+
+    /// Name of the format, as parsed
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputFormat::Tsv => "tsv",
+            OutputFormat::Json => "json",
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Protobuf => "protobuf",
+            OutputFormat::Avro => "avro",
+            OutputFormat::Npy => "npy",
+        }
+    }
+
+    /// Error for a report asked for in a format only count tables have
+    pub fn unsupported_report(self) -> anyhow::Error {
+        anyhow!("reports cannot be written as {}", self.name())
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "tsv" => Ok(OutputFormat::Tsv),
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            "protobuf" => Ok(OutputFormat::Protobuf),
+            "avro" => Ok(OutputFormat::Avro),
+            "npy" => Ok(OutputFormat::Npy),
+            _ => Err(format!("Unknown output format: {}", s)),
+        }
+    }
+}
+
+/// How lowercase bases, as in soft-masked genomes, are counted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaseHandling {
+    /// count lowercase bases as uppercase, so `atg` is `ATG`
+    Upper,
+    /// leave out kmers overlapping a lowercase (masked) base
+    SkipMasked,
+    /// count `atg` and `ATG` as different kmers
+    Sensitive,
+}
+
+impl CaseHandling {
+    pub const VARIANTS: &'static [&'static str] = &["upper", "skip-masked", "sensitive"];
+
+    /// Name of the handling, as parsed
+    pub fn name(self) -> &'static str {
+        match self {
+            CaseHandling::Upper => "upper",
+            CaseHandling::SkipMasked => "skip-masked",
+            CaseHandling::Sensitive => "sensitive",
+        }
+    }
+
+    /// `sequence` as it is counted: uppercased with `Upper`, if it has any
+    /// lowercase base, and as is otherwise
+    pub fn normalize<'a>(&self, sequence: &'a [u8]) -> Cow<'a, [u8]> {
+        if *self == CaseHandling::Upper && sequence.iter().any(u8::is_ascii_lowercase) {
+            Cow::Owned(sequence.to_ascii_uppercase())
+        } else {
+            Cow::Borrowed(sequence)
+        }
+    }
+
+    /// Whether `kmer` of a normalized sequence is counted: all are but those
+    /// with a lowercase base under `SkipMasked`
+    pub fn counts(&self, kmer: &str) -> bool {
+        *self != CaseHandling::SkipMasked || !kmer.bytes().any(|base| base.is_ascii_lowercase())
+    }
+}
+
+impl FromStr for CaseHandling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "upper" => Ok(CaseHandling::Upper),
+            "skip-masked" => Ok(CaseHandling::SkipMasked),
+            "sensitive" => Ok(CaseHandling::Sensitive),
+            _ => Err(format!("Unknown case handling: {}", s)),
+        }
+    }
+}
+
+/// What to save for an input in which no record could be counted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmptyInputs {
+    /// a table with the header but no kmers
+    Table,
+    /// no output file
+    Skip,
+}
+
+impl EmptyInputs {
+    pub const VARIANTS: &'static [&'static str] = &["table", "skip"];
+}
+
+impl FromStr for EmptyInputs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "table" => Ok(EmptyInputs::Table),
+            "skip" => Ok(EmptyInputs::Skip),
+            _ => Err(format!("Unknown empty input handling: {}", s)),
+        }
+    }
+}
+
+/// How a record repeating the id of an earlier record of the same input is
+/// handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateIds {
+    /// fail the input
+    Error,
+    /// count the record under the id it shares
+    Merge,
+    /// count the record as `id.1`, `id.2`, and so on
+    Suffix,
+}
+
+impl DuplicateIds {
+    pub const VARIANTS: &'static [&'static str] = &["merge", "error", "suffix"];
+}
+
+impl FromStr for DuplicateIds {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(DuplicateIds::Error),
+            "merge" => Ok(DuplicateIds::Merge),
+            "suffix" => Ok(DuplicateIds::Suffix),
+            _ => Err(format!("Unknown duplicate id handling: {}", s)),
+        }
+    }
+}
+
+/// Longest kmer counted without complaint; longer kmers are almost all unique
+/// and their tables as large as the input
+pub const MAX_PRACTICAL_K: usize = 256;
+
+/// What to do when `k` exceeds `MAX_PRACTICAL_K`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LargeK {
+    /// warn and count anyway
+    Warn,
+    /// refuse to count
+    Error,
+}
+
+impl LargeK {
+    pub const VARIANTS: &'static [&'static str] = &["warn", "error"];
+}
+
+impl FromStr for LargeK {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "warn" => Ok(LargeK::Warn),
+            "error" => Ok(LargeK::Error),
+            _ => Err(format!("Unknown large k handling: {}", s)),
+        }
+    }
+}
+
+/// How the representative of a kmer and its reverse complement is chosen
+/// when counting canonical kmers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CanonicalRule {
+    /// the lexicographically smaller, decided by the first differing base
+    Lex,
+    /// the one with the smaller `sketch::hash_bytes` hash under seed 0, as
+    /// tools ordering kmers by hash do; ties fall back to `Lex`
+    Hash,
+}
+
+impl CanonicalRule {
+    pub const VARIANTS: &'static [&'static str] = &["lex", "hash"];
+
+    /// The representative of `kmer` and its reverse complement
+    pub fn canonical(self, kmer: &[u8]) -> Vec<u8> {
+        match self {
+            CanonicalRule::Lex => sketch::canonical(kmer),
+            CanonicalRule::Hash => {
+                let rc = reverse_complement(kmer);
+                let (hash, rc_hash) = (sketch::hash_bytes(kmer, 0), sketch::hash_bytes(&rc, 0));
+                if rc_hash < hash || (rc_hash == hash && rc.as_slice() < kmer) {
+                    rc
+                } else {
+                    kmer.to_vec()
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for CanonicalRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "lex" => Ok(CanonicalRule::Lex),
+            "hash" => Ok(CanonicalRule::Hash),
+            _ => Err(format!("Unknown canonical rule: {}", s)),
+        }
+    }
+}
+
+/// Settings controlling how kmers are counted and saved
+#[derive(Debug, Clone, PartialEq)]
+pub struct CountOptions {
+    /// length of kmer
+    pub k: usize,
+
+    /// also count every kmer within this Hamming distance of each observed kmer
+    pub mismatches: usize,
+
+    /// save tables sorted by kmer with a prefix index (see `index`)
+    pub indexed_output: bool,
+
+    /// orient mates of a stranded paired-end library onto the transcribed strand
+    pub library_type: LibraryType,
+
+    /// write sublinear term frequencies, `ln(1 + count)`, instead of raw counts
+    pub log_tf: bool,
+
+    /// save compact binary tables of varint-encoded kmer IDs (see `varint`)
+    pub varint_output: bool,
+
+    /// format of plain count tables: TSV, JSON, or one of the binary or
+    /// line-delimited formats of `OutputFormat`
+    pub format: OutputFormat,
+
+    /// count amino acid kmers of the six-frame translation (see `translate`)
+    pub translate: bool,
+
+    /// how stop codons are treated when translating
+    pub stop_codons: StopCodons,
+
+    /// begin text tables with `#` comment lines recording the run settings
+    pub metadata_header: bool,
+
+    /// count each kmer together with its reverse complement, as the one of
+    /// the two chosen by `canonical_rule`
+    pub canonical: bool,
+
+    /// how `canonical` chooses between a kmer and its reverse complement
+    pub canonical_rule: CanonicalRule,
+
+    /// how lowercase bases are counted
+    pub case: CaseHandling,
+
+    /// leave out kmers with a base other than A, T, C, or G instead of
+    /// counting them
+    pub skip_ambiguous: bool,
+
+    /// save only kmers with at least this GC fraction
+    pub gc_min: Option<f64>,
+
+    /// save only kmers with at most this GC fraction
+    pub gc_max: Option<f64>,
+
+    /// save only this many most abundant kmers; of kmers tied in count at
+    /// the cutoff, those first in alphabetical order are kept
+    pub top_n: Option<usize>,
+
+    /// save only kmers counted at least this many times; 1 keeps them all
+    pub min_count: u64,
+
+    /// what to save for inputs without any countable record
+    pub empty_inputs: EmptyInputs,
+
+    /// count only kmers within open reading frames of at least this many
+    /// bases (see `orf`)
+    pub orf_min_len: Option<usize>,
+
+    /// how records repeating an earlier record id are handled
+    pub duplicate_ids: DuplicateIds,
+
+    /// stop counting once this instant has passed, saving the counts of the
+    /// records counted so far
+    pub deadline: Option<Instant>,
+
+    /// skip records repeating the sequence of an earlier record of the same
+    /// input (see `dedup::SeenRecords`)
+    pub dedup_records: bool,
+
+    /// with `dedup_records`, also skip records whose sketch similarity to an
+    /// earlier record is at least this
+    pub dedup_threshold: Option<f64>,
+
+    /// save the kmers in train and test tables beside the output instead of
+    /// one table (see `split`)
+    pub split: Option<KmerSplit>,
+
+    /// save the kmers in one table per minimizer shard beside the output
+    /// instead of one table (see `minimizer`)
+    pub minimizer_shards: Option<MinimizerShards>,
+
+    /// whether a `k` above `MAX_PRACTICAL_K` is a warning or an error
+    pub large_k: LargeK,
+
+    /// add each kmer's melting temperature to text tables (see `tm`)
+    pub tm: bool,
+
+    /// add each kmer's reverse complement to text tables
+    pub revcomp_column: bool,
+
+    /// also post each table, as it is saved, to an HTTP endpoint (see `sink`)
+    pub sink: Option<sink::Sink>,
+
+    /// leave out kmers overlapping these positions of each record (see
+    /// `variants`)
+    pub variant_mask: Option<variants::VariantMask>,
+}
+
+impl CountOptions {
+    /// Options for exact counting of kmers of length `k`
+    pub fn new(k: usize) -> Self {
+        CountOptions {
+            k,
+            mismatches: 0,
+            indexed_output: false,
+            library_type: LibraryType::Unstranded,
+            log_tf: false,
+            varint_output: false,
+            format: OutputFormat::Tsv,
+            translate: false,
+            stop_codons: StopCodons::Split,
+            metadata_header: false,
+            canonical: false,
+            canonical_rule: CanonicalRule::Lex,
+            case: CaseHandling::Upper,
+            skip_ambiguous: false,
+            gc_min: None,
+            gc_max: None,
+            top_n: None,
+            min_count: 1,
+            empty_inputs: EmptyInputs::Table,
+            orf_min_len: None,
+            duplicate_ids: DuplicateIds::Merge,
+            deadline: None,
+            dedup_records: false,
+            dedup_threshold: None,
+            split: None,
+            minimizer_shards: None,
+            large_k: LargeK::Warn,
+            tm: false,
+            revcomp_column: false,
+            sink: None,
+            variant_mask: None,
+        }
+    }
+
+    /// Check the options before counting: `k` must be at least 1 and within
+    /// the limit of the selected outputs, and above `MAX_PRACTICAL_K` it is
+    /// reported as configured by `large_k`; fractions must be between 0 and
+    /// 1, settings must not contradict each other, and the output format must
+    /// be compiled in
+    pub fn validate(&self) -> Result<()> {
+        let k = self.k;
+        if k == 0 {
+            return Err(KmerError::KmerLengthTooSmall { k }.into());
+        }
+        if self.varint_output && k > varint::MAX_K {
+            return Err(KmerError::KmerLengthAboveLimit {
+                k,
+                limit: varint::MAX_K,
+                backend: "varint output, which packs each kmer into 64 bits",
+            }
+            .into());
+        }
+        if k > MAX_PRACTICAL_K {
+            let err = KmerError::KmerLengthAboveLimit {
+                k,
+                limit: MAX_PRACTICAL_K,
+                backend: "practical kmer lengths, beyond which nearly every kmer is unique",
+            };
+            match self.large_k {
+                LargeK::Warn => eprintln!("WARNING: {}", err),
+                LargeK::Error => return Err(err.into()),
+            }
+        }
+        // neighborhoods are built over uppercase ATCG, so lowercase kmers
+        // would be dropped without a word
+        if self.mismatches > 0 && self.case == CaseHandling::Sensitive {
+            return Err(anyhow!("--mismatch cannot be used with --case sensitive"));
+        }
+        // peptides have no soft-masked bases to leave out
+        if self.case == CaseHandling::SkipMasked && self.translate {
+            return Err(anyhow!(
+                "--case skip-masked cannot be used with --translate"
+            ));
+        }
+        if self.format == OutputFormat::Protobuf && !cfg!(feature = "protobuf") {
+            return Err(anyhow!(
+                "--format protobuf requires building with `--features protobuf`"
+            ));
+        }
+        if self.format == OutputFormat::Avro && !cfg!(feature = "avro") {
+            return Err(anyhow!(
+                "--format avro requires building with `--features avro`"
+            ));
+        }
+        if self.sink.is_some() && !cfg!(feature = "http") {
+            return Err(anyhow!(
+                "--sink-url requires building with `--features http`"
+            ));
+        }
+        let fraction = |value: f64| (0.0..=1.0).contains(&value);
+        for &gc in self.gc_min.iter().chain(&self.gc_max) {
+            if !fraction(gc) {
+                return Err(anyhow!("--gc-min and --gc-max must be between 0 and 1"));
+            }
+        }
+        if let (Some(min), Some(max)) = (self.gc_min, self.gc_max) {
+            if min > max {
+                return Err(anyhow!("--gc-min must not exceed --gc-max"));
+            }
+        }
+        if !self.dedup_threshold.is_none_or(fraction) {
+            return Err(anyhow!("--dedup-threshold must be between 0 and 1"));
+        }
+        if let Some(split) = self.split {
+            if !fraction(split.train_fraction) {
+                return Err(anyhow!("--split must be between 0 and 1"));
+            }
+        }
+        if let Some(minimizer) = self.minimizer_shards {
+            if minimizer.shards == 0 {
+                return Err(anyhow!("--minimizer-shard must be at least 1"));
+            }
+            if minimizer.m == 0 || minimizer.m > k {
+                return Err(anyhow!("--minimizer-len must be between 1 and k"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `deadline` has passed
+    pub fn past_deadline(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// The kmer `kmer` is counted as: its canonical form under
+    /// `canonical_rule` if `canonical` is set, else itself
+    pub fn count_key(&self, kmer: &str) -> String {
+        if self.canonical {
+            // the canonical form of an ASCII kmer is ASCII
+            String::from_utf8(self.canonical_rule.canonical(kmer.as_bytes())).unwrap()
+        } else {
+            kmer.to_owned()
+        }
+    }
+
+    /// Whether the GC content of `kmer` lies within `gc_min` and `gc_max`
+    pub fn in_gc_window(&self, kmer: &str) -> bool {
+        let gc = gc_content(kmer.as_bytes());
+        self.gc_min.is_none_or(|min| gc >= min) && self.gc_max.is_none_or(|max| gc <= max)
+    }
+}
+
+/// Path standing for stdin as an input and for stdout as an output
+pub const STDIO_PATH: &str = "-";
+
+/// Open the fasta file at `fasta_path` for reading
+///
+/// A file whose name ends in `.gz` is gunzipped as it is read. An `http://` or
+/// `https://` URL is streamed instead (see `remote`), which requires the
+/// `http` feature. A FASTQ file, by its extension (see `is_fastq`), is read as
+/// fasta records without qualities (see `seqio`). `STDIO_PATH` reads fasta
+/// from stdin.
+pub fn open_fasta(
+    fasta_path: &Path,
+) -> Result<seqio::SequenceReader<BufReader<Box<dyn Read + Send>>>> {
+    let input = BufReader::new(open_input(fasta_path)?);
+    Ok(seqio::SequenceReader::new(input, is_fastq(fasta_path)))
+}
+
+/// Extensions of FASTQ inputs, matched without case
+pub const FASTQ_EXTENSIONS: &[&str] = &["fastq", "fq"];
+
+/// Whether `path` is a FASTQ file, judging by its extension before any `.gz`
+pub fn is_fastq(path: &Path) -> bool {
+    let name = if is_gzipped(path) {
+        path.file_stem()
+    } else {
+        path.file_name()
+    };
+    name.and_then(|name| Path::new(name).extension())
+        .and_then(OsStr::to_str)
+        .is_some_and(|extension| {
+            FASTQ_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+        })
+}
+
+/// Open the input at `path` for reading its decompressed bytes, as for
+/// `open_fasta`, or stdin if it is `STDIO_PATH`
+pub fn open_input(path: &Path) -> Result<Box<dyn Read + Send>> {
+    if path == Path::new(STDIO_PATH) {
+        return Ok(Box::new(std::io::stdin()));
+    }
+    if let Some(url) = path.to_str().filter(|path| is_url(path)) {
+        return open_url(url);
+    }
+    let file = File::open(path).with_context(|| format!("Could not open {:?}", path))?;
+    if is_gzipped(path) {
+        // concatenated gzip members, as written by bgzip, are read in turn
+        Ok(Box::new(MultiGzDecoder::new(file)))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+/// Whether the file at `path` is gzipped, judging by a `.gz` extension
+pub fn is_gzipped(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "gz")
+}
+
+/// File name of `path` without its extension, or without the extension under
+/// `.gz` for gzipped files, so `a.fasta.gz` gives `a`
+pub fn input_stem(path: &Path) -> Option<&OsStr> {
+    let stem = path.file_stem()?;
+    if is_gzipped(path) {
+        Path::new(stem).file_stem()
+    } else {
+        Some(stem)
+    }
+}
+
+/// Whether `path` is an `http://` or `https://` URL rather than a file path
+pub fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+#[cfg(feature = "http")]
+fn open_url(url: &str) -> Result<Box<dyn Read + Send>> {
+    remote::open_url(url)
+}
+
+#[cfg(not(feature = "http"))]
+fn open_url(url: &str) -> Result<Box<dyn Read + Send>> {
+    Err(anyhow!(
+        "Reading {} requires building with the `http` feature",
+        url
+    ))
+}
+
+/// Save kmer counts from the fasta file at `fasta_path` at `output_path`
+///
+/// Fails before reading the input if `k` is rejected by
+/// `CountOptions::validate`.
+pub fn run_fasta_kmer_count(
+    fasta_path: &Path,
+    options: &CountOptions,
+    output_path: &Path,
+) -> Result<()> {
+    options.validate()?;
+    let metadata = if options.metadata_header {
+        Some(RunMetadata::for_count(options, fasta_path))
+    } else {
+        None
+    };
+    run_fasta_kmer_count_timed(
+        fasta_path,
+        options,
+        metadata.as_ref(),
+        Some(output_path),
+        &mut PhaseTimings::disabled(),
+    )
+}
+
+/// Records of one input by how their counting went
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecordTally {
+    /// records whose kmers were counted
+    pub processed: u64,
+    /// records shorter than `k`, including empty ones
+    pub too_short: u64,
+    /// records skipped for any other error
+    pub failed: u64,
+}
+
+impl RecordTally {
+    /// Tally the outcome of counting record `id`, reporting errors other than
+    /// a too short record at once
+    pub(crate) fn add(&mut self, id: &str, result: Result<(), KmerError>) {
+        match result {
+            Ok(()) => self.processed += 1,
+            Err(KmerError::KmerLengthTooLong { .. }) => self.too_short += 1,
+            Err(err) => {
+                self.failed += 1;
+                eprintln!("ERROR: Skipping record {}: {}", id, err);
+            }
+        }
+    }
+
+    pub fn skipped(&self) -> u64 {
+        self.too_short + self.failed
+    }
+
+    /// Report the records of `fasta_path` shorter than `k`, if any, in a
+    /// single warning
+    pub(crate) fn warn_too_short(&self, fasta_path: &Path, k: usize) {
+        if self.too_short > 0 {
+            eprintln!(
+                "WARNING: Skipped {} record(s) of {:?} shorter than k = {}",
+                self.too_short, fasta_path, k
+            );
+        }
+    }
+}
+
+/// Record ids seen so far in one input
+#[derive(Debug, Clone, Default)]
+pub struct RecordIds {
+    seen: HashSet<String>,
+    /// records whose id repeated an earlier one
+    pub duplicates: u64,
+}
+
+impl RecordIds {
+    /// Id under which record `id` is counted, handling a repeated id as
+    /// `policy` says
+    ///
+    /// A suffixed id skips suffixes already taken by other records.
+    pub fn resolve(&mut self, id: &str, policy: DuplicateIds) -> Result<String> {
+        if self.seen.insert(id.to_owned()) {
+            return Ok(id.to_owned());
+        }
+        self.duplicates += 1;
+        match policy {
+            DuplicateIds::Error => Err(anyhow!("Duplicate record id {:?}", id)),
+            DuplicateIds::Merge => Ok(id.to_owned()),
+            DuplicateIds::Suffix => Ok((1..)
+                .map(|n| format!("{}.{}", id, n))
+                .find(|suffixed| self.seen.insert(suffixed.clone()))
+                .expect("suffixes are unbounded")),
+        }
+    }
+}
+
+/// Count kmers in the fasta file at `fasta_path`, recording each pipeline
+/// phase in `timings`
+///
+/// Counts are saved at `output_path` after the `metadata` header if given, or
+/// discarded if `output_path` is `None`. The header gains `records_processed`,
+/// `records_skipped`, `records_skipped_too_short`, `records_duplicate_ids`, and
+/// `records_with_suspect_bases` entries, and a `suspect_bases` total of each
+/// byte other than ATCG if there are any. Records shorter than `k` contribute
+/// nothing to the counts; they, and records repeating an earlier id, are each
+/// reported in a single warning per input. `options.duplicate_ids` decides how
+/// the latter are counted. Suspect bases are reported for the first
+/// `MAX_SUSPECT_BASE_WARNINGS` records with any, then rolled up.
+///
+/// With `options.dedup_records`, records repeating an earlier record's
+/// sequence are not counted; they are reported in a single warning and the
+/// header gains a `records_collapsed` entry.
+///
+/// Once `options.deadline` has passed, counting stops after the current record
+/// and the counts so far are saved; with a deadline, the header gains a
+/// `partial` entry telling whether any record was left uncounted.
+///
+/// A kmer whose count would exceed `u64::MAX` fails the input instead of
+/// wrapping around. If no record could be counted, the input is reported and
+/// `options.empty_inputs` decides whether a table is saved.
+pub fn run_fasta_kmer_count_timed(
+    fasta_path: &Path,
+    options: &CountOptions,
+    metadata: Option<&RunMetadata>,
+    output_path: Option<&Path>,
+    timings: &mut PhaseTimings,
+) -> Result<()> {
+    let mut records = open_fasta(fasta_path)?.records();
+    let mut counts = InputCounts::new(options);
+    let mut partial = false;
+
+    while let Some(record) = timings.time(Phase::Parse, || records.next()) {
+        let record = record?;
+        timings.record(record.seq().len());
+        counts.add_record(&record, options, timings)?;
+
+        if options.past_deadline() {
+            partial = records.next().is_some();
+            break;
+        }
+    }
+    counts.save(fasta_path, options, metadata, output_path, partial, timings)
+}
+
+/// Kmer counts of the records of one input counted so far, with the tallies
+/// reported for it once all are counted
+#[derive(Debug, Clone, Default)]
+pub struct InputCounts {
+    pub counter: HashMap<String, u64>,
+    pub tally: RecordTally,
+    pub ids: RecordIds,
+    pub seen: SeenRecords,
+    pub suspect: SuspectBases,
+    /// windows left out by `skip_ambiguous`
+    pub skipped_ambiguous: u64,
+    /// windows left out by `variant_mask`
+    pub masked: u64,
+}
+
+impl InputCounts {
+    /// Counts before any record, for counting as configured by `options`
+    pub fn new(options: &CountOptions) -> Self {
+        InputCounts {
+            seen: SeenRecords::new(options.dedup_threshold),
+            ..InputCounts::default()
+        }
+    }
+
+    /// Count `record` as configured by `options`, recording the time spent in
+    /// `timings`
+    ///
+    /// Fails on a repeated record id under `DuplicateIds::Error` and on a
+    /// count overflowing `u64`; other records that cannot be counted are
+    /// tallied and skipped.
+    pub fn add_record(
+        &mut self,
+        record: &fasta::Record,
+        options: &CountOptions,
+        timings: &mut PhaseTimings,
+    ) -> Result<()> {
+        let id = self.ids.resolve(record.id(), options.duplicate_ids)?;
+
+        self.suspect.check(&id, record.seq(), options.case);
+
+        let mate = Mate::from_header(record.id(), record.desc());
+        let sequence = strand::orient(record.seq(), mate, options.library_type);
+
+        if options.dedup_records && self.seen.is_duplicate(&sequence) {
+            return Ok(());
+        }
+        let counter = &mut self.counter;
+        let result = match &options.variant_mask {
+            Some(mask) => {
+                let segments = mask.segments(record.id(), record.seq());
+                self.masked += variants::masked_windows(record.seq(), &segments, options.k);
+                variants::add_segment_counts(counter, &segments, mate, options, timings)
+            }
+            None => add_sequence_counts_skipping(counter, &sequence, options, timings),
+        };
+        match result {
+            Err(err) if err.is_fatal() => return Err(err.into()),
+            Ok(skipped) => {
+                self.skipped_ambiguous += skipped;
+                self.tally.add(&id, Ok(()))
+            }
+            Err(err) => self.tally.add(&id, Err(err)),
+        }
+        Ok(())
+    }
+
+    /// Report the records of `fasta_path` and save the counts at
+    /// `output_path` as `run_fasta_kmer_count_timed` does, `partial` telling
+    /// whether records were left uncounted at the deadline
+    pub fn save(
+        self,
+        fasta_path: &Path,
+        options: &CountOptions,
+        metadata: Option<&RunMetadata>,
+        output_path: Option<&Path>,
+        partial: bool,
+        timings: &mut PhaseTimings,
+    ) -> Result<()> {
+        let InputCounts {
+            counter,
+            tally,
+            ids,
+            seen,
+            suspect,
+            skipped_ambiguous,
+            masked,
+        } = self;
+        if partial {
+            eprintln!(
+                "WARNING: Time limit reached; counts of {:?} are partial, from its first {} record(s)",
+                fasta_path,
+                tally.processed + tally.skipped() + seen.collapsed
+            );
+        }
+
+        tally.warn_too_short(fasta_path, options.k);
+        suspect.warn_remaining(fasta_path);
+        if options.skip_ambiguous {
+            log::info!(
+                "Skipped {} kmer(s) of {:?} with ambiguous bases",
+                skipped_ambiguous,
+                fasta_path
+            );
+        }
+        if options.variant_mask.is_some() {
+            log::info!(
+                "Skipped {} kmer(s) of {:?} overlapping masked variants",
+                masked,
+                fasta_path
+            );
+        }
+        if ids.duplicates > 0 {
+            eprintln!(
+                "WARNING: {} record(s) of {:?} repeat an earlier id; {}",
+                ids.duplicates,
+                fasta_path,
+                match options.duplicate_ids {
+                    DuplicateIds::Suffix => "they were counted with .1, .2, ... suffixes",
+                    _ => "their counts were merged",
+                }
+            );
+        }
+        if seen.collapsed > 0 {
+            eprintln!(
+                "WARNING: Collapsed {} record(s) of {:?} repeating an earlier record; they were not counted",
+                seen.collapsed, fasta_path
+            );
+        }
+        let save = tally.processed > 0 || options.empty_inputs == EmptyInputs::Table;
+        if tally.processed == 0 {
+            eprintln!(
+                "WARNING: No records counted in {:?}{}",
+                fasta_path,
+                if save { "" } else { "; no output written" }
+            );
+        }
+        let metadata = metadata.map(|metadata| {
+            let metadata = metadata
+                .clone()
+                .with("records_processed", tally.processed)
+                .with("records_skipped", tally.skipped())
+                .with("records_skipped_too_short", tally.too_short)
+                .with("records_duplicate_ids", ids.duplicates)
+                .with("records_with_suspect_bases", suspect.records);
+            let metadata = if suspect.records > 0 {
+                metadata.with("suspect_bases", suspect.summary())
+            } else {
+                metadata
+            };
+            let metadata = if options.dedup_records {
+                metadata.with("records_collapsed", seen.collapsed)
+            } else {
+                metadata
+            };
+            let metadata = if options.skip_ambiguous {
+                metadata.with("kmers_skipped_ambiguous", skipped_ambiguous)
+            } else {
+                metadata
+            };
+            let metadata = if options.variant_mask.is_some() {
+                metadata.with("kmers_skipped_masked", masked)
+            } else {
+                metadata
+            };
+            if options.deadline.is_some() {
+                metadata.with("partial", partial)
+            } else {
+                metadata
+            }
+        });
+
+        timings.counter_size(counter.len());
+        let kmer_count = timings.time(Phase::Sort, || ordered_kmer_count(counter));
+        if let Some(output_path) = output_path.filter(|_| save) {
+            timings.time(Phase::Write, || {
+                save_counts(kmer_count, options, metadata.as_ref(), output_path)
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// Add the kmers of `sequence`, counted as configured by `options`, to
+/// `counter`, recording the time spent in `timings`
+///
+/// With `options.orf_min_len`, only the open reading frames of `sequence` are
+/// counted, each in its coding orientation; ORFs shorter than `k` are skipped.
+pub(crate) fn add_sequence_counts(
+    counter: &mut HashMap<String, u64>,
+    sequence: &[u8],
+    options: &CountOptions,
+    timings: &mut PhaseTimings,
+) -> Result<(), KmerError> {
+    add_sequence_counts_skipping(counter, sequence, options, timings).map(|_| ())
+}
+
+/// Add the kmers of `sequence` to `counter` as `add_sequence_counts` does,
+/// returning the number of the windows it examined that `skip_ambiguous`
+/// left out
+pub(crate) fn add_sequence_counts_skipping(
+    counter: &mut HashMap<String, u64>,
+    sequence: &[u8],
+    options: &CountOptions,
+    timings: &mut PhaseTimings,
+) -> Result<u64, KmerError> {
+    let sequence = &options.case.normalize(sequence)[..];
+    match options.orf_min_len {
+        Some(min_len) => {
+            let mut skipped = 0;
+            for orf in orf::find_orfs(sequence, min_len) {
+                match add_region_counts(counter, &orf.bases(sequence), options, timings) {
+                    Ok(orf_skipped) => skipped += orf_skipped,
+                    Err(KmerError::KmerLengthTooLong { .. }) => {}
+                    Err(err) => return Err(err),
+                }
+            }
+            Ok(skipped)
+        }
+        None => add_region_counts(counter, sequence, options, timings),
+    }
+}
+
+/// Add the kmers of the whole of `sequence` to `counter`, as for
+/// `add_sequence_counts_skipping`
+fn add_region_counts(
+    counter: &mut HashMap<String, u64>,
+    sequence: &[u8],
+    options: &CountOptions,
+    timings: &mut PhaseTimings,
+) -> Result<u64, KmerError> {
+    let key = |kmer: &str| options.count_key(kmer);
+    if options.translate || options.mismatches > 0 {
+        let kmer_count = count_sequence(sequence, options, timings)?;
+        timings.time(Phase::Count, || {
+            for kmer in kmer_count {
+                add_count(counter, key(&kmer.seq), kmer.count)?;
+            }
+            Ok(0)
+        })
+    } else {
+        timings.time(Phase::Count, || {
+            for (kmer, count) in kmer_counter(sequence, options.k, options.skip_ambiguous)? {
+                if options.case.counts(kmer) {
+                    add_count(counter, key(kmer), count)?;
+                }
+            }
+            if !options.skip_ambiguous {
+                return Ok(0);
+            }
+            // masked lowercase windows are left out by case, not as ambiguous
+            Ok(match options.case {
+                CaseHandling::SkipMasked => {
+                    ambiguous_windows(&sequence.to_ascii_uppercase(), options.k)
+                }
+                _ => ambiguous_windows(sequence, options.k),
+            })
+        })
+    }
+}
+
+/// Add `count` to the count of `kmer` in `counter`, failing rather than
+/// wrapping around if the sum exceeds `u64::MAX`
+pub(crate) fn add_count<K>(
+    counter: &mut HashMap<K, u64>,
+    kmer: K,
+    count: u64,
+) -> Result<(), KmerError>
+where
+    K: Eq + Hash + AsRef<str>,
+{
+    match counter.entry(kmer) {
+        Entry::Occupied(mut entry) => {
+            let sum = entry
+                .get()
+                .checked_add(count)
+                .ok_or_else(|| KmerError::CountOverflow {
+                    kmer: entry.key().as_ref().to_owned(),
+                })?;
+            entry.insert(sum);
+        }
+        Entry::Vacant(entry) => {
+            entry.insert(count);
+        }
+    }
+    Ok(())
+}
+
+/// Count kmers over all records of the fasta file at `fasta_path` as
+/// configured by `options`
+///
+/// Records that cannot be counted are skipped, those shorter than `k` with a
+/// single warning for the file; a count overflowing `u64` fails the input.
+pub fn fasta_kmer_counter(
+    fasta_path: &Path,
+    options: &CountOptions,
+) -> Result<HashMap<String, u64>> {
+    let mut counter = HashMap::new();
+    let mut tally = RecordTally::default();
+    for record in open_fasta(fasta_path)?.records() {
+        let record = record?;
+        let mate = Mate::from_header(record.id(), record.desc());
+        let sequence = strand::orient(record.seq(), mate, options.library_type);
+        let mut timings = PhaseTimings::disabled();
+        match add_sequence_counts(&mut counter, &sequence, options, &mut timings) {
+            Err(err) if err.is_fatal() => return Err(err.into()),
+            result => tally.add(record.id(), result),
+        }
+    }
+    tally.warn_too_short(fasta_path, options.k);
+    Ok(counter)
+}
+
+/// Most and least abundant kmers of a count table, with their counts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KmerExtremes {
+    pub max: (String, u64),
+    pub min: (String, u64),
+}
+
+/// First and last rows of the ordered `kmer_count`, or `None` if it is empty
+fn kmer_extremes(kmer_count: &KmerCount) -> Option<KmerExtremes> {
+    let row = |kmer: &KmerRecord| (kmer.seq.to_string(), kmer.count);
+    Some(KmerExtremes {
+        max: row(kmer_count.first()?),
+        min: row(kmer_count.last()?),
+    })
+}
+
+/// Most and least abundant kmers over all records of the fasta file at
+/// `fasta_path`, without saving a table
+///
+/// These are the first and last rows of the table that would be saved, so
+/// ties among equal counts follow its alphabetical order and kmers outside
+/// the GC window are left out. Returns `None` if no kmers were counted.
+pub fn fasta_kmer_extremes(
+    fasta_path: &Path,
+    options: &CountOptions,
+) -> Result<Option<KmerExtremes>> {
+    let mut kmer_count = ordered_kmer_count(fasta_kmer_counter(fasta_path, options)?);
+    kmer_count.retain(|kmer| options.in_gc_window(&kmer.seq));
+    Ok(kmer_extremes(&kmer_count))
+}
+
+/// Save `kmer_count` to `output_path` in the table format selected by `options`
+///
+/// Kmers outside the GC window of `options` or counted fewer than
+/// `options.min_count` times are left out, and with `options.top_n` all but
+/// the most abundant of the rest. With `options.split`, the kmers are saved
+/// at the `split::split_path` of each split instead, each header gaining a
+/// `split` entry, and with `options.minimizer_shards` at the
+/// `minimizer::shard_path` of each shard, each header gaining a `shard`
+/// entry.
+fn save_counts(
+    mut kmer_count: KmerCount,
+    options: &CountOptions,
+    metadata: Option<&RunMetadata>,
+    output_path: &Path,
+) -> Result<()> {
+    if options.gc_min.is_some() || options.gc_max.is_some() {
+        kmer_count.retain(|kmer| options.in_gc_window(&kmer.seq));
+    }
+    if options.min_count > 1 {
+        kmer_count.retain(|kmer| kmer.count >= options.min_count);
+    }
+    if let Some(top_n) = options.top_n {
+        // the table is ordered by descending count, then alphabetically
+        kmer_count.truncate(top_n);
+    }
+    if let Some(sink) = &options.sink {
+        let table = output_path.file_name().unwrap_or_default();
+        sink.send(&kmer_count, &table.to_string_lossy())?;
+    }
+    match &options.split {
+        Some(split) => {
+            let (train, test) = split.partition(kmer_count);
+            for (kmers, part) in [(train, Split::Train), (test, Split::Test)] {
+                let metadata = metadata.map(|metadata| metadata.clone().with("split", part.name()));
+                let path = split::split_path(output_path, part);
+                save_table(kmers, options, metadata.as_ref(), &path)?;
+            }
+            Ok(())
+        }
+        None => match &options.minimizer_shards {
+            Some(shards) => {
+                let paths = shards.shard_paths(output_path);
+                let partition = shards.partition(kmer_count);
+                for (shard, (kmers, path)) in partition.into_iter().zip(paths).enumerate() {
+                    let metadata = metadata.map(|metadata| metadata.clone().with("shard", shard));
+                    save_table(kmers, options, metadata.as_ref(), &path)?;
+                }
+                Ok(())
+            }
+            None => save_table(kmer_count, options, metadata, output_path),
+        },
+    }
+}
+
+/// Save `kmer_count` as is to `output_path` in the table format selected by
+/// `options`
+fn save_table(
+    kmer_count: KmerCount,
+    options: &CountOptions,
+    metadata: Option<&RunMetadata>,
+    output_path: &Path,
+) -> Result<()> {
+    if options.indexed_output {
+        index::save_indexed_kmer_count(kmer_count, metadata, output_path)
+    } else if options.varint_output {
+        varint::save_varint_kmer_count(kmer_count, options.k, output_path)
+    } else if options.format == OutputFormat::Protobuf {
+        save_protobuf(kmer_count, output_path)
+    } else if options.format == OutputFormat::Avro {
+        save_avro(kmer_count, output_path)
+    } else if options.format == OutputFormat::Npy {
+        npy::save_npy_kmer_count(kmer_count, output_path)
+    } else if options.format == OutputFormat::Ndjson {
+        save_ndjson(kmer_count, output_path)
+    } else if options.format == OutputFormat::Json {
+        save_json(kmer_count, output_path)
+    } else if options.log_tf {
+        save_log_tf(kmer_count, metadata, output_path)
+    } else if options.tm {
+        tm::save_tm_kmer_count(kmer_count, metadata, output_path)
+    } else if options.revcomp_column {
+        save_revcomp_kmer_count(kmer_count, metadata, output_path)
+    } else {
+        save_kmer_count(kmer_count, metadata, output_path)
+    }
+}
+
+#[cfg(feature = "protobuf")]
+fn save_protobuf(kmer_count: KmerCount, output_path: &Path) -> Result<()> {
+    protobuf::save_protobuf_kmer_count(kmer_count, output_path)
+}
+
+#[cfg(not(feature = "protobuf"))]
+fn save_protobuf(_kmer_count: KmerCount, _output_path: &Path) -> Result<()> {
+    Err(anyhow!(
+        "protobuf output requires building with the `protobuf` feature"
+    ))
+}
+
+#[cfg(feature = "avro")]
+fn save_avro(kmer_count: KmerCount, output_path: &Path) -> Result<()> {
+    avro::save_avro_kmer_count(kmer_count, output_path)
+}
+
+#[cfg(not(feature = "avro"))]
+fn save_avro(_kmer_count: KmerCount, _output_path: &Path) -> Result<()> {
+    Err(anyhow!("Avro output requires building with the `avro` feature"))
+}
+
+/// Count the kmers of `sequence` as configured by `options`, ordered from
+/// most to least abundant, recording each phase in `timings`
+pub(crate) fn count_sequence<'a>(
+    sequence: &'a [u8],
+    options: &CountOptions,
+    timings: &mut PhaseTimings,
+) -> Result<KmerCount<'a>, KmerError> {
+    if let Cow::Owned(sequence) = options.case.normalize(sequence) {
+        let kmer_count = count_sequence(&sequence, options, timings)?;
+        return Ok(kmer_count.into_iter().map(KmerRecord::into_owned).collect());
+    }
+    let mut kmer_count = count_normalized_sequence(sequence, options, timings)?;
+    if options.case == CaseHandling::SkipMasked {
+        kmer_count.retain(|kmer| options.case.counts(&kmer.seq));
+    }
+    Ok(kmer_count)
+}
+
+/// Count the kmers of `sequence`, as it is counted under `options.case`, as
+/// for `count_sequence`
+fn count_normalized_sequence<'a>(
+    sequence: &'a [u8],
+    options: &CountOptions,
+    timings: &mut PhaseTimings,
+) -> Result<KmerCount<'a>, KmerError> {
+    if options.translate {
+        timings.time(Phase::Count, || {
+            Ok(translate::count_peptide_kmers(
+                sequence,
+                options.k,
+                options.stop_codons,
+            ))
+        })
+    } else if options.mismatches > 0 {
+        timings.time(Phase::Count, || {
+            mismatch::count_kmers_with_mismatches(sequence, options.k, options.mismatches)
+        })
+    } else {
+        let counter = timings.time(Phase::Count, || {
+            kmer_counter(sequence, options.k, options.skip_ambiguous)
+        })?;
+        timings.counter_size(counter.len());
+        Ok(timings.time(Phase::Sort, || ordered_kmer_count(counter)))
+    }
+}
+
+/// Return frequency of all kmers of length `k` in `sequence`, ordered from most to least abundant
+///
+/// With `skip_ambiguous`, kmers with a base other than A, T, C, or G are left out.
+/// Kmers of equal count are in lexicographic order. Counting is case
+/// sensitive; the returned kmers borrow from `sequence`.
+///
+/// # Examples
+///
+/// ```
+/// let counts = kmer::count_kmers(b"ATCGGATCG", 3, false)?;
+/// let rows: Vec<(&str, u64)> = counts.iter().map(|r| (r.kmer(), r.count())).collect();
+/// assert_eq!(
+///     rows,
+///     [("ATC", 2), ("TCG", 2), ("CGG", 1), ("GAT", 1), ("GGA", 1)]
+/// );
+///
+/// // a sequence shorter than k cannot be counted
+/// assert!(kmer::count_kmers(b"AT", 3, false).is_err());
+/// # Ok::<(), kmer::KmerError>(())
+/// ```
+pub fn count_kmers(
+    sequence: &[u8],
+    k: usize,
+    skip_ambiguous: bool,
+) -> Result<KmerCount<'_>, KmerError> {
+    let counter = kmer_counter(sequence, k, skip_ambiguous)?;
+    Ok(ordered_kmer_count(counter))
+}
+
+/// Return frequency of all kmers of length `k` in `sequence`, unordered,
+/// leaving out kmers with a base other than A, T, C, or G if `skip_ambiguous`
+fn kmer_counter(
+    sequence: &[u8],
+    k: usize,
+    skip_ambiguous: bool,
+) -> Result<HashMap<&str, u64>, KmerError> {
+    let mut counter: HashMap<&str, u64> = HashMap::new();
+    for kmer in kmers(sequence, k)? {
+        if skip_ambiguous && !kmer.bytes().all(|base| b"ATCG".contains(&base)) {
+            continue;
+        }
+        *counter.entry(kmer).or_insert(0) += 1;
+    }
+    Ok(counter)
+}
+
+/// Number of the windows of length `k` of `sequence` with a base other than
+/// A, T, C, or G, those left out by `skip_ambiguous`
+pub fn ambiguous_windows(sequence: &[u8], k: usize) -> u64 {
+    let mut last_ambiguous = None;
+    let mut windows = 0;
+    for (end, base) in sequence.iter().enumerate() {
+        if !b"ATCG".contains(base) {
+            last_ambiguous = Some(end);
+        }
+        // the window ending at `end` starts at `end + 1 - k`
+        if end + 1 >= k && last_ambiguous.is_some_and(|position| position + k > end) {
+            windows += 1;
+        }
+    }
+    windows
+}
+
+/// Convert a kmer frequency map into a `KmerCount` ordered from most to least abundant
+fn ordered_kmer_count<'a, S>(counter: HashMap<S, u64>) -> KmerCount<'a>
+where
+    S: Into<Cow<'a, str>>,
+{
+    let mut ordered: Vec<_> = counter
+        .into_iter()
+        .map(|(k, v)| KmerRecord {
+            seq: k.into(),
+            count: v,
+        })
+        .collect();
+
+    // first, sort kmers alphabetically so order among equal counts is deterministic
+    // second, sort by descending count
+    //
+    // n.b. this could be implemented by the Ord/PartialOrd traits on KmerRecord,
+    // but for this simple program, putting the sorting logic here is clearer and
+    // results in less boilerplate.
+    ordered.sort_by(|a, b| a.seq.cmp(&b.seq));
+    ordered.sort_by(|a, b| a.count.cmp(&b.count).reverse());
+    ordered
+}
+
+/// Return all subsequences of length k from the given sequence
+///
+/// `sequence` must be ASCII, which is sufficient for sequencing data. A
+/// non-ASCII byte is an error naming its position, so every one of the
+/// `len - k + 1` windows is returned or none are.
+fn kmers(sequence: &[u8], k: usize) -> Result<impl Iterator<Item = &str>, KmerError> {
+    if k == 0 {
+        return Err(KmerError::KmerLengthTooSmall { k });
+    }
+
+    if sequence.len() < k {
+        return Err(KmerError::KmerLengthTooLong {
+            k,
+            seq_len: sequence.len(),
+        });
+    }
+
+    if let Some(position) = sequence.iter().position(|base| !base.is_ascii()) {
+        return Err(KmerError::NonAsciiBase {
+            byte: sequence[position],
+            position,
+        });
+    }
+    // ASCII is valid UTF-8, and every window of it is too
+    let sequence = str::from_utf8(sequence).unwrap();
+    Ok((0..=sequence.len() - k).map(move |start| &sequence[start..start + k]))
+}
+
+/// Return all subsequences of length k from the given sequence, with their start positions
+fn kmer_positions(
+    sequence: &[u8],
+    k: usize,
+) -> Result<impl Iterator<Item = (usize, &str)>, KmerError> {
+    Ok(kmers(sequence, k)?.enumerate())
+}
+
+/// Check that all bases in `seq` are A, T, C, or G, or their lowercase unless
+/// `case` is `CaseHandling::Sensitive`.
+///
+/// The error lists each other byte once, in byte order, with its number of
+/// occurrences.
+fn check_bases(seq: &[u8], case: CaseHandling) -> Result<(), KmerError> {
+    let mut occurrences = [0u64; 256];
+    for &base in seq {
+        occurrences[base as usize] += 1;
+    }
+    let valid: &[u8] = match case {
+        CaseHandling::Sensitive => b"ATCG",
+        CaseHandling::Upper | CaseHandling::SkipMasked => b"ATCGatcg",
+    };
+    let bases: Vec<(u8, u64)> = (0..=u8::MAX)
+        .filter(|base| !valid.contains(base))
+        .map(|base| (base, occurrences[base as usize]))
+        .filter(|&(_, count)| count > 0)
+        .collect();
+
+    if bases.is_empty() {
+        Ok(())
+    } else {
+        Err(KmerError::IncorrectBases { bases })
+    }
+}
+
+/// `bases` as `N (3), n (12)`, with non-printable bytes escaped
+fn format_base_counts<'a, I>(bases: I) -> String
+where
+    I: IntoIterator<Item = &'a (u8, u64)>,
+{
+    let bases: Vec<String> = bases
+        .into_iter()
+        .map(|(base, count)| format!("{} ({})", base.escape_ascii(), count))
+        .collect();
+    bases.join(", ")
+}
+
+/// Most records per input whose suspect bases are reported one by one
+pub const MAX_SUSPECT_BASE_WARNINGS: u64 = 5;
+
+/// Bases other than A, T, C, and G found in the records of one input
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SuspectBases {
+    /// records with at least one suspect base
+    pub records: u64,
+    /// occurrences of each suspect byte over all records
+    pub counts: BTreeMap<u8, u64>,
+}
+
+impl SuspectBases {
+    /// Check the sequence `seq` of record `id`, counted under `case`, warning
+    /// of its suspect bases if it is among the first
+    /// `MAX_SUSPECT_BASE_WARNINGS` records with any
+    pub(crate) fn check(&mut self, id: &str, seq: &[u8], case: CaseHandling) {
+        if let Err(KmerError::IncorrectBases { bases }) = check_bases(seq, case) {
+            self.records += 1;
+            for &(base, count) in &bases {
+                *self.counts.entry(base).or_default() += count;
+            }
+            if self.records <= MAX_SUSPECT_BASE_WARNINGS {
+                eprintln!(
+                    "WARNING: Record {}: {}",
+                    id,
+                    KmerError::IncorrectBases { bases }
+                );
+            }
+        }
+    }
+
+    /// Totals of all suspect bytes, as `N (3), n (12)`
+    pub fn summary(&self) -> String {
+        let counts: Vec<(u8, u64)> = self.counts.iter().map(|(&b, &c)| (b, c)).collect();
+        format_base_counts(&counts)
+    }
+
+    /// Roll up the records of `fasta_path` whose suspect bases were not
+    /// reported one by one, if any, in a single warning
+    pub(crate) fn warn_remaining(&self, fasta_path: &Path) {
+        if self.records > MAX_SUSPECT_BASE_WARNINGS {
+            eprintln!(
+                "WARNING: ... and {} more record(s) of {:?} with suspect bases; {} in all records",
+                self.records - MAX_SUSPECT_BASE_WARNINGS,
+                fasta_path,
+                self.summary()
+            );
+        }
+    }
+}
+
+/// Fraction of the A, T, C, and G bases in `seq` that are G or C
+///
+/// Case-insensitive. Other bases are ignored; returns 0 if there are no ATCG bases.
+pub fn gc_content(seq: &[u8]) -> f64 {
+    let (mut gc, mut atcg) = (0usize, 0usize);
+    for base in seq {
+        match base.to_ascii_uppercase() {
+            b'G' | b'C' => {
+                gc += 1;
+                atcg += 1;
+            }
+            b'A' | b'T' => atcg += 1,
+            _ => {}
+        }
+    }
+    if atcg == 0 {
+        0.0
+    } else {
+        gc as f64 / atcg as f64
+    }
+}
+
+/// Index of `base` in `ACGT`, ignoring case, or `None` for any other base
+pub(crate) fn base_index(base: u8) -> Option<usize> {
+    match base.to_ascii_uppercase() {
+        b'A' => Some(0),
+        b'C' => Some(1),
+        b'G' => Some(2),
+        b'T' => Some(3),
+        _ => None,
+    }
+}
+
+/// Return the reverse complement of `seq`
+///
+/// Case is preserved. Bases other than A, T, C, and G are left unchanged.
+pub fn reverse_complement(seq: &[u8]) -> Vec<u8> {
+    seq.iter()
+        .rev()
+        .map(|base| match base {
+            b'A' => b'T',
+            b'T' => b'A',
+            b'C' => b'G',
+            b'G' => b'C',
+            b'a' => b't',
+            b't' => b'a',
+            b'c' => b'g',
+            b'g' => b'c',
+            other => *other,
+        })
+        .collect()
+}
+
+/// Drive or share of a Windows path prefix, compared so that the verbatim
+/// (`\\?\C:`) and plain (`C:`) spellings and either case of a drive letter
+/// name the same drive
+#[derive(Debug, PartialEq, Eq)]
+enum PrefixKey<'a> {
+    Disk(u8),
+    Unc(&'a OsStr, &'a OsStr),
+    Other(&'a OsStr),
+}
+
+fn prefix_key(prefix: Prefix) -> PrefixKey {
+    match prefix {
+        Prefix::Disk(drive) | Prefix::VerbatimDisk(drive) => {
+            PrefixKey::Disk(drive.to_ascii_uppercase())
+        }
+        Prefix::UNC(server, share) | Prefix::VerbatimUNC(server, share) => {
+            PrefixKey::Unc(server, share)
+        }
+        Prefix::Verbatim(name) | Prefix::DeviceNS(name) => PrefixKey::Other(name),
+    }
+}
+
+/// Path of `path` relative to `root`, or `None` if `path` is not under `root`
+///
+/// Paths are compared component by component, so separators do not matter,
+/// and Windows prefixes match as described for `PrefixKey`: a path from
+/// `fs::canonicalize` (`\\?\C:\data`) is under a root as typed (`c:\data`).
+/// Elsewhere this is `Path::strip_prefix`.
+pub fn relative_to(path: &Path, root: &Path) -> Option<PathBuf> {
+    let mut components = path.components();
+    for root_component in root.components() {
+        let same = match (components.next()?, root_component) {
+            (Component::Prefix(a), Component::Prefix(b)) => {
+                prefix_key(a.kind()) == prefix_key(b.kind())
+            }
+            (a, b) => a == b,
+        };
+        if !same {
+            return None;
+        }
+    }
+    Some(components.as_path().to_owned())
+}
+
+/// Derive an output file path from the suffix of the input path
+pub fn output_path_from_input(
+    input_path: &Path,
+    input_root: &Path,
+    output_root: &Path,
+) -> Result<PathBuf> {
+    output_path_with_suffix(input_path, input_root, output_root, "_kmer.txt")
+}
+
+/// Derive an output file path from the suffix of the input path, replacing the
+/// input's extension, and a `.gz` after it, with `suffix`
+///
+/// Fails, naming the input, if it is not under `input_root` or has no file
+/// name. File names need not be UTF-8. The output path is rebuilt from the
+/// components of `output_root` and the input's path below `input_root`, so it
+/// uses the platform's separator throughout.
+pub fn output_path_with_suffix(
+    input_path: &Path,
+    input_root: &Path,
+    output_root: &Path,
+    suffix: &str,
+) -> Result<PathBuf> {
+    let path_stub = relative_to(input_path, input_root).ok_or_else(|| {
+        anyhow!(
+            "Input {:?} is not under the input directory {:?}",
+            input_path,
+            input_root
+        )
+    })?;
+    let stem =
+        input_stem(input_path).ok_or_else(|| anyhow!("Input {:?} has no file name", input_path))?;
+    let mut file_name = stem.to_os_string();
+    file_name.push(suffix);
+
+    let mut output_path: PathBuf = output_root
+        .components()
+        .chain(path_stub.components())
+        .collect();
+    output_path.set_file_name(file_name);
+    Ok(output_path)
+}
+
+/// Path of the table of the record named `record_name` of the input whose
+/// output, ending in `suffix`, is at `output_path`: the name is inserted
+/// before the suffix, e.g. `sample1__contig_17_kmer.txt`
+///
+/// `record_name` should come from `perrecord::RecordNames` so it is safe in a
+/// file name. A file name that is not UTF-8 is converted lossily.
+pub fn record_output_path(output_path: &Path, suffix: &str, record_name: &str) -> PathBuf {
+    let file_name = output_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let stem = file_name.strip_suffix(suffix).unwrap_or(&file_name);
+    output_path.with_file_name(format!("{}__{}{}", stem, record_name, suffix))
+}
+
+/// File name suffixes of the tables written for each input by directory runs
+pub const OUTPUT_SUFFIXES: &[&str] = &[
+    "_kmer.txt",
+    "_kmer.kmv",
+    "_kmer.pb",
+    "_kmer.avro",
+    "_kmer.npy",
+    "_kmer.labels.txt",
+    "_kmer.ndjson",
+    "_kmer.json",
+    "_composition.txt",
+    "_cooccurrence.txt",
+    "_first_last.txt",
+    "_coverage.txt",
+    "_chargaff.txt",
+    "_specificity.txt",
+    "_tnf.txt",
+    "_entropy_rate.txt",
+    "_dinucleotide_signature.txt",
+];
+
+/// Whether the file at `path` was written by a previous directory run
+///
+/// Outputs are named with one of `OUTPUT_SUFFIXES` (or are the run manifest)
+/// and, unlike fasta files, do not begin with a `>` header.
+pub fn is_previous_output(path: &Path) -> Result<bool> {
+    let name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name,
+        None => return Ok(false),
+    };
+    if name == manifest::MANIFEST_FILE_NAME {
+        return Ok(true);
+    }
+    if !OUTPUT_SUFFIXES.iter().any(|suffix| name.ends_with(suffix)) {
+        return Ok(false);
+    }
+    let mut first = [0u8; 1];
+    let n = File::open(path)?.read(&mut first)?;
+    Ok(n == 0 || first[0] != b'>')
+}
+
+/// Whether `output_path` exists and was modified no earlier than `input_path`
+pub fn is_up_to_date(input_path: &Path, output_path: &Path) -> Result<bool> {
+    let output_modified = match fs::metadata(output_path) {
+        Ok(metadata) => metadata.modified()?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err.into()),
+    };
+    Ok(output_modified >= fs::metadata(input_path)?.modified()?)
+}
+
+/// Default extensions of input files
+pub const DEFAULT_EXTENSIONS: &str = "fa,fasta,fna,fq,fastq";
+
+/// Parse one `--extensions` value: surrounding whitespace and a leading dot
+/// are removed, so ` .fa` becomes `fa`
+pub fn parse_extension(value: &str) -> Result<String, String> {
+    let extension = value.trim();
+    let extension = extension.strip_prefix('.').unwrap_or(extension);
+    if extension.is_empty() {
+        Err(format!("Empty file extension in {:?}", value))
+    } else if extension.contains(['.', '/', '\\']) {
+        Err(format!(
+            "Invalid file extension {:?}; only the part after the last dot is matched",
+            value
+        ))
+    } else {
+        Ok(extension.to_owned())
+    }
+}
+
+/// How `find_files_with_extensions` treats a symlink to a file outside the
+/// searched directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExternalSymlinks {
+    /// find it at the symlink's own location in the directory
+    Count,
+    /// leave it out
+    Skip,
+}
+
+impl ExternalSymlinks {
+    pub const VARIANTS: &'static [&'static str] = &["count", "skip"];
+}
+
+impl FromStr for ExternalSymlinks {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "count" => Ok(ExternalSymlinks::Count),
+            "skip" => Ok(ExternalSymlinks::Skip),
+            _ => Err(format!("Unknown external symlink handling: {}", s)),
+        }
+    }
+}
+
+/// Files found in a directory by `find_files_with_extensions`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FoundFiles {
+    /// canonical paths of the matching files, or for symlinks to files
+    /// outside the directory, the symlink's path below the canonical directory
+    pub files: Vec<PathBuf>,
+    /// entries with a matching extension that could not be resolved, such as
+    /// dangling symlinks
+    pub unresolved: Vec<PathBuf>,
+    /// matching symlinks left out for resolving outside the directory, with
+    /// the canonical paths of their targets
+    pub external: Vec<(PathBuf, PathBuf)>,
+}
+
+/// Find all files in `dir` with one of the given `extensions`, skipping
+/// unresolvable entries with a warning
+pub fn fs_find_files_with_extensions<T>(dir: &Path, extensions: &[T]) -> Result<Vec<PathBuf>>
+where
+    T: AsRef<str>,
+{
+    Ok(find_files_with_extensions(dir, extensions, false, ExternalSymlinks::Count, None)?.files)
+}
+
+/// `path` made absolute and resolved through symlinks as far as it exists,
+/// so it can be compared with canonical paths before it is created
+pub fn resolve_path(path: &Path) -> Result<PathBuf> {
+    if let Ok(resolved) = fs::canonicalize(path) {
+        return Ok(resolved);
+    }
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => Ok(resolve_path(parent)?.join(name)),
+        _ => Ok(std::env::current_dir()?.join(path)),
+    }
+}
+
+/// Find all files in `dir` with one of the given `extensions`, sorted by
+/// path whatever order the file system lists them in
+///
+/// A gzipped file is found by the extension before its `.gz`, so `a.fa.gz`
+/// is found with `fa`.
+///
+/// Entries are resolved to canonical paths. An entry that cannot be, such as
+/// a dangling symlink, is an error if `strict`; otherwise it is skipped, and
+/// listed in `unresolved` with a warning if its extension matches.
+///
+/// A symlink resolving outside `dir` has no path below it to name its outputs
+/// by, so `external` decides: it is found at its own location, where reading
+/// follows the link, or it is skipped and listed in `external`.
+///
+/// Files resolving to inside `exclude`, the resolved output root of a run, are
+/// left out, so a run never counts earlier outputs. An `exclude` containing
+/// `dir` itself is ignored; in-place runs filter their outputs by name instead.
+pub fn find_files_with_extensions<T>(
+    dir: &Path,
+    extensions: &[T],
+    strict: bool,
+    external: ExternalSymlinks,
+    exclude: Option<&Path>,
+) -> Result<FoundFiles>
+where
+    T: AsRef<str>,
+{
+    fn has_extension<T: AsRef<str>>(p: &Path, exts: &[T]) -> bool {
+        let p = match p.file_stem() {
+            Some(stem) if is_gzipped(p) => Path::new(stem),
+            _ => p,
+        };
+        p.extension()
+            .map(|s| exts.iter().any(|e| s == e.as_ref()))
+            .unwrap_or(false)
+    }
+
+    let dir_resolved = fs::canonicalize(dir)?;
+    let exclude = exclude.filter(|root| relative_to(&dir_resolved, root).is_none());
+    let mut found = FoundFiles::default();
+    for entry in dir.read_dir()? {
+        let entry = entry?;
+        let path = match fs::canonicalize(entry.path()) {
+            Ok(path) => path,
+            Err(err) if strict => {
+                return Err(anyhow!("Could not resolve {:?}: {}", entry.path(), err))
+            }
+            Err(err) => {
+                if has_extension(&entry.path(), extensions) {
+                    log::warn!("Skipping {:?}: {}", entry.path(), err);
+                    found.unresolved.push(entry.path());
+                }
+                continue;
+            }
+        };
+        if !(path.is_file() && has_extension(&path, extensions)) {
+            continue;
+        }
+        if exclude.is_some_and(|root| relative_to(&path, root).is_some()) {
+            log::info!("Ignoring {:?} in the output root", entry.path());
+        } else if relative_to(&path, &dir_resolved).is_some() {
+            found.files.push(path);
+        } else {
+            match external {
+                ExternalSymlinks::Count => found.files.push(dir_resolved.join(entry.file_name())),
+                ExternalSymlinks::Skip => found.external.push((entry.path(), path)),
+            }
+        }
+    }
+    found.files.sort();
+    found.unresolved.sort();
+    found.external.sort();
+    Ok(found)
+}
+
+/// Create the file at `output_path` for writing, as for `create_file`, or
+/// write to stdout if it is `STDIO_PATH`
+pub fn create_output(output_path: &Path) -> Result<Box<dyn Write + Send>> {
+    if output_path == Path::new(STDIO_PATH) {
+        return Ok(Box::new(std::io::stdout()));
+    }
+    Ok(Box::new(create_file(output_path)?))
+}
+
+/// Create the file at `output_path` for writing, first creating any missing
+/// parent directories
+pub fn create_file(output_path: &Path) -> Result<File> {
+    if let Some(parent) = output_path.parent() {
+        if !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Could not create directory {:?}", parent))?;
+        }
+    }
+    File::create(output_path).with_context(|| format!("Could not create {:?}", output_path))
+}
+
+/// Save kmer count to `output_path`, after the `metadata` header if given
+fn save_kmer_count(
+    kmer_count: KmerCount,
+    metadata: Option<&RunMetadata>,
+    output_path: &Path,
+) -> Result<()> {
+    let mut file = BufWriter::new(create_output(output_path)?);
+    metadata::write_header(metadata, &mut file)?;
+
+    writeln!(file, "kmer\tcount")?;
+    let rows = kmer_count.len();
+    for kmer in kmer_count {
+        writeln!(file, "{}\t{}", kmer.seq, kmer.count)?;
+    }
+    validate::write_trailer(metadata, rows, &mut file)?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Save kmer count to `output_path` with a `revcomp` column of each kmer's
+/// reverse complement, after the `metadata` header if given
+fn save_revcomp_kmer_count(
+    kmer_count: KmerCount,
+    metadata: Option<&RunMetadata>,
+    output_path: &Path,
+) -> Result<()> {
+    let mut file = BufWriter::new(create_output(output_path)?);
+    metadata::write_header(metadata, &mut file)?;
+
+    writeln!(file, "kmer\trevcomp\tcount")?;
+    let rows = kmer_count.len();
+    for kmer in kmer_count {
+        let revcomp = reverse_complement(kmer.seq.as_bytes());
+        writeln!(
+            file,
+            "{}\t{}\t{}",
+            kmer.seq,
+            String::from_utf8_lossy(&revcomp),
+            kmer.count
+        )?;
+    }
+    validate::write_trailer(metadata, rows, &mut file)?;
+    file.flush()?;
+    Ok(())
+}
+
+/// One line of an NDJSON count table
+#[derive(Serialize)]
+struct NdjsonRow<'a> {
+    kmer: &'a str,
+    count: u64,
+}
+
+/// Save kmer count to `output_path` as newline-delimited JSON, one
+/// `{"kmer":...,"count":...}` object per line
+///
+/// Each row is written as it is serialized; no document of the whole table is
+/// built.
+fn save_ndjson(kmer_count: KmerCount, output_path: &Path) -> Result<()> {
+    let mut out = BufWriter::new(create_output(output_path)?);
+    for kmer in &kmer_count {
+        let row = NdjsonRow {
+            kmer: &kmer.seq,
+            count: kmer.count,
+        };
+        serde_json::to_writer(&mut out, &row)?;
+        out.write_all(b"\n")?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Save kmer count to `output_path` as a JSON array of
+/// `{"kmer":...,"count":...}` objects, in table order
+fn save_json(kmer_count: KmerCount, output_path: &Path) -> Result<()> {
+    let mut out = BufWriter::new(create_output(output_path)?);
+    serde_json::to_writer(&mut out, &kmer_count)?;
+    out.write_all(b"\n")?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Sublinear term frequency scaling of a kmer count, `ln(1 + count)`
+///
+/// Damps the dominance of highly repeated kmers before computing distances.
+pub fn log_tf(count: u64) -> f64 {
+    (count as f64).ln_1p()
+}
+
+/// Save kmer count to `output_path`, with counts scaled by `log_tf`
+fn save_log_tf(
+    kmer_count: KmerCount,
+    metadata: Option<&RunMetadata>,
+    output_path: &Path,
+) -> Result<()> {
+    let mut file = BufWriter::new(create_output(output_path)?);
+    metadata::write_header(metadata, &mut file)?;
+
+    writeln!(file, "kmer\tlog_tf")?;
+    let rows = kmer_count.len();
+    for kmer in kmer_count {
+        writeln!(file, "{}\t{:.6}", kmer.seq, log_tf(kmer.count))?;
+    }
+    validate::write_trailer(metadata, rows, &mut file)?;
+    file.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    // test helper to run the kmers iterator
+    fn kmers_vec(sequence: &[u8], k: usize) -> Vec<&str> {
+        kmers(sequence, k).unwrap().collect()
+    }
+
+    #[test]
+    /// kmers_vec simplifies comparisons
+    fn test_kmers_vec_helper_demo() -> Result<(), KmerError> {
+        assert_eq!(
+            kmers(b"ABCD", 2)?.collect::<Vec<_>>(),
+            kmers_vec(b"ABCD", 2)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_kmers_fn_basic() {
+        assert_eq!(kmers_vec(b"ABCD", 1), ["A", "B", "C", "D"]);
+        assert_eq!(kmers_vec(b"ABCD", 2), ["AB", "BC", "CD"]);
+        assert_eq!(kmers_vec(b"ABCD", 3), ["ABC", "BCD"]);
+        assert_eq!(kmers_vec(b"ABCD", 4), ["ABCD"]);
+    }
+
+    #[test]
+    fn test_kmer_0() -> Result<(), String>{
+        match kmers(b"ABCD", 0) {
+            #[allow(clippy::unit_arg)]
+            Err(e) => Ok(assert_eq!(e, KmerError::KmerLengthTooSmall { k: 0 })),
+            Ok(_) => Err(String::from("Should have generated error on k = 0")),
+        }
+    }
+
+    #[test]
+    fn test_validate_k() {
+        let validate = |options: CountOptions| options.validate().map_err(|err| err.to_string());
+        assert_eq!(
+            validate(CountOptions::new(0)),
+            Err(KmerError::KmerLengthTooSmall { k: 0 }.to_string())
+        );
+        assert!(validate(CountOptions::new(1)).is_ok());
+        assert!(validate(CountOptions::new(MAX_PRACTICAL_K)).is_ok());
+        assert!(validate(CountOptions::new(MAX_PRACTICAL_K + 1)).is_ok());
+
+        let strict = |k| CountOptions {
+            large_k: LargeK::Error,
+            ..CountOptions::new(k)
+        };
+        assert!(validate(strict(MAX_PRACTICAL_K)).is_ok());
+        let err = validate(strict(MAX_PRACTICAL_K + 1)).unwrap_err();
+        assert!(err.contains("257 exceeds 256"), "{}", err);
+
+        let varint = |k| CountOptions {
+            varint_output: true,
+            ..CountOptions::new(k)
+        };
+        assert!(validate(varint(varint::MAX_K)).is_ok());
+        let err = validate(varint(varint::MAX_K + 1)).unwrap_err();
+        assert!(
+            err.contains("33 exceeds 32, the limit of varint output"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_validate_mismatch_case() {
+        let options = |case| CountOptions {
+            mismatches: 1,
+            case,
+            ..CountOptions::new(3)
+        };
+        assert!(options(CaseHandling::Upper).validate().is_ok());
+        assert!(options(CaseHandling::SkipMasked).validate().is_ok());
+        let err = options(CaseHandling::Sensitive).validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "--mismatch cannot be used with --case sensitive"
+        );
+    }
+
+    #[test]
+    fn test_validate_options() {
+        let validate = |options: CountOptions| options.validate().map_err(|err| err.to_string());
+        let err = validate(CountOptions {
+            gc_min: Some(0.6),
+            gc_max: Some(0.4),
+            ..CountOptions::new(3)
+        });
+        assert_eq!(err, Err("--gc-min must not exceed --gc-max".to_owned()));
+        let err = validate(CountOptions {
+            dedup_threshold: Some(1.5),
+            ..CountOptions::new(3)
+        });
+        assert_eq!(
+            err,
+            Err("--dedup-threshold must be between 0 and 1".to_owned())
+        );
+        let err = validate(CountOptions {
+            minimizer_shards: Some(MinimizerShards { m: 4, shards: 2 }),
+            ..CountOptions::new(3)
+        });
+        assert_eq!(
+            err,
+            Err("--minimizer-len must be between 1 and k".to_owned())
+        );
+        let err = validate(CountOptions {
+            case: CaseHandling::SkipMasked,
+            translate: true,
+            ..CountOptions::new(3)
+        });
+        assert_eq!(
+            err,
+            Err("--case skip-masked cannot be used with --translate".to_owned())
+        );
+        assert!(validate(CountOptions {
+            gc_min: Some(0.4),
+            gc_max: Some(0.6),
+            minimizer_shards: Some(MinimizerShards { m: 3, shards: 2 }),
+            ..CountOptions::new(3)
+        })
+        .is_ok());
+    }
+
+    #[cfg(not(feature = "http"))]
+    #[test]
+    fn test_open_fasta_url_needs_http_feature() {
+        let err = open_fasta(Path::new("https://example.org/ref.fasta"))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("`http` feature"), "{}", err);
+    }
+
+    #[test]
+    fn test_run_fasta_kmer_count_rejects_k_0() -> Result<()> {
+        let dir = tempdir()?;
+        let output_path = dir.path().join("never_kmer.txt");
+        // the input is not even opened
+        let missing = dir.path().join("missing.fasta");
+        let err = run_fasta_kmer_count(&missing, &CountOptions::new(0), &output_path).unwrap_err();
+        assert!(err.to_string().contains("must be 1 or greater"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn test_kmer_empty_string() -> Result<(), String> {
+        match kmers(b"", 10) {
+            #[allow(clippy::unit_arg)]
+            Err(err) => Ok(assert_eq!(err, KmerError::KmerLengthTooLong { k: 10, seq_len: 0 })),
+            Ok(_) => Err(String::from("Should have generated error on empty string"))
+        }
+    }
+
+    #[test]
+    fn test_kmer_k_too_big() -> Result<(), String> {
+        match kmers(b"ABC", 10) {
+            #[allow(clippy::unit_arg)]
+            Err(err) => Ok(assert_eq!(err, KmerError::KmerLengthTooLong { k: 10, seq_len: 3 })),
+            Ok(_) => Err(String::from("Should have generated error when k > length of sequence"))
+        }
+    }
+
+    #[test]
+    fn test_kmers_non_ascii() {
+        assert_eq!(
+            kmers(b"ATC\xffGAT", 3).err(),
+            Some(KmerError::NonAsciiBase {
+                byte: 0xff,
+                position: 3
+            })
+        );
+        assert_eq!(
+            KmerError::NonAsciiBase {
+                byte: 0xff,
+                position: 3
+            }
+            .to_string(),
+            "Non-ASCII byte 0xff at position 3. Sequences must be ASCII"
+        );
+        assert_eq!(
+            check_bases(b"ATC\xffN", CaseHandling::Upper),
+            Err(KmerError::IncorrectBases {
+                bases: vec![(b'N', 1), (0xff, 1)]
+            })
+        );
+        assert_eq!(
+            check_bases(b"ATC\xffN", CaseHandling::Upper)
+                .unwrap_err()
+                .to_string(),
+            "Suspect base(s) found: N (1), \\xff (1). Use only ATCG bases"
+        );
+    }
+
+    #[test]
+    fn test_kmers_multibyte_character() {
+        // 'é' is the two bytes 0xc3 0xa9 at positions 2 and 3; windows of
+        // k = 2 start inside it, while k = 5 spans it whole
+        let sequence = "ACéGTA".as_bytes();
+        for k in [1, 2, 3, 5] {
+            assert_eq!(
+                kmers(sequence, k).err(),
+                Some(KmerError::NonAsciiBase {
+                    byte: 0xc3,
+                    position: 2
+                }),
+                "k = {}",
+                k
+            );
+        }
+        assert!(kmer_counter(sequence, 2, false).is_err());
+    }
+
+    #[test]
+    fn test_run_fasta_kmer_count_non_ascii_record() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("corrupt.fasta");
+        // files that are not UTF-8 are rejected by the reader, but multi-byte
+        // characters reach the counter
+        fs::write(&fasta_path, ">ok\nAAAA\n>corrupt\nAAA\u{e9}AAA\n")?;
+
+        // the corrupt record is reported and skipped as a whole, not counted
+        // without the windows covering the bad bytes
+        let output_path = dir.path().join("corrupt_kmer.txt");
+        run_fasta_kmer_count(&fasta_path, &CountOptions::new(3), &output_path)?;
+        assert_eq!(
+            table::read_kmer_table(&output_path)?,
+            [("AAA".to_owned(), 2)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_fasta_kmer_count_recordless_inputs() -> Result<()> {
+        let dir = tempdir()?;
+        for (name, contents) in &[("empty", ""), ("header_only", ">chr1\n")] {
+            let fasta_path = dir.path().join(format!("{}.fasta", name));
+            fs::write(&fasta_path, contents)?;
+
+            let output_path = dir.path().join(format!("{}_kmer.txt", name));
+            let options = CountOptions {
+                metadata_header: true,
+                ..CountOptions::new(3)
+            };
+            run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
+            assert!(table::read_kmer_table(&output_path)?.is_empty());
+            let metadata = RunMetadata::read(&output_path)?;
+            assert_eq!(metadata.get("records_processed"), Some("0"));
+
+            fs::remove_file(&output_path)?;
+            let options = CountOptions {
+                empty_inputs: EmptyInputs::Skip,
+                ..options
+            };
+            run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
+            assert!(!output_path.exists());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_fasta_kmer_count_empty_records() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("gaps.fasta");
+        fs::write(&fasta_path, ">empty1\n>chr1\nACGT\n>empty2\n\n>short\nAC\n")?;
+
+        let output_path = dir.path().join("gaps_kmer.txt");
+        let options = CountOptions {
+            metadata_header: true,
+            empty_inputs: EmptyInputs::Skip,
+            ..CountOptions::new(3)
+        };
+        run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
+        assert_eq!(
+            table::read_kmer_table(&output_path)?,
+            [("ACG".to_owned(), 1), ("CGT".to_owned(), 1)]
+        );
+        let metadata = RunMetadata::read(&output_path)?;
+        assert_eq!(metadata.get("records_processed"), Some("1"));
+        assert_eq!(metadata.get("records_skipped"), Some("3"));
+        assert_eq!(metadata.get("records_skipped_too_short"), Some("3"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_fasta_kmer_counter_orf_only() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("orf.fasta");
+        fs::write(&fasta_path, ">chr1\nCCCCATGAAATTTGGGTAACCCC\n")?;
+
+        let options = CountOptions {
+            orf_min_len: Some(15),
+            ..CountOptions::new(3)
+        };
+        let counter = fasta_kmer_counter(&fasta_path, &options)?;
+        let orf: HashMap<String, u64> = kmer_counter(b"ATGAAATTTGGGTAA", 3, false)?
+            .into_iter()
+            .map(|(kmer, count)| (kmer.to_owned(), count))
+            .collect();
+        assert_eq!(counter, orf);
+        assert!(!counter.contains_key("CCC"));
+
+        let options = CountOptions {
+            orf_min_len: Some(18),
+            ..options
+        };
+        assert!(fasta_kmer_counter(&fasta_path, &options)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_fasta_kmer_count_ndjson() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("seq.fasta");
+        fs::write(&fasta_path, ">seq\nATCGGATCG\n")?;
+
+        let output_path = dir.path().join("seq_kmer.ndjson");
+        let options = CountOptions {
+            format: OutputFormat::Ndjson,
+            metadata_header: true,
+            ..CountOptions::new(3)
+        };
+        run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
+
+        let text = fs::read_to_string(&output_path)?;
+        let rows = text
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<serde_json::Value>, _>>()?;
+        assert_eq!(rows.len(), 5);
+        assert_eq!(rows[0], serde_json::json!({"kmer": "ATC", "count": 2}));
+        assert_eq!(rows[4], serde_json::json!({"kmer": "GGA", "count": 1}));
+        assert!(text.ends_with("}\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_fasta_kmer_count_top_n() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("seq.fasta");
+        // AAA: 3, then AAC, ACG, CGT, GTT, TTT tied at 1
+        fs::write(&fasta_path, ">seq\nAAAAACGTTT\n")?;
+        let output_path = dir.path().join("seq_kmer.txt");
+        let top = |top_n| -> Result<Vec<(String, u64)>> {
+            let options = CountOptions {
+                top_n: Some(top_n),
+                ..CountOptions::new(3)
+            };
+            run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
+            table::read_kmer_table(&output_path)
+        };
+        // of the kmers tied at the cutoff, the alphabetically first are kept
+        assert_eq!(
+            top(3)?,
+            [
+                ("AAA".to_owned(), 3),
+                ("AAC".to_owned(), 1),
+                ("ACG".to_owned(), 1),
+            ]
+        );
+        assert_eq!(top(100)?.len(), 6);
+        assert_eq!(top(0)?, []);
+        assert_eq!(fs::read_to_string(&output_path)?, "kmer\tcount\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_fasta_kmer_count_min_count() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("seq.fasta");
+        // AAA: 3, ACG and CGT: 2, AAC, GTT, and TTT: 1
+        fs::write(&fasta_path, ">a\nAAAAACGTTT\n>b\nACGT\n")?;
+        let output_path = dir.path().join("seq_kmer.txt");
+        let at_least = |min_count| -> Result<Vec<(String, u64)>> {
+            let options = CountOptions {
+                min_count,
+                ..CountOptions::new(3)
+            };
+            run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
+            table::read_kmer_table(&output_path)
+        };
+        assert_eq!(at_least(1)?.len(), 6);
+        // kmers at exactly the threshold are kept, in table order
+        assert_eq!(
+            at_least(2)?,
+            [
+                ("AAA".to_owned(), 3),
+                ("ACG".to_owned(), 2),
+                ("CGT".to_owned(), 2),
+            ]
+        );
+        assert_eq!(at_least(3)?, [("AAA".to_owned(), 3)]);
+        assert_eq!(at_least(4)?, []);
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_fasta_kmer_count_revcomp_column() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("seq.fasta");
+        fs::write(&fasta_path, ">seq\nAAACGTN\n")?;
+        let output_path = dir.path().join("seq_kmer.txt");
+        let options = CountOptions {
+            revcomp_column: true,
+            metadata_header: true,
+            ..CountOptions::new(3)
+        };
+        let metadata = RunMetadata::for_count(&options, &fasta_path);
+        run_fasta_kmer_count_timed(
+            &fasta_path,
+            &options,
+            Some(&metadata),
+            Some(&output_path),
+            &mut PhaseTimings::disabled(),
+        )?;
+        let table = fs::read_to_string(&output_path)?;
+        assert!(table.contains("# revcomp_column: true\n"));
+        assert!(table.ends_with(
+            "kmer\trevcomp\tcount\n\
+             AAA\tTTT\t1\n\
+             AAC\tGTT\t1\n\
+             ACG\tCGT\t1\n\
+             CGT\tACG\t1\n\
+             GTN\tNAC\t1\n\
+             # rows: 5\n"
+        ));
+        assert_eq!(validate::check_table(&output_path)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_fasta_kmer_count_json() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("seq.fasta");
+        let sequence = testutil::random_sequence(500, 7);
+        fs::write(&fasta_path, [&b">seq\n"[..], &sequence, b"\n"].concat())?;
+
+        let output_path = dir.path().join("seq_kmer.json");
+        let options = CountOptions {
+            format: OutputFormat::Json,
+            metadata_header: true,
+            ..CountOptions::new(4)
+        };
+        run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
+
+        let text = fs::read_to_string(&output_path)?;
+        let rows: Vec<KmerRecord> = serde_json::from_str(&text)?;
+        let counts: HashMap<String, u64> = rows
+            .into_iter()
+            .map(|row| (row.seq.into_owned(), row.count))
+            .collect();
+        let expected: HashMap<String, u64> = kmer_counter(&sequence, 4, false)?
+            .into_iter()
+            .map(|(kmer, count)| (kmer.to_owned(), count))
+            .collect();
+        assert_eq!(counts, expected);
+        assert!(text.starts_with("[{\"kmer\":"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_fasta_kmer_count_mask_variants() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("ref.fasta");
+        fs::write(&fasta_path, ">chr1 first\nAAAACAAAA\n>chr2\nAAAACAAAA\n")?;
+        let mask_path = dir.path().join("snps.txt");
+        fs::write(&mask_path, "chr1\t5\n")?;
+
+        let output_path = dir.path().join("ref_kmer.txt");
+        let options = CountOptions {
+            variant_mask: Some(variants::VariantMask::read(&mask_path)?),
+            ..CountOptions::new(3)
+        };
+        let metadata = RunMetadata::for_count(&options, &fasta_path);
+        run_fasta_kmer_count_timed(
+            &fasta_path,
+            &options,
+            Some(&metadata),
+            Some(&output_path),
+            &mut PhaseTimings::disabled(),
+        )?;
+        // the windows of chr1 overlapping its C at position 5 are dropped
+        let table = fs::read_to_string(&output_path)?;
+        assert!(table.contains("# kmers_skipped_masked: 3\n"), "{}", table);
+        assert!(table.ends_with("\nAAA\t8\nAAC\t1\nACA\t1\nCAA\t1\n# rows: 4\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_fasta_kmer_count_deadline() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("large.fasta");
+        let mut fasta = String::new();
+        for seed in 1..=200 {
+            let seq = String::from_utf8(testutil::random_sequence(1000, seed))?;
+            fasta.push_str(&format!(">chr{}\n{}\n", seed, seq));
+        }
+        fs::write(&fasta_path, fasta)?;
+        let output_path = dir.path().join("large_kmer.txt");
+
+        // a deadline already passed stops counting after the first record
+        let options = CountOptions {
+            metadata_header: true,
+            deadline: Some(Instant::now()),
+            ..CountOptions::new(4)
+        };
+        run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
+        let metadata = RunMetadata::read(&output_path)?;
+        assert_eq!(metadata.get("partial"), Some("true"));
+        assert_eq!(metadata.get("records_processed"), Some("1"));
+        let total: u64 = table::read_kmer_table(&output_path)?
+            .iter()
+            .map(|(_, count)| count)
+            .sum();
+        assert_eq!(total, 1000 - 4 + 1);
+
+        let options = CountOptions {
+            deadline: Some(Instant::now() + std::time::Duration::from_secs(3600)),
+            ..options
+        };
+        run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
+        let metadata = RunMetadata::read(&output_path)?;
+        assert_eq!(metadata.get("partial"), Some("false"));
+        assert_eq!(metadata.get("records_processed"), Some("200"));
+
+        let options = CountOptions {
+            deadline: None,
+            ..options
+        };
+        run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
+        assert_eq!(RunMetadata::read(&output_path)?.get("partial"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_count_overflow() {
+        let mut counter: HashMap<&str, u64> = HashMap::new();
+        add_count(&mut counter, "ACG", u64::MAX - 1).unwrap();
+        add_count(&mut counter, "ACG", 1).unwrap();
+        assert_eq!(counter["ACG"], u64::MAX);
+
+        let err = add_count(&mut counter, "ACG", 1).unwrap_err();
+        assert_eq!(
+            err,
+            KmerError::CountOverflow {
+                kmer: "ACG".to_owned()
+            }
+        );
+        assert!(err.is_fatal());
+        assert_eq!(counter["ACG"], u64::MAX);
+    }
+
+    #[test]
+    fn test_record_tally() {
+        let mut tally = RecordTally::default();
+        tally.add("a", Ok(()));
+        tally.add("b", Err(KmerError::KmerLengthTooLong { k: 3, seq_len: 0 }));
+        tally.add("c", Err(KmerError::KmerLengthTooSmall { k: 0 }));
+        assert_eq!(
+            tally,
+            RecordTally {
+                processed: 1,
+                too_short: 1,
+                failed: 1
+            }
+        );
+        assert_eq!(tally.skipped(), 2);
+    }
+
+    #[test]
+    fn test_suspect_bases() {
+        let mut suspect = SuspectBases::default();
+        suspect.check("a", b"ACGT", CaseHandling::Sensitive);
+        suspect.check("b", b"acgtNN", CaseHandling::Sensitive);
+        suspect.check("c", b"ACnnn", CaseHandling::Sensitive);
+        suspect.check("d", b"acgt", CaseHandling::Upper);
+        assert_eq!(suspect.records, 2);
+        assert_eq!(
+            suspect.summary(),
+            "N (2), a (1), c (1), g (1), n (3), t (1)"
+        );
+    }
+
+    #[test]
+    fn test_run_fasta_kmer_count_soft_masked() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("masked.fasta");
+        let mut fasta = String::new();
+        for n in 0..1000 {
+            fasta.push_str(&format!(">scaffold{}\nACGTacgtaa\n", n));
+        }
+        fs::write(&fasta_path, fasta)?;
+        let output_path = dir.path().join("masked_kmer.txt");
+        let options = CountOptions {
+            metadata_header: true,
+            case: CaseHandling::Sensitive,
+            ..CountOptions::new(4)
+        };
+        run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
+        let metadata = RunMetadata::read(&output_path)?;
+        assert_eq!(metadata.get("records_with_suspect_bases"), Some("1000"));
+        assert_eq!(
+            metadata.get("suspect_bases"),
+            Some("a (3000), c (1000), g (1000), t (1000)")
+        );
+
+        // soft-masked bases are only suspect when case is significant
+        let options = CountOptions {
+            case: CaseHandling::Upper,
+            ..options
+        };
+        run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
+        let metadata = RunMetadata::read(&output_path)?;
+        assert_eq!(metadata.get("records_with_suspect_bases"), Some("0"));
+        assert_eq!(metadata.get("case"), Some("upper"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_record_ids() -> Result<()> {
+        let mut ids = RecordIds::default();
+        assert_eq!(ids.resolve("a", DuplicateIds::Suffix)?, "a");
+        assert_eq!(ids.resolve("a.1", DuplicateIds::Suffix)?, "a.1");
+        assert_eq!(ids.resolve("a", DuplicateIds::Suffix)?, "a.2");
+        assert_eq!(ids.resolve("a", DuplicateIds::Suffix)?, "a.3");
+        assert_eq!(ids.resolve("a", DuplicateIds::Merge)?, "a");
+        assert!(ids.resolve("a", DuplicateIds::Error).is_err());
+        assert_eq!(ids.duplicates, 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_fasta_kmer_count_duplicate_ids() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("chunks.fasta");
+        fs::write(&fasta_path, ">chr1\nACGT\n>chr2\nTTT\n>chr1\nACGA\n")?;
+        let output_path = dir.path().join("chunks_kmer.txt");
+
+        for policy in [DuplicateIds::Merge, DuplicateIds::Suffix] {
+            let options = CountOptions {
+                metadata_header: true,
+                duplicate_ids: policy,
+                ..CountOptions::new(3)
+            };
+            run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
+            assert_eq!(
+                table::read_kmer_table(&output_path)?,
+                [
+                    ("ACG".to_owned(), 2),
+                    ("CGA".to_owned(), 1),
+                    ("CGT".to_owned(), 1),
+                    ("TTT".to_owned(), 1)
+                ]
+            );
+            let metadata = RunMetadata::read(&output_path)?;
+            assert_eq!(metadata.get("records_processed"), Some("3"));
+            assert_eq!(metadata.get("records_duplicate_ids"), Some("1"));
+        }
+
+        fs::remove_file(&output_path)?;
+        let options = CountOptions {
+            duplicate_ids: DuplicateIds::Error,
+            ..CountOptions::new(3)
+        };
+        let err = run_fasta_kmer_count(&fasta_path, &options, &output_path).unwrap_err();
+        assert_eq!(err.to_string(), "Duplicate record id \"chr1\"");
+        assert!(!output_path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_fasta_kmer_count_creates_parent_directories() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("genome.fasta");
+        fs::write(&fasta_path, ">a\nACGT\n")?;
+
+        let output_path = dir.path().join("x/y/z/genome_kmer.txt");
+        run_fasta_kmer_count(&fasta_path, &CountOptions::new(3), &output_path)?;
+        assert_eq!(table::read_kmer_table(&output_path)?.len(), 2);
+
+        // a file where a directory should be is reported by path
+        let blocked = dir.path().join("genome.fasta/out/genome_kmer.txt");
+        let err = run_fasta_kmer_count(&fasta_path, &CountOptions::new(3), &blocked).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Could not create directory {:?}",
+                dir.path().join("genome.fasta/out")
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_fasta_kmer_count_split() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("genome.fasta");
+        fs::write(&fasta_path, ">a\nACGTTGCAAGGCTTAC\n")?;
+        let output_path = dir.path().join("genome_kmer.txt");
+
+        let full_path = dir.path().join("full_kmer.txt");
+        run_fasta_kmer_count(&fasta_path, &CountOptions::new(3), &full_path)?;
+        let split = KmerSplit {
+            train_fraction: 0.5,
+            seed: 3,
+        };
+        let options = CountOptions {
+            metadata_header: true,
+            split: Some(split),
+            ..CountOptions::new(3)
+        };
+        run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
+        assert!(!output_path.exists());
+
+        let mut combined = Vec::new();
+        for part in [Split::Train, Split::Test] {
+            let path = split::split_path(&output_path, part);
+            assert_eq!(RunMetadata::read(&path)?.get("split"), Some(part.name()));
+            let table = table::read_kmer_table(&path)?;
+            assert!(!table.is_empty());
+            assert!(table.iter().all(|(kmer, _)| split.assign(kmer) == part));
+            combined.extend(table);
+        }
+        combined.sort();
+        let mut full = table::read_kmer_table(&full_path)?;
+        full.sort();
+        assert_eq!(combined, full);
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_fasta_kmer_count_minimizer_shards() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("genome.fasta");
+        fs::write(&fasta_path, ">a\nACGTTGCAAGGCTTACGATCCAGTAGGCATTCAG\n")?;
+        let output_path = dir.path().join("genome_kmer.txt");
+
+        let full_path = dir.path().join("full_kmer.txt");
+        run_fasta_kmer_count(&fasta_path, &CountOptions::new(8), &full_path)?;
+        let shards = MinimizerShards { m: 4, shards: 3 };
+        let options = CountOptions {
+            metadata_header: true,
+            minimizer_shards: Some(shards),
+            ..CountOptions::new(8)
+        };
+        run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
+        assert!(!output_path.exists());
+
+        let mut combined = Vec::new();
+        let mut shard_of_minimizer = HashMap::new();
+        for (shard, path) in shards.shard_paths(&output_path).iter().enumerate() {
+            let expected = shard.to_string();
+            assert_eq!(
+                RunMetadata::read(path)?.get("shard"),
+                Some(expected.as_str())
+            );
+            let table = table::read_kmer_table(path)?;
+            for (kmer, _) in &table {
+                let minimizer = shards.minimizer(kmer).unwrap().0.to_owned();
+                // kmers sharing a minimizer land in the same shard
+                assert_eq!(*shard_of_minimizer.entry(minimizer).or_insert(shard), shard);
+            }
+            combined.extend(table);
+        }
+        assert!(shard_of_minimizer.len() < combined.len());
+        combined.sort();
+        let mut full = table::read_kmer_table(&full_path)?;
+        full.sort();
+        assert_eq!(combined, full);
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_fasta_kmer_count_dedup_records() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("copies.fasta");
+        fs::write(&fasta_path, ">a\nACGT\n>b\nTTT\n>a_copy\nACGT\n")?;
+        let output_path = dir.path().join("copies_kmer.txt");
+
+        let options = CountOptions {
+            metadata_header: true,
+            dedup_records: true,
+            ..CountOptions::new(3)
+        };
+        run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
+        assert_eq!(
+            table::read_kmer_table(&output_path)?,
+            [
+                ("ACG".to_owned(), 1),
+                ("CGT".to_owned(), 1),
+                ("TTT".to_owned(), 1)
+            ]
+        );
+        let metadata = RunMetadata::read(&output_path)?;
+        assert_eq!(metadata.get("records_processed"), Some("2"));
+        assert_eq!(metadata.get("records_collapsed"), Some("1"));
+        assert_eq!(metadata.get("dedup_records"), Some("exact"));
+
+        let options = CountOptions {
+            metadata_header: true,
+            ..CountOptions::new(3)
+        };
+        run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
+        assert_eq!(
+            table::read_kmer_table(&output_path)?[0],
+            ("ACG".to_owned(), 2)
+        );
+        assert_eq!(
+            RunMetadata::read(&output_path)?.get("records_collapsed"),
+            None
+        );
+        Ok(())
+    }
+
+    /// test helper to convert tuple vector to KmerCount
+    fn kmer_count_from_tuples<'a>(item: Vec<(&'a str, u64)>) -> KmerCount<'a> {
+        item.into_iter()
+            .map(|x| KmerRecord {
+                seq: x.0.into(),
+                count: x.1,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_count_kmers() {
+        let sequence = b"ATCGGATCG";
+        let expected: KmerCount = kmer_count_from_tuples(vec![
+            ("ATC", 2),
+            ("TCG", 2),
+            ("CGG", 1),
+            ("GAT", 1),
+            ("GGA", 1),
+        ]);
+        assert_eq!(count_kmers(sequence, 3, false).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_count_kmers_skip_ambiguous() {
+        // the N run spans windows 2 to 7; the flanks are counted
+        let sequence = b"ATCGNNNNGATC";
+        let expected: KmerCount = kmer_count_from_tuples(vec![("ATC", 2), ("GAT", 1), ("TCG", 1)]);
+        assert_eq!(count_kmers(sequence, 3, true).unwrap(), expected);
+        assert_eq!(ambiguous_windows(sequence, 3), 6);
+        assert_eq!(count_kmers(sequence, 3, false).unwrap().len(), 8);
+        assert!(count_kmers(b"NNNNN", 3, true).unwrap().is_empty());
+        assert_eq!(ambiguous_windows(b"NNNNN", 3), 3);
+        assert_eq!(ambiguous_windows(b"ACGTR", 3), 1);
+        assert_eq!(ambiguous_windows(b"AC", 3), 0);
+    }
+
+    #[test]
+    fn test_count_sequence_case() -> Result<()> {
+        let count = |case| -> Result<Vec<(String, u64)>> {
+            let options = CountOptions {
+                case,
+                ..CountOptions::new(3)
+            };
+            let mut timings = PhaseTimings::disabled();
+            let kmer_count = count_sequence(b"ATGatg", &options, &mut timings)?;
+            Ok(kmer_count
+                .into_iter()
+                .map(|kmer| (kmer.seq.into_owned(), kmer.count))
+                .collect())
+        };
+        let rows = |rows: &[(&str, u64)]| -> Vec<(String, u64)> {
+            rows.iter().map(|&(k, c)| (k.to_owned(), c)).collect()
+        };
+        assert_eq!(
+            count(CaseHandling::Upper)?,
+            rows(&[("ATG", 2), ("GAT", 1), ("TGA", 1)])
+        );
+        assert_eq!(count(CaseHandling::SkipMasked)?, rows(&[("ATG", 1)]));
+        assert_eq!(count(CaseHandling::Sensitive)?.len(), 4);
+
+        // counting into a table agrees
+        for (case, atg) in [(CaseHandling::Upper, 2), (CaseHandling::SkipMasked, 1)] {
+            let options = CountOptions {
+                case,
+                ..CountOptions::new(3)
+            };
+            let mut counter = HashMap::new();
+            let mut timings = PhaseTimings::disabled();
+            add_sequence_counts(&mut counter, b"ATGatg", &options, &mut timings)?;
+            assert_eq!(counter["ATG"], atg);
+            assert!(!counter.contains_key("atg"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_kmer_extremes() {
+        let extremes = kmer_extremes(&count_kmers(b"ATCGGATCG", 3, false).unwrap()).unwrap();
+        assert_eq!(extremes.max, ("ATC".to_owned(), 2));
+        assert_eq!(extremes.min, ("GGA".to_owned(), 1));
+        assert_eq!(kmer_extremes(&Vec::new()), None);
+    }
+
+    #[test]
+    fn test_reverse_complement() {
+        assert_eq!(reverse_complement(b"ATCGG"), b"CCGAT");
+        assert_eq!(reverse_complement(b"aNcg"), b"cgNt");
+        assert_eq!(reverse_complement(b""), b"");
+    }
+
+    #[test]
+    fn test_run_fasta_kmer_count_library_type() -> Result<()> {
+        // mate 2 is the reverse complement of mate 1, as from an fr library
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("pair.fasta");
+        let mut fasta = File::create(&fasta_path)?;
+        writeln!(fasta, ">frag/1\nAAACG\n>frag/2\nCGTTT")?;
+
+        let output_path = dir.path().join("pair_kmer.txt");
+        let options = CountOptions {
+            library_type: LibraryType::Fr,
+            ..CountOptions::new(3)
+        };
+        run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
+
+        // oriented onto the transcribed strand, both mates yield the same kmers
+        let kmer_set = |path: &Path| -> Result<Vec<String>> {
+            let mut kmers: Vec<String> = table::read_kmer_table(path)?
+                .into_iter()
+                .map(|(kmer, _)| kmer)
+                .collect();
+            kmers.sort();
+            Ok(kmers)
+        };
+        assert_eq!(kmer_set(&output_path)?, ["AAA", "AAC", "ACG"]);
+
+        let options = CountOptions {
+            library_type: LibraryType::Rf,
+            ..CountOptions::new(3)
+        };
+        run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
+        assert_eq!(kmer_set(&output_path)?, ["CGT", "GTT", "TTT"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_log_tf() {
+        assert_eq!(log_tf(0), 0.0);
+        assert_eq!(log_tf(1), 2f64.ln());
+        assert!((log_tf(99) - 100f64.ln()).abs() < 1e-12);
+    }
 
-#[derive(Eq, PartialEq, Debug)]
-struct KmerRecord<'b> {
-    seq: &'b str,
-    count: u64,
-}
+    #[test]
+    fn test_is_up_to_date() -> Result<()> {
+        let dir = tempdir()?;
+        let input = dir.path().join("a.fasta");
+        let output = dir.path().join("a_kmer.txt");
+        fs::write(&input, ">a\nACGT\n")?;
+        assert!(!is_up_to_date(&input, &output)?);
 
-/// Aggregate count of all Kmers
-type KmerCount<'a> = Vec<KmerRecord<'a>>;
+        fs::write(&output, "kmer\tcount\n")?;
+        let now = std::time::SystemTime::now();
+        let hour = std::time::Duration::from_secs(3600);
+        File::options().write(true).open(&input)?.set_modified(now - hour)?;
+        assert!(is_up_to_date(&input, &output)?);
 
-/// Save counts for length `k` kmers from the fasta file at `fasta_path` at `output_path`
-pub fn run_fasta_kmer_count(fasta_path: &PathBuf, k: usize, output_path: &PathBuf) -> Result<()> {
-    let fasta_file = File::open(fasta_path)?;
-    let reader = fasta::Reader::new(fasta_file);
+        File::options().write(true).open(&input)?.set_modified(now + hour)?;
+        assert!(!is_up_to_date(&input, &output)?);
+        Ok(())
+    }
 
-    for record in reader.records() {
-        let record = record?;
+    #[test]
+    fn test_run_fasta_kmer_count_multiple_records() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("genome.fasta");
+        let mut fasta = File::create(&fasta_path)?;
+        writeln!(fasta, ">chr1\nAAAAC\n>chr2\nGGGGA")?;
 
-        if let Err(err) = check_bases(record.seq()) {
-            println!("WARNING: {}", err);
-        }
+        let output_path = dir.path().join("genome_kmer.txt");
+        run_fasta_kmer_count(&fasta_path, &CountOptions::new(3), &output_path)?;
 
-        match count_kmers(record.seq(), k) {
-            Ok(kmer_count) => save_kmer_count(kmer_count, output_path)?,
-            Err(err) => eprintln!("ERROR: {}", err),
-        }
+        // kmers of both records are saved, not only those of the last record
+        assert_eq!(
+            fs::read_to_string(&output_path)?,
+            "kmer\tcount\nAAA\t2\nGGG\t2\nAAC\t1\nGGA\t1\n"
+        );
+        Ok(())
     }
-    Ok(())
-}
 
-/// Return frequency of all kmers of length `k` in `sequence`, ordered from most to least abundant
-fn count_kmers(sequence: &[u8], k: usize) -> Result<KmerCount, KmerError> {
-    // calculate kmer frequencies
-    let mut counter: HashMap<&str, u64> = HashMap::new();
-    for kmer in kmers(sequence, k)? {
-        *counter.entry(kmer).or_insert(0) += 1;
+    #[test]
+    fn test_run_fasta_kmer_count_sums_records() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("contigs.fasta");
+        fs::write(&fasta_path, ">contig1\nAACGT\n>contig2\nCGTAA\n")?;
+
+        let output_path = dir.path().join("contigs_kmer.txt");
+        run_fasta_kmer_count(&fasta_path, &CountOptions::new(3), &output_path)?;
+
+        // CGT occurs once in each contig and is counted twice
+        let table: HashMap<String, u64> =
+            table::read_kmer_table(&output_path)?.into_iter().collect();
+        assert_eq!(table.len(), 5);
+        assert_eq!(table["CGT"], 2);
+        assert_eq!(table["AAC"], 1);
+        assert_eq!(table["TAA"], 1);
+        Ok(())
     }
 
-    // order from most to least abundant
-    let mut ordered: Vec<_> = counter
-        .into_iter()
-        .map(|(k, v)| KmerRecord { seq: k, count: v })
-        .collect();
+    #[test]
+    fn test_run_fasta_kmer_count_canonical_gc_window() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("probe.fasta");
+        let mut fasta = File::create(&fasta_path)?;
+        writeln!(fasta, ">a\nAATTGCGC")?;
 
-    // first, sort kmers alphabetically so order among equal counts is deterministic
-    // second, sort by descending count
-    //
-    // n.b. this could be implemented by the Ord/PartialOrd traits on KmerRecord,
-    // but for this simple program, putting the sorting logic here is clearer and
-    // results in less boilerplate.
-    ordered.sort_by(|a, b| a.seq.cmp(b.seq));
-    ordered.sort_by(|a, b| a.count.cmp(&b.count).reverse());
-    Ok(ordered)
-}
+        let output_path = dir.path().join("probe_kmer.txt");
+        let options = CountOptions {
+            canonical: true,
+            gc_min: Some(0.5),
+            gc_max: Some(1.0),
+            ..CountOptions::new(4)
+        };
+        run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
 
-/// Return all subsequences of length k from the given sequence
-///
-/// `sequence` must be an ASCII string, which is sufficient for sequencing data.
-/// Multi-byte UTF-8 characters are not handled correctly.
-fn kmers(sequence: &[u8], k: usize) -> Result<impl Iterator<Item = &str>, KmerError> {
-    if k <= 0 {
-        return Err(KmerError::KmerLengthTooSmall { k: k });
+        // AATT and ATTG are too AT-rich; TTGC folds into GCAA, TGCG into CGCA
+        assert_eq!(
+            fs::read_to_string(&output_path)?,
+            "kmer\tcount\nCGCA\t1\nGCAA\t1\nGCGC\t1\n"
+        );
+        Ok(())
     }
 
-    if sequence.len() < k {
-        return Err(KmerError::KmerLengthTooLong {
-            k: k,
-            seq_len: sequence.len(),
-        });
+    #[test]
+    fn test_fasta_kmer_counter_canonical() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("seq.fasta");
+        // N has no complement and is kept as is, as check_bases only warns
+        fs::write(&fasta_path, ">a\nATCG\n>b\nGANT\n")?;
+        let options = CountOptions {
+            canonical: true,
+            ..CountOptions::new(2)
+        };
+        let counter = fasta_kmer_counter(&fasta_path, &options)?;
+
+        // AT and CG are their own reverse complements; TC folds into GA, and
+        // NT into AN
+        let expected: HashMap<String, u64> = [("AT", 1), ("CG", 1), ("GA", 2), ("AN", 2)]
+            .iter()
+            .map(|&(kmer, count)| (kmer.to_owned(), count))
+            .collect();
+        assert_eq!(counter, expected);
+        Ok(())
     }
 
-    Ok(sequence.windows(k).flat_map(|x| str::from_utf8(x))) // from string, so utf-8 cast will always succeed
-}
+    #[test]
+    fn test_canonical_counts_ignore_strand() -> Result<()> {
+        let dir = tempdir()?;
+        let sequence = testutil::random_sequence(500, 9);
+        let forward = dir.path().join("forward.fasta");
+        let reverse = dir.path().join("reverse.fasta");
+        fs::write(&forward, [b">f\n", &sequence[..], b"\n"].concat())?;
+        fs::write(
+            &reverse,
+            [b">r\n", &reverse_complement(&sequence)[..], b"\n"].concat(),
+        )?;
 
-/// Check that all bases in `seq` are A, T, C, or G.
-fn check_bases(seq: &[u8]) -> Result<(), KmerError> {
-    let mut bad_bases = Vec::new();
-    for base in seq {
-        if !b"ATCG".contains(base) {
-            bad_bases.push(*base);
+        for &canonical_rule in &[CanonicalRule::Lex, CanonicalRule::Hash] {
+            let options = CountOptions {
+                canonical: true,
+                canonical_rule,
+                ..CountOptions::new(5)
+            };
+            let counts = fasta_kmer_counter(&forward, &options)?;
+            assert_eq!(counts, fasta_kmer_counter(&reverse, &options)?);
+            // only one kmer of each pair is a key
+            assert!(counts.keys().all(|kmer| kmer == &options.count_key(kmer)));
         }
+        // without --canonical the strands differ
+        let options = CountOptions::new(5);
+        assert_ne!(
+            fasta_kmer_counter(&forward, &options)?,
+            fasta_kmer_counter(&reverse, &options)?
+        );
+        Ok(())
     }
 
-    if bad_bases.is_empty() {
+    #[test]
+    fn test_canonical_rules_can_disagree() -> Result<()> {
+        assert_eq!(CanonicalRule::Lex.canonical(b"TTT"), b"AAA");
+        assert_eq!(CanonicalRule::Hash.canonical(b"AAA"), b"TTT");
+        assert_eq!(CanonicalRule::Hash.canonical(b"GTT"), b"AAC");
+        assert_eq!(CanonicalRule::Hash.canonical(b"ACGT"), b"ACGT");
+
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("seq.fasta");
+        fs::write(&fasta_path, ">a\nAAACTTT\n")?;
+        let output_path = dir.path().join("seq_kmer.txt");
+        let table = |canonical_rule| -> Result<String> {
+            let options = CountOptions {
+                canonical: true,
+                canonical_rule,
+                ..CountOptions::new(3)
+            };
+            run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
+            Ok(fs::read_to_string(&output_path)?)
+        };
+        assert_eq!(
+            table(CanonicalRule::Lex)?,
+            "kmer\tcount\nAAA\t2\nAAC\t1\nAAG\t1\nACT\t1\n"
+        );
+        assert_eq!(
+            table(CanonicalRule::Hash)?,
+            "kmer\tcount\nTTT\t2\nAAC\t1\nAAG\t1\nAGT\t1\n"
+        );
         Ok(())
-    } else {
-        let bases: String = String::from_utf8(bad_bases).unwrap();
-        Err(KmerError::IncorrectBases { bases: bases })
     }
-}
 
-/// Derive an output file path from the suffix of the input path
-pub fn output_path_from_input(
-    input_path: &PathBuf,
-    input_root: &PathBuf,
-    output_root: &PathBuf,
-) -> Result<PathBuf> {
-    let path_stub = input_path.strip_prefix(&input_root)?;
-    let mut output_path = output_root.join(path_stub);
-    output_path.set_file_name(format!(
-        "{}_kmer.txt",
-        input_path.file_stem().unwrap().to_str().unwrap()
-    ));
-    Ok(output_path)
-}
+    #[test]
+    fn test_run_fasta_kmer_count_log_tf() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("seq.fasta");
+        let mut fasta = File::create(&fasta_path)?;
+        writeln!(fasta, ">a\nATCGGATCG")?;
 
-/// Find all files in directory `dir` with one of the given `extensions`
-pub fn fs_find_files_with_extensions<T>(dir: &Path, extensions: &[T]) -> Result<Vec<PathBuf>>
-where
-    T: AsRef<str>,
-{
-    fn is_file_type<T: AsRef<str>>(p: &PathBuf, exts: &[T]) -> bool {
-        p.is_file()
-            && p.extension()
-                .map(|s| exts.iter().any(|e| s == e.as_ref()))
-                .unwrap_or(false)
-    }
+        let output_path = dir.path().join("seq_kmer.txt");
+        let options = CountOptions {
+            log_tf: true,
+            ..CountOptions::new(3)
+        };
+        run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
 
-    let mut files = Vec::new();
-    for entry in dir.read_dir()? {
-        let entry = entry?;
-        let path = fs::canonicalize(entry.path())?;
-        if is_file_type(&path, &extensions) {
-            files.push(path);
-        }
+        // ln(3) for the two kmers seen twice, ln(2) for the rest
+        assert_eq!(
+            fs::read_to_string(&output_path)?,
+            "kmer\tlog_tf\nATC\t1.098612\nTCG\t1.098612\n\
+             CGG\t0.693147\nGAT\t0.693147\nGGA\t0.693147\n"
+        );
+        Ok(())
     }
-    Ok(files)
-}
-
-/// Save kmer count to `output_path`
-fn save_kmer_count(kmer_count: KmerCount, output_path: &PathBuf) -> Result<()> {
-    let mut file = File::create(output_path)?;
 
-    writeln!(file, "kmer\tcount")?;
-    for kmer in kmer_count {
-        writeln!(file, "{}\t{}", kmer.seq, kmer.count)?;
+    #[test]
+    fn test_output_path_from_input() {
+        let input_path = PathBuf::from("/a/input/dir/path.txt");
+        let input_root = PathBuf::from("/a/input");
+        let output_root = PathBuf::from("/output");
+        assert_eq!(
+            "/output/dir/path_kmer.txt",
+            output_path_from_input(&input_path, &input_root, &output_root)
+                .unwrap()
+                .to_str()
+                .unwrap()
+        );
     }
-    Ok(())
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tempfile::tempdir;
+    #[test]
+    fn test_run_fasta_kmer_count_skip_ambiguous() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("gaps.fasta");
+        fs::write(&fasta_path, ">gap\nATCGNNNNGATC\n>gaps_only\nNNNNNNNN\n")?;
+        let options = CountOptions {
+            skip_ambiguous: true,
+            metadata_header: true,
+            ..CountOptions::new(3)
+        };
+        let output_path = dir.path().join("gaps_kmer.txt");
+        let metadata = RunMetadata::new().with("k", 3);
+        run_fasta_kmer_count_timed(
+            &fasta_path,
+            &options,
+            Some(&metadata),
+            Some(&output_path),
+            &mut PhaseTimings::disabled(),
+        )?;
+        let expected = [
+            ("ATC".to_owned(), 2),
+            ("GAT".to_owned(), 1),
+            ("TCG".to_owned(), 1),
+        ];
+        assert_eq!(table::read_kmer_table(&output_path)?, expected);
+        let header = RunMetadata::read(&output_path)?;
+        assert_eq!(header.get("kmers_skipped_ambiguous"), Some("12"));
+        assert_eq!(header.get("records_processed"), Some("2"));
 
-    // test helper to run the kmers iterator
-    fn kmers_vec(sequence: &[u8], k: usize) -> Vec<&str> {
-        kmers(sequence, k).unwrap().collect()
+        // windows lost to a masked variant are not also reported as ambiguous:
+        // of the gap's 6 ambiguous windows, only CGN, NNG, and NGA are left
+        let mask_path = dir.path().join("variants.txt");
+        fs::write(&mask_path, "gap\t6\n")?;
+        let masked = CountOptions {
+            variant_mask: Some(variants::VariantMask::read(&mask_path)?),
+            ..options.clone()
+        };
+        run_fasta_kmer_count_timed(
+            &fasta_path,
+            &masked,
+            Some(&metadata),
+            Some(&output_path),
+            &mut PhaseTimings::disabled(),
+        )?;
+        let header = RunMetadata::read(&output_path)?;
+        assert_eq!(header.get("kmers_skipped_ambiguous"), Some("9"));
+        assert_eq!(header.get("kmers_skipped_masked"), Some("3"));
+
+        // a file of gaps alone gives an empty table
+        fs::write(&fasta_path, ">gaps_only\nNNNNNNNN\n")?;
+        run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
+        assert!(table::read_kmer_table(&output_path)?.is_empty());
+        Ok(())
     }
 
     #[test]
-    /// kmers_vec simplifies comparisons
-    fn test_kmers_vec_helper_demo() -> Result<(), KmerError> {
+    fn test_run_fasta_kmer_count_fastq() -> Result<()> {
+        let dir = tempdir()?;
+        let fastq = "@read1 lane 1\nATCGGATCG\n+\nIIIIIIIII\n@read2\nGGATCCA\n+read2\n#######\n@short\nAC\n+\nII\n";
+        let fastq_path = dir.path().join("reads.FQ");
+        fs::write(&fastq_path, fastq)?;
+        let fasta_path = dir.path().join("reads.fasta");
+        fs::write(
+            &fasta_path,
+            ">read1 lane 1\nATCGGATCG\n>read2\nGGATCCA\n>short\nAC\n",
+        )?;
+        assert!(is_fastq(&fastq_path));
+        assert!(is_fastq(Path::new("reads.fastq.gz")));
+        assert!(!is_fastq(&fasta_path));
+
+        let records: Vec<fasta::Record> = open_fasta(&fastq_path)?
+            .records()
+            .collect::<Result<_, _>>()?;
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].id(), "read1");
+        assert_eq!(records[0].desc(), Some("lane 1"));
+        assert_eq!(records[1].seq(), b"GGATCCA");
+
+        let options = CountOptions::new(3);
+        let fastq_output = dir.path().join("fastq_kmer.txt");
+        run_fasta_kmer_count(&fastq_path, &options, &fastq_output)?;
+        let fasta_output = dir.path().join("fasta_kmer.txt");
+        run_fasta_kmer_count(&fasta_path, &options, &fasta_output)?;
         assert_eq!(
-            kmers(b"ABCD", 2)?.collect::<Vec<_>>(),
-            kmers_vec(b"ABCD", 2)
+            table::read_kmer_table(&fastq_output)?,
+            table::read_kmer_table(&fasta_output)?
         );
+
+        // a read missing its quality line fails the input
+        fs::write(&fastq_path, "@read1\nATCG\n+\n")?;
+        assert!(run_fasta_kmer_count(&fastq_path, &options, &fastq_output).is_err());
         Ok(())
     }
 
     #[test]
-    fn test_kmers_fn_basic() {
-        assert_eq!(kmers_vec(b"ABCD", 1), ["A", "B", "C", "D"]);
-        assert_eq!(kmers_vec(b"ABCD", 2), ["AB", "BC", "CD"]);
-        assert_eq!(kmers_vec(b"ABCD", 3), ["ABC", "BCD"]);
-        assert_eq!(kmers_vec(b"ABCD", 4), ["ABCD"]);
+    fn test_run_fasta_kmer_count_gzipped() -> Result<()> {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+
+        let dir = tempdir()?;
+        let fasta = ">a\nATCGGATCG\n>b\nGGATCCA\n";
+        let plain_path = dir.path().join("plain.fasta");
+        fs::write(&plain_path, fasta)?;
+        // two gzip members, as bgzip writes, each holding one record
+        let gzipped_path = dir.path().join("gzipped.fasta.gz");
+        let mut gzipped = Vec::new();
+        for record in [">a\nATCGGATCG\n", ">b\nGGATCCA\n"] {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(record.as_bytes())?;
+            gzipped.extend(encoder.finish()?);
+        }
+        fs::write(&gzipped_path, gzipped)?;
+
+        let found = fs_find_files_with_extensions(dir.path(), &["fasta"])?;
+        assert_eq!(found.len(), 2);
+        let output_root = dir.path().join("out");
+        let output_path =
+            output_path_from_input(&found[0], found[0].parent().unwrap(), &output_root)?;
+        assert_eq!(output_path, output_root.join("gzipped_kmer.txt"));
+
+        let options = CountOptions::new(3);
+        let plain_output = dir.path().join("plain_kmer.txt");
+        run_fasta_kmer_count(&plain_path, &options, &plain_output)?;
+        run_fasta_kmer_count(&gzipped_path, &options, &output_path)?;
+        assert_eq!(
+            fs::read_to_string(&output_path)?,
+            fs::read_to_string(&plain_output)?
+        );
+        Ok(())
     }
 
     #[test]
-    fn test_kmer_0() -> Result<(), String>{
-        match kmers(b"ABCD", 0) {
-            Err(e) => Ok(assert_eq!(e, KmerError::KmerLengthTooSmall { k: 0 })),
-            Ok(_) => Err(String::from("Should have generated error on k = 0")),
-        }
+    fn test_output_path_unusual_names() {
+        let root = Path::new("/in");
+        let output = |name: &str| output_path_from_input(&root.join(name), root, Path::new("/out"));
+
+        // a leading dot does not start an extension
+        assert_eq!(output(".fasta").unwrap(), Path::new("/out/.fasta_kmer.txt"));
+        assert_eq!(
+            output(".hidden.fa").unwrap(),
+            Path::new("/out/.hidden_kmer.txt")
+        );
+        assert_eq!(output("noext").unwrap(), Path::new("/out/noext_kmer.txt"));
+        assert_eq!(output("a.b.fasta").unwrap(), Path::new("/out/a.b_kmer.txt"));
+
+        let err = output_path_from_input(Path::new("/in/.."), root, Path::new("/out")).unwrap_err();
+        assert!(err.to_string().contains("has no file name"));
+        let err = output_path_from_input(Path::new("/elsewhere/a.fa"), root, Path::new("/out"))
+            .unwrap_err();
+        assert!(err.to_string().contains("\"/elsewhere/a.fa\""));
     }
 
     #[test]
-    fn test_kmer_empty_string() -> Result<(), String> {
-        match kmers(b"", 10) {
-            Err(err) => Ok(assert_eq!(err, KmerError::KmerLengthTooLong { k: 10, seq_len: 0 })),
-            Ok(_) => Err(String::from("Should have generated error on empty string"))
-        }
+    fn test_prefix_key() {
+        assert_eq!(
+            prefix_key(Prefix::VerbatimDisk(b'C')),
+            prefix_key(Prefix::Disk(b'c'))
+        );
+        assert_ne!(
+            prefix_key(Prefix::Disk(b'C')),
+            prefix_key(Prefix::Disk(b'D'))
+        );
+        let (server, share) = (OsStr::new("server"), OsStr::new("share"));
+        assert_eq!(
+            prefix_key(Prefix::VerbatimUNC(server, share)),
+            prefix_key(Prefix::UNC(server, share))
+        );
+        assert_ne!(
+            prefix_key(Prefix::UNC(server, share)),
+            prefix_key(Prefix::Disk(b'C'))
+        );
     }
 
     #[test]
-    fn test_kmer_k_too_big() -> Result<(), String> {
-        match kmers(b"ABC", 10) {
-            Err(err) => Ok(assert_eq!(err, KmerError::KmerLengthTooLong { k: 10, seq_len: 3 })),
-            Ok(_) => Err(String::from("Should have generated error when k > length of sequence"))
-        }
+    fn test_relative_to() {
+        let relative = |path: &str, root: &str| relative_to(Path::new(path), Path::new(root));
+        assert_eq!(
+            relative("/a/input/dir/x.fa", "/a/input"),
+            Some("dir/x.fa".into())
+        );
+        assert_eq!(
+            relative("/a//input/./dir/x.fa", "/a/input/"),
+            Some("dir/x.fa".into())
+        );
+        assert_eq!(relative("/a/input", "/a/input"), Some(PathBuf::new()));
+        assert_eq!(relative("/a/inputs/x.fa", "/a/input"), None);
+        assert_eq!(relative("/a", "/a/input"), None);
+        assert_eq!(relative("a/x.fa", "/a"), None);
     }
 
-    /// test helper to convert tuple vector to KmerCount
-    fn kmer_count_from_tuples<'a>(item: Vec<(&'a str, u64)>) -> KmerCount<'a> {
-        item.into_iter()
-            .map(|x| KmerRecord {
-                seq: x.0,
-                count: x.1,
-            })
-            .collect()
+    #[test]
+    fn test_output_path_components() {
+        let output = output_path_from_input(
+            Path::new("/in/dir/x.fa"),
+            Path::new("/in/"),
+            Path::new("out//nested/"),
+        )
+        .unwrap();
+        assert_eq!(output.to_str(), Some("out/nested/dir/x_kmer.txt"));
     }
 
+    #[cfg(windows)]
     #[test]
-    fn test_count_kmers() {
-        let sequence = b"ATCGGATCG";
-        let expected: KmerCount = kmer_count_from_tuples(vec![
-            ("ATC", 2),
-            ("TCG", 2),
-            ("CGG", 1),
-            ("GAT", 1),
-            ("GGA", 1),
-        ]);
-        assert_eq!(count_kmers(sequence, 3).unwrap(), expected);
+    fn test_output_path_windows() {
+        // as returned by fs::canonicalize, against roots as typed
+        let input_path = Path::new(r"\\?\C:\data\run1\sample.fa");
+        let output =
+            output_path_from_input(input_path, Path::new(r"c:\data"), Path::new("D:/out")).unwrap();
+        assert_eq!(output.to_str(), Some(r"D:\out\run1\sample_kmer.txt"));
+
+        assert_eq!(
+            relative_to(Path::new(r"C:\data\x.fa"), Path::new(r"\\?\C:\data")),
+            Some(PathBuf::from("x.fa"))
+        );
+        assert_eq!(
+            relative_to(
+                Path::new(r"\\?\UNC\server\share\x.fa"),
+                Path::new(r"\\server\share")
+            ),
+            Some(PathBuf::from("x.fa"))
+        );
+        assert_eq!(
+            relative_to(Path::new(r"D:\data\x.fa"), Path::new(r"C:\data")),
+            None
+        );
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_output_path_from_input() {
-        let input_path = PathBuf::from("/a/input/dir/path.txt");
-        let input_root = PathBuf::from("/a/input");
-        let output_root = PathBuf::from("/output");
+    fn test_output_path_non_utf8() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let name = OsStr::from_bytes(b"sample\xff.fasta");
+        let output = output_path_from_input(
+            &Path::new("/in").join(name),
+            Path::new("/in"),
+            Path::new("/out"),
+        )
+        .unwrap();
         assert_eq!(
-            "/output/dir/path_kmer.txt",
-            output_path_from_input(&input_path, &input_root, &output_root)
-                .unwrap()
-                .to_str()
-                .unwrap()
+            output.file_name().unwrap().as_bytes(),
+            b"sample\xff_kmer.txt"
         );
     }
 
+    #[test]
+    fn test_gc_content() {
+        assert_eq!(gc_content(b"ATCG"), 0.5);
+        assert_eq!(gc_content(b"gcGC"), 1.0);
+        assert_eq!(gc_content(b"ATNNNNGC"), 0.5);
+        assert_eq!(gc_content(b"NNN"), 0.0);
+    }
+
     #[test]
     fn test_check_bases_success() {
-        check_bases(b"ATCGATGCAAA").unwrap();
+        check_bases(b"ATCGATGCAAA", CaseHandling::Sensitive).unwrap();
+        check_bases(b"ATCGatgcaaa", CaseHandling::Upper).unwrap();
     }
 
     #[test]
     fn test_check_bases_bad_base() {
-        assert_eq!(check_bases(b"ATCNTTZ").unwrap_err(),
-        KmerError::IncorrectBases { bases: String::from("NZ") });
+        assert_eq!(check_bases(b"ATCNTTZa", CaseHandling::SkipMasked).unwrap_err(),
+        KmerError::IncorrectBases { bases: vec![(b'N', 1), (b'Z', 1)] });
+    }
+
+    #[test]
+    fn test_is_previous_output() -> Result<()> {
+        let dir = tempdir()?;
+        let table = dir.path().join("a_kmer.txt");
+        fs::write(&table, "kmer\tcount\nACG\t1\n")?;
+        let fasta = dir.path().join("b_kmer.txt");
+        fs::write(&fasta, ">b\nACGT\n")?;
+        let other = dir.path().join("c.txt");
+        fs::write(&other, "kmer\tcount\n")?;
+
+        assert!(is_previous_output(&table)?);
+        assert!(!is_previous_output(&fasta)?);
+        assert!(!is_previous_output(&other)?);
+        Ok(())
     }
 
     #[test]
@@ -273,7 +3537,7 @@ mod tests {
         let missing_file_path = dir.path().join("bar.baz");
         File::create(&missing_file_path)?;
 
-        let files = fs_find_files_with_extensions(dir.path(), &vec!["rs", "txt"])?;
+        let files = fs_find_files_with_extensions(dir.path(), &["rs", "txt"])?;
 
         println!("{:?}", files);
         println!("{:?}", found_file_path);
@@ -284,9 +3548,146 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_find_files_sorted() -> Result<()> {
+        let dir = tempdir()?;
+        for name in &["m.fasta", "z.fasta", "a.fasta", "k.fasta"] {
+            File::create(dir.path().join(name))?;
+        }
+        let files = fs_find_files_with_extensions(dir.path(), &["fasta"])?;
+        let names: Vec<_> = files.iter().map(|path| path.file_name().unwrap()).collect();
+        assert_eq!(names, ["a.fasta", "k.fasta", "m.fasta", "z.fasta"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_extension() {
+        assert_eq!(parse_extension("fa"), Ok("fa".to_owned()));
+        assert_eq!(parse_extension(" fasta "), Ok("fasta".to_owned()));
+        assert_eq!(parse_extension(".fna"), Ok("fna".to_owned()));
+        assert_eq!(parse_extension(" .fa"), Ok("fa".to_owned()));
+        assert!(parse_extension("").is_err());
+        assert!(parse_extension(" . ").is_err());
+        assert!(parse_extension("fa.gz").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_files_dangling_symlink() -> Result<()> {
+        let dir = tempdir()?;
+        let valid_path = dir.path().join("a.fasta");
+        File::create(&valid_path)?;
+        let dangling_path = dir.path().join("b.fasta");
+        std::os::unix::fs::symlink(dir.path().join("missing.fasta"), &dangling_path)?;
+        std::os::unix::fs::symlink(dir.path().join("missing.txt"), dir.path().join("c.txt"))?;
+
+        let found = find_files_with_extensions(
+            dir.path(),
+            &["fasta"],
+            false,
+            ExternalSymlinks::Count,
+            None,
+        )?;
+        assert_eq!(found.files, [valid_path.canonicalize()?]);
+        assert_eq!(found.unresolved, [dangling_path]);
+        assert_eq!(
+            fs_find_files_with_extensions(dir.path(), &["fasta"])?,
+            found.files
+        );
+
+        let err =
+            find_files_with_extensions(dir.path(), &["fasta"], true, ExternalSymlinks::Count, None)
+                .unwrap_err();
+        assert!(err.to_string().contains("Could not resolve"), "{}", err);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_files_external_symlink() -> Result<()> {
+        let dir = tempdir()?;
+        let input_root = dir.path().join("input");
+        let elsewhere = dir.path().join("elsewhere");
+        fs::create_dir(&input_root)?;
+        fs::create_dir(&elsewhere)?;
+        let target = elsewhere.join("ref.fasta");
+        fs::write(&target, ">r\nACGT\n")?;
+        let link = input_root.join("linked.fasta");
+        std::os::unix::fs::symlink(&target, &link)?;
+        let input_root = input_root.canonicalize()?;
+
+        let find =
+            |external| find_files_with_extensions(&input_root, &["fasta"], false, external, None);
+        let found = find(ExternalSymlinks::Count)?;
+        assert_eq!(found.files, [input_root.join("linked.fasta")]);
+        assert!(found.external.is_empty());
+        assert_eq!(
+            output_path_from_input(&found.files[0], &input_root, Path::new("out"))?,
+            Path::new("out/linked_kmer.txt")
+        );
+        let output_path = dir.path().join("linked_kmer.txt");
+        run_fasta_kmer_count(&found.files[0], &CountOptions::new(4), &output_path)?;
+        assert_eq!(
+            table::read_kmer_table(&output_path)?,
+            [("ACGT".to_owned(), 1)]
+        );
+
+        let found = find(ExternalSymlinks::Skip)?;
+        assert!(found.files.is_empty());
+        assert_eq!(found.external, [(link, target.canonicalize()?)]);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_files_excludes_output_root() -> Result<()> {
+        let dir = tempdir()?;
+        let output_root = dir.path().join("output");
+        fs::create_dir(&output_root)?;
+        let input_path = dir.path().join("a.txt");
+        File::create(&input_path)?;
+        let output_path = output_root.join("a_kmer.txt");
+        File::create(&output_path)?;
+        std::os::unix::fs::symlink(&output_path, dir.path().join("link.txt"))?;
+
+        let output_root = resolve_path(&output_root)?;
+        let find = |exclude| {
+            find_files_with_extensions(
+                dir.path(),
+                &["txt"],
+                false,
+                ExternalSymlinks::Count,
+                exclude,
+            )
+        };
+        let found = find(Some(&output_root))?;
+        assert_eq!(found.files, [input_path.canonicalize()?]);
+
+        // an output root containing the input directory excludes nothing
+        let parent = resolve_path(dir.path())?;
+        let found = find(Some(&parent))?;
+        assert_eq!(found.files.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_path() -> Result<()> {
+        let dir = tempdir()?;
+        let missing = dir.path().join("output").join("nested");
+        assert_eq!(
+            resolve_path(&missing)?,
+            dir.path().canonicalize()?.join("output").join("nested")
+        );
+        assert!(resolve_path(Path::new("output"))?.is_absolute());
+        Ok(())
+    }
+
     #[test]
     #[should_panic(expected = "Not a directory")]
     fn test_find_files_dir_is_file() {
-        fs_find_files_with_extensions(&Path::new("./output.txt"), &vec!["rs", "txt"]).unwrap();
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("output.txt");
+        File::create(&file_path).unwrap();
+        fs_find_files_with_extensions(&file_path, &["rs", "txt"]).unwrap();
     }
 }
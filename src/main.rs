@@ -1,60 +1,2154 @@
 //! Calculate kmer frequency
 
-use log::info;
+use log::{error, info, warn, Level};
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 
+use std::collections::{BTreeMap, HashMap};
+use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use clap_verbosity_flag::Verbosity;
+use rayon::prelude::*;
+use regex::Regex;
+use structopt::clap::{AppSettings, ArgGroup, Error as ClapError, ErrorKind};
 use structopt::StructOpt;
 
+use kmer::checkpoint::CheckpointConfig;
+use kmer::cluster::DistanceMetric;
+use kmer::dedup::SimilarityMetric;
+use kmer::inputs::{InputGroup, InputOrder};
+use kmer::labels::LabelTemplate;
+use kmer::manifest::{ManifestEntry, MANIFEST_FILE_NAME};
+use kmer::mask::{AbundantKmers, MaskStyle};
+use kmer::metadata::RunMetadata;
+use kmer::minimizer::{MinimizerShards, DEFAULT_MINIMIZER_LEN};
+use kmer::screen::{Reference, ReferenceSpec, ScreenRule, TiePolicy};
+use kmer::search::SearchMetric;
+use kmer::sink::Sink;
+use kmer::sketch::Sketch;
+use kmer::split::{split_path, KmerSplit, Split};
+use kmer::strand::LibraryType;
+use kmer::timing::PhaseTimings;
+use kmer::translate::StopCodons;
+use kmer::validate;
+use kmer::variants::VariantMask;
+use kmer::workbook::CountWorkbook;
+use kmer::{
+    CanonicalRule, CaseHandling, DuplicateIds, EmptyInputs, ExternalSymlinks, LargeK, OutputFormat,
+};
+
+/// Flags writing count tables in another format or with other columns
+const TABLE_FORMATS: &[&str] = &[
+    "format",
+    "log-tf",
+    "tm",
+    "with-revcomp-column",
+    "indexed-output",
+    "varint-output",
+];
+
+/// Flags writing something other than count tables, or nothing at all; flags
+/// filtering count tables conflict with these
+const NON_TABLE_OUTPUTS: &[&str] = &[
+    "composition-report",
+    "cooccurrence",
+    "first-last-matrix",
+    "coverage-track",
+    "tnf",
+    "entropy-rate",
+    "dinucleotide-signature",
+    "record-specificity",
+    "chargaff",
+    "xlsx",
+    "colored-output",
+    "extremes",
+    "estimate",
+];
+
 #[derive(Debug, StructOpt)]
 #[structopt(
     name = "kmer count",
-    about = "Count frequency of all kmers for all fasta files in directory"
+    about = "Count frequency of all kmers for all fasta files in directory",
+    setting = AppSettings::SubcommandsNegateReqs,
+    // at most one output mode is given; conflicts with a mode are declared on
+    // the other flag, as clap extends those of a group member to the group
+    group = ArgGroup::with_name("output-mode").args(TABLE_FORMATS).args(NON_TABLE_OUTPUTS)
 )]
 struct Opt {
-    /// length of kmer
-    #[structopt(short)]
-    k: usize,
+    /// length of kmer (required when counting)
+    #[structopt(short, conflicts_with_all = &["dinucleotide-signature"])]
+    k: Option<usize>,
+
+    /// when k exceeds 256, warn and count anyway or stop
+    #[structopt(long, default_value = "warn", possible_values = LargeK::VARIANTS, case_insensitive = true)]
+    large_k: LargeK,
+
+    /// also count all kmers within this Hamming distance of each observed kmer; kmers with a base other than ACGT are skipped, as with --skip-ambiguous [default: 0]
+    #[structopt(long = "mismatch", conflicts_with_all = &["translate", "entropy-rate", "dinucleotide-signature"])]
+    mismatches: Option<usize>,
+
+    /// write tables sorted by kmer, with a `.idx` prefix index for `lookup`
+    #[structopt(long)]
+    indexed_output: bool,
+
+    /// write sublinear term frequencies, ln(1 + count), instead of raw counts
+    #[structopt(long)]
+    log_tf: bool,
+
+    /// add a `tm` column of each kmer's melting temperature by the Wallace rule, 2(A+T) + 4(G+C) °C
+    #[structopt(long)]
+    tm: bool,
+
+    /// add a `revcomp` column of each kmer's reverse complement, for lookups by either strand
+    #[structopt(long)]
+    with_revcomp_column: bool,
+
+    /// write compact binary tables of delta-gapped varint kmer IDs and counts (k <= 32); see `decode`
+    #[structopt(long)]
+    varint_output: bool,
+
+    /// format of count tables: `kmer\tcount` rows, a JSON array of {"kmer":...,"count":...} objects written as `_kmer.json`, one such object per line written as `_kmer.ndjson`, length-delimited protobuf messages (see proto/kmer_count.proto) written as `_kmer.pb` (needs the `protobuf` feature), an Avro container file with an embedded {kmer: string, count: long} schema written as `_kmer.avro` (needs the `avro` feature), or a NumPy .npy uint64 array written as `_kmer.npy` with a `.labels.txt` file of the kmers in the same order [default: tsv]
+    #[structopt(long, possible_values = OutputFormat::VARIANTS, case_insensitive = true)]
+    format: Option<OutputFormat>,
+
+    /// also POST each table as it is saved to this URL, as NDJSON batches, retrying transient failures; needs the `http` feature
+    #[structopt(long, value_name = "URL", conflicts_with_all = NON_TABLE_OUTPUTS, conflicts_with_all = &["self-check"])]
+    sink_url: Option<String>,
+
+    /// count amino acid kmers of the six-frame translation (standard genetic code)
+    #[structopt(long, conflicts_with_all = &["varint-output", "composition-report", "cooccurrence", "tm", "with-revcomp-column", "coverage-track", "tnf", "entropy-rate", "dinucleotide-signature", "chargaff"])]
+    translate: bool,
+
+    /// count each kmer together with its reverse complement, reported as the one chosen by --canonical-rule
+    #[structopt(long, conflicts_with_all = &["translate", "composition-report", "cooccurrence", "xlsx", "entropy-rate", "dinucleotide-signature", "chargaff"])]
+    canonical: bool,
+
+    /// with --canonical, report the lexicographically smaller of a kmer and its reverse complement, or the one with the smaller hash
+    #[structopt(long, default_value = "lex", possible_values = CanonicalRule::VARIANTS, case_insensitive = true)]
+    canonical_rule: CanonicalRule,
+
+    /// how lowercase (soft-masked) bases are counted: as uppercase, by leaving out the kmers overlapping them, or as distinct from uppercase
+    #[structopt(long, default_value = "upper", possible_values = CaseHandling::VARIANTS, case_insensitive = true)]
+    case: CaseHandling,
+
+    /// count only kmers within open reading frames, ATG to stop codon, found in all six frames
+    #[structopt(long, conflicts_with_all = &["translate", "composition-report", "cooccurrence", "xlsx", "coverage-track", "tnf", "dinucleotide-signature"])]
+    orf_only: bool,
+
+    /// leave out kmers with a base other than A, T, C, or G, such as those spanning N gaps, instead of counting them
+    #[structopt(long, conflicts_with_all = &["translate", "orf-only", "composition-report", "cooccurrence"])]
+    skip_ambiguous: bool,
+
+    /// leave out kmers overlapping the positions listed in FILE, lines of record id and 1-based position such as a VCF
+    #[structopt(long, value_name = "FILE", conflicts_with_all = NON_TABLE_OUTPUTS, conflicts_with_all = &["core-fraction", "per-record", "fragment", "heavy-hitters", "group-by"])]
+    mask_variants: Option<PathBuf>,
+
+    /// shortest ORF counted with --orf-only, in bases including the stop codon
+    #[structopt(long, default_value = "90")]
+    min_orf_len: usize,
+
+    /// keep only kmers with at least this GC fraction (0 to 1)
+    #[structopt(long, conflicts_with_all = &["translate", "composition-report", "cooccurrence", "xlsx", "dinucleotide-signature"])]
+    gc_min: Option<f64>,
+
+    /// keep only kmers with at most this GC fraction (0 to 1)
+    #[structopt(long, conflicts_with_all = &["translate", "composition-report", "cooccurrence", "xlsx", "dinucleotide-signature"])]
+    gc_max: Option<f64>,
+
+    /// keep only the N most abundant kmers of each table; kmers tied at the cutoff are kept in alphabetical order until N are kept
+    #[structopt(long, value_name = "N", conflicts_with_all = NON_TABLE_OUTPUTS)]
+    top_n: Option<usize>,
+
+    /// keep only kmers counted at least this many times; 1 keeps them all [default: 1]
+    #[structopt(long, value_name = "N", conflicts_with_all = NON_TABLE_OUTPUTS)]
+    min_count: Option<u64>,
+
+    /// with --translate, whether stop codons end peptides or are counted as `*`
+    #[structopt(long, default_value = "split", possible_values = StopCodons::VARIANTS, case_insensitive = true)]
+    stop_codons: StopCodons,
+
+    /// strandedness of a paired-end library; mates are reoriented onto the transcribed strand
+    #[structopt(long, default_value = "unstranded", possible_values = LibraryType::VARIANTS, case_insensitive = true)]
+    library_type: LibraryType,
+
+    /// for inputs without any countable record, write a table with only the header or no output
+    #[structopt(long, default_value = "table", possible_values = EmptyInputs::VARIANTS, case_insensitive = true)]
+    empty_inputs: EmptyInputs,
+
+    /// for records repeating an earlier record id of the same input, merge their counts, fail the input, or count them as id.1, id.2, ...
+    #[structopt(long, default_value = "merge", possible_values = DuplicateIds::VARIANTS, case_insensitive = true)]
+    duplicate_ids: DuplicateIds,
+
+    /// write a per-record composition report (length, GC, kmer diversity, entropy) instead of counts
+    #[structopt(long)]
+    composition_report: bool,
+
+    /// write a 4x4 table of kmer counts by first and last base instead of counts
+    #[structopt(long)]
+    first_last_matrix: bool,
+
+    /// write each base's mean count of the kmers overlapping it, as `record_id\tposition\tcoverage`, instead of counts
+    #[structopt(long)]
+    coverage_track: bool,
+
+    /// write each record's 136 canonical tetranucleotide frequencies, for metagenomic binning, instead of counts (k is 4)
+    #[structopt(long)]
+    tnf: bool,
+
+    /// write each input's entropy rate, in bits per base, under a Markov model of order k - 1 (see --order), instead of counts
+    #[structopt(long)]
+    entropy_rate: bool,
+
+    /// with --entropy-rate, the number of preceding bases each base is conditioned on; sets k to N + 1
+    #[structopt(long, value_name = "N", requires = "entropy-rate")]
+    order: Option<usize>,
+
+    /// write each input's 16 dinucleotide odds ratios f(XY) / (f(X) f(Y)), its genomic signature, in the order AA, AC, ..., TT, instead of counts
+    #[structopt(long)]
+    dinucleotide_signature: bool,
+
+    /// write each record's count of distinct kmers found in no other record of its file, as `record_id\tspecific_kmers\ttotal_kmers`, instead of counts
+    #[structopt(long)]
+    record_specificity: bool,
+
+    /// write each kmer with its reverse complement, their count ratio, and a flag for ratios below --chargaff-min-ratio, instead of counts
+    #[structopt(long)]
+    chargaff: bool,
+
+    /// ratio of the smaller to the larger count of a kmer and its reverse complement below which --chargaff flags the pair (0 to 1)
+    #[structopt(long, default_value = "0.5")]
+    chargaff_min_ratio: f64,
+
+    /// count files with identical contents once; duplicates share the first file's output
+    #[structopt(long, conflicts_with_all = &["colored-output"])]
+    dedupe_inputs: bool,
+
+    /// report the kmer pairs found together in the most records instead of counts
+    #[structopt(long)]
+    cooccurrence: bool,
+
+    /// number of pairs reported with --cooccurrence
+    #[structopt(long, default_value = "100")]
+    top_pairs: usize,
+
+    /// most abundant distinct kmers per record paired with --cooccurrence
+    #[structopt(long, default_value = "200")]
+    max_record_kmers: usize,
+
+    /// count every input twice and fail if the two outputs differ
+    #[structopt(long, conflicts_with_all = &["tnf", "entropy-rate", "dinucleotide-signature", "record-specificity", "xlsx", "colored-output"])]
+    self_check: bool,
+
+    /// write outputs next to their inputs instead of under the output root
+    #[structopt(long, conflicts_with_all = &["colored-output"])]
+    in_place: bool,
+
+    /// do not begin text outputs with `#` comment lines recording the run settings
+    #[structopt(long)]
+    no_metadata_header: bool,
 
-    /// input file extensions to find
-    #[structopt(short, long, default_value = "fasta")]
+    /// also record the full command line and working directory in the `#` header of text outputs
+    #[structopt(long, conflicts_with = "no-metadata-header")]
+    provenance: bool,
+
+    /// write each record's most abundant kmers to its own sheet of this workbook instead of tables
+    #[structopt(long, parse(from_os_str))]
+    xlsx: Option<PathBuf>,
+
+    /// most sheets written with --xlsx; further records are skipped
+    #[structopt(long, default_value = "100")]
+    xlsx_max_sheets: usize,
+
+    /// kmers per sheet written with --xlsx
+    #[structopt(long, default_value = "100")]
+    xlsx_top: usize,
+
+    /// save a resumable checkpoint of each file's counts after this many bases
+    #[structopt(long, conflicts_with_all = &["composition-report", "cooccurrence", "xlsx", "self-check", "first-last-matrix", "coverage-track", "tnf", "entropy-rate", "dinucleotide-signature", "record-specificity", "chargaff", "colored-output"])]
+    checkpoint_bases: Option<u64>,
+
+    /// save a resumable checkpoint of each file's counts after this many minutes
+    #[structopt(long, conflicts_with_all = &["composition-report", "cooccurrence", "xlsx", "self-check", "first-last-matrix", "coverage-track", "tnf", "entropy-rate", "dinucleotide-signature", "record-specificity", "chargaff", "colored-output"])]
+    checkpoint_minutes: Option<u64>,
+
+    /// continue from checkpoints left by an interrupted run with the same input and settings
+    #[structopt(long, conflicts_with_all = &["composition-report", "cooccurrence", "xlsx", "self-check", "first-last-matrix", "coverage-track", "tnf", "entropy-rate", "dinucleotide-signature", "record-specificity", "chargaff", "colored-output"])]
+    resume_checkpoint: bool,
+
+    /// keep only kmers present in at least this fraction of each file's records
+    #[structopt(long, conflicts_with_all = &["composition-report", "cooccurrence", "xlsx", "checkpoint-bases", "checkpoint-minutes", "resume-checkpoint", "first-last-matrix", "coverage-track", "tnf", "entropy-rate", "dinucleotide-signature", "record-specificity", "chargaff", "colored-output", "extremes"])]
+    core_fraction: Option<f64>,
+
+    /// write a table per record, named by the sanitized record id after the input name, e.g. `sample1__contig_17_kmer.txt`
+    #[structopt(long, conflicts_with_all = &["composition-report", "cooccurrence", "first-last-matrix", "coverage-track", "record-specificity", "chargaff", "xlsx", "core-fraction", "fragment", "heavy-hitters", "group-by", "colored-output", "extremes", "estimate", "checkpoint-bases", "checkpoint-minutes", "resume-checkpoint", "time-limit", "dedup-records", "skip-existing", "self-check", "tnf", "entropy-rate", "dinucleotide-signature"])]
+    per_record: bool,
+
+    /// count interleaved mate pairs as fragments: mate 1, a gap no kmer spans, and mate 2 reverse complemented
+    #[structopt(long, conflicts_with_all = &["composition-report", "cooccurrence", "xlsx", "core-fraction", "checkpoint-bases", "checkpoint-minutes", "resume-checkpoint", "extremes", "first-last-matrix", "coverage-track", "tnf", "entropy-rate", "dinucleotide-signature", "record-specificity", "chargaff", "colored-output"])]
+    fragment: bool,
+
+    /// keep only approximate counts of about this many most abundant kmers per input, in memory bounded by it (Space-Saving)
+    #[structopt(long, conflicts_with_all = &["composition-report", "cooccurrence", "first-last-matrix", "coverage-track", "xlsx", "core-fraction", "fragment", "group-by", "colored-output", "extremes", "estimate", "checkpoint-bases", "checkpoint-minutes", "resume-checkpoint", "time-limit", "dedup-records", "tnf", "entropy-rate", "dinucleotide-signature", "record-specificity", "chargaff"])]
+    heavy_hitters: Option<usize>,
+
+    /// pool files into one count table per sample, named by the first capture group of this regex in each file name
+    #[structopt(long, conflicts_with_all = &["composition-report", "cooccurrence", "first-last-matrix", "xlsx", "core-fraction", "fragment", "extremes", "estimate", "checkpoint-bases", "checkpoint-minutes", "resume-checkpoint", "dedupe-inputs", "skip-existing", "coverage-track", "tnf", "entropy-rate", "dinucleotide-signature", "record-specificity", "chargaff", "colored-output"])]
+    group_by: Option<Regex>,
+
+    /// write one table of all inputs' kmers with a bit per input marking those containing each, for colored graph tools
+    #[structopt(long)]
+    colored_output: bool,
+
+    /// label each input by components of its path, e.g. '{-3}/{-2}' (negative indices count from the file name)
+    #[structopt(long)]
+    label_from_path: Option<LabelTemplate>,
+
+    /// print estimated memory, output size, and runtime per input from a sample, without counting
+    #[structopt(long)]
+    estimate: bool,
+
+    /// print the most and least abundant kmer of each input to stderr, without writing tables
+    #[structopt(long)]
+    extremes: bool,
+
+    /// bases sampled from the start of each input with --estimate
+    #[structopt(long, default_value = "10000000")]
+    estimate_sample_bases: u64,
+
+    /// when inputs differing only by extension would share an output, keep their extensions in the output names
+    #[structopt(long)]
+    disambiguate_outputs: bool,
+
+    /// skip inputs whose output already exists, is complete, and is newer than the input
+    #[structopt(long, conflicts_with = "xlsx", conflicts_with_all = &["colored-output"])]
+    skip_existing: bool,
+
+    /// stop at the first input that cannot be counted instead of counting the rest and failing at the end
+    #[structopt(long)]
+    fail_fast: bool,
+
+    /// count this many inputs at once [default: one per CPU]
+    #[structopt(long)]
+    threads: Option<usize>,
+
+    /// stop counting this many seconds into the run and write the counts so far, marked `partial`
+    #[structopt(long, conflicts_with_all = &["composition-report", "cooccurrence", "first-last-matrix", "xlsx", "core-fraction", "fragment", "group-by", "extremes", "estimate", "checkpoint-bases", "checkpoint-minutes", "resume-checkpoint", "self-check", "coverage-track", "tnf", "entropy-rate", "dinucleotide-signature", "record-specificity", "chargaff", "colored-output"])]
+    time_limit: Option<f64>,
+
+    /// count only the first of records with identical sequences in each input, reporting how many were collapsed
+    #[structopt(long, conflicts_with_all = &["composition-report", "cooccurrence", "first-last-matrix", "coverage-track", "xlsx", "core-fraction", "fragment", "group-by", "extremes", "estimate", "checkpoint-bases", "checkpoint-minutes", "resume-checkpoint", "colored-output"])]
+    dedup_records: bool,
+
+    /// with --dedup-records, also collapse records whose sketch Jaccard similarity to an earlier record is at least this (0 to 1)
+    #[structopt(long, requires = "dedup-records")]
+    dedup_threshold: Option<f64>,
+
+    /// write train and test tables (`.train`/`.test` before the extension) with this fraction of kmers in train, by a seeded hash of each kmer
+    #[structopt(long, conflicts_with_all = &["composition-report", "cooccurrence", "first-last-matrix", "coverage-track", "xlsx", "extremes", "estimate", "self-check", "tnf", "entropy-rate", "dinucleotide-signature", "record-specificity", "chargaff", "colored-output"])]
+    split: Option<f64>,
+
+    /// seed of the kmer hash assigning splits with --split; the same seed splits every sample alike
+    #[structopt(long, requires = "split")]
+    seed: Option<u64>,
+
+    /// write this many tables (`.shard0` to `.shardN-1` before the extension), each kmer in the one chosen by its minimizer
+    #[structopt(long, conflicts_with_all = &["split", "composition-report", "cooccurrence", "first-last-matrix", "coverage-track", "xlsx", "extremes", "estimate", "self-check", "tnf", "entropy-rate", "dinucleotide-signature", "record-specificity", "chargaff", "colored-output"])]
+    minimizer_shard: Option<usize>,
+
+    /// length of the minimizers choosing shards with --minimizer-shard [default: 11, or k if shorter]
+    #[structopt(long, requires = "minimizer-shard")]
+    minimizer_len: Option<usize>,
+
+    /// order in which inputs are processed and listed in the manifest: by path, largest first, or oldest first
+    #[structopt(long, default_value = "name", possible_values = InputOrder::VARIANTS, case_insensitive = true)]
+    sort_inputs: InputOrder,
+
+    /// stop if a directory entry cannot be resolved, e.g. a dangling symlink, instead of skipping it
+    #[structopt(long)]
+    strict_discovery: bool,
+
+    /// for symlinks to files outside the input directory, count them under the symlink's own name or skip them
+    #[structopt(long, default_value = "count", possible_values = ExternalSymlinks::VARIANTS, case_insensitive = true)]
+    external_symlinks: ExternalSymlinks,
+
+    /// input file extensions to find, comma separated or repeated
+    #[structopt(short, long, default_value = kmer::DEFAULT_EXTENSIONS, use_delimiter = true, parse(try_from_str = kmer::parse_extension))]
     extensions: Vec<String>,
 
-    /// input directory
+    /// with `-` as the input, write its table to this file instead of stdout
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// input directory, or `-` to count a fasta stream from stdin
     #[structopt(parse(from_os_str), default_value = ".")]
     directory: PathBuf,
 
-    /// output directory root
-    #[structopt(parse(from_os_str), default_value = "./output")]
-    output_root: PathBuf,
+    /// output directory root [default: ./output]
+    #[structopt(parse(from_os_str))]
+    output_root: Option<PathBuf>,
 
     /// verbosity
     #[structopt(flatten)]
     verbose: Verbosity,
+
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+}
+
+impl Opt {
+    /// Check the settings of a count run outside `CountOptions`, which
+    /// `CountOptions::validate` checks: the kmer length implied by the output
+    /// mode, the values of run-level flags, and the flags an input of `-`
+    /// rules out
+    fn validate(&self) -> Result<(), ClapError> {
+        let conflict = |message: &str| -> Result<(), ClapError> {
+            Err(ClapError::with_description(
+                message,
+                ErrorKind::ArgumentConflict,
+            ))
+        };
+        let invalid = |message: &str| -> Result<(), ClapError> {
+            Err(ClapError::with_description(
+                message,
+                ErrorKind::InvalidValue,
+            ))
+        };
+        if self.tnf && self.k.is_some_and(|k| k != kmer::tnf::TNF_K) {
+            return conflict("--tnf counts tetranucleotides; leave out -k or pass -k 4");
+        }
+        if let (Some(order), Some(k)) = (self.order, self.k) {
+            if k != order + 1 {
+                return conflict(&format!(
+                    "--order {} conditions on {} bases, counting kmers of length {}; leave out -k or pass -k {}",
+                    order,
+                    order,
+                    order + 1,
+                    order + 1
+                ));
+            }
+        }
+        if let Some(seconds) = self.time_limit {
+            if Duration::try_from_secs_f64(seconds).map_or(true, |limit| limit.is_zero()) {
+                return invalid("--time-limit must be a positive number of seconds");
+            }
+        }
+        if self.threads == Some(0) {
+            return invalid("--threads must be at least 1");
+        }
+        if let Some(fraction) = self.core_fraction {
+            if !(0.0..=1.0).contains(&fraction) {
+                return invalid("--core-fraction must be between 0 and 1");
+            }
+        }
+        if !(0.0..=1.0).contains(&self.chargaff_min_ratio) {
+            return invalid("--chargaff-min-ratio must be between 0 and 1");
+        }
+        if self.heavy_hitters == Some(0) {
+            return invalid("--heavy-hitters must keep at least one kmer");
+        }
+        if let Some(pattern) = &self.group_by {
+            if pattern.captures_len() < 2 {
+                return invalid("--group-by needs a capture group for the sample name");
+            }
+        }
+
+        if self.directory == Path::new(kmer::STDIO_PATH) {
+            let stdin_conflicts = [
+                (
+                    self.output_root.is_some(),
+                    "an output directory (pass --output FILE)",
+                ),
+                (self.in_place, "--in-place"),
+                (self.skip_existing, "--skip-existing"),
+                (self.self_check, "--self-check"),
+                (self.checkpoint_bases.is_some(), "--checkpoint-bases"),
+                (self.checkpoint_minutes.is_some(), "--checkpoint-minutes"),
+                (self.resume_checkpoint, "--resume-checkpoint"),
+                (self.xlsx.is_some(), "--xlsx"),
+                (self.colored_output, "--colored-output"),
+                (self.group_by.is_some(), "--group-by"),
+                (self.label_from_path.is_some(), "--label-from-path"),
+                (self.per_record, "--per-record"),
+                (self.split.is_some(), "--split"),
+                (self.minimizer_shard.is_some(), "--minimizer-shard"),
+            ];
+            if let Some((_, with)) = stdin_conflicts.iter().find(|(set, _)| *set) {
+                return conflict(&format!("`-` as the input cannot be used with {}", with));
+            }
+            // their sidecar files need a path next to the table
+            let sidecars = self.indexed_output || self.format == Some(OutputFormat::Npy);
+            if self.output.is_none() && sidecars {
+                return conflict(
+                    "--indexed-output and --format npy cannot write to stdout; pass --output FILE",
+                );
+            }
+        } else if self.output.is_some() {
+            return conflict(
+                "--output needs `-` as the input; tables of an input directory go under the output directory",
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Measure counting throughput and per-phase timings on a fasta file
+    Bench(BenchOpt),
+
+    /// Score each record of a fasta file by kmer diversity (linguistic complexity)
+    Complexity(ComplexityOpt),
+
+    /// Estimate genome size from the kmer spectrum of a fasta file or histogram
+    Genomesize(GenomeSizeOpt),
+
+    /// Estimate the per-base sequencing error rate from the kmer spectrum of a histogram or count table
+    Errorrate(ErrorRateOpt),
+
+    /// Estimate assembly consensus quality (QV) and completeness against a count table of its reads
+    Qv(QvOpt),
+
+    /// Report the strand bias of a non-canonical count table from kmer and reverse complement counts
+    Strandbias(StrandBiasOpt),
+
+    /// Print a table written with --varint-output as `kmer\tcount` rows
+    Decode(DecodeOpt),
+
+    /// Look up kmers by prefix in a table written with --indexed-output
+    Lookup(LookupOpt),
+
+    /// Classify reads by the labeled reference kmer tables containing most of their kmers
+    Screen(ScreenOpt),
+
+    /// Find clusters of near-duplicate records by kmer profile
+    Dedup(DedupOpt),
+
+    /// Rank the records of a database fasta file by kmer profile similarity to a query
+    Search(SearchOpt),
+
+    /// Query a count table interactively: kmer counts, top kmers, histogram, pattern search
+    Shell(ShellOpt),
+
+    /// Mask sequence regions covered by kmers that are abundant in a count table
+    Mask(MaskOpt),
+
+    /// Write a MinHash sketch of the canonical kmers of a fasta file or count table
+    Sketch(SketchOpt),
+
+    /// Estimate the Mash distance between two fasta files from MinHash sketches of their canonical kmers
+    MashDist(MashDistOpt),
+
+    /// Compute pairwise kmer distances between the fasta files or sketches of a directory, with an optional UPGMA tree
+    Cluster(ClusterOpt),
+
+    /// Check the count tables under a directory for truncated or malformed files
+    ValidateOutputs(ValidateOutputsOpt),
+
+    /// Compare a count table with an expected one, failing if their kmers or counts differ
+    Verify(VerifyOpt),
+}
+
+#[derive(Debug, StructOpt)]
+struct BenchOpt {
+    /// length of kmer
+    #[structopt(short)]
+    k: usize,
+
+    /// number of timed runs
+    #[structopt(long, default_value = "3")]
+    repeat: usize,
+
+    /// save counts here on every run [default: counts are discarded]
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// input fasta file
+    #[structopt(parse(from_os_str))]
+    fasta: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct ComplexityOpt {
+    /// length of kmer
+    #[structopt(short)]
+    k: usize,
+
+    /// output format
+    #[structopt(long, default_value = "tsv", possible_values = OutputFormat::REPORT_VARIANTS, case_insensitive = true)]
+    format: OutputFormat,
+
+    /// sum distinct and possible words over every word size from 1 to k instead of k alone
+    #[structopt(long)]
+    linguistic_complexity: bool,
+
+    /// keep records in file order instead of sorting by ascending complexity
+    #[structopt(long)]
+    unsorted: bool,
+
+    /// output file [default: stdout]
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// input fasta file
+    #[structopt(parse(from_os_str))]
+    fasta: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct GenomeSizeOpt {
+    /// length of kmer
+    #[structopt(short, required_unless = "histogram")]
+    k: Option<usize>,
+
+    /// treat the input as an `abundance frequency` histogram (e.g. from `jellyfish histo`)
+    #[structopt(long)]
+    histogram: bool,
+
+    /// output format
+    #[structopt(long, default_value = "tsv", possible_values = OutputFormat::REPORT_VARIANTS, case_insensitive = true)]
+    format: OutputFormat,
+
+    /// input fasta file, or histogram with --histogram
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct ErrorRateOpt {
+    /// length of the counted kmers
+    #[structopt(short)]
+    k: usize,
+
+    /// treat the input as a count table written by the count command instead of a histogram
+    #[structopt(long)]
+    table: bool,
+
+    /// output format
+    #[structopt(long, default_value = "tsv", possible_values = OutputFormat::REPORT_VARIANTS, case_insensitive = true)]
+    format: OutputFormat,
+
+    /// `abundance frequency` histogram (e.g. from `jellyfish histo`), or count table with --table
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct QvOpt {
+    /// length of kmer, that of the count table
+    #[structopt(short)]
+    k: usize,
+
+    /// minimum count of a solid read kmer, the read kmers completeness is measured against
+    #[structopt(long, default_value = "2")]
+    min_count: u64,
+
+    /// the count table holds canonical kmers; match assembly kmers on both strands
+    #[structopt(long)]
+    canonical: bool,
+
+    /// output format
+    #[structopt(long, default_value = "tsv", possible_values = OutputFormat::REPORT_VARIANTS, case_insensitive = true)]
+    format: OutputFormat,
+
+    /// kmer count table of the reads, TSV or varint
+    #[structopt(parse(from_os_str))]
+    counts: PathBuf,
+
+    /// assembly fasta file
+    #[structopt(parse(from_os_str))]
+    assembly: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct StrandBiasOpt {
+    /// leave out kmer pairs with a summed count below this
+    #[structopt(long, default_value = "1")]
+    min_total: u64,
+
+    /// absolute log2 ratio from which a pair is flagged as extreme
+    #[structopt(long, default_value = "1")]
+    extreme: f64,
+
+    /// write each kmer pair with its log2 ratio here, most biased first
+    #[structopt(long, parse(from_os_str))]
+    pairs: Option<PathBuf>,
+
+    /// summary output format
+    #[structopt(long, default_value = "tsv", possible_values = OutputFormat::REPORT_VARIANTS, case_insensitive = true)]
+    format: OutputFormat,
+
+    /// non-canonical kmer count table, TSV or varint
+    #[structopt(parse(from_os_str))]
+    table: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct SketchOpt {
+    /// length of kmer
+    #[structopt(short, required_unless = "table", conflicts_with = "table")]
+    k: Option<usize>,
+
+    /// number of minimum hash values kept
+    #[structopt(short, long, default_value = "1000")]
+    size: usize,
+
+    /// hash seed; only sketches with equal seeds and k can be compared
+    #[structopt(long, default_value = "42")]
+    seed: u64,
+
+    /// treat the input as a count table, TSV or varint, whose kmer length gives k
+    #[structopt(long)]
+    table: bool,
+
+    /// output file [default: stdout]
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// input fasta file or count table
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct MashDistOpt {
+    /// length of kmer
+    #[structopt(short, default_value = "21")]
+    k: usize,
+
+    /// number of minimum hash values kept per sketch
+    #[structopt(short, long, default_value = "1000")]
+    size: usize,
+
+    /// hash seed
+    #[structopt(long, default_value = "42")]
+    seed: u64,
+
+    /// first fasta file
+    #[structopt(parse(from_os_str))]
+    a: PathBuf,
+
+    /// second fasta file
+    #[structopt(parse(from_os_str))]
+    b: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct ClusterOpt {
+    /// length of kmer
+    #[structopt(short, required_unless = "sketches")]
+    k: Option<usize>,
+
+    /// distance between kmer profiles
+    #[structopt(long, default_value = "cosine", possible_values = DistanceMetric::VARIANTS, case_insensitive = true)]
+    metric: DistanceMetric,
+
+    /// compare the .sketch files written by `kmer sketch` by one minus their estimated Jaccard similarity
+    #[structopt(long, conflicts_with_all = &["k", "metric", "log-tf", "extensions"])]
+    sketches: bool,
+
+    /// weight kmers by ln(1 + count) instead of raw counts
+    #[structopt(long)]
+    log_tf: bool,
+
+    /// label each input by components of its path [default: file name without extension]
+    #[structopt(long)]
+    label_from_path: Option<LabelTemplate>,
+
+    /// write a UPGMA tree of the samples in Newick format here
+    #[structopt(long, parse(from_os_str))]
+    tree: Option<PathBuf>,
+
+    /// distance matrix output file [default: stdout]
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// input file extensions to find, comma separated or repeated
+    #[structopt(short, long, default_value = kmer::DEFAULT_EXTENSIONS, use_delimiter = true, parse(try_from_str = kmer::parse_extension))]
+    extensions: Vec<String>,
+
+    /// input directory
+    #[structopt(parse(from_os_str))]
+    directory: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct DecodeOpt {
+    /// varint count table
+    #[structopt(parse(from_os_str))]
+    table: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct ShellOpt {
+    /// count table, TSV or written with --varint-output
+    #[structopt(parse(from_os_str))]
+    table: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct LookupOpt {
+    /// indexed count table
+    #[structopt(parse(from_os_str))]
+    table: PathBuf,
+
+    /// kmers or kmer prefixes to look up
+    #[structopt(required = true)]
+    queries: Vec<String>,
+}
+
+#[derive(Debug, StructOpt)]
+struct ScreenOpt {
+    /// labeled reference kmer table, as LABEL:PATH (repeatable)
+    #[structopt(long = "ref", required = true, number_of_values = 1)]
+    references: Vec<ReferenceSpec>,
+
+    /// minimum fraction of a read's kmers a reference must contain to be a hit (0 to 1)
+    #[structopt(long, default_value = "0.5")]
+    min_fraction: f64,
+
+    /// how reads tied between references are counted
+    #[structopt(long, default_value = "ambiguous", possible_values = TiePolicy::VARIANTS, case_insensitive = true)]
+    ties: TiePolicy,
+
+    /// output format
+    #[structopt(long, default_value = "tsv", possible_values = OutputFormat::REPORT_VARIANTS, case_insensitive = true)]
+    format: OutputFormat,
+
+    /// reads fasta file
+    #[structopt(parse(from_os_str))]
+    reads: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct SearchOpt {
+    /// length of kmer
+    #[structopt(short)]
+    k: usize,
+
+    /// similarity to the query: fraction of query kmers in the record, or cosine of the count vectors
+    #[structopt(long, default_value = "containment", possible_values = SearchMetric::VARIANTS, case_insensitive = true)]
+    metric: SearchMetric,
+
+    /// number of hits reported
+    #[structopt(long, default_value = "10")]
+    top: usize,
+
+    /// count each kmer together with its reverse complement, so hits on either strand are found
+    #[structopt(long)]
+    canonical: bool,
+
+    /// with --metric cosine, weight kmers by ln(1 + count) instead of raw counts
+    #[structopt(long)]
+    log_tf: bool,
+
+    /// output format
+    #[structopt(long, default_value = "tsv", possible_values = OutputFormat::REPORT_VARIANTS, case_insensitive = true)]
+    format: OutputFormat,
+
+    /// query fasta file; all its records are counted together
+    #[structopt(long, parse(from_os_str))]
+    query: PathBuf,
+
+    /// database fasta file whose records are ranked
+    #[structopt(long, parse(from_os_str))]
+    db: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct DedupOpt {
+    /// length of kmer
+    #[structopt(short)]
+    k: usize,
+
+    /// minimum similarity for two records to be near-duplicates
+    #[structopt(long, default_value = "0.95")]
+    threshold: f64,
+
+    /// similarity between record kmer sets
+    #[structopt(long, default_value = "jaccard", possible_values = SimilarityMetric::VARIANTS, case_insensitive = true)]
+    metric: SimilarityMetric,
+
+    /// number of MinHash values kept per record
+    #[structopt(long, default_value = "1000")]
+    sketch_size: usize,
+
+    /// output format
+    #[structopt(long, default_value = "tsv", possible_values = OutputFormat::REPORT_VARIANTS, case_insensitive = true)]
+    format: OutputFormat,
+
+    /// also write the input with only cluster representatives and unclustered records
+    #[structopt(long, parse(from_os_str))]
+    write_deduped: Option<PathBuf>,
+
+    /// input fasta file
+    #[structopt(parse(from_os_str))]
+    fasta: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct MaskOpt {
+    /// minimum count for a kmer to be masked
+    #[structopt(long)]
+    min_count: u64,
+
+    /// the count table holds canonical kmers; match windows on both strands
+    #[structopt(long)]
+    canonical: bool,
+
+    /// replace masked bases with N instead of lowercasing them
+    #[structopt(long)]
+    hard: bool,
+
+    /// masked fasta output file
+    #[structopt(short, long, parse(from_os_str))]
+    output: PathBuf,
+
+    /// kmer count table
+    #[structopt(parse(from_os_str))]
+    counts: PathBuf,
+
+    /// fasta file to mask
+    #[structopt(parse(from_os_str))]
+    fasta: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct ValidateOutputsOpt {
+    /// delete invalid tables and their index sidecars, so a rerun with --skip-existing counts them again
+    #[structopt(long)]
+    delete: bool,
+
+    /// output directory to check, searched recursively
+    #[structopt(parse(from_os_str))]
+    dir: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct VerifyOpt {
+    /// let counts differ by up to this fraction of the larger of the two, e.g. 0.01 for 1%; kmers missing from either table always differ
+    #[structopt(long, default_value = "0")]
+    tolerance: f64,
+
+    /// number of differing kmers to print
+    #[structopt(long, default_value = "10")]
+    show: usize,
+
+    /// expected count table, TSV or varint
+    #[structopt(parse(from_os_str))]
+    expected: PathBuf,
+
+    /// count table to check, TSV or varint
+    #[structopt(parse(from_os_str))]
+    actual: PathBuf,
 }
 
 fn main() -> Result<()> {
     let opt: Opt = Opt::from_args();
-    opt.verbose.log_level().map(loggerv::init_with_level);
+    match opt.verbose.log_level() {
+        // the table of a stdin input may go to stdout, so logs must not
+        Some(level) if opt.directory == Path::new(kmer::STDIO_PATH) => {
+            let mut logger = loggerv::Logger::new().max_level(level);
+            for level in [Level::Info, Level::Debug, Level::Trace] {
+                logger = logger.output(&level, loggerv::Output::Stderr);
+            }
+            logger.init()?;
+        }
+        Some(level) => loggerv::init_with_level(level)?,
+        None => {}
+    }
+
+    match opt.cmd {
+        Some(Command::Bench(cmd)) => run_bench(cmd),
+        Some(Command::Complexity(cmd)) => run_complexity(cmd),
+        Some(Command::Genomesize(cmd)) => run_genomesize(cmd),
+        Some(Command::Errorrate(cmd)) => run_errorrate(cmd),
+        Some(Command::Qv(cmd)) => run_qv(cmd),
+        Some(Command::Strandbias(cmd)) => run_strandbias(cmd),
+        Some(Command::Decode(cmd)) => run_decode(cmd),
+        Some(Command::Lookup(cmd)) => run_lookup(cmd),
+        Some(Command::Screen(cmd)) => run_screen(cmd),
+        Some(Command::Dedup(cmd)) => run_dedup(cmd),
+        Some(Command::Search(cmd)) => run_search(cmd),
+        Some(Command::Mask(cmd)) => run_mask(cmd),
+        Some(Command::Shell(cmd)) => run_shell(cmd),
+        Some(Command::Sketch(cmd)) => run_sketch(cmd),
+        Some(Command::MashDist(cmd)) => run_mash_dist(cmd),
+        Some(Command::Cluster(cmd)) => run_cluster(cmd),
+        Some(Command::ValidateOutputs(cmd)) => run_validate_outputs(cmd),
+        Some(Command::Verify(cmd)) => run_verify(cmd),
+        None => run_count(opt),
+    }
+}
+
+/// Count kmers for every fasta file in the input directory
+fn run_count(opt: Opt) -> Result<()> {
+    if let Err(err) = opt.validate() {
+        err.exit()
+    }
+    let k = match opt.k.or(opt.order.map(|order| order + 1)) {
+        Some(k) => k,
+        None if opt.tnf => kmer::tnf::TNF_K,
+        None if opt.dinucleotide_signature => 2,
+        None => ClapError::with_description(
+            "The following required arguments were not provided:\n    -k <k>",
+            ErrorKind::MissingRequiredArgument,
+        )
+        .exit(),
+    };
+    let deadline = opt
+        .time_limit
+        .map(|seconds| Instant::now() + Duration::from_secs_f64(seconds));
+    let variant_mask = match &opt.mask_variants {
+        Some(path) => {
+            let mask = VariantMask::read(path)?;
+            info!("Masking {} variant position(s) from {:?}", mask.len(), path);
+            Some(mask)
+        }
+        None => None,
+    };
+    let options = kmer::CountOptions {
+        mismatches: opt.mismatches.unwrap_or(0),
+        indexed_output: opt.indexed_output,
+        library_type: opt.library_type,
+        log_tf: opt.log_tf,
+        tm: opt.tm,
+        revcomp_column: opt.with_revcomp_column,
+        varint_output: opt.varint_output,
+        format: opt.format.unwrap_or(OutputFormat::Tsv),
+        sink: opt.sink_url.as_deref().map(Sink::new),
+        translate: opt.translate,
+        stop_codons: opt.stop_codons,
+        metadata_header: !opt.no_metadata_header,
+        canonical: opt.canonical,
+        canonical_rule: opt.canonical_rule,
+        case: opt.case,
+        skip_ambiguous: opt.skip_ambiguous,
+        variant_mask,
+        gc_min: opt.gc_min,
+        gc_max: opt.gc_max,
+        top_n: opt.top_n,
+        min_count: opt.min_count.unwrap_or(1),
+        empty_inputs: opt.empty_inputs,
+        duplicate_ids: opt.duplicate_ids,
+        deadline,
+        dedup_records: opt.dedup_records,
+        dedup_threshold: opt.dedup_threshold,
+        large_k: opt.large_k,
+        split: opt.split.map(|train_fraction| KmerSplit {
+            train_fraction,
+            seed: opt.seed.unwrap_or(0),
+        }),
+        minimizer_shards: opt.minimizer_shard.map(|shards| MinimizerShards {
+            m: opt
+                .minimizer_len
+                .unwrap_or_else(|| DEFAULT_MINIMIZER_LEN.min(k)),
+            shards,
+        }),
+        orf_min_len: if opt.orf_only {
+            Some(opt.min_orf_len)
+        } else {
+            None
+        },
+        ..kmer::CountOptions::new(k)
+    };
+    if let Err(err) = options.validate() {
+        ClapError::with_description(&err.to_string(), ErrorKind::InvalidValue).exit()
+    }
+    // working directory recorded with --provenance
+    let provenance = if opt.provenance {
+        Some(env::current_dir()?)
+    } else {
+        None
+    };
+    let from_stdin = opt.directory == Path::new(kmer::STDIO_PATH);
+    let (input_root, output_root, mut fasta_paths) = if from_stdin {
+        let stdin = PathBuf::from(kmer::STDIO_PATH);
+        (stdin.clone(), stdin.clone(), vec![stdin])
+    } else {
+        let input_root = opt.directory.canonicalize()?;
+        let output_root = if opt.in_place {
+            input_root.clone()
+        } else {
+            opt.output_root
+                .clone()
+                .unwrap_or_else(|| PathBuf::from("./output"))
+        };
+        let fasta_paths = find_inputs(&opt, &input_root, &output_root)?;
+        (input_root, output_root, fasta_paths)
+    };
+    let suffix = if opt.composition_report {
+        "_composition.txt"
+    } else if opt.cooccurrence {
+        "_cooccurrence.txt"
+    } else if opt.first_last_matrix {
+        "_first_last.txt"
+    } else if opt.coverage_track {
+        "_coverage.txt"
+    } else if opt.tnf {
+        "_tnf.txt"
+    } else if opt.entropy_rate {
+        "_entropy_rate.txt"
+    } else if opt.dinucleotide_signature {
+        "_dinucleotide_signature.txt"
+    } else if opt.record_specificity {
+        "_specificity.txt"
+    } else if opt.chargaff {
+        "_chargaff.txt"
+    } else if opt.varint_output {
+        "_kmer.kmv"
+    } else if options.format == OutputFormat::Protobuf {
+        "_kmer.pb"
+    } else if options.format == OutputFormat::Avro {
+        "_kmer.avro"
+    } else if options.format == OutputFormat::Npy {
+        "_kmer.npy"
+    } else if options.format == OutputFormat::Ndjson {
+        "_kmer.ndjson"
+    } else if options.format == OutputFormat::Json {
+        "_kmer.json"
+    } else {
+        "_kmer.txt"
+    };
+
+    if opt.in_place {
+        let mut inputs = Vec::new();
+        for path in fasta_paths {
+            if kmer::is_previous_output(&path)? {
+                info!("Ignoring previous output {:?}", path);
+            } else {
+                inputs.push(path);
+            }
+        }
+        fasta_paths = inputs;
+        check_in_place_collisions(&fasta_paths, &input_root, suffix)?;
+    }
+    if !from_stdin {
+        kmer::inputs::sort_inputs(&mut fasta_paths, opt.sort_inputs)?;
+    }
+
+    let groups = if opt.dedupe_inputs && !from_stdin {
+        kmer::inputs::group_identical_files(&fasta_paths)?
+    } else {
+        fasta_paths.into_iter().map(InputGroup::single).collect()
+    };
+
+    if opt.estimate {
+        let estimates = groups
+            .iter()
+            .map(|group| {
+                info!("Estimating resources for {:?}", group.primary);
+                kmer::estimate::estimate_fasta(&group.primary, &options, opt.estimate_sample_bases)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        return kmer::estimate::write_estimates(&estimates, io::stdout().lock());
+    }
+
+    if opt.extremes {
+        for group in &groups {
+            match kmer::fasta_kmer_extremes(&group.primary, &options)? {
+                Some(extremes) => {
+                    let (max, max_count) = &extremes.max;
+                    let (min, min_count) = &extremes.min;
+                    eprintln!(
+                        "{}\tmax\t{}\t{}\tmin\t{}\t{}",
+                        group.primary.display(),
+                        max,
+                        max_count,
+                        min,
+                        min_count
+                    );
+                }
+                None => error!("No kmers counted in {:?}", group.primary),
+            }
+        }
+        return Ok(());
+    }
 
-    let input_root = opt.directory.canonicalize()?;
+    let labels: HashMap<PathBuf, String> = match &opt.label_from_path {
+        Some(template) => {
+            let inputs: Vec<PathBuf> = groups
+                .iter()
+                .flat_map(|group| std::iter::once(&group.primary).chain(&group.aliases))
+                .cloned()
+                .collect();
+            let labels = template.unique_labels(&inputs)?;
+            inputs.into_iter().zip(labels).collect()
+        }
+        None => HashMap::new(),
+    };
 
-    let fasta_paths = kmer::fs_find_files_with_extensions(input_root.as_path(), &opt.extensions)?;
-    for fasta_path in fasta_paths {
-        let output_path = kmer::output_path_from_input(&fasta_path, &input_root, &opt.output_root)?;
-        fs::create_dir_all(output_path.parent().expect("Invalid paths"))
-            .expect("Could not create directory");
+    let checkpointed =
+        opt.checkpoint_bases.is_some() || opt.checkpoint_minutes.is_some() || opt.resume_checkpoint;
 
+    let mut manifest = Vec::new();
+    let mut failures = Vec::new();
+    if let Some(xlsx_path) = &opt.xlsx {
+        let mut workbook = CountWorkbook::new(opt.xlsx_max_sheets, opt.xlsx_top);
+        for group in &groups {
+            info!("Adding records of {:?} to {:?}", group.primary, xlsx_path);
+            workbook.add_fasta(&group.primary, &options)?;
+            manifest.extend(kmer::manifest::group_entries(group, xlsx_path));
+        }
+        workbook.save(xlsx_path)?;
+    } else if opt.colored_output {
+        let samples: Vec<(String, PathBuf)> = groups
+            .iter()
+            .map(|group| {
+                let name = match labels.get(&group.primary) {
+                    Some(label) => label.clone(),
+                    None => group.primary.display().to_string(),
+                };
+                (name, group.primary.clone())
+            })
+            .collect();
+        let output_path = output_root.join(kmer::colored::COLORED_FILE_NAME);
         info!(
-            "Counting kmers in {:?}. Output to {:?}",
-            fasta_path, output_path
+            "Coloring kmers of {} input(s). Output to {:?}",
+            samples.len(),
+            output_path
         );
-        kmer::run_fasta_kmer_count(&fasta_path, opt.k, &output_path)?
+        let metadata = if opt.no_metadata_header {
+            None
+        } else {
+            let inputs: Vec<String> = samples
+                .iter()
+                .map(|(_, path)| path.display().to_string())
+                .collect();
+            let metadata = RunMetadata::for_count(&options, &input_root)
+                .with("input", inputs.join(","))
+                .with("output", "colored");
+            Some(match &provenance {
+                Some(working_directory) => {
+                    metadata.with_provenance(env::args_os(), working_directory)
+                }
+                None => metadata,
+            })
+        };
+        kmer::colored::run_colored_kmer_count(&samples, &options, metadata.as_ref(), &output_root)?;
+        for group in &groups {
+            manifest.extend(kmer::manifest::group_entries(group, &output_path));
+        }
+    } else if let Some(pattern) = &opt.group_by {
+        let inputs: Vec<PathBuf> = groups.into_iter().map(|group| group.primary).collect();
+        let (samples, unmatched) = kmer::pool::pool_by_sample(&inputs, pattern);
+        if !unmatched.is_empty() {
+            let unmatched: Vec<String> =
+                unmatched.iter().map(|path| format!("{:?}", path)).collect();
+            eprintln!(
+                "WARNING: Skipped {} input(s) without a sample name in --group-by: {}",
+                unmatched.len(),
+                unmatched.join(", ")
+            );
+        }
+        fs::create_dir_all(&output_root)?;
+        for (sample, fasta_paths) in &samples {
+            let output_path = output_root.join(format!("{}{}", sample, suffix));
+            info!(
+                "Pooling kmers in {:?}. Output to {:?}",
+                fasta_paths, output_path
+            );
+            let metadata = if opt.no_metadata_header {
+                None
+            } else {
+                let inputs: Vec<String> = fasta_paths
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect();
+                let metadata = RunMetadata::for_count(&options, &fasta_paths[0])
+                    .with("input", inputs.join(","))
+                    .with("sample", sample)
+                    .with("record_mode", "pooled");
+                Some(match &provenance {
+                    Some(working_directory) => {
+                        metadata.with_provenance(env::args_os(), working_directory)
+                    }
+                    None => metadata,
+                })
+            };
+            let run = |output_path: &Path| {
+                kmer::pool::run_pooled_kmer_count(
+                    fasta_paths,
+                    &options,
+                    metadata.as_ref(),
+                    output_path,
+                )
+            };
+            let result = if opt.self_check {
+                kmer::selfcheck::run_checked(&output_path, run)
+            } else {
+                run(&output_path)
+            };
+            let mut entries: Vec<_> = fasta_paths
+                .iter()
+                .flat_map(|path| {
+                    kmer::manifest::group_entries(&InputGroup::single(path.clone()), &output_path)
+                })
+                .collect();
+            if let Err(err) = result {
+                let err = err.context(format!("Could not pool sample {:?}", sample));
+                if opt.fail_fast {
+                    return Err(err);
+                }
+                error!("{:#}", err);
+                for entry in &mut entries {
+                    entry.error = Some(format!("{:#}", err));
+                }
+                failures.push(err);
+            }
+            manifest.extend(entries);
+        }
+    } else {
+        let (groups, outputs) = if from_stdin {
+            let output = opt.output.clone();
+            (
+                groups,
+                vec![output.unwrap_or_else(|| PathBuf::from(kmer::STDIO_PATH))],
+            )
+        } else {
+            // inputs without a valid output name are reported and left out
+            let groups: Vec<InputGroup> = groups
+                .into_iter()
+                .filter(|group| {
+                    match kmer::output_path_with_suffix(
+                        &group.primary,
+                        &input_root,
+                        &output_root,
+                        suffix,
+                    ) {
+                        Ok(_) => true,
+                        Err(err) => {
+                            error!("Skipping input: {}", err);
+                            false
+                        }
+                    }
+                })
+                .collect();
+            let outputs = plan_outputs(
+                &groups,
+                &input_root,
+                &output_root,
+                suffix,
+                opt.disambiguate_outputs,
+            )?;
+            (groups, outputs)
+        };
+        // each input writes its own output, so inputs are counted in parallel;
+        // results are gathered in input order
+        let count_group = |group: &InputGroup, output_path: &PathBuf| -> Result<CountedGroup> {
+            let fasta_path = &group.primary;
+            // with --split or --minimizer-shard, the split or shard tables are
+            // written instead of the output
+            let saved_paths = match (opt.split, &options.minimizer_shards) {
+                (Some(_), _) => vec![
+                    split_path(output_path, Split::Train),
+                    split_path(output_path, Split::Test),
+                ],
+                (None, Some(shards)) => shards.shard_paths(output_path),
+                (None, None) => vec![output_path.clone()],
+            };
+            let up_to_date = || -> Result<bool> {
+                for path in &saved_paths {
+                    if !kmer::is_up_to_date(fasta_path, path)? {
+                        return Ok(false);
+                    }
+                    if validate::is_count_table(path) {
+                        if let Some(reason) = validate::check_table(path)? {
+                            eprintln!("WARNING: Recounting {:?}: {}", path, reason);
+                            return Ok(false);
+                        }
+                    }
+                }
+                Ok(true)
+            };
+            if opt.skip_existing && up_to_date()? {
+                info!("Skipping {:?}, {:?} is up to date", fasta_path, output_path);
+                return Ok((kmer::manifest::group_entries(group, output_path), None));
+            }
+            info!(
+                "Counting kmers in {:?}. Output to {:?}",
+                fasta_path, output_path
+            );
+            let metadata = if opt.no_metadata_header {
+                None
+            } else {
+                let mut metadata = RunMetadata::for_count(&options, fasta_path);
+                if let Some(label) = labels.get(fasta_path) {
+                    metadata = metadata.with("label", label);
+                }
+                if let Some(working_directory) = &provenance {
+                    metadata = metadata.with_provenance(env::args_os(), working_directory);
+                }
+                Some(if opt.composition_report {
+                    metadata.with("output", "composition")
+                } else if opt.cooccurrence {
+                    metadata
+                        .with("output", "cooccurrence")
+                        .with("top_pairs", opt.top_pairs)
+                        .with("max_record_kmers", opt.max_record_kmers)
+                } else if opt.first_last_matrix {
+                    metadata.with("output", "first_last_matrix")
+                } else if opt.coverage_track {
+                    metadata.with("output", "coverage_track")
+                } else if opt.tnf {
+                    metadata.with("output", "tnf").with("canonical", true)
+                } else if opt.entropy_rate {
+                    metadata.with("output", "entropy_rate").with("order", k - 1)
+                } else if opt.dinucleotide_signature {
+                    metadata.with("output", "dinucleotide_signature")
+                } else if opt.record_specificity {
+                    metadata.with("output", "record_specificity")
+                } else if opt.chargaff {
+                    metadata.with("output", "chargaff")
+                } else if let Some(fraction) = opt.core_fraction {
+                    metadata.with("core_fraction", fraction)
+                } else if opt.per_record {
+                    metadata.with("record_mode", "per_record")
+                } else if opt.fragment {
+                    metadata.with("record_mode", "fragment")
+                } else if let Some(capacity) = opt.heavy_hitters {
+                    metadata.with("heavy_hitters", capacity)
+                } else {
+                    metadata
+                })
+            };
+            let run = |output_path: &Path| -> Result<()> {
+                if opt.composition_report {
+                    kmer::composition::run_fasta_composition_report(
+                        fasta_path,
+                        k,
+                        metadata.as_ref(),
+                        output_path,
+                    )
+                } else if opt.cooccurrence {
+                    kmer::cooccurrence::run_fasta_cooccurrence(
+                        fasta_path,
+                        k,
+                        opt.top_pairs,
+                        opt.max_record_kmers,
+                        metadata.as_ref(),
+                        output_path,
+                    )
+                } else if opt.first_last_matrix {
+                    kmer::firstlast::run_fasta_first_last_matrix(
+                        fasta_path,
+                        &options,
+                        metadata.as_ref(),
+                        output_path,
+                    )
+                } else if opt.coverage_track {
+                    kmer::coverage::run_fasta_coverage_track(
+                        fasta_path,
+                        &options,
+                        metadata.as_ref(),
+                        output_path,
+                    )
+                } else if opt.tnf {
+                    kmer::tnf::run_fasta_tnf(fasta_path, metadata.as_ref(), output_path)
+                } else if opt.entropy_rate {
+                    kmer::entropy::run_fasta_entropy_rate(
+                        fasta_path,
+                        &options,
+                        metadata.as_ref(),
+                        output_path,
+                    )
+                } else if opt.dinucleotide_signature {
+                    kmer::dinucleotide::run_fasta_dinucleotide_signature(
+                        fasta_path,
+                        &options,
+                        metadata.as_ref(),
+                        output_path,
+                    )
+                } else if opt.record_specificity {
+                    kmer::specificity::run_fasta_record_specificity(
+                        fasta_path,
+                        &options,
+                        metadata.as_ref(),
+                        output_path,
+                    )
+                } else if opt.chargaff {
+                    kmer::chargaff::run_fasta_chargaff(
+                        fasta_path,
+                        &options,
+                        opt.chargaff_min_ratio,
+                        metadata.as_ref(),
+                        output_path,
+                    )
+                    .map(|_| ())
+                } else if let Some(fraction) = opt.core_fraction {
+                    kmer::presence::run_fasta_core_kmer_count(
+                        fasta_path,
+                        &options,
+                        fraction,
+                        metadata.as_ref(),
+                        output_path,
+                    )
+                } else if opt.per_record {
+                    kmer::perrecord::run_fasta_kmer_count_per_record(
+                        fasta_path,
+                        &options,
+                        metadata.as_ref(),
+                        |name| kmer::record_output_path(output_path, suffix, name),
+                    )
+                    .map(|_| ())
+                } else if opt.fragment {
+                    kmer::fragment::run_fasta_fragment_kmer_count(
+                        fasta_path,
+                        &options,
+                        metadata.as_ref(),
+                        output_path,
+                    )
+                } else if let Some(capacity) = opt.heavy_hitters {
+                    kmer::heavy::run_fasta_heavy_hitters(
+                        fasta_path,
+                        &options,
+                        capacity,
+                        metadata.as_ref(),
+                        output_path,
+                    )
+                } else if checkpointed {
+                    let config = CheckpointConfig {
+                        every_bases: opt.checkpoint_bases,
+                        every: opt
+                            .checkpoint_minutes
+                            .map(|minutes| Duration::from_secs(60 * minutes)),
+                        ..CheckpointConfig::for_output(output_path)
+                    };
+                    kmer::checkpoint::run_fasta_kmer_count_checkpointed(
+                        fasta_path,
+                        &options,
+                        &config,
+                        opt.resume_checkpoint,
+                        metadata.as_ref(),
+                        output_path,
+                    )
+                } else {
+                    kmer::run_fasta_kmer_count_timed(
+                        fasta_path,
+                        &options,
+                        metadata.as_ref(),
+                        Some(output_path),
+                        &mut PhaseTimings::disabled(),
+                    )
+                }
+            };
+            let result = if opt.self_check {
+                kmer::selfcheck::run_checked(output_path, run)
+            } else {
+                run(output_path)
+            };
+            let mut entries = kmer::manifest::group_entries(group, output_path);
+            for alias in &group.aliases {
+                info!("Skipping {:?}, identical to {:?}", alias, fasta_path);
+            }
+            match result {
+                Ok(()) => Ok((entries, None)),
+                Err(err) => {
+                    let err = err.context(format!("Could not count {:?}", fasta_path));
+                    if opt.fail_fast {
+                        return Err(err);
+                    }
+                    error!("{:#}", err);
+                    for entry in &mut entries {
+                        entry.error = Some(format!("{:#}", err));
+                    }
+                    Ok((entries, Some(err)))
+                }
+            }
+        };
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(opt.threads.unwrap_or(0))
+            .build()?;
+        let counted = pool.install(|| {
+            groups
+                .par_iter()
+                .zip(&outputs)
+                .map(|(group, output_path)| count_group(group, output_path))
+                .collect::<Result<Vec<_>>>()
+        })?;
+        for (entries, failure) in counted {
+            manifest.extend(entries);
+            failures.extend(failure);
+        }
+    }
+
+    for entry in &mut manifest {
+        entry.label = labels.get(&entry.input).cloned();
+    }
+    // a stdin input has only its table, no output directory to hold a manifest
+    if !from_stdin {
+        fs::create_dir_all(&output_root)?;
+        kmer::manifest::write_manifest(&output_root.join(MANIFEST_FILE_NAME), &manifest)?;
     }
 
+    if !failures.is_empty() {
+        eprintln!("{} input(s) failed:", failures.len());
+        for err in &failures {
+            eprintln!("  {:#}", err);
+        }
+        return Err(anyhow!("{} input(s) could not be counted", failures.len()));
+    }
     Ok(())
 }
+
+/// Inputs under `input_root` with one of the extensions of `opt`, reporting
+/// those skipped and warning if `output_root` lies inside `input_root`
+fn find_inputs(opt: &Opt, input_root: &Path, output_root: &Path) -> Result<Vec<PathBuf>> {
+    let resolved_output_root = kmer::resolve_path(output_root)?;
+    let nested = kmer::relative_to(&resolved_output_root, input_root);
+    if nested.is_some_and(|stub| stub.components().next().is_some()) {
+        warn!(
+            "Output root {:?} is inside the input directory {:?}; files in it are never counted",
+            output_root, input_root
+        );
+    }
+    let found = kmer::find_files_with_extensions(
+        input_root,
+        &opt.extensions,
+        opt.strict_discovery,
+        opt.external_symlinks,
+        Some(&resolved_output_root),
+    )?;
+    if !found.unresolved.is_empty() {
+        let unresolved: Vec<String> = found
+            .unresolved
+            .iter()
+            .map(|path| format!("{:?}", path))
+            .collect();
+        eprintln!(
+            "WARNING: Skipped {} input(s) that could not be resolved: {}",
+            unresolved.len(),
+            unresolved.join(", ")
+        );
+    }
+    for (link, target) in &found.external {
+        eprintln!(
+            "WARNING: Skipped {:?}, a symlink to {:?} outside the input directory",
+            link, target
+        );
+    }
+    Ok(found.files)
+}
+
+/// Manifest entries of an input group, with the error counting it, if any
+type CountedGroup = (Vec<ManifestEntry>, Option<anyhow::Error>);
+
+/// Output path of the primary input of each of `groups`
+///
+/// Inputs that differ only by extension, such as `sample.fa` and
+/// `sample.fasta`, map to the same output. Such collisions fail, listing the
+/// colliding inputs, unless `disambiguate` is set; then the colliding inputs
+/// keep their extension in the output name (`sample.fa_kmer.txt`).
+fn plan_outputs(
+    groups: &[InputGroup],
+    input_root: &Path,
+    output_root: &Path,
+    suffix: &str,
+    disambiguate: bool,
+) -> Result<Vec<PathBuf>> {
+    let mut outputs = groups
+        .iter()
+        .map(|group| kmer::output_path_with_suffix(&group.primary, input_root, output_root, suffix))
+        .collect::<Result<Vec<_>>>()?;
+
+    if disambiguate {
+        let colliding: Vec<PathBuf> = colliding_outputs(groups, &outputs)
+            .into_keys()
+            .map(Path::to_owned)
+            .collect();
+        for (group, output) in groups.iter().zip(outputs.iter_mut()) {
+            if colliding.contains(output) {
+                let extension = group.primary.extension().unwrap_or_default();
+                let suffix = format!(".{}{}", extension.to_string_lossy(), suffix);
+                *output = kmer::output_path_with_suffix(
+                    &group.primary,
+                    input_root,
+                    output_root,
+                    &suffix,
+                )?;
+            }
+        }
+    }
+
+    let colliding = colliding_outputs(groups, &outputs);
+    if colliding.is_empty() {
+        return Ok(outputs);
+    }
+    let collisions: Vec<String> = colliding
+        .iter()
+        .map(|(output, inputs)| {
+            let inputs: Vec<String> = inputs.iter().map(|p| format!("{:?}", p)).collect();
+            format!("{} would all write {:?}", inputs.join(", "), output)
+        })
+        .collect();
+    Err(anyhow!(
+        "Several inputs map to the same output (pass --disambiguate-outputs to keep their extensions):\n  {}",
+        collisions.join("\n  ")
+    ))
+}
+
+/// Outputs shared by more than one of `groups`, with the inputs sharing each
+fn colliding_outputs<'a>(
+    groups: &'a [InputGroup],
+    outputs: &'a [PathBuf],
+) -> BTreeMap<&'a Path, Vec<&'a Path>> {
+    let mut by_output: BTreeMap<&Path, Vec<&Path>> = BTreeMap::new();
+    for (group, output) in groups.iter().zip(outputs) {
+        by_output.entry(output).or_default().push(&group.primary);
+    }
+    by_output.retain(|_, inputs| inputs.len() > 1);
+    by_output
+}
+
+/// Fail if writing outputs next to `inputs` would overwrite any of them
+fn check_in_place_collisions(inputs: &[PathBuf], input_root: &Path, suffix: &str) -> Result<()> {
+    let mut collisions = Vec::new();
+    for input in inputs {
+        // inputs without a valid output name are skipped later
+        let output = match kmer::output_path_with_suffix(input, input_root, input_root, suffix) {
+            Ok(output) => output,
+            Err(_) => continue,
+        };
+        if inputs.contains(&output) {
+            collisions.push(format!("{:?} would overwrite input {:?}", input, output));
+        }
+    }
+    if collisions.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Refusing to write outputs in place:\n  {}",
+            collisions.join("\n  ")
+        ))
+    }
+}
+
+/// Report throughput and phase timings of repeated counting runs
+fn run_bench(opt: BenchOpt) -> Result<()> {
+    let options = kmer::CountOptions::new(opt.k);
+    options.validate()?;
+    let runs = kmer::bench::bench_fasta(&opt.fasta, &options, opt.output.as_deref(), opt.repeat)?;
+    kmer::bench::write_report(&runs, io::stdout().lock())
+}
+
+/// Report per-record complexity scores for a single fasta file
+fn run_complexity(opt: ComplexityOpt) -> Result<()> {
+    let min_k = if opt.linguistic_complexity { 1 } else { opt.k };
+    let mut scores = kmer::complexity::fasta_linguistic_complexity(&opt.fasta, min_k..=opt.k)?;
+    if !opt.unsorted {
+        kmer::complexity::sort_by_complexity(&mut scores);
+    }
+
+    match opt.output {
+        Some(path) => kmer::complexity::write_complexity(&scores, opt.format, File::create(path)?),
+        None => kmer::complexity::write_complexity(&scores, opt.format, io::stdout().lock()),
+    }
+}
+
+/// Report a genome size estimate from a kmer spectrum
+fn run_genomesize(opt: GenomeSizeOpt) -> Result<()> {
+    let histogram = match opt.k {
+        Some(k) if !opt.histogram => kmer::histogram::fasta_kmer_histogram(&opt.input, k)?,
+        _ => kmer::histogram::read_histogram(&opt.input)?,
+    };
+    let estimate = kmer::genomesize::estimate_genome_size(&histogram)?;
+    kmer::genomesize::write_estimate(&estimate, opt.k, opt.format, io::stdout().lock())
+}
+
+/// Estimate the sequencing error rate from a histogram or count table
+fn run_errorrate(opt: ErrorRateOpt) -> Result<()> {
+    let histogram = if opt.table {
+        let table = kmer::table::read_count_table(&opt.input)?;
+        kmer::histogram::histogram_from_counts(table.into_iter().map(|(_, count)| count))
+    } else {
+        kmer::histogram::read_histogram(&opt.input)?
+    };
+    let estimate = kmer::errorrate::estimate_error_rate(&histogram, opt.k)?;
+    kmer::errorrate::write_error_rate(&estimate, opt.format, io::stdout().lock())
+}
+
+/// Write the MinHash sketch of a fasta file or count table
+fn run_sketch(opt: SketchOpt) -> Result<()> {
+    let sketch = if opt.table {
+        let rows = kmer::table::read_count_table(&opt.input)?;
+        Sketch::from_table(&rows, opt.size, opt.seed)
+            .with_context(|| format!("Could not sketch {:?}", opt.input))?
+    } else {
+        let k = opt.k.expect("-k is required without --table");
+        kmer::CountOptions::new(k).validate()?;
+        Sketch::from_fasta(&opt.input, k, opt.size, opt.seed)?
+    };
+    match opt.output {
+        Some(path) => sketch.write(io::BufWriter::new(File::create(path)?)),
+        None => sketch.write(io::stdout().lock()),
+    }
+}
+
+/// Print the Mash distance and Jaccard estimate between two fasta files
+fn run_mash_dist(opt: MashDistOpt) -> Result<()> {
+    kmer::CountOptions::new(opt.k).validate()?;
+    let a = Sketch::from_fasta(&opt.a, opt.k, opt.size, opt.seed)?;
+    let b = Sketch::from_fasta(&opt.b, opt.k, opt.size, opt.seed)?;
+    for (path, sketch) in [(&opt.a, &a), (&opt.b, &b)].iter() {
+        if sketch.is_empty() {
+            return Err(anyhow!("No kmers of length {} in {:?}", opt.k, path));
+        }
+    }
+    println!(
+        "{}\t{}\t{:.6}\t{:.6}",
+        opt.a.display(),
+        opt.b.display(),
+        a.mash_distance(&b),
+        a.jaccard(&b)
+    );
+    Ok(())
+}
+
+/// Report pairwise kmer distances between fasta files or sketches, and optionally their UPGMA tree
+fn run_cluster(opt: ClusterOpt) -> Result<()> {
+    let extensions = if opt.sketches {
+        vec!["sketch".to_owned()]
+    } else {
+        opt.extensions.clone()
+    };
+    let paths = kmer::fs_find_files_with_extensions(&opt.directory, &extensions)?;
+    let labels = match &opt.label_from_path {
+        Some(template) => template.unique_labels(&paths)?,
+        None => paths
+            .iter()
+            .map(|path| {
+                path.file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect(),
+    };
+
+    let matrix = match opt.k {
+        None => {
+            let sketches = paths
+                .iter()
+                .map(|path| Sketch::read(path))
+                .collect::<Result<Vec<_>>>()?;
+            kmer::cluster::sketch_distance_matrix(&sketches)?
+        }
+        Some(k) => {
+            if opt.log_tf && matches!(opt.metric, DistanceMetric::D2S | DistanceMetric::D2Star) {
+                ClapError::with_description(
+                    "--log-tf cannot be used with --metric d2s or d2star, which compare raw counts",
+                    ErrorKind::ArgumentConflict,
+                )
+                .exit()
+            }
+            let options = kmer::CountOptions {
+                log_tf: opt.log_tf,
+                ..kmer::CountOptions::new(k)
+            };
+            options.validate()?;
+            let profiles = paths
+                .iter()
+                .map(|path| {
+                    info!("Counting kmers in {:?}", path);
+                    kmer::cluster::fasta_profile(path, &options)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            kmer::cluster::distance_matrix(&profiles, opt.metric)
+        }
+    };
+
+    match &opt.output {
+        Some(path) => kmer::cluster::write_matrix(&labels, &matrix, File::create(path)?)?,
+        None => kmer::cluster::write_matrix(&labels, &matrix, io::stdout().lock())?,
+    }
+    if let Some(tree_path) = &opt.tree {
+        let tree = kmer::cluster::upgma(&matrix)
+            .ok_or_else(|| anyhow!("No inputs found in {:?}", opt.directory))?;
+        fs::write(tree_path, format!("{}\n", tree.newick(&labels)))?;
+    }
+    Ok(())
+}
+
+/// Report the QV and completeness of an assembly against its reads' kmers
+fn run_qv(opt: QvOpt) -> Result<()> {
+    kmer::CountOptions::new(opt.k).validate()?;
+    let rows = kmer::table::read_count_table(&opt.counts)?;
+    let estimate = kmer::qv::estimate_qv(rows, &opt.assembly, opt.k, opt.canonical, opt.min_count)
+        .with_context(|| format!("Could not evaluate {:?}", opt.assembly))?;
+    kmer::qv::write_qv(&estimate, opt.format, io::stdout().lock())
+}
+
+/// Report the strand bias of a count table, optionally writing every kmer pair
+fn run_strandbias(opt: StrandBiasOpt) -> Result<()> {
+    let rows = kmer::table::read_count_table(&opt.table)?;
+    // varint tables have no metadata header
+    let metadata = RunMetadata::read(&opt.table).ok();
+    let (pairs, summary) =
+        kmer::strandbias::strand_bias(&rows, metadata.as_ref(), opt.min_total, opt.extreme)
+            .with_context(|| format!("Could not measure the strand bias of {:?}", opt.table))?;
+    if let Some(path) = &opt.pairs {
+        kmer::strandbias::write_bias_pairs(
+            &pairs,
+            opt.extreme,
+            io::BufWriter::new(File::create(path)?),
+        )?;
+    }
+    kmer::strandbias::write_bias_summary(&summary, opt.format, io::stdout().lock())
+}
+
+/// Print every row of a varint table
+fn run_decode(opt: DecodeOpt) -> Result<()> {
+    let mut out = io::stdout().lock();
+    writeln!(out, "kmer\tcount")?;
+    for (kmer, count) in kmer::varint::read_varint_table(&opt.table)? {
+        writeln!(out, "{}\t{}", kmer, count)?;
+    }
+    Ok(())
+}
+
+/// Print the rows of an indexed table matching each query prefix
+fn run_lookup(opt: LookupOpt) -> Result<()> {
+    let mut out = io::stdout().lock();
+    writeln!(out, "kmer\tcount")?;
+    for query in &opt.queries {
+        for (kmer, count) in kmer::index::lookup(&opt.table, query)? {
+            writeln!(out, "{}\t{}", kmer, count)?;
+        }
+    }
+    Ok(())
+}
+
+/// Report the fraction of reads classified to each reference
+fn run_screen(opt: ScreenOpt) -> Result<()> {
+    if !(0.0..=1.0).contains(&opt.min_fraction) {
+        ClapError::with_description(
+            "--min-fraction must be between 0 and 1",
+            ErrorKind::InvalidValue,
+        )
+        .exit()
+    }
+    let references = opt
+        .references
+        .iter()
+        .map(Reference::load)
+        .collect::<Result<Vec<_>>>()?;
+    let rule = ScreenRule {
+        min_fraction: opt.min_fraction,
+        ties: opt.ties,
+    };
+
+    let summary = kmer::screen::screen_fasta(&opt.reads, &references, &rule)?;
+    kmer::screen::write_summary(&summary, opt.format, io::stdout().lock())
+}
+
+/// Report clusters of near-duplicate records, optionally writing a deduplicated copy
+fn run_dedup(opt: DedupOpt) -> Result<()> {
+    let records = kmer::dedup::sketch_fasta(&opt.fasta, opt.k, opt.sketch_size)?;
+    let clusters = kmer::dedup::find_clusters(&records, opt.metric, opt.threshold);
+    kmer::dedup::write_clusters(&records, &clusters, opt.format, io::stdout().lock())?;
+
+    if let Some(output_path) = opt.write_deduped {
+        let written = kmer::dedup::write_deduped(&opt.fasta, &clusters, &output_path)?;
+        info!(
+            "Wrote {} of {} records to {:?}",
+            written,
+            records.len(),
+            output_path
+        );
+    }
+    Ok(())
+}
+
+/// Report the database records most similar to the query by kmer profile
+fn run_search(opt: SearchOpt) -> Result<()> {
+    let options = kmer::CountOptions {
+        canonical: opt.canonical,
+        log_tf: opt.log_tf,
+        ..kmer::CountOptions::new(opt.k)
+    };
+    options.validate()?;
+    let query = kmer::cluster::fasta_profile(&opt.query, &options)?;
+    if query.is_empty() {
+        return Err(anyhow!("No kmers of length {} in {:?}", opt.k, opt.query));
+    }
+    let hits = kmer::search::search_fasta(&query, &opt.db, &options, opt.metric, opt.top)?;
+    kmer::search::write_hits(&hits, opt.format, io::stdout().lock())
+}
+
+/// Write a copy of a fasta file with regions covered by abundant kmers masked
+fn run_mask(opt: MaskOpt) -> Result<()> {
+    let abundant = AbundantKmers::load(&opt.counts, opt.min_count, opt.canonical)?;
+    let style = if opt.hard {
+        MaskStyle::Hard
+    } else {
+        MaskStyle::Soft
+    };
+
+    let masked = kmer::mask::mask_fasta(&opt.fasta, &abundant, style, &opt.output)?;
+    info!("Masked {} bases. Output to {:?}", masked, opt.output);
+    Ok(())
+}
+
+/// Print each invalid count table under a directory with the reason, failing
+/// unless they were all deleted
+fn run_validate_outputs(opt: ValidateOutputsOpt) -> Result<()> {
+    let tables = validate::find_count_tables(&opt.dir)?;
+    let mut out = io::stdout().lock();
+    let mut invalid = 0;
+    for table in &tables {
+        if let Some(reason) = validate::check_table(table)? {
+            writeln!(out, "{}\t{}", table.display(), reason)?;
+            invalid += 1;
+            if opt.delete {
+                fs::remove_file(table)?;
+                let index = kmer::index::index_path(table);
+                if index.exists() {
+                    fs::remove_file(index)?;
+                }
+            }
+        }
+    }
+    info!("Checked {} count tables, {} invalid", tables.len(), invalid);
+    if invalid > 0 && !opt.delete {
+        return Err(anyhow!(
+            "{} of {} count tables under {:?} are invalid",
+            invalid,
+            tables.len(),
+            opt.dir
+        ));
+    }
+    Ok(())
+}
+
+/// Compare a count table with the expected one, printing the differences
+fn run_verify(opt: VerifyOpt) -> Result<()> {
+    if !(0.0..=1.0).contains(&opt.tolerance) {
+        ClapError::with_description(
+            "--tolerance must be between 0 and 1",
+            ErrorKind::InvalidValue,
+        )
+        .exit()
+    }
+    let expected = kmer::table::read_count_table(&opt.expected)?;
+    let actual = kmer::table::read_count_table(&opt.actual)?;
+    let verification = kmer::verify::compare_tables(&expected, &actual, opt.tolerance);
+    kmer::verify::write_report(&verification, opt.show, io::stdout().lock())?;
+    if !verification.matches() {
+        return Err(anyhow!(
+            "{:?} differs from {:?} in {} kmer(s)",
+            opt.actual,
+            opt.expected,
+            verification.differences.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Answer queries against a count table read from stdin until `quit`
+fn run_shell(opt: ShellOpt) -> Result<()> {
+    let table = kmer::shell::CountTable::load(&opt.table)?;
+    info!("Loaded {} kmers of length {}", table.len(), table.k);
+    kmer::shell::run(&table, io::stdin().lock(), io::stdout().lock(), true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn groups(paths: &[&str]) -> Vec<InputGroup> {
+        paths
+            .iter()
+            .map(|path| InputGroup::single(PathBuf::from(path)))
+            .collect()
+    }
+
+    fn plan(paths: &[&str], disambiguate: bool) -> Result<Vec<PathBuf>> {
+        plan_outputs(
+            &groups(paths),
+            Path::new("/in"),
+            Path::new("/out"),
+            "_kmer.txt",
+            disambiguate,
+        )
+    }
+
+    #[test]
+    fn test_plan_outputs() {
+        assert_eq!(
+            plan(&["/in/a.fasta", "/in/b.fa"], false).unwrap(),
+            [
+                PathBuf::from("/out/a_kmer.txt"),
+                PathBuf::from("/out/b_kmer.txt")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_outputs_same_stem_collision() {
+        let err = plan(
+            &["/in/sample.fa", "/in/sample.fasta", "/in/other.fa"],
+            false,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains(
+            "\"/in/sample.fa\", \"/in/sample.fasta\" would all write \"/out/sample_kmer.txt\""
+        ));
+        assert!(!err.contains("other"));
+    }
+
+    #[test]
+    fn test_plan_outputs_disambiguate() {
+        assert_eq!(
+            plan(&["/in/sample.fa", "/in/sample.fasta", "/in/other.fa"], true).unwrap(),
+            [
+                PathBuf::from("/out/sample.fa_kmer.txt"),
+                PathBuf::from("/out/sample.fasta_kmer.txt"),
+                PathBuf::from("/out/other_kmer.txt")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_outputs_nested_directories() {
+        // the same name in different directories keeps distinct outputs
+        assert_eq!(
+            plan(&["/in/x/sample.fa", "/in/y/sample.fa"], false).unwrap(),
+            [
+                PathBuf::from("/out/x/sample_kmer.txt"),
+                PathBuf::from("/out/y/sample_kmer.txt")
+            ]
+        );
+        assert!(plan(&["/in/x/sample.fa", "/in/x/sample.fasta"], false).is_err());
+        assert_eq!(
+            plan(&["/in/x/sample.fa", "/in/x/sample.fasta"], true).unwrap(),
+            [
+                PathBuf::from("/out/x/sample.fa_kmer.txt"),
+                PathBuf::from("/out/x/sample.fasta_kmer.txt")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extensions() {
+        let extensions = |args: &[&str]| {
+            let args = ["kmer", "-k", "3"].iter().chain(args);
+            Opt::from_iter_safe(args).map(|opt| opt.extensions)
+        };
+        assert_eq!(
+            extensions(&[]).unwrap(),
+            ["fa", "fasta", "fna", "fq", "fastq"]
+        );
+        assert_eq!(extensions(&["-e", "fa, .fasta"]).unwrap(), ["fa", "fasta"]);
+        assert_eq!(
+            extensions(&["-e", "fa", "-e", "fasta,fna"]).unwrap(),
+            ["fa", "fasta", "fna"]
+        );
+        assert!(extensions(&["-e", "fa,,fasta"]).is_err());
+    }
+}
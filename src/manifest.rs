@@ -0,0 +1,124 @@
+//! Run manifest mapping every input to its result
+//!
+//! Directory runs write `manifest.tsv` to the output root with one row per
+//! discovered input, so inputs whose results were shared with another input
+//! can still be traced to an output, and inputs that failed carry the error.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::inputs::InputGroup;
+
+/// File name of the manifest in the output root
+pub const MANIFEST_FILE_NAME: &str = "manifest.tsv";
+
+/// Result of processing one input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    /// identical input whose output this input shares
+    pub duplicate_of: Option<PathBuf>,
+    /// sample label, with --label-from-path
+    pub label: Option<String>,
+    /// why the input could not be counted
+    pub error: Option<String>,
+}
+
+/// Entries mapping the primary input of `group` and all its aliases to `output`
+pub fn group_entries(group: &InputGroup, output: &Path) -> Vec<ManifestEntry> {
+    let primary = ManifestEntry {
+        input: group.primary.clone(),
+        output: output.to_owned(),
+        duplicate_of: None,
+        label: None,
+        error: None,
+    };
+    let aliases = group.aliases.iter().map(|alias| ManifestEntry {
+        input: alias.clone(),
+        output: output.to_owned(),
+        duplicate_of: Some(group.primary.clone()),
+        label: None,
+        error: None,
+    });
+    std::iter::once(primary).chain(aliases).collect()
+}
+
+/// Write `entries` as a tab-separated manifest to `path`
+pub fn write_manifest(path: &Path, entries: &[ManifestEntry]) -> Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "input\toutput\tduplicate_of\tlabel\terror")?;
+    for entry in entries {
+        // keep each error on one line and in one column
+        let error = entry
+            .error
+            .as_deref()
+            .unwrap_or_default()
+            .replace(['\t', '\n', '\r'], " ");
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}",
+            entry.input.display(),
+            entry.output.display(),
+            entry
+                .duplicate_of
+                .as_ref()
+                .map(|p| p.display().to_string())
+                .unwrap_or_default(),
+            entry.label.as_deref().unwrap_or_default(),
+            error
+        )?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_write_manifest() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join(MANIFEST_FILE_NAME);
+        write_manifest(
+            &path,
+            &[
+                ManifestEntry {
+                    input: PathBuf::from("/in/a.fasta"),
+                    output: PathBuf::from("/out/a_kmer.txt"),
+                    duplicate_of: None,
+                    label: Some("a".to_owned()),
+                    error: None,
+                },
+                ManifestEntry {
+                    input: PathBuf::from("/in/b.fasta"),
+                    output: PathBuf::from("/out/a_kmer.txt"),
+                    duplicate_of: Some(PathBuf::from("/in/a.fasta")),
+                    label: None,
+                    error: None,
+                },
+                ManifestEntry {
+                    input: PathBuf::from("/in/c.fasta"),
+                    output: PathBuf::from("/out/c_kmer.txt"),
+                    duplicate_of: None,
+                    label: None,
+                    error: Some("Could not count:\n\tbad header".to_owned()),
+                },
+            ],
+        )?;
+
+        assert_eq!(
+            std::fs::read_to_string(&path)?,
+            "input\toutput\tduplicate_of\tlabel\terror\n\
+             /in/a.fasta\t/out/a_kmer.txt\t\ta\t\n\
+             /in/b.fasta\t/out/a_kmer.txt\t/in/a.fasta\t\t\n\
+             /in/c.fasta\t/out/c_kmer.txt\t\t\tCould not count:  bad header\n"
+        );
+        Ok(())
+    }
+}
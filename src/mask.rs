@@ -0,0 +1,247 @@
+//! Masking of sequence regions covered by abundant kmers
+//!
+//! A quick repeat masker: every base covered by at least one kmer whose
+//! abundance in a count table meets a threshold is soft-masked (lowercased)
+//! or hard-masked (replaced by `N`).
+
+use std::collections::HashSet;
+use std::io::BufWriter;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+
+use crate::seqio::{detect_line_width, WrappingFastaWriter, DEFAULT_LINE_WIDTH};
+use crate::sketch::canonical;
+use crate::table::read_kmer_table;
+use crate::{create_output, kmer_positions, open_fasta};
+
+/// How masked bases are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaskStyle {
+    /// lowercase masked bases
+    Soft,
+    /// replace masked bases with `N`
+    Hard,
+}
+
+/// Union of half-open `[start, end)` intervals added in order of start
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct IntervalUnion {
+    intervals: Vec<(usize, usize)>,
+}
+
+impl IntervalUnion {
+    pub fn new() -> Self {
+        IntervalUnion::default()
+    }
+
+    /// Add `[start, end)`; `start` must not precede the start of the last interval added
+    pub fn add(&mut self, start: usize, end: usize) {
+        match self.intervals.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => self.intervals.push((start, end)),
+        }
+    }
+
+    /// Disjoint intervals in ascending order
+    pub fn intervals(&self) -> &[(usize, usize)] {
+        &self.intervals
+    }
+}
+
+/// Kmers meeting the abundance threshold
+#[derive(Debug, Clone)]
+pub struct AbundantKmers {
+    pub k: usize,
+    /// table holds canonical kmers, so windows are matched on both strands
+    pub canonical: bool,
+    kmers: HashSet<String>,
+}
+
+impl AbundantKmers {
+    /// Load the kmers of the count table at `table_path` with count at least `min_count`
+    pub fn load(table_path: &Path, min_count: u64, canonical: bool) -> Result<Self> {
+        let rows = read_kmer_table(table_path)?;
+        let k = match rows.first() {
+            Some((kmer, _)) => kmer.len(),
+            None => return Err(anyhow!("Count table {:?} is empty", table_path)),
+        };
+        Ok(AbundantKmers {
+            k,
+            canonical,
+            kmers: rows
+                .into_iter()
+                .filter(|(_, count)| *count >= min_count)
+                .map(|(kmer, _)| kmer)
+                .collect(),
+        })
+    }
+
+    fn contains(&self, window: &str) -> bool {
+        if self.canonical {
+            // canonical() of an ASCII window is ASCII
+            let canonical = canonical(window.as_bytes());
+            self.kmers
+                .contains(std::str::from_utf8(&canonical).unwrap())
+        } else {
+            self.kmers.contains(window)
+        }
+    }
+}
+
+/// Regions of `sequence` covered by at least one abundant kmer
+///
+/// Sequences shorter than the kmer length have no covered regions.
+pub fn covered_regions(sequence: &[u8], abundant: &AbundantKmers) -> IntervalUnion {
+    let mut covered = IntervalUnion::new();
+    if let Ok(windows) = kmer_positions(sequence, abundant.k) {
+        for (start, window) in windows {
+            if abundant.contains(window) {
+                covered.add(start, start + abundant.k);
+            }
+        }
+    }
+    covered
+}
+
+/// Mask the `regions` of `sequence` in place
+pub fn apply_mask(sequence: &mut [u8], regions: &IntervalUnion, style: MaskStyle) {
+    for &(start, end) in regions.intervals() {
+        for base in &mut sequence[start..end] {
+            *base = match style {
+                MaskStyle::Soft => base.to_ascii_lowercase(),
+                MaskStyle::Hard => b'N',
+            };
+        }
+    }
+}
+
+/// Write a masked copy of the fasta file at `fasta_path` to `output_path`
+///
+/// Headers are kept and sequences are wrapped at the input's line width.
+/// Returns the total number of masked bases.
+pub fn mask_fasta(
+    fasta_path: &Path,
+    abundant: &AbundantKmers,
+    style: MaskStyle,
+    output_path: &Path,
+) -> Result<usize> {
+    let line_width = detect_line_width(fasta_path)?.unwrap_or(DEFAULT_LINE_WIDTH);
+    let mut writer =
+        WrappingFastaWriter::new(BufWriter::new(create_output(output_path)?), line_width);
+
+    let mut masked = 0;
+    for record in open_fasta(fasta_path)?.records() {
+        let record = record?;
+        let regions = covered_regions(record.seq(), abundant);
+        masked += regions
+            .intervals()
+            .iter()
+            .map(|(start, end)| end - start)
+            .sum::<usize>();
+
+        let mut sequence = record.seq().to_vec();
+        apply_mask(&mut sequence, &regions, style);
+        writer.write(record.id(), record.desc(), &sequence)?;
+    }
+    writer.flush()?;
+    Ok(masked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reverse_complement;
+    use std::fs::File;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    const MOTIF: &str = "GATTACA";
+
+    /// table in which only the kmers of MOTIF are abundant
+    fn motif_table(dir: &Path, canonical_kmers: bool) -> Result<std::path::PathBuf> {
+        let path = dir.join("counts_kmer.txt");
+        let mut file = File::create(&path)?;
+        writeln!(file, "kmer\tcount")?;
+        for kmer in crate::kmers(MOTIF.as_bytes(), 4)? {
+            let kmer = if canonical_kmers {
+                String::from_utf8(canonical(kmer.as_bytes()))?
+            } else {
+                kmer.to_owned()
+            };
+            writeln!(file, "{}\t100", kmer)?;
+        }
+        writeln!(file, "CCCC\t1")?;
+        Ok(path)
+    }
+
+    #[test]
+    fn test_interval_union() {
+        let mut union = IntervalUnion::new();
+        union.add(0, 4);
+        union.add(1, 5);
+        union.add(5, 9);
+        union.add(12, 16);
+        assert_eq!(union.intervals(), [(0, 9), (12, 16)]);
+    }
+
+    #[test]
+    fn test_covered_regions() -> Result<()> {
+        let dir = tempdir()?;
+        let abundant = AbundantKmers::load(&motif_table(dir.path(), false)?, 100, false)?;
+
+        //              motif at 3..10      and 15..22
+        let sequence = b"CCCGATTACACCCCCGATTACACC";
+        let regions = covered_regions(sequence, &abundant);
+        assert_eq!(regions.intervals(), [(3, 10), (15, 22)]);
+
+        let mut masked = sequence.to_vec();
+        apply_mask(&mut masked, &regions, MaskStyle::Soft);
+        assert_eq!(masked, b"CCCgattacaCCCCCgattacaCC");
+        apply_mask(&mut masked, &regions, MaskStyle::Hard);
+        assert_eq!(masked, b"CCCNNNNNNNCCCCCNNNNNNNCC");
+        Ok(())
+    }
+
+    #[test]
+    fn test_covered_regions_canonical() -> Result<()> {
+        let dir = tempdir()?;
+        let table = motif_table(dir.path(), true)?;
+
+        // the motif's reverse complement only matches a canonical table
+        let mut sequence = b"CCC".to_vec();
+        sequence.extend(reverse_complement(MOTIF.as_bytes()));
+        sequence.extend(b"CC");
+
+        let abundant = AbundantKmers::load(&table, 100, true)?;
+        assert_eq!(covered_regions(&sequence, &abundant).intervals(), [(3, 10)]);
+
+        let stranded = AbundantKmers::load(&motif_table(dir.path(), false)?, 100, false)?;
+        assert!(covered_regions(&sequence, &stranded).intervals().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mask_fasta() -> Result<()> {
+        let dir = tempdir()?;
+        let abundant = AbundantKmers::load(&motif_table(dir.path(), false)?, 100, false)?;
+
+        let fasta_path = dir.path().join("genome.fasta");
+        let mut fasta = File::create(&fasta_path)?;
+        write!(
+            fasta,
+            ">chr1 first\nCCCGATTA\nCACCCCCG\nATTACACC\n>chr2\nCCCC\n"
+        )?;
+
+        let output_path = dir.path().join("masked.fasta");
+        assert_eq!(
+            mask_fasta(&fasta_path, &abundant, MaskStyle::Soft, &output_path)?,
+            14
+        );
+        assert_eq!(
+            std::fs::read_to_string(&output_path)?,
+            ">chr1 first\nCCCgatta\ncaCCCCCg\nattacaCC\n>chr2\nCCCC\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,311 @@
+//! Run settings recorded as `#` comment lines at the top of text outputs
+//!
+//! Each line is `# key: value`. Loaders in this crate skip `#` lines, so
+//! tables with a metadata header remain readable by every subcommand.
+
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+
+use crate::{CountOptions, OutputFormat};
+
+/// Ordered `key: value` settings describing how an output was produced
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunMetadata {
+    entries: Vec<(String, String)>,
+}
+
+impl RunMetadata {
+    pub fn new() -> Self {
+        RunMetadata::default()
+    }
+
+    /// Add the setting `key`, replacing any earlier value
+    pub fn with<V: ToString>(mut self, key: &str, value: V) -> Self {
+        self.entries.retain(|(k, _)| k != key);
+        self.entries.push((key.to_owned(), value.to_string()));
+        self
+    }
+
+    /// Settings of a count run of `options` over the input at `input_path`,
+    /// stamped with the current time
+    pub fn for_count(options: &CountOptions, input_path: &Path) -> Self {
+        let mut metadata = RunMetadata::new()
+            .with(
+                "version",
+                concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION")),
+            )
+            .with("k", options.k)
+            .with("mismatches", options.mismatches)
+            .with(
+                "library_type",
+                format!("{:?}", options.library_type).to_lowercase(),
+            )
+            .with("translate", options.translate)
+            .with("canonical", options.canonical)
+            .with("case", options.case.name())
+            .with("output", output_mode(options));
+        if options.canonical {
+            metadata = metadata.with(
+                "canonical_rule",
+                format!("{:?}", options.canonical_rule).to_lowercase(),
+            );
+        }
+        if let Some(gc_min) = options.gc_min {
+            metadata = metadata.with("gc_min", gc_min);
+        }
+        if let Some(gc_max) = options.gc_max {
+            metadata = metadata.with("gc_max", gc_max);
+        }
+        if let Some(top_n) = options.top_n {
+            metadata = metadata.with("top_n", top_n);
+        }
+        if options.min_count > 1 {
+            metadata = metadata.with("min_count", options.min_count);
+        }
+        if let Some(min_len) = options.orf_min_len {
+            metadata = metadata.with("orf_min_len", min_len);
+        }
+        if options.dedup_records {
+            metadata = metadata.with(
+                "dedup_records",
+                options
+                    .dedup_threshold
+                    .map_or_else(|| "exact".to_owned(), |threshold| threshold.to_string()),
+            );
+        }
+        if let Some(split) = options.split {
+            metadata = metadata
+                .with("split_train_fraction", split.train_fraction)
+                .with("split_seed", split.seed);
+        }
+        if let Some(shards) = options.minimizer_shards {
+            metadata = metadata
+                .with("minimizer_len", shards.m)
+                .with("minimizer_shards", shards.shards);
+        }
+        if options.revcomp_column {
+            metadata = metadata.with("revcomp_column", true);
+        }
+        if options.tm {
+            metadata = metadata.with("tm", "wallace");
+        }
+        if options.translate {
+            metadata = metadata.with(
+                "stop_codons",
+                format!("{:?}", options.stop_codons).to_lowercase(),
+            );
+        }
+        metadata
+            .with("input", input_path.display())
+            .with("record_mode", "aggregate")
+            .with("created", utc_timestamp(SystemTime::now()))
+    }
+
+    /// Add the `command` line `args` that produced the output, quoted for a
+    /// POSIX shell, and the `working_directory` it ran in
+    pub fn with_provenance<I, S>(self, args: I, working_directory: &Path) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let args: Vec<String> = args
+            .into_iter()
+            .map(|arg| shell_quote(&arg.as_ref().to_string_lossy()))
+            .collect();
+        self.with("command", args.join(" "))
+            .with("working_directory", working_directory.display())
+    }
+
+    /// Value of the setting `key`
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn entries(&self) -> &[(String, String)] {
+        &self.entries
+    }
+
+    /// Write the settings as `# key: value` lines
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for (key, value) in &self.entries {
+            writeln!(out, "# {}: {}", key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Read the settings from the leading `#` lines of the file at `path`
+    ///
+    /// Comment lines that are not `key: value` pairs are ignored.
+    pub fn read(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Could not open {:?}", path))?;
+        let mut metadata = RunMetadata::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            let comment = match line.strip_prefix('#') {
+                Some(comment) => comment.trim(),
+                None => break,
+            };
+            if let Some((key, value)) = comment.split_once(": ") {
+                metadata = metadata.with(key, value);
+            }
+        }
+        Ok(metadata)
+    }
+}
+
+fn output_mode(options: &CountOptions) -> &'static str {
+    if options.indexed_output {
+        "indexed"
+    } else if options.varint_output {
+        "varint"
+    } else if options.format != OutputFormat::Tsv {
+        options.format.name()
+    } else if options.log_tf {
+        "log_tf"
+    } else {
+        "counts"
+    }
+}
+
+/// Write `metadata`, if any, to `out`
+pub(crate) fn write_header<W: Write>(
+    metadata: Option<&RunMetadata>,
+    out: &mut W,
+) -> io::Result<()> {
+    match metadata {
+        Some(metadata) => metadata.write(out),
+        None => Ok(()),
+    }
+}
+
+/// `time` as an ISO 8601 UTC timestamp, e.g. `2021-03-04T05:06:07Z`
+pub fn utc_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = ((secs / 86_400) as i64, secs % 86_400);
+
+    // civil date from days since the epoch (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// `arg` as a single word for a POSIX shell, single-quoted unless it is made
+/// of characters no shell treats specially
+///
+/// Line breaks are written as `\n` so the result stays on one header line.
+fn shell_quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+    let quoted = if !arg.is_empty() && arg.chars().all(plain) {
+        arg.to_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    };
+    quoted.replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::read_kmer_table;
+    use crate::timing::PhaseTimings;
+    use crate::{run_fasta_kmer_count, run_fasta_kmer_count_timed};
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_utc_timestamp() {
+        assert_eq!(utc_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        let leap_day = UNIX_EPOCH + Duration::from_secs(951_782_400 + 3_723);
+        assert_eq!(utc_timestamp(leap_day), "2000-02-29T01:02:03Z");
+    }
+
+    #[test]
+    fn test_metadata_roundtrip() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("m.fasta");
+        std::fs::write(&fasta_path, ">a\nACGTACGA\n")?;
+        let output_path = dir.path().join("m_kmer.txt");
+
+        let options = CountOptions {
+            mismatches: 1,
+            metadata_header: true,
+            ..CountOptions::new(3)
+        };
+        run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
+
+        let metadata = RunMetadata::read(&output_path)?;
+        assert_eq!(metadata.get("k"), Some("3"));
+        assert_eq!(metadata.get("mismatches"), Some("1"));
+        assert_eq!(metadata.get("library_type"), Some("unstranded"));
+        assert_eq!(metadata.get("input"), Some(fasta_path.to_str().unwrap()));
+        assert!(metadata.get("version").unwrap().starts_with("kmer "));
+        assert!(metadata.get("created").is_some());
+
+        // the crate's own loader still reads the table
+        assert!(!read_kmer_table(&output_path)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("--split=0.8"), "--split=0.8");
+        assert_eq!(shell_quote("my reads"), "'my reads'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("a\nb"), "'a\\nb'");
+    }
+
+    #[test]
+    fn test_provenance() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("p.fasta");
+        std::fs::write(&fasta_path, ">a\nACGTACGA\n")?;
+        let output_path = dir.path().join("p_kmer.txt");
+
+        let options = CountOptions::new(3);
+        let metadata = RunMetadata::for_count(&options, &fasta_path)
+            .with_provenance(["kmer", "-k", "3", "in dir"], Path::new("/work"));
+        run_fasta_kmer_count_timed(
+            &fasta_path,
+            &options,
+            Some(&metadata),
+            Some(&output_path),
+            &mut PhaseTimings::disabled(),
+        )?;
+
+        let read = RunMetadata::read(&output_path)?;
+        assert_eq!(read.get("k"), Some("3"));
+        assert!(read.get("version").unwrap().starts_with("kmer "));
+        assert_eq!(read.get("command"), Some("kmer -k 3 'in dir'"));
+        assert_eq!(read.get("working_directory"), Some("/work"));
+        assert_eq!(read_kmer_table(&output_path)?.len(), 5);
+        Ok(())
+    }
+}
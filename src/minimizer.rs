@@ -0,0 +1,148 @@
+//! Partitions of counted kmers by minimizer
+//!
+//! The minimizer of a kmer is its length `m` substring with the smallest
+//! `sketch::hash_bytes` hash under seed 0, the leftmost on ties. Hash order
+//! rather than lexicographic order keeps poly-A runs from drawing most kmers
+//! into one shard. A kmer goes to shard `h mod shards`, `h` being the hash of
+//! its minimizer, so kmers sharing a minimizer always share a shard, and
+//! overlapping kmers of a sequence, which mostly share their minimizer, mostly
+//! do too.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use crate::sketch::hash_bytes;
+use crate::KmerCount;
+
+/// Minimizer length used when none is given, or `k` if it is shorter
+pub const DEFAULT_MINIMIZER_LEN: usize = 11;
+
+/// How kmers are partitioned into shards by minimizer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MinimizerShards {
+    /// minimizer length, from 1 to `k`
+    pub m: usize,
+    /// number of shards, at least 1
+    pub shards: usize,
+}
+
+impl MinimizerShards {
+    /// Minimizer of `kmer` and its hash, or `None` if `kmer` is shorter than `m`
+    pub fn minimizer<'a>(&self, kmer: &'a str) -> Option<(&'a str, u64)> {
+        let mut best: Option<(&str, u64)> = None;
+        for start in 0..(kmer.len() + 1).checked_sub(self.m)? {
+            let window = &kmer[start..start + self.m];
+            let hash = hash_bytes(window.as_bytes(), 0);
+            if best.is_none_or(|(_, best_hash)| hash < best_hash) {
+                best = Some((window, hash));
+            }
+        }
+        best
+    }
+
+    /// Shard of `kmer`, from 0 to `shards - 1`
+    ///
+    /// A kmer shorter than `m` has no minimizer and goes to shard 0.
+    pub fn assign(&self, kmer: &str) -> usize {
+        self.minimizer(kmer)
+            .map_or(0, |(_, hash)| (hash % self.shards as u64) as usize)
+    }
+
+    /// Kmers of `kmer_count` in each shard, each in the original order
+    pub(crate) fn partition<'a>(&self, kmer_count: KmerCount<'a>) -> Vec<KmerCount<'a>> {
+        let mut shards: Vec<KmerCount> = (0..self.shards).map(|_| Vec::new()).collect();
+        for kmer in kmer_count {
+            shards[self.assign(&kmer.seq)].push(kmer);
+        }
+        shards
+    }
+
+    /// Paths of the shard tables of the output at `output_path`, in shard order
+    pub fn shard_paths(&self, output_path: &Path) -> Vec<PathBuf> {
+        (0..self.shards)
+            .map(|shard| shard_path(output_path, shard))
+            .collect()
+    }
+}
+
+/// Path of table `shard` of the output at `output_path`, with the shard
+/// inserted before the extension, e.g. `a_kmer.shard3.txt`
+pub fn shard_path(output_path: &Path, shard: usize) -> PathBuf {
+    let mut name = OsString::from(output_path.file_stem().unwrap_or_default());
+    name.push(format!(".shard{}", shard));
+    if let Some(extension) = output_path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    output_path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KmerRecord;
+    use std::borrow::Cow;
+
+    #[test]
+    fn test_minimizer() {
+        let shards = MinimizerShards { m: 3, shards: 4 };
+        let (minimizer, hash) = shards.minimizer("ACGTTGCA").unwrap();
+        let windows = ["ACG", "CGT", "GTT", "TTG", "TGC", "GCA"];
+        let smallest = windows
+            .iter()
+            .min_by_key(|window| hash_bytes(window.as_bytes(), 0))
+            .unwrap();
+        assert_eq!(minimizer, *smallest);
+        assert_eq!(hash, hash_bytes(smallest.as_bytes(), 0));
+        assert_eq!(shards.minimizer("AC"), None);
+        assert_eq!(shards.assign("AC"), 0);
+    }
+
+    #[test]
+    fn test_shared_minimizer_shares_shard() {
+        let shards = MinimizerShards { m: 5, shards: 16 };
+        let minimizer = shards.minimizer("ACGTTGCATG").unwrap().0;
+        // every kmer containing the minimizer and no window hashing lower
+        // has the same minimizer, so the same shard
+        let mut with_minimizer = 0;
+        for prefix in ["", "A", "CC", "GTA", "TTTTT"] {
+            for suffix in ["", "G", "TA", "CAG"] {
+                let kmer = format!("{}{}{}", prefix, minimizer, suffix);
+                if shards.minimizer(&kmer).unwrap().0 == minimizer {
+                    with_minimizer += 1;
+                    assert_eq!(shards.assign(&kmer), shards.assign(minimizer));
+                }
+            }
+        }
+        assert!(with_minimizer > 1);
+
+        let kmer_count: KmerCount = ["ACGTTGCATG", "CGTTGCATGA", "TTTTTAAAAA", "GGGCCCGGGC"]
+            .iter()
+            .map(|&kmer| KmerRecord {
+                seq: Cow::from(kmer),
+                count: 1,
+            })
+            .collect();
+        let partition = shards.partition(kmer_count);
+        assert_eq!(partition.len(), 16);
+        assert_eq!(partition.iter().map(Vec::len).sum::<usize>(), 4);
+        for (shard, kmers) in partition.iter().enumerate() {
+            assert!(kmers.iter().all(|kmer| shards.assign(&kmer.seq) == shard));
+        }
+    }
+
+    #[test]
+    fn test_shard_path() {
+        assert_eq!(
+            shard_path(Path::new("out/a_kmer.txt"), 3),
+            Path::new("out/a_kmer.shard3.txt")
+        );
+        assert_eq!(
+            MinimizerShards { m: 3, shards: 2 }.shard_paths(Path::new("a_kmer.txt")),
+            [
+                PathBuf::from("a_kmer.shard0.txt"),
+                PathBuf::from("a_kmer.shard1.txt")
+            ]
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Approximate kmer counting over Hamming neighborhoods
+//!
+//! Each observed kmer also increments every kmer within Hamming distance `d`
+//! of it, substituting bases from the ATCG alphabet. Kmers with a base other
+//! than A, T, C, or G are left out, as with `skip_ambiguous`: such a base has
+//! four substitutions rather than three, so its neighborhood would outgrow the
+//! `neighborhood_size` checked against `MAX_NEIGHBORHOOD_SIZE`.
+
+use std::collections::HashMap;
+
+use crate::{kmers, ordered_kmer_count, KmerCount, KmerError};
+
+/// Largest neighborhood enumerated per kmer before giving up
+pub const MAX_NEIGHBORHOOD_SIZE: u128 = 100_000;
+
+const BASES: &[u8] = b"ATCG";
+
+/// Return frequency of all kmers within Hamming distance `d` of the length `k` kmers in
+/// `sequence`, ordered from most to least abundant
+///
+/// Kmers with a base other than A, T, C, or G are left out.
+///
+/// Fails with `KmerError::NeighborhoodTooLarge` if a single kmer's neighborhood would
+/// exceed `MAX_NEIGHBORHOOD_SIZE` kmers.
+pub(crate) fn count_kmers_with_mismatches(
+    sequence: &[u8],
+    k: usize,
+    d: usize,
+) -> Result<KmerCount<'static>, KmerError> {
+    let size = neighborhood_size(k, d);
+    if size > MAX_NEIGHBORHOOD_SIZE {
+        return Err(KmerError::NeighborhoodTooLarge {
+            k,
+            d,
+            size,
+            limit: MAX_NEIGHBORHOOD_SIZE,
+        });
+    }
+
+    // count exact kmers first so each distinct kmer's neighborhood is enumerated once
+    let mut exact: HashMap<&str, u64> = HashMap::new();
+    for kmer in kmers(sequence, k)? {
+        if !kmer.bytes().all(|base| BASES.contains(&base)) {
+            continue;
+        }
+        *exact.entry(kmer).or_insert(0) += 1;
+    }
+
+    let mut counter: HashMap<String, u64> = HashMap::new();
+    for (kmer, count) in exact {
+        for neighbor in hamming_neighborhood(kmer.as_bytes(), d) {
+            // neighbors are built from an ASCII kmer and ASCII bases
+            let neighbor = String::from_utf8(neighbor).unwrap();
+            *counter.entry(neighbor).or_insert(0) += count;
+        }
+    }
+    Ok(ordered_kmer_count(counter))
+}
+
+/// Number of ATCG kmers within Hamming distance `d` of a length `k` kmer, including itself
+///
+/// Saturates at `u128::MAX` rather than overflowing.
+pub fn neighborhood_size(k: usize, d: usize) -> u128 {
+    let mut total: u128 = 0;
+    let mut choose: u128 = 1; // C(k, i)
+    let mut substitutions: u128 = 1; // 3^i
+    for i in 0..=d.min(k) {
+        if i > 0 {
+            choose = choose.saturating_mul((k - i + 1) as u128) / i as u128;
+            substitutions = substitutions.saturating_mul(3);
+        }
+        total = total.saturating_add(choose.saturating_mul(substitutions));
+    }
+    total
+}
+
+/// All kmers within Hamming distance `d` of `kmer`, including `kmer` itself
+///
+/// For a `kmer` of ATCG bases, these number `neighborhood_size(kmer.len(), d)`.
+pub fn hamming_neighborhood(kmer: &[u8], d: usize) -> Vec<Vec<u8>> {
+    fn extend(current: &mut Vec<u8>, start: usize, d: usize, out: &mut Vec<Vec<u8>>) {
+        out.push(current.clone());
+        if d == 0 {
+            return;
+        }
+        for pos in start..current.len() {
+            let original = current[pos];
+            for &base in BASES.iter().filter(|&&b| b != original) {
+                current[pos] = base;
+                extend(current, pos + 1, d - 1, out);
+            }
+            current[pos] = original;
+        }
+    }
+
+    let mut out = Vec::new();
+    extend(&mut kmer.to_vec(), 0, d, &mut out);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_neighborhood_size() {
+        assert_eq!(neighborhood_size(2, 0), 1);
+        assert_eq!(neighborhood_size(2, 1), 7);
+        assert_eq!(neighborhood_size(2, 2), 16);
+        assert_eq!(neighborhood_size(2, 5), 16);
+        assert_eq!(neighborhood_size(5, 2), 1 + 15 + 90);
+    }
+
+    #[test]
+    fn test_hamming_neighborhood() {
+        let mut neighbors: Vec<_> = hamming_neighborhood(b"AT", 1)
+            .into_iter()
+            .map(|n| String::from_utf8(n).unwrap())
+            .collect();
+        neighbors.sort();
+        assert_eq!(neighbors, ["AA", "AC", "AG", "AT", "CT", "GT", "TT"]);
+        assert_eq!(hamming_neighborhood(b"ATCG", 4).len(), 256);
+    }
+
+    #[test]
+    fn test_count_kmers_with_mismatches() {
+        let counts = count_kmers_with_mismatches(b"ATT", 2, 1).unwrap();
+        let get = |seq: &str| counts.iter().find(|r| r.seq == seq).map(|r| r.count);
+
+        // AT and TT are each other's neighbors
+        assert_eq!(get("AT"), Some(2));
+        assert_eq!(get("TT"), Some(2));
+        // neighbors of only one window
+        assert_eq!(get("AC"), Some(1));
+        assert_eq!(get("CT"), Some(2));
+        assert_eq!(get("TA"), Some(1));
+        // outside both neighborhoods
+        assert_eq!(get("CC"), None);
+    }
+
+    #[test]
+    fn test_count_kmers_with_mismatches_skips_ambiguous() {
+        // AN and NT would have 4 substitutions at the N, so neither is counted
+        let counts = count_kmers_with_mismatches(b"ANT", 2, 1).unwrap();
+        assert!(counts.is_empty());
+        let counts = count_kmers_with_mismatches(b"ATNT", 2, 1).unwrap();
+        assert_eq!(counts.len() as u128, neighborhood_size(2, 1));
+        assert!(counts.iter().all(|r| r.count == 1 && !r.seq.contains('N')));
+    }
+
+    #[test]
+    fn test_count_kmers_with_mismatches_too_large() {
+        assert_eq!(
+            count_kmers_with_mismatches(b"ATCGATCGATCGATCGATCG", 20, 10).unwrap_err(),
+            KmerError::NeighborhoodTooLarge {
+                k: 20,
+                d: 10,
+                size: neighborhood_size(20, 10),
+                limit: MAX_NEIGHBORHOOD_SIZE,
+            }
+        );
+    }
+}
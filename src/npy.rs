@@ -0,0 +1,220 @@
+//! Count vectors in NumPy `.npy` format with a sidecar file of kmer labels
+//!
+//! Counts are saved as a 1-D little-endian `uint64` array (`<u8`) in format
+//! version 1.0, loadable with `numpy.load`. The kmer of each element is on
+//! the corresponding line of the labels file, so both list the table in the
+//! same order, from most to least abundant.
+//!
+//! Layout: the magic bytes `\x93NUMPY`, the version bytes `1 0`, a
+//! little-endian `u16` header length, and an ASCII header dict padded with
+//! spaces to a newline so the data starts on a 64-byte boundary; then the
+//! counts, eight bytes each.
+
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use thiserror::Error;
+
+use crate::{create_output, KmerCount};
+
+/// Leading bytes identifying a `.npy` file
+pub const MAGIC: &[u8; 6] = b"\x93NUMPY";
+
+/// Alignment of the start of the array data
+const ALIGNMENT: usize = 64;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum NpyError {
+    #[error("not a .npy file")]
+    BadMagic,
+
+    #[error("unsupported .npy version {major}.{minor}")]
+    UnsupportedVersion { major: u8, minor: u8 },
+
+    #[error("unsupported .npy header {0:?}; expected a 1-D little-endian uint64 array")]
+    UnsupportedHeader(String),
+
+    #[error("array ends after {found:?} of {expected:?} elements")]
+    Truncated { expected: usize, found: usize },
+}
+
+/// Path of the labels file accompanying the array at `npy_path`:
+/// `sample_kmer.npy` is labelled by `sample_kmer.labels.txt`
+pub fn labels_path(npy_path: &Path) -> PathBuf {
+    npy_path.with_extension("labels.txt")
+}
+
+/// Header dict describing a 1-D `uint64` array of `len` elements
+fn header(len: usize) -> String {
+    let dict = format!(
+        "{{'descr': '<u8', 'fortran_order': False, 'shape': ({},), }}",
+        len
+    );
+    // magic, version, header length, dict, and the closing newline
+    let unpadded = MAGIC.len() + 2 + 2 + dict.len() + 1;
+    let padding = (ALIGNMENT - unpadded % ALIGNMENT) % ALIGNMENT;
+    format!("{}{}\n", dict, " ".repeat(padding))
+}
+
+/// Write `values` as a 1-D `uint64` `.npy` array to `out`
+pub fn write_npy_u64<W: Write>(values: &[u64], mut out: W) -> Result<()> {
+    let header = header(values.len());
+    out.write_all(MAGIC)?;
+    out.write_all(&[1, 0])?;
+    out.write_all(&(header.len() as u16).to_le_bytes())?;
+    out.write_all(header.as_bytes())?;
+    for value in values {
+        out.write_all(&value.to_le_bytes())?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Read a 1-D `uint64` `.npy` array, as written by `write_npy_u64`, from `input`
+pub fn read_npy_u64<R: Read>(mut input: R) -> Result<Vec<u64>> {
+    let mut preamble = [0u8; 10];
+    input.read_exact(&mut preamble)?;
+    if &preamble[..6] != MAGIC {
+        return Err(NpyError::BadMagic.into());
+    }
+    let (major, minor) = (preamble[6], preamble[7]);
+    if major != 1 {
+        return Err(NpyError::UnsupportedVersion { major, minor }.into());
+    }
+    let mut header = vec![0u8; u16::from_le_bytes([preamble[8], preamble[9]]) as usize];
+    input.read_exact(&mut header)?;
+    let header = String::from_utf8_lossy(&header).into_owned();
+    let len = parse_len(&header).ok_or(NpyError::UnsupportedHeader(header))?;
+
+    let mut values = Vec::with_capacity(len);
+    let mut bytes = [0u8; 8];
+    for found in 0..len {
+        input
+            .read_exact(&mut bytes)
+            .map_err(|_| NpyError::Truncated {
+                expected: len,
+                found,
+            })?;
+        values.push(u64::from_le_bytes(bytes));
+    }
+    Ok(values)
+}
+
+/// Length of the array described by `header`, if it is a 1-D C-order
+/// `uint64` array
+fn parse_len(header: &str) -> Option<usize> {
+    let compact: String = header.chars().filter(|c| !c.is_whitespace()).collect();
+    let descr = compact.contains("'descr':'<u8'");
+    let c_order = compact.contains("'fortran_order':False");
+    let shape = compact.split("'shape':(").nth(1)?;
+    let len = shape.split(",)").next()?;
+    if descr && c_order {
+        len.parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Save the counts of `kmer_count` as a `.npy` array at `output_path`, and
+/// its kmers, one per line, at `labels_path(output_path)`
+pub(crate) fn save_npy_kmer_count(kmer_count: KmerCount, output_path: &Path) -> Result<()> {
+    let counts: Vec<u64> = kmer_count.iter().map(|kmer| kmer.count).collect();
+    let file = create_output(output_path)?;
+    write_npy_u64(&counts, BufWriter::new(file))?;
+
+    let mut labels = BufWriter::new(File::create(labels_path(output_path))?);
+    for kmer in &kmer_count {
+        writeln!(labels, "{}", kmer.seq)?;
+    }
+    labels.flush()?;
+    Ok(())
+}
+
+/// Read the kmers and counts saved at `path` by `save_npy_kmer_count`, in order
+pub fn read_npy_table(path: &Path) -> Result<Vec<(String, u64)>> {
+    let file = File::open(path).with_context(|| format!("Could not open {:?}", path))?;
+    let counts = read_npy_u64(BufReader::new(file))?;
+    let labels = fs::read_to_string(labels_path(path))?;
+    let labels: Vec<&str> = labels.lines().collect();
+    if labels.len() != counts.len() {
+        anyhow::bail!(
+            "{:?} has {} labels for {} counts",
+            labels_path(path),
+            labels.len(),
+            counts.len()
+        );
+    }
+    Ok(labels.into_iter().map(str::to_owned).zip(counts).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::count_kmers;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_npy_header() {
+        let mut bytes = Vec::new();
+        write_npy_u64(&[1, 258], &mut bytes).unwrap();
+        assert_eq!(&bytes[..8], b"\x93NUMPY\x01\x00");
+        let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        assert_eq!((10 + header_len) % ALIGNMENT, 0);
+        assert!(bytes[10..10 + header_len]
+            .starts_with(b"{'descr': '<u8', 'fortran_order': False, 'shape': (2,), }"));
+        assert_eq!(bytes[10 + header_len - 1], b'\n');
+        assert_eq!(
+            &bytes[10 + header_len..],
+            [1, 0, 0, 0, 0, 0, 0, 0, 2, 1, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_npy_roundtrip() -> Result<()> {
+        let values = vec![0, 7, u64::MAX, 1 << 40];
+        let mut bytes = Vec::new();
+        write_npy_u64(&values, &mut bytes)?;
+        assert_eq!(read_npy_u64(bytes.as_slice())?, values);
+
+        let mut bytes = Vec::new();
+        write_npy_u64(&[], &mut bytes)?;
+        assert_eq!(read_npy_u64(bytes.as_slice())?, Vec::<u64>::new());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_npy_errors() {
+        let err = read_npy_u64(&b"\x93NUMPX\x01\x00\x00\x00"[..]).unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&NpyError::BadMagic));
+
+        let mut bytes = Vec::new();
+        write_npy_u64(&[1, 2], &mut bytes).unwrap();
+        bytes.truncate(bytes.len() - 4);
+        let err = read_npy_u64(bytes.as_slice()).unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&NpyError::Truncated {
+                expected: 2,
+                found: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_save_npy_kmer_count() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("sample_kmer.npy");
+        save_npy_kmer_count(count_kmers(b"ATCGGATCG", 3, false)?, &path)?;
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("sample_kmer.labels.txt"))?,
+            "ATC\nTCG\nCGG\nGAT\nGGA\n"
+        );
+        let file = File::open(&path)?;
+        assert_eq!(read_npy_u64(BufReader::new(file))?, [2, 2, 1, 1, 1]);
+        assert_eq!(read_npy_table(&path)?[0], ("ATC".to_owned(), 2));
+        Ok(())
+    }
+}
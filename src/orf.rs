@@ -0,0 +1,139 @@
+//! Open reading frames found on the fly
+//!
+//! Each record is scanned in the three forward frames and the three frames of
+//! its reverse complement. An ORF runs from an `ATG` start codon to the first
+//! in-frame stop codon of the standard genetic code, stop included; after a
+//! stop, the next ORF begins at the next start, so nested starts do not yield
+//! shorter copies of the same ORF. ORFs still open at the end of a record are
+//! ignored.
+
+use crate::reverse_complement;
+use crate::translate::{translate_codon, STOP};
+
+/// Open reading frame of a sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Orf {
+    /// whether the ORF reads along the reverse complement
+    pub reverse: bool,
+    /// first base on the forward strand
+    pub start: usize,
+    /// end on the forward strand, exclusive, including the stop codon
+    pub end: usize,
+}
+
+impl Orf {
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Bases of the ORF in `sequence`, in coding orientation
+    pub fn bases(&self, sequence: &[u8]) -> Vec<u8> {
+        let bases = &sequence[self.start..self.end];
+        if self.reverse {
+            reverse_complement(bases)
+        } else {
+            bases.to_vec()
+        }
+    }
+}
+
+/// Start and end of the ORFs of `strand` read in the frame at `offset`
+fn frame_orfs(strand: &[u8], offset: usize) -> Vec<(usize, usize)> {
+    let mut orfs = Vec::new();
+    let mut start = None;
+    let mut pos = offset;
+    while pos + 3 <= strand.len() {
+        let codon = &strand[pos..pos + 3];
+        if start.is_none() && codon.eq_ignore_ascii_case(b"ATG") {
+            start = Some(pos);
+        } else if translate_codon(codon) == STOP {
+            if let Some(start) = start.take() {
+                orfs.push((start, pos + 3));
+            }
+        }
+        pos += 3;
+    }
+    orfs
+}
+
+/// ORFs of at least `min_len` bases, stop codon included, in all six frames
+/// of `sequence`, forward frames first
+pub fn find_orfs(sequence: &[u8], min_len: usize) -> Vec<Orf> {
+    let rc = reverse_complement(sequence);
+    let mut orfs = Vec::new();
+    for (reverse, strand) in [(false, sequence), (true, rc.as_slice())] {
+        for offset in 0..3 {
+            for (start, end) in frame_orfs(strand, offset) {
+                let (start, end) = if reverse {
+                    (strand.len() - end, strand.len() - start)
+                } else {
+                    (start, end)
+                };
+                orfs.push(Orf {
+                    reverse,
+                    start,
+                    end,
+                });
+            }
+        }
+    }
+    orfs.retain(|orf| orf.len() >= min_len);
+    orfs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_orfs() {
+        // ATG AAA CCC TAA in frame 1, after the stop ATG TTT with no stop
+        let seq = b"GATGAAACCCTAAATGTTT";
+        let orfs = find_orfs(seq, 0);
+        let forward = Orf {
+            reverse: false,
+            start: 1,
+            end: 13,
+        };
+        assert!(orfs.contains(&forward));
+        assert_eq!(forward.bases(seq), b"ATGAAACCCTAA");
+        assert!(orfs.iter().all(|orf| orf.start != 13));
+
+        assert_eq!(find_orfs(seq, 12), [forward]);
+        assert!(find_orfs(seq, 13).is_empty());
+    }
+
+    #[test]
+    fn test_find_orfs_reverse() {
+        // reverse complement of ATG CCC TGA
+        let seq = b"TCAGGGCAT";
+        assert_eq!(
+            find_orfs(seq, 9),
+            [Orf {
+                reverse: true,
+                start: 0,
+                end: 9
+            }]
+        );
+        assert_eq!(find_orfs(seq, 9)[0].bases(seq), b"ATGCCCTGA");
+    }
+
+    #[test]
+    fn test_nested_starts() {
+        // the inner ATG lies inside the ORF begun by the first
+        let orfs = find_orfs(b"ATGATGTAG", 0);
+        let forward: Vec<&Orf> = orfs.iter().filter(|orf| !orf.reverse).collect();
+        assert_eq!(
+            forward,
+            [&Orf {
+                reverse: false,
+                start: 0,
+                end: 9
+            }]
+        );
+    }
+}
@@ -0,0 +1,185 @@
+//! Per-record count tables
+//!
+//! Each record of an input gets its own table, named after the input's output
+//! with the record id inserted before the suffix, e.g.
+//! `sample1__contig_17_kmer.txt`. Ids are reduced to characters safe in a
+//! file name on every platform, so NCBI-style ids such as `gi|42|ref|NC_1.1|`
+//! become `gi_42_ref_NC_1.1_`. Records whose names would collide, compared
+//! without case for case-insensitive file systems, are numbered `.1`, `.2`,
+//! ... as with `--duplicate-ids suffix`, or fail the input with
+//! `--duplicate-ids error`; records cannot be merged into one table here.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+use crate::metadata::RunMetadata;
+use crate::strand::{self, Mate};
+use crate::timing::PhaseTimings;
+use crate::{
+    add_sequence_counts, open_fasta, ordered_kmer_count, save_counts, CountOptions, DuplicateIds,
+    RecordTally,
+};
+
+/// `id` with every character other than ASCII letters, digits, `.`, `-`, and
+/// `_` replaced by `_`
+pub fn sanitize_record_id(id: &str) -> String {
+    id.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "._-".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// File names taken so far by the records of one input
+#[derive(Debug, Clone, Default)]
+pub struct RecordNames {
+    /// lowercased names
+    taken: HashSet<String>,
+    /// records whose name collided with an earlier one
+    pub collisions: u64,
+}
+
+impl RecordNames {
+    /// Name of the table of record `id`, numbering a name taken by an earlier
+    /// record unless `policy` is `DuplicateIds::Error`
+    pub fn name(&mut self, id: &str, policy: DuplicateIds) -> Result<String> {
+        let name = sanitize_record_id(id);
+        if self.taken.insert(name.to_lowercase()) {
+            return Ok(name);
+        }
+        self.collisions += 1;
+        if policy == DuplicateIds::Error {
+            bail!(
+                "Record id {:?} collides with an earlier record as {:?}",
+                id,
+                name
+            );
+        }
+        Ok((1..)
+            .map(|n| format!("{}.{}", name, n))
+            .find(|numbered| self.taken.insert(numbered.to_lowercase()))
+            .expect("numbers are unbounded"))
+    }
+}
+
+/// Count the kmers of each record of the fasta file at `fasta_path` as
+/// configured by `options`, saving each record's table at `record_path` of
+/// its name, after the `metadata` header, if given, with a `record_id` entry
+///
+/// Returns the paths written, in record order. Records that cannot be counted
+/// get no table; those shorter than `k` are reported in a single warning.
+pub fn run_fasta_kmer_count_per_record<F>(
+    fasta_path: &Path,
+    options: &CountOptions,
+    metadata: Option<&RunMetadata>,
+    mut record_path: F,
+) -> Result<Vec<PathBuf>>
+where
+    F: FnMut(&str) -> PathBuf,
+{
+    let mut names = RecordNames::default();
+    let mut tally = RecordTally::default();
+    let mut paths = Vec::new();
+    for record in open_fasta(fasta_path)?.records() {
+        let record = record?;
+        let mate = Mate::from_header(record.id(), record.desc());
+        let sequence = strand::orient(record.seq(), mate, options.library_type);
+        let mut counter = HashMap::new();
+        let mut timings = PhaseTimings::disabled();
+        match add_sequence_counts(&mut counter, &sequence, options, &mut timings) {
+            Ok(()) => tally.add(record.id(), Ok(())),
+            Err(err) if err.is_fatal() => return Err(err.into()),
+            Err(err) => {
+                tally.add(record.id(), Err(err));
+                continue;
+            }
+        }
+
+        let path = record_path(&names.name(record.id(), options.duplicate_ids)?);
+        let metadata = metadata.map(|metadata| metadata.clone().with("record_id", record.id()));
+        save_counts(
+            ordered_kmer_count(counter),
+            options,
+            metadata.as_ref(),
+            &path,
+        )?;
+        paths.push(path);
+    }
+    tally.warn_too_short(fasta_path, options.k);
+    if names.collisions > 0 {
+        eprintln!(
+            "WARNING: {} record(s) of {:?} share a table name with an earlier record; their tables were numbered .1, .2, ...",
+            names.collisions, fasta_path
+        );
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record_output_path;
+    use crate::table::read_kmer_table;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_record_names() -> Result<()> {
+        assert_eq!(
+            sanitize_record_id("gi|42|ref|NC_1.1| x/y"),
+            "gi_42_ref_NC_1.1__x_y"
+        );
+        let mut names = RecordNames::default();
+        assert_eq!(names.name("contig 1", DuplicateIds::Merge)?, "contig_1");
+        assert_eq!(names.name("contig/1", DuplicateIds::Merge)?, "contig_1.1");
+        assert_eq!(names.name("Contig_1", DuplicateIds::Suffix)?, "Contig_1.2");
+        assert_eq!(names.collisions, 2);
+        assert!(names.name("contig|1", DuplicateIds::Error).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_fasta_kmer_count_per_record() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("sample1.fasta");
+        fs::write(
+            &fasta_path,
+            ">contig_17 desc\nAAAAC\n>ref|x|\nGGGA\n>short\nAC\n>contig_17\nCCCC\n",
+        )?;
+        let output_path = dir.path().join("out").join("sample1_kmer.txt");
+        let options = CountOptions {
+            metadata_header: false,
+            ..CountOptions::new(3)
+        };
+        let metadata = RunMetadata::new().with("k", 3);
+        let paths =
+            run_fasta_kmer_count_per_record(&fasta_path, &options, Some(&metadata), |name| {
+                record_output_path(&output_path, "_kmer.txt", name)
+            })?;
+
+        let out = dir.path().join("out");
+        assert_eq!(
+            paths,
+            [
+                out.join("sample1__contig_17_kmer.txt"),
+                out.join("sample1__ref_x__kmer.txt"),
+                out.join("sample1__contig_17.1_kmer.txt"),
+            ]
+        );
+        assert_eq!(
+            read_kmer_table(&paths[0])?,
+            [("AAA".to_owned(), 2), ("AAC".to_owned(), 1)]
+        );
+        assert_eq!(read_kmer_table(&paths[2])?, [("CCC".to_owned(), 2)]);
+        let header = RunMetadata::read(&paths[1])?;
+        assert_eq!(header.get("record_id"), Some("ref|x|"));
+        assert!(!out.join("sample1_kmer.txt").exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+//! Counts pooled across the files of one sample
+//!
+//! A regular expression picks a sample name out of each input's file name,
+//! from its first capture group, and the kmers of all files sharing a sample
+//! name are counted into a single table, as for lanes of one sample sequenced
+//! into separate files.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use regex::Regex;
+
+use crate::metadata::RunMetadata;
+use crate::{add_count, fasta_kmer_counter, ordered_kmer_count, save_counts, CountOptions};
+
+/// Sample name captured by the first group of `pattern` in the file name of
+/// `path`, or `None` if the pattern does not match or captures nothing
+pub fn sample_name(pattern: &Regex, path: &Path) -> Option<String> {
+    let file_name = path.file_name()?.to_str()?;
+    let sample = pattern.captures(file_name)?.get(1)?.as_str();
+    if sample.is_empty() {
+        None
+    } else {
+        Some(sample.to_owned())
+    }
+}
+
+/// Inputs of `paths` that should be pooled, by sample name, and the inputs
+/// without a sample name, both in the order of `paths`
+pub fn pool_by_sample(
+    paths: &[PathBuf],
+    pattern: &Regex,
+) -> (BTreeMap<String, Vec<PathBuf>>, Vec<PathBuf>) {
+    let mut samples: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    let mut unmatched = Vec::new();
+    for path in paths {
+        match sample_name(pattern, path) {
+            Some(sample) => samples.entry(sample).or_default().push(path.clone()),
+            None => unmatched.push(path.clone()),
+        }
+    }
+    (samples, unmatched)
+}
+
+/// Save the kmer counts of all records of all `fasta_paths`, counted as
+/// configured by `options`, as one table at `output_path` after the
+/// `metadata` header if given
+pub fn run_pooled_kmer_count(
+    fasta_paths: &[PathBuf],
+    options: &CountOptions,
+    metadata: Option<&RunMetadata>,
+    output_path: &Path,
+) -> Result<()> {
+    let mut pooled: HashMap<String, u64> = HashMap::new();
+    for fasta_path in fasta_paths {
+        let counter = fasta_kmer_counter(fasta_path, options)
+            .with_context(|| format!("Could not count {:?}", fasta_path))?;
+        for (kmer, count) in counter {
+            add_count(&mut pooled, kmer, count)?;
+        }
+    }
+    save_counts(ordered_kmer_count(pooled), options, metadata, output_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::read_kmer_table;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_sample_name() {
+        let pattern = Regex::new(r"^(.+)_lane\d+").unwrap();
+        let name = |path: &str| sample_name(&pattern, Path::new(path));
+        assert_eq!(name("runs/sampleA_lane1.fasta"), Some("sampleA".to_owned()));
+        assert_eq!(name("sampleA.fasta"), None);
+        assert_eq!(name("_lane2.fasta"), None);
+    }
+
+    #[test]
+    fn test_pool_by_sample() {
+        let pattern = Regex::new(r"^([^_]+)_").unwrap();
+        let paths: Vec<PathBuf> = ["b_1.fa", "a_2.fa", "c.fa", "a_1.fa"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        let (samples, unmatched) = pool_by_sample(&paths, &pattern);
+        assert_eq!(samples.keys().collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(
+            samples["a"],
+            [PathBuf::from("a_2.fa"), PathBuf::from("a_1.fa")]
+        );
+        assert_eq!(unmatched, [PathBuf::from("c.fa")]);
+    }
+
+    #[test]
+    fn test_run_pooled_kmer_count() -> Result<()> {
+        let dir = tempdir()?;
+        let lane1 = dir.path().join("sampleA_lane1.fasta");
+        let lane2 = dir.path().join("sampleA_lane2.fasta");
+        fs::write(&lane1, ">r1\nACGT\n")?;
+        fs::write(&lane2, ">r1\nACGA\n")?;
+
+        let output_path = dir.path().join("sampleA_kmer.txt");
+        run_pooled_kmer_count(&[lane1, lane2], &CountOptions::new(3), None, &output_path)?;
+        assert_eq!(
+            read_kmer_table(&output_path)?,
+            [
+                ("ACG".to_owned(), 2),
+                ("CGA".to_owned(), 1),
+                ("CGT".to_owned(), 1)
+            ]
+        );
+        Ok(())
+    }
+}
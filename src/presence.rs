@@ -0,0 +1,173 @@
+//! Core kmers: kmers present in at least a fraction of records
+//!
+//! Alongside the total count of each kmer, the number of records containing
+//! it at least once is tracked. Only kmers whose record presence meets the
+//! threshold are kept.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::metadata::RunMetadata;
+use crate::strand::{self, Mate};
+use crate::timing::PhaseTimings;
+use crate::{
+    add_sequence_counts, open_fasta, ordered_kmer_count, save_counts, CountOptions, KmerError,
+    RecordTally,
+};
+
+/// Total count and record presence of each kmer over a set of records
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PresenceCounts {
+    /// records added
+    pub records: u64,
+    /// kmer -> (total count, records containing the kmer)
+    counts: HashMap<String, (u64, u64)>,
+}
+
+impl PresenceCounts {
+    pub fn new() -> Self {
+        PresenceCounts::default()
+    }
+
+    /// Add the counts of one record, given as `(kmer, count)` pairs with each
+    /// kmer listed once
+    ///
+    /// Fails, leaving the totals of the record's earlier kmers added, if a
+    /// total would exceed `u64::MAX`.
+    pub fn add_record<I, S>(&mut self, kmer_counts: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (S, u64)>,
+        S: Into<String>,
+    {
+        self.records += 1;
+        for (kmer, count) in kmer_counts {
+            let kmer = kmer.into();
+            let entry = self.counts.entry(kmer.clone()).or_insert((0, 0));
+            entry.0 = entry
+                .0
+                .checked_add(count)
+                .ok_or(KmerError::CountOverflow { kmer })?;
+            entry.1 += 1;
+        }
+        Ok(())
+    }
+
+    /// Records containing `kmer`
+    pub fn presence(&self, kmer: &str) -> u64 {
+        self.counts.get(kmer).map_or(0, |&(_, records)| records)
+    }
+
+    /// Total counts of the kmers present in at least `fraction` of the records
+    pub fn core(&self, fraction: f64) -> HashMap<String, u64> {
+        let records = self.records as f64;
+        self.counts
+            .iter()
+            .filter(|(_, &(_, present))| present as f64 >= fraction * records)
+            .map(|(kmer, &(count, _))| (kmer.clone(), count))
+            .collect()
+    }
+}
+
+/// Track kmer counts and record presence over every record of `fasta_path`
+///
+/// Records that cannot be counted are skipped and do not count towards the
+/// number of records; those shorter than `k` are reported in a single warning.
+pub fn fasta_presence_counts(fasta_path: &Path, options: &CountOptions) -> Result<PresenceCounts> {
+    let mut presence = PresenceCounts::new();
+    let mut tally = RecordTally::default();
+    for record in open_fasta(fasta_path)?.records() {
+        let record = record?;
+        let mate = Mate::from_header(record.id(), record.desc());
+        let sequence = strand::orient(record.seq(), mate, options.library_type);
+        let mut counter = HashMap::new();
+        let mut timings = PhaseTimings::disabled();
+        match add_sequence_counts(&mut counter, &sequence, options, &mut timings) {
+            Ok(()) => {
+                presence.add_record(counter)?;
+                tally.add(record.id(), Ok(()));
+            }
+            Err(err) if err.is_fatal() => return Err(err.into()),
+            Err(err) => tally.add(record.id(), Err(err)),
+        }
+    }
+    tally.warn_too_short(fasta_path, options.k);
+    Ok(presence)
+}
+
+/// Save the total counts of the kmers present in at least `fraction` of the
+/// records of `fasta_path` at `output_path`
+pub fn run_fasta_core_kmer_count(
+    fasta_path: &Path,
+    options: &CountOptions,
+    fraction: f64,
+    metadata: Option<&RunMetadata>,
+    output_path: &Path,
+) -> Result<()> {
+    let core = fasta_presence_counts(fasta_path, options)?.core(fraction);
+    save_counts(ordered_kmer_count(core), options, metadata, output_path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::read_kmer_table;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_core_fraction() -> Result<()> {
+        let mut presence = PresenceCounts::new();
+        presence.add_record(vec![("AAA", 3), ("CCC", 1)])?;
+        presence.add_record(vec![("AAA", 1), ("GGG", 2)])?;
+        presence.add_record(vec![("GGG", 1)])?;
+        assert_eq!(presence.presence("CCC"), 1);
+
+        let core = presence.core(0.5);
+        assert_eq!(core.len(), 2);
+        assert_eq!(core["AAA"], 4);
+        assert_eq!(core["GGG"], 3);
+
+        assert_eq!(presence.core(1.0), HashMap::new());
+        assert_eq!(presence.core(0.0).len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_record_overflow() -> Result<()> {
+        let mut presence = PresenceCounts::new();
+        presence.add_record(vec![("AAA", u64::MAX - 1)])?;
+        presence.add_record(vec![("AAA", 1)])?;
+        assert_eq!(presence.core(1.0)["AAA"], u64::MAX);
+
+        let err = presence.add_record(vec![("AAA", 1)]).unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&KmerError::CountOverflow {
+                kmer: "AAA".to_owned()
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_fasta_core_kmer_count() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("multi.fasta");
+        fs::write(&fasta_path, ">r1\nAAAA\n>r2\nAAACCC\n>r3\nAAAT\n")?;
+
+        let output_path = dir.path().join("multi_kmer.txt");
+        let options = CountOptions {
+            metadata_header: false,
+            ..CountOptions::new(3)
+        };
+        run_fasta_core_kmer_count(&fasta_path, &options, 0.5, None, &output_path)?;
+
+        // CCC and its neighbours appear only in r2, AAT only in r3
+        let table = read_kmer_table(&output_path)?;
+        assert_eq!(table, [("AAA".to_owned(), 4)]);
+        Ok(())
+    }
+}
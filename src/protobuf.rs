@@ -0,0 +1,97 @@
+//! Count tables as length-delimited protobuf messages
+//!
+//! The messages mirror `proto/kmer_count.proto`. A table file holds a single
+//! `KmerCount` message prefixed by its varint-encoded byte length, as read by
+//! `parseDelimitedFrom` and similar helpers of protobuf libraries.
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use prost::Message;
+
+use crate::create_output;
+
+/// One kmer and its count
+#[derive(Clone, PartialEq, Message)]
+pub struct KmerRecord {
+    #[prost(string, tag = "1")]
+    pub kmer: String,
+    #[prost(uint64, tag = "2")]
+    pub count: u64,
+}
+
+/// A count table, ordered from most to least abundant
+#[derive(Clone, PartialEq, Message)]
+pub struct KmerCount {
+    #[prost(message, repeated, tag = "1")]
+    pub records: Vec<KmerRecord>,
+}
+
+/// Save `kmer_count` at `output_path` as a length-delimited `KmerCount`
+pub(crate) fn save_protobuf_kmer_count(
+    kmer_count: crate::KmerCount,
+    output_path: &Path,
+) -> Result<()> {
+    let message = KmerCount {
+        records: kmer_count
+            .into_iter()
+            .map(|kmer| KmerRecord {
+                kmer: kmer.seq.into_owned(),
+                count: kmer.count,
+            })
+            .collect(),
+    };
+    create_output(output_path)?.write_all(&message.encode_length_delimited_to_vec())?;
+    Ok(())
+}
+
+/// Read the `KmerCount` message saved at `path`
+pub fn read_protobuf_table(path: &Path) -> Result<KmerCount> {
+    let bytes = fs::read(path).with_context(|| format!("Could not open {:?}", path))?;
+    KmerCount::decode_length_delimited(bytes.as_slice())
+        .with_context(|| format!("Invalid protobuf count table {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{run_fasta_kmer_count, CountOptions, OutputFormat};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_protobuf_roundtrip() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("seq.fasta");
+        fs::write(&fasta_path, ">a\nATCGGATCG\n")?;
+
+        let output_path = dir.path().join("seq_kmer.pb");
+        let options = CountOptions {
+            format: OutputFormat::Protobuf,
+            ..CountOptions::new(3)
+        };
+        run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
+
+        let records: Vec<(String, u64)> = read_protobuf_table(&output_path)?
+            .records
+            .into_iter()
+            .map(|r| (r.kmer, r.count))
+            .collect();
+        assert_eq!(
+            records,
+            [
+                ("ATC".to_owned(), 2),
+                ("TCG".to_owned(), 2),
+                ("CGG".to_owned(), 1),
+                ("GAT".to_owned(), 1),
+                ("GGA".to_owned(), 1),
+            ]
+        );
+
+        // the length prefix covers exactly the rest of the file
+        let bytes = fs::read(&output_path)?;
+        assert_eq!(bytes[0] as usize, bytes.len() - 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,246 @@
+//! Assembly consensus quality (QV) and completeness from read kmers
+//!
+//! Following Merqury, an assembly kmer absent from the kmers of the reads is
+//! taken to contain an assembly error. With a fraction `f` of the assembly's
+//! kmers found in the reads, a base is correct with probability `f^(1/k)`, so
+//! the per-base error rate is `e = 1 - f^(1/k)` and the consensus quality is
+//! the Phred-scaled `QV = -10 log10(e)`. Completeness is the fraction of the
+//! solid read kmers, those with a count of at least a threshold, found in the
+//! assembly. Assembly kmers with bases other than ACGT are left out, and the
+//! assembly is compared in upper case.
+
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use log::warn;
+use serde::Serialize;
+
+use crate::{kmers, open_fasta, CanonicalRule, OutputFormat};
+
+/// Quality and completeness of an assembly against a read kmer table
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QvEstimate {
+    pub k: usize,
+    /// kmer windows of the assembly
+    pub assembly_kmers: u64,
+    /// assembly kmer windows not found in the reads
+    pub missing_kmers: u64,
+    /// estimated per-base error rate
+    pub error_rate: f64,
+    /// Phred-scaled consensus quality, infinite without missing kmers
+    pub qv: f64,
+    /// read kmers with a count of at least the solid threshold
+    pub solid_read_kmers: usize,
+    /// solid read kmers found in the assembly
+    pub solid_found: usize,
+    /// `solid_found / solid_read_kmers`
+    pub completeness: f64,
+}
+
+/// Estimate the QV and completeness of the assembly at `assembly_path`
+/// against the kmers and counts `read_rows` of a table counted from reads
+///
+/// With `canonical`, the table holds canonical kmers (as counted with the
+/// default `--canonical-rule`), and assembly windows are matched on both
+/// strands. Read kmers with a count of at least `min_count` are solid.
+/// Fails if the table is empty or holds kmers of another length than `k`,
+/// if no assembly record is at least `k` long, or if no read kmer is solid.
+pub fn estimate_qv(
+    read_rows: Vec<(String, u64)>,
+    assembly_path: &Path,
+    k: usize,
+    canonical: bool,
+    min_count: u64,
+) -> Result<QvEstimate> {
+    match read_rows.first() {
+        None => bail!("The read count table is empty"),
+        Some((kmer, _)) if kmer.len() != k => bail!(
+            "The read count table holds kmers of length {}, not {}",
+            kmer.len(),
+            k
+        ),
+        Some(_) => {}
+    }
+    let reads: HashMap<String, u64> = read_rows.into_iter().collect();
+    let key = |window: &str| -> String {
+        if canonical {
+            // the canonical form of an ASCII window is ASCII
+            String::from_utf8(CanonicalRule::Lex.canonical(window.as_bytes())).unwrap()
+        } else {
+            window.to_owned()
+        }
+    };
+
+    let mut assembly_kmers = 0u64;
+    let mut missing_kmers = 0u64;
+    let mut in_assembly = HashSet::new();
+    for record in open_fasta(assembly_path)?.records() {
+        let record = record?;
+        let sequence = record.seq().to_ascii_uppercase();
+        let windows = match kmers(&sequence, k) {
+            Ok(windows) => windows,
+            Err(err) => {
+                warn!("Skipping record {}: {}", record.id(), err);
+                continue;
+            }
+        };
+        for window in windows.filter(|window| window.bytes().all(|base| b"ACGT".contains(&base))) {
+            let kmer = key(window);
+            assembly_kmers += 1;
+            if !reads.contains_key(&kmer) {
+                missing_kmers += 1;
+            }
+            in_assembly.insert(kmer);
+        }
+    }
+    if assembly_kmers == 0 {
+        return Err(anyhow!(
+            "No ACGT kmers of length {} in the assembly {:?}",
+            k,
+            assembly_path
+        ));
+    }
+
+    let solid: Vec<&String> = reads
+        .iter()
+        .filter(|&(_, &count)| count >= min_count)
+        .map(|(kmer, _)| kmer)
+        .collect();
+    if solid.is_empty() {
+        bail!("No read kmers with a count of at least {}", min_count);
+    }
+    let solid_found = solid
+        .iter()
+        .filter(|&&kmer| in_assembly.contains(kmer))
+        .count();
+
+    let found_fraction = 1.0 - missing_kmers as f64 / assembly_kmers as f64;
+    let error_rate = 1.0 - found_fraction.powf(1.0 / k as f64);
+    Ok(QvEstimate {
+        k,
+        assembly_kmers,
+        missing_kmers,
+        error_rate,
+        qv: -10.0 * error_rate.log10(),
+        solid_read_kmers: solid.len(),
+        solid_found,
+        completeness: solid_found as f64 / solid.len() as f64,
+    })
+}
+
+/// Write the QV estimate to `out` in the requested `format`
+///
+/// An infinite QV is written as `inf` in TSV and `null` in JSON.
+pub fn write_qv<W: Write>(estimate: &QvEstimate, format: OutputFormat, mut out: W) -> Result<()> {
+    match format {
+        OutputFormat::Tsv => {
+            writeln!(out, "k\t{}", estimate.k)?;
+            writeln!(out, "assembly_kmers\t{}", estimate.assembly_kmers)?;
+            writeln!(out, "missing_kmers\t{}", estimate.missing_kmers)?;
+            writeln!(out, "error_rate\t{:.6e}", estimate.error_rate)?;
+            writeln!(out, "qv\t{:.2}", estimate.qv)?;
+            writeln!(out, "solid_read_kmers\t{}", estimate.solid_read_kmers)?;
+            writeln!(out, "solid_found\t{}", estimate.solid_found)?;
+            writeln!(out, "completeness\t{:.6}", estimate.completeness)?;
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, estimate)?;
+            writeln!(out)?;
+        }
+        other => return Err(other.unsupported_report()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::random_sequence;
+    use crate::{fasta_kmer_counter, reverse_complement, CountOptions};
+    use std::fs;
+    use std::str;
+    use tempfile::{tempdir, TempDir};
+
+    const K: usize = 21;
+
+    /// Canonical kmer counts of error-free 100 bp reads tiling both strands
+    /// of `genome` every 5 bases
+    fn perfect_read_counts(dir: &TempDir, genome: &[u8]) -> Result<Vec<(String, u64)>> {
+        let mut reads = String::new();
+        for (i, start) in (0..=genome.len() - 100).step_by(5).enumerate() {
+            let read = &genome[start..start + 100];
+            let read = if i % 2 == 0 {
+                read.to_vec()
+            } else {
+                reverse_complement(read)
+            };
+            reads.push_str(&format!(">r{}\n{}\n", i, str::from_utf8(&read)?));
+        }
+        let reads_path = dir.path().join("reads.fasta");
+        fs::write(&reads_path, reads)?;
+        let options = CountOptions {
+            canonical: true,
+            ..CountOptions::new(K)
+        };
+        Ok(fasta_kmer_counter(&reads_path, &options)?
+            .into_iter()
+            .collect())
+    }
+
+    fn write_assembly(dir: &TempDir, name: &str, sequence: &[u8]) -> Result<std::path::PathBuf> {
+        let path = dir.path().join(name);
+        fs::write(&path, format!(">contig\n{}\n", str::from_utf8(sequence)?))?;
+        Ok(path)
+    }
+
+    #[test]
+    fn test_qv_drops_with_an_assembly_error() -> Result<()> {
+        let dir = tempdir()?;
+        let genome = random_sequence(2000, 11);
+        let reads = perfect_read_counts(&dir, &genome)?;
+
+        let perfect = write_assembly(&dir, "perfect.fasta", &genome)?;
+        let estimate = estimate_qv(reads.clone(), &perfect, K, true, 2)?;
+        assert_eq!(estimate.assembly_kmers, 2000 - K as u64 + 1);
+        assert_eq!(estimate.missing_kmers, 0);
+        assert_eq!(estimate.qv, f64::INFINITY);
+        assert_eq!(estimate.completeness, 1.0);
+
+        // the reverse strand of the assembly matches canonical read kmers
+        let flipped = write_assembly(&dir, "flipped.fasta", &reverse_complement(&genome))?;
+        assert_eq!(estimate_qv(reads.clone(), &flipped, K, true, 2)?, estimate);
+
+        let mut erroneous = genome.clone();
+        erroneous[1000] = if erroneous[1000] == b'A' { b'C' } else { b'A' };
+        let erroneous = write_assembly(&dir, "erroneous.fasta", &erroneous)?;
+        let estimate = estimate_qv(reads, &erroneous, K, true, 2)?;
+        // every kmer overlapping the substituted base is missing
+        assert_eq!(estimate.missing_kmers, K as u64);
+        let f = 1.0 - K as f64 / (2000 - K + 1) as f64;
+        assert!((estimate.qv - -10.0 * (1.0 - f.powf(1.0 / K as f64)).log10()).abs() < 1e-9);
+        assert!(estimate.qv.is_finite() && estimate.qv > 30.0);
+        assert!(estimate.completeness < 1.0);
+        assert_eq!(estimate.solid_read_kmers - estimate.solid_found, K);
+        Ok(())
+    }
+
+    #[test]
+    fn test_qv_edge_cases() -> Result<()> {
+        let dir = tempdir()?;
+        let genome = random_sequence(300, 12);
+        let reads = perfect_read_counts(&dir, &genome)?;
+
+        let short = write_assembly(&dir, "short.fasta", &genome[..K - 1])?;
+        let err = estimate_qv(reads.clone(), &short, K, true, 2).unwrap_err();
+        assert!(err.to_string().contains("No ACGT kmers"), "{}", err);
+
+        let assembly = write_assembly(&dir, "assembly.fasta", &genome)?;
+        let err = estimate_qv(Vec::new(), &assembly, K, true, 2).unwrap_err();
+        assert!(err.to_string().contains("empty"), "{}", err);
+        assert!(estimate_qv(reads.clone(), &assembly, K + 1, true, 2).is_err());
+        assert!(estimate_qv(reads, &assembly, K, true, 1000).is_err());
+        Ok(())
+    }
+}
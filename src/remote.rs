@@ -0,0 +1,90 @@
+//! Inputs streamed over HTTP
+//!
+//! A fasta "path" that is an `http://` or `https://` URL is fetched as it is
+//! read instead of opened from disk, so a remote reference is counted without
+//! first being downloaded. A URL ending in `.gz` is gunzipped on the fly.
+
+use std::io::Read;
+
+use anyhow::{Context, Result};
+use flate2::read::MultiGzDecoder;
+
+/// Stream the body of `url`, decompressed if it ends in `.gz`
+pub fn open_url(url: &str) -> Result<Box<dyn Read + Send>> {
+    let response = ureq::get(url)
+        .call()
+        .with_context(|| format!("Could not fetch {}", url))?;
+    let body = response.into_reader();
+    // the query string is not part of the resource name
+    let resource = url.split(['?', '#']).next().unwrap_or(url);
+    if resource.ends_with(".gz") {
+        Ok(Box::new(MultiGzDecoder::new(body)))
+    } else {
+        Ok(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::read_kmer_table;
+    use crate::{run_fasta_kmer_count, CountOptions};
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::path::Path;
+    use std::thread;
+    use tempfile::tempdir;
+
+    /// Serve `body` to a single request on a local port, returning the base URL
+    fn serve_once(body: Vec<u8>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            // skip the request line and headers
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let mut stream = stream;
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            )
+            .unwrap();
+            stream.write_all(&body).unwrap();
+        });
+        format!("http://{}", address)
+    }
+
+    #[test]
+    fn test_run_fasta_kmer_count_from_url() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta = b">a\nACGT\n>b\nACG\n".to_vec();
+        let url = format!("{}/ref.fasta", serve_once(fasta.clone()));
+        let output_path = dir.path().join("ref_kmer.txt");
+        run_fasta_kmer_count(Path::new(&url), &CountOptions::new(3), &output_path)?;
+        let expected = [("ACG".to_owned(), 2), ("CGT".to_owned(), 1)];
+        assert_eq!(read_kmer_table(&output_path)?, expected);
+
+        let mut gzipped = GzEncoder::new(Vec::new(), Compression::default());
+        gzipped.write_all(&fasta)?;
+        let url = format!("{}/ref.fasta.gz?version=2", serve_once(gzipped.finish()?));
+        run_fasta_kmer_count(Path::new(&url), &CountOptions::new(3), &output_path)?;
+        assert_eq!(read_kmer_table(&output_path)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_url_unreachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/ref.fasta", listener.local_addr().unwrap());
+        drop(listener);
+        let err = open_url(&url).err().unwrap();
+        assert!(format!("{:#}", err).contains("Could not fetch"));
+    }
+}
@@ -0,0 +1,560 @@
+//! Contamination screening of reads against labeled reference kmer sets
+//!
+//! Each read is classified by the fraction of its kmers found in each
+//! reference:
+//!
+//!   1. a reference is a *hit* if at least `min_fraction` of the read's kmers
+//!      are present in it;
+//!   2. with no hits the read is unclassified;
+//!   3. otherwise the read is assigned to the hit with the highest fraction;
+//!   4. if several hits share the highest fraction, the tie policy decides:
+//!      `ambiguous` counts the read once as ambiguous, `all` credits the read
+//!      to every tied reference.
+//!
+//! Reads shorter than the reference kmer length are unclassified. A reference
+//! counted with `canonical` matches a read kmer by its canonical form under
+//! the reference's `CanonicalRule`, as recorded in the table's metadata
+//! header; tables without one are matched kmer for kmer.
+//!
+//! Every reference is loaded whole into a set of its kmers, so memory grows
+//! with the distinct kmers of all references rather than with the reads.
+
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+
+use crate::metadata::RunMetadata;
+use crate::table::{is_varint_table, read_count_table};
+use crate::{kmers, open_fasta, CanonicalRule, OutputFormat};
+
+/// A reference kmer table given on the command line as `label:path`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReferenceSpec {
+    pub label: String,
+    pub path: PathBuf,
+}
+
+impl FromStr for ReferenceSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((label, path)) if !label.is_empty() && !path.is_empty() => Ok(ReferenceSpec {
+                label: label.to_owned(),
+                path: PathBuf::from(path),
+            }),
+            _ => Err(format!("Expected LABEL:PATH, got {:?}", s)),
+        }
+    }
+}
+
+/// Set of kmers present in a labeled reference
+#[derive(Debug, Clone)]
+pub struct Reference {
+    pub label: String,
+    pub k: usize,
+    /// rule the reference's kmers were made canonical by, if they were
+    pub canonical: Option<CanonicalRule>,
+    kmers: HashSet<String>,
+}
+
+impl Reference {
+    /// Build a reference from its kmers, all of which must have length `k`
+    pub fn new<I>(label: &str, k: usize, kmers: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let kmers: HashSet<String> = kmers.into_iter().collect();
+        if let Some(bad) = kmers.iter().find(|kmer| kmer.len() != k) {
+            return Err(anyhow!(
+                "Reference {} mixes kmer lengths: {:?} is not length {}",
+                label,
+                bad,
+                k
+            ));
+        }
+        Ok(Reference {
+            label: label.to_owned(),
+            k,
+            canonical: None,
+            kmers,
+        })
+    }
+
+    /// Match read kmers by their canonical form under `rule`
+    pub fn with_canonical(mut self, rule: CanonicalRule) -> Self {
+        self.canonical = Some(rule);
+        self
+    }
+
+    /// Load the kmers of the count table named by `spec`, TSV or varint,
+    /// canonical if its metadata header says so
+    pub fn load(spec: &ReferenceSpec) -> Result<Self> {
+        let rows = read_count_table(&spec.path)?;
+        let k = match rows.first() {
+            Some((kmer, _)) => kmer.len(),
+            None => return Err(anyhow!("Reference table {:?} is empty", spec.path)),
+        };
+        let reference = Reference::new(&spec.label, k, rows.into_iter().map(|(kmer, _)| kmer))?;
+
+        // varint tables have no metadata header
+        if is_varint_table(&spec.path)? {
+            return Ok(reference);
+        }
+        let metadata = RunMetadata::read(&spec.path)?;
+        if metadata.get("canonical") != Some("true") {
+            return Ok(reference);
+        }
+        let rule = match metadata.get("canonical_rule") {
+            Some(rule) => rule
+                .parse()
+                .map_err(|err: String| anyhow!("Reference table {:?}: {}", spec.path, err))?,
+            None => CanonicalRule::Lex,
+        };
+        Ok(reference.with_canonical(rule))
+    }
+
+    /// Whether the reference holds `kmer`, or its canonical form if the
+    /// reference is canonical
+    pub fn contains(&self, kmer: &str) -> bool {
+        match self.canonical {
+            // the canonical form of an ASCII kmer is ASCII
+            Some(rule) => self
+                .kmers
+                .contains(&String::from_utf8(rule.canonical(kmer.as_bytes())).unwrap()),
+            None => self.kmers.contains(kmer),
+        }
+    }
+}
+
+/// How reads tied between several references are counted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TiePolicy {
+    /// count the read once as ambiguous
+    Ambiguous,
+    /// credit the read to every tied reference
+    All,
+}
+
+impl TiePolicy {
+    pub const VARIANTS: &'static [&'static str] = &["ambiguous", "all"];
+}
+
+impl FromStr for TiePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ambiguous" => Ok(TiePolicy::Ambiguous),
+            "all" => Ok(TiePolicy::All),
+            _ => Err(format!("Unknown tie policy: {}", s)),
+        }
+    }
+}
+
+/// Per-read decision rule, see the module documentation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScreenRule {
+    /// minimum fraction of a read's kmers a reference must contain to be a hit
+    pub min_fraction: f64,
+    pub ties: TiePolicy,
+}
+
+impl Default for ScreenRule {
+    fn default() -> Self {
+        ScreenRule {
+            min_fraction: 0.5,
+            ties: TiePolicy::Ambiguous,
+        }
+    }
+}
+
+/// Outcome of classifying one read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Classification {
+    Unclassified,
+    /// indices of the references the read is credited to
+    Assigned(Vec<usize>),
+    /// indices of the references tied for the read
+    Ambiguous(Vec<usize>),
+}
+
+/// Classify `sequence` against `references`, which must share one kmer length
+pub fn classify(sequence: &[u8], references: &[Reference], rule: &ScreenRule) -> Classification {
+    let k = match references.first() {
+        Some(reference) => reference.k,
+        None => return Classification::Unclassified,
+    };
+    let read_kmers: Vec<&str> = match kmers(sequence, k) {
+        Ok(kmers) => kmers.collect(),
+        Err(_) => return Classification::Unclassified,
+    };
+
+    let fractions: Vec<f64> = references
+        .iter()
+        .map(|reference| {
+            let found = read_kmers
+                .iter()
+                .filter(|kmer| reference.contains(kmer))
+                .count();
+            found as f64 / read_kmers.len() as f64
+        })
+        .collect();
+
+    let best = fractions
+        .iter()
+        .cloned()
+        .filter(|&f| f >= rule.min_fraction)
+        .fold(None, |best: Option<f64>, f| {
+            Some(best.map_or(f, |b| b.max(f)))
+        });
+    let best = match best {
+        Some(best) => best,
+        None => return Classification::Unclassified,
+    };
+
+    let tied: Vec<usize> = (0..references.len())
+        .filter(|&i| fractions[i] == best)
+        .collect();
+    match (tied.len(), rule.ties) {
+        (1, _) | (_, TiePolicy::All) => Classification::Assigned(tied),
+        (_, TiePolicy::Ambiguous) => Classification::Ambiguous(tied),
+    }
+}
+
+/// Number and fraction of reads credited to a reference or outcome
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScreenRow {
+    pub label: String,
+    pub reads: u64,
+    pub fraction: f64,
+}
+
+/// Read classification totals for one input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScreenSummary {
+    pub labels: Vec<String>,
+    pub total_reads: u64,
+    /// reads credited to each reference, in reference order
+    pub assigned: Vec<u64>,
+    pub ambiguous: u64,
+    pub unclassified: u64,
+}
+
+impl ScreenSummary {
+    pub fn new(references: &[Reference]) -> Self {
+        ScreenSummary {
+            labels: references.iter().map(|r| r.label.clone()).collect(),
+            total_reads: 0,
+            assigned: vec![0; references.len()],
+            ambiguous: 0,
+            unclassified: 0,
+        }
+    }
+
+    pub fn add(&mut self, classification: &Classification) {
+        self.total_reads += 1;
+        match classification {
+            Classification::Unclassified => self.unclassified += 1,
+            Classification::Ambiguous(_) => self.ambiguous += 1,
+            Classification::Assigned(indices) => {
+                for &i in indices {
+                    self.assigned[i] += 1;
+                }
+            }
+        }
+    }
+
+    /// One row per reference, then `ambiguous` and `unclassified`
+    ///
+    /// Fractions are of the total number of reads, so with the `all` tie
+    /// policy they may sum to more than 1.
+    pub fn rows(&self) -> Vec<ScreenRow> {
+        let fraction = |reads: u64| {
+            if self.total_reads == 0 {
+                0.0
+            } else {
+                reads as f64 / self.total_reads as f64
+            }
+        };
+
+        self.labels
+            .iter()
+            .zip(&self.assigned)
+            .map(|(label, &reads)| (label.as_str(), reads))
+            .chain(vec![
+                ("ambiguous", self.ambiguous),
+                ("unclassified", self.unclassified),
+            ])
+            .map(|(label, reads)| ScreenRow {
+                label: label.to_owned(),
+                reads,
+                fraction: fraction(reads),
+            })
+            .collect()
+    }
+}
+
+/// Classify every read in the fasta file at `reads_path`
+pub fn screen_fasta(
+    reads_path: &Path,
+    references: &[Reference],
+    rule: &ScreenRule,
+) -> Result<ScreenSummary> {
+    if let Some(reference) = references.iter().find(|r| r.k != references[0].k) {
+        return Err(anyhow!(
+            "Reference {} has kmer length {}, but {} has {}",
+            reference.label,
+            reference.k,
+            references[0].label,
+            references[0].k
+        ));
+    }
+
+    let mut summary = ScreenSummary::new(references);
+    for record in open_fasta(reads_path)?.records() {
+        let record = record?;
+        summary.add(&classify(record.seq(), references, rule));
+    }
+    Ok(summary)
+}
+
+/// Write the classification table to `out` in the requested `format`
+pub fn write_summary<W: Write>(
+    summary: &ScreenSummary,
+    format: OutputFormat,
+    mut out: W,
+) -> Result<()> {
+    let rows = summary.rows();
+    match format {
+        OutputFormat::Tsv => {
+            writeln!(out, "reference\treads\tfraction")?;
+            for row in rows {
+                writeln!(out, "{}\t{}\t{:.6}", row.label, row.reads, row.fraction)?;
+            }
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, &rows)?;
+            writeln!(out)?;
+        }
+        other => return Err(other.unsupported_report()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::random_sequence;
+    use crate::{reverse_complement, run_fasta_kmer_count, CountOptions};
+    use std::fs::{self, File};
+    use tempfile::tempdir;
+
+    const K: usize = 11;
+
+    fn reference(label: &str, genome: &[u8]) -> Reference {
+        let kmers = kmers(genome, K).unwrap().map(|kmer| kmer.to_owned());
+        Reference::new(label, K, kmers).unwrap()
+    }
+
+    #[test]
+    fn test_reference_spec_from_str() {
+        assert_eq!(
+            "human:refs/human.txt".parse::<ReferenceSpec>(),
+            Ok(ReferenceSpec {
+                label: "human".to_owned(),
+                path: PathBuf::from("refs/human.txt")
+            })
+        );
+        assert!("human.txt".parse::<ReferenceSpec>().is_err());
+        assert!(":human.txt".parse::<ReferenceSpec>().is_err());
+    }
+
+    #[test]
+    fn test_classify() {
+        let a = random_sequence(500, 1);
+        let b = random_sequence(500, 2);
+        let references = [reference("a", &a), reference("b", &b)];
+        let rule = ScreenRule::default();
+
+        assert_eq!(
+            classify(&a[100..200], &references, &rule),
+            Classification::Assigned(vec![0])
+        );
+        assert_eq!(
+            classify(&b[300..400], &references, &rule),
+            Classification::Assigned(vec![1])
+        );
+        assert_eq!(
+            classify(&random_sequence(100, 3), &references, &rule),
+            Classification::Unclassified
+        );
+        assert_eq!(
+            classify(&a[..5], &references, &rule),
+            Classification::Unclassified
+        );
+    }
+
+    #[test]
+    fn test_classify_ties() {
+        let shared = random_sequence(200, 4);
+        let references = [reference("a", &shared), reference("b", &shared)];
+        let read = &shared[50..150];
+
+        let ambiguous = ScreenRule::default();
+        assert_eq!(
+            classify(read, &references, &ambiguous),
+            Classification::Ambiguous(vec![0, 1])
+        );
+
+        let all = ScreenRule {
+            ties: TiePolicy::All,
+            ..ScreenRule::default()
+        };
+        assert_eq!(
+            classify(read, &references, &all),
+            Classification::Assigned(vec![0, 1])
+        );
+    }
+
+    #[test]
+    fn test_classify_min_fraction() {
+        let a = random_sequence(500, 1);
+        let references = [reference("a", &a)];
+
+        // half the read from the reference, half unrelated
+        let mut read = a[0..50].to_vec();
+        read.extend(random_sequence(50, 5));
+
+        let strict = ScreenRule {
+            min_fraction: 0.9,
+            ..ScreenRule::default()
+        };
+        assert_eq!(
+            classify(&read, &references, &strict),
+            Classification::Unclassified
+        );
+
+        let lenient = ScreenRule {
+            min_fraction: 0.3,
+            ..ScreenRule::default()
+        };
+        assert_eq!(
+            classify(&read, &references, &lenient),
+            Classification::Assigned(vec![0])
+        );
+    }
+
+    #[test]
+    fn test_screen_fasta() -> Result<()> {
+        let a = random_sequence(1000, 1);
+        let b = random_sequence(1000, 2);
+        let dir = tempdir()?;
+
+        // reference tables on disk, as written by the count command
+        let mut specs = Vec::new();
+        for (label, genome) in &[("a", &a), ("b", &b)] {
+            let path = dir.path().join(format!("{}_kmer.txt", label));
+            let mut file = File::create(&path)?;
+            writeln!(file, "kmer\tcount")?;
+            for kmer in kmers(genome, K)? {
+                writeln!(file, "{}\t1", kmer)?;
+            }
+            specs.push(ReferenceSpec {
+                label: label.to_string(),
+                path,
+            });
+        }
+        let references: Vec<Reference> =
+            specs.iter().map(Reference::load).collect::<Result<_>>()?;
+
+        let reads_path = dir.path().join("reads.fasta");
+        let mut reads = File::create(&reads_path)?;
+        for (i, start) in [0, 200, 400].iter().enumerate() {
+            writeln!(
+                reads,
+                ">a{}\n{}",
+                i,
+                str::from_utf8(&a[*start..start + 100])?
+            )?;
+        }
+        writeln!(reads, ">b0\n{}", str::from_utf8(&b[600..700])?)?;
+        writeln!(reads, ">x0\n{}", str::from_utf8(&random_sequence(100, 9))?)?;
+
+        let summary = screen_fasta(&reads_path, &references, &ScreenRule::default())?;
+        let rows: Vec<(String, u64)> = summary
+            .rows()
+            .into_iter()
+            .map(|row| (row.label, row.reads))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ("a".to_owned(), 3),
+                ("b".to_owned(), 1),
+                ("ambiguous".to_owned(), 0),
+                ("unclassified".to_owned(), 1),
+            ]
+        );
+        assert_eq!(summary.rows()[0].fraction, 0.6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_canonical_reference() -> Result<()> {
+        let a = random_sequence(500, 1);
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("a.fasta");
+        fs::write(&fasta_path, format!(">a\n{}\n", str::from_utf8(&a)?))?;
+
+        let reads_path = dir.path().join("reads.fasta");
+        let read = reverse_complement(&a[100..200]);
+        fs::write(&reads_path, format!(">r\n{}\n", str::from_utf8(&read)?))?;
+
+        for rule in [CanonicalRule::Lex, CanonicalRule::Hash] {
+            let table_path = dir.path().join("a_kmer.txt");
+            let options = CountOptions {
+                canonical: true,
+                canonical_rule: rule,
+                metadata_header: true,
+                ..CountOptions::new(K)
+            };
+            run_fasta_kmer_count(&fasta_path, &options, &table_path)?;
+
+            let reference = Reference::load(&ReferenceSpec {
+                label: "a".to_owned(),
+                path: table_path,
+            })?;
+            assert_eq!(reference.canonical, Some(rule));
+            let summary = screen_fasta(&reads_path, &[reference], &ScreenRule::default())?;
+            assert_eq!(summary.assigned, [1]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_varint_reference() -> Result<()> {
+        let a = random_sequence(500, 1);
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("a.fasta");
+        fs::write(&fasta_path, format!(">a\n{}\n", str::from_utf8(&a)?))?;
+        let table_path = dir.path().join("a_kmer.kmv");
+        let options = CountOptions {
+            varint_output: true,
+            ..CountOptions::new(K)
+        };
+        run_fasta_kmer_count(&fasta_path, &options, &table_path)?;
+
+        let reference = Reference::load(&ReferenceSpec {
+            label: "a".to_owned(),
+            path: table_path,
+        })?;
+        assert_eq!(reference.k, K);
+        assert!(kmers(&a, K)?.all(|kmer| reference.contains(kmer)));
+        Ok(())
+    }
+}
@@ -0,0 +1,226 @@
+//! Alignment-free search of database records by kmer profile
+//!
+//! The kmers of the whole query file are counted into one profile, and every
+//! record of the database is counted into its own profile and scored against
+//! it. Hits are ranked from most to least similar, ties in database order.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::cluster::{counter_profile, DistanceMetric, Profile};
+use crate::strand::{self, Mate};
+use crate::timing::PhaseTimings;
+use crate::{add_sequence_counts, open_fasta, CountOptions, OutputFormat, RecordTally};
+
+/// Similarity between the query and a record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchMetric {
+    /// fraction of the query's distinct kmers found in the record
+    Containment,
+    /// cosine similarity of the kmer weight vectors
+    Cosine,
+}
+
+impl SearchMetric {
+    pub const VARIANTS: &'static [&'static str] = &["containment", "cosine"];
+
+    /// Similarity of `record` to `query`, from 0 (nothing shared) to 1
+    pub fn similarity(self, query: &Profile, record: &Profile) -> f64 {
+        match self {
+            SearchMetric::Containment => {
+                if query.is_empty() {
+                    return 0.0;
+                }
+                let shared = query
+                    .keys()
+                    .filter(|kmer| record.contains_key(*kmer))
+                    .count();
+                shared as f64 / query.len() as f64
+            }
+            SearchMetric::Cosine => {
+                if query.is_empty() || record.is_empty() {
+                    return 0.0;
+                }
+                1.0 - DistanceMetric::Cosine.distance(query, record)
+            }
+        }
+    }
+}
+
+impl FromStr for SearchMetric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "containment" => Ok(SearchMetric::Containment),
+            "cosine" => Ok(SearchMetric::Cosine),
+            _ => Err(format!("Unknown search metric: {}", s)),
+        }
+    }
+}
+
+/// Similarity of one database record to the query
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SearchHit {
+    pub rank: usize,
+    pub record: String,
+    pub length: usize,
+    /// distinct kmers of the record
+    pub kmers: usize,
+    pub similarity: f64,
+}
+
+/// Score every record of the fasta file at `db_path` against `query`, with
+/// record kmers counted as configured by `options`, and keep the `top` most
+/// similar
+///
+/// Records shorter than `k` are skipped and reported in a single warning.
+pub fn search_fasta(
+    query: &Profile,
+    db_path: &Path,
+    options: &CountOptions,
+    metric: SearchMetric,
+    top: usize,
+) -> Result<Vec<SearchHit>> {
+    let mut hits = Vec::new();
+    let mut tally = RecordTally::default();
+    for record in open_fasta(db_path)?.records() {
+        let record = record?;
+        let mate = Mate::from_header(record.id(), record.desc());
+        let sequence = strand::orient(record.seq(), mate, options.library_type);
+        let mut counter = HashMap::new();
+        let mut timings = PhaseTimings::disabled();
+        match add_sequence_counts(&mut counter, &sequence, options, &mut timings) {
+            Err(err) if err.is_fatal() => return Err(err.into()),
+            Err(err) => tally.add(record.id(), Err(err)),
+            Ok(()) => {
+                tally.add(record.id(), Ok(()));
+                let profile = counter_profile(counter, options);
+                hits.push(SearchHit {
+                    rank: 0,
+                    record: record.id().to_owned(),
+                    length: record.seq().len(),
+                    kmers: profile.len(),
+                    similarity: metric.similarity(query, &profile),
+                });
+            }
+        }
+    }
+    tally.warn_too_short(db_path, options.k);
+
+    // stable, so equally similar records stay in database order
+    hits.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+    hits.truncate(top);
+    for (n, hit) in hits.iter_mut().enumerate() {
+        hit.rank = n + 1;
+    }
+    Ok(hits)
+}
+
+/// Write one row per hit to `out` in the requested `format`
+pub fn write_hits<W: Write>(hits: &[SearchHit], format: OutputFormat, mut out: W) -> Result<()> {
+    match format {
+        OutputFormat::Tsv => {
+            writeln!(out, "rank\trecord\tlength\tkmers\tsimilarity")?;
+            for hit in hits {
+                writeln!(
+                    out,
+                    "{}\t{}\t{}\t{}\t{:.6}",
+                    hit.rank, hit.record, hit.length, hit.kmers, hit.similarity
+                )?;
+            }
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, hits)?;
+            writeln!(out)?;
+        }
+        other => return Err(other.unsupported_report()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::fasta_profile;
+    use crate::testutil::random_sequence;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn profile(weights: &[(&str, f64)]) -> Profile {
+        weights.iter().map(|&(k, w)| (k.to_owned(), w)).collect()
+    }
+
+    #[test]
+    fn test_similarity() {
+        let query = profile(&[("AA", 1.0), ("CC", 1.0)]);
+        let record = profile(&[("AA", 3.0), ("GG", 1.0), ("TT", 1.0)]);
+        assert_eq!(SearchMetric::Containment.similarity(&query, &record), 0.5);
+        let cosine = SearchMetric::Cosine.similarity(&query, &record);
+        assert!((cosine - 3.0 / (2f64.sqrt() * 11f64.sqrt())).abs() < 1e-12);
+
+        assert_eq!(SearchMetric::Containment.similarity(&query, &query), 1.0);
+        assert_eq!(
+            SearchMetric::Cosine.similarity(&query, &Profile::new()),
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_search_fasta() -> Result<()> {
+        let dir = tempdir()?;
+        let target = random_sequence(400, 1);
+        let query_path = dir.path().join("query.fasta");
+        fs::write(
+            &query_path,
+            format!(">q\n{}\n", std::str::from_utf8(&target[100..300])?),
+        )?;
+        let db_path = dir.path().join("db.fasta");
+        let mut db = String::new();
+        for (id, seq) in &[
+            ("other1", random_sequence(400, 2)),
+            ("target", target),
+            ("short", b"ACG".to_vec()),
+            ("other2", random_sequence(400, 3)),
+        ] {
+            db.push_str(&format!(">{}\n{}\n", id, std::str::from_utf8(seq)?));
+        }
+        fs::write(&db_path, db)?;
+
+        let options = CountOptions::new(8);
+        let query = fasta_profile(&query_path, &options)?;
+        for metric in [SearchMetric::Containment, SearchMetric::Cosine] {
+            let hits = search_fasta(&query, &db_path, &options, metric, 2)?;
+            assert_eq!(hits.len(), 2);
+            assert_eq!((hits[0].rank, hits[0].record.as_str()), (1, "target"));
+            assert!(hits[0].similarity > hits[1].similarity);
+        }
+        let hits = search_fasta(&query, &db_path, &options, SearchMetric::Containment, 10)?;
+        assert_eq!(hits.len(), 3);
+        assert_eq!(hits[0].similarity, 1.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_hits() -> Result<()> {
+        let hits = [SearchHit {
+            rank: 1,
+            record: "chr1".to_owned(),
+            length: 10,
+            kmers: 8,
+            similarity: 0.5,
+        }];
+        let mut out = Vec::new();
+        write_hits(&hits, OutputFormat::Tsv, &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "rank\trecord\tlength\tkmers\tsimilarity\n1\tchr1\t10\t8\t0.500000\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+//! Reproducibility self-test of the counting pipeline
+//!
+//! The same input is processed twice and the outputs compared byte for byte,
+//! so any nondeterminism, such as unstable ordering of equal counts, fails the
+//! run instead of silently producing differing tables. Leading `#` metadata
+//! lines, which record the time of each run, are not compared.
+
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+
+use crate::index::index_path;
+
+/// Path of the second, comparison output for `output_path`
+pub fn check_path(output_path: &Path) -> PathBuf {
+    let mut path = OsString::from(output_path.as_os_str());
+    path.push(".check");
+    PathBuf::from(path)
+}
+
+/// Run `run` at `output_path` and again at `check_path(output_path)`, failing
+/// if the two outputs differ
+///
+/// The comparison output, and an index sidecar written beside it, are removed
+/// afterwards.
+pub fn run_checked<F>(output_path: &Path, run: F) -> Result<()>
+where
+    F: Fn(&Path) -> Result<()>,
+{
+    let check = check_path(output_path);
+    run(output_path)?;
+    run(&check)?;
+
+    let mut compared = vec![(output_path.to_owned(), check)];
+    if index_path(output_path).exists() {
+        compared.push((index_path(output_path), index_path(&compared[0].1)));
+    }
+
+    let mut result = Ok(());
+    for (output, check) in &compared {
+        if result.is_ok() && !same_contents(output, check)? {
+            result = Err(nondeterministic(output));
+        }
+    }
+    for (_, check) in &compared {
+        fs::remove_file(check)?;
+    }
+    result
+}
+
+/// Whether the files at `a` and `b` are identical after their leading `#` lines
+fn same_contents(a: &Path, b: &Path) -> Result<bool> {
+    let body = |path: &Path| -> Result<_> {
+        Ok(BufReader::new(File::open(path)?)
+            .split(b'\n')
+            .skip_while(|line| matches!(line, Ok(line) if line.starts_with(b"#"))))
+    };
+    let (mut a, mut b) = (body(a)?, body(b)?);
+    loop {
+        match (a.next().transpose()?, b.next().transpose()?) {
+            (None, None) => return Ok(true),
+            (Some(x), Some(y)) if x == y => continue,
+            _ => return Ok(false),
+        }
+    }
+}
+
+fn nondeterministic(path: &Path) -> anyhow::Error {
+    anyhow!(
+        "Self-check failed: counting the same input twice produced different {:?}",
+        path
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{run_fasta_kmer_count, CountOptions};
+    use std::cell::Cell;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_self_check_passes() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("s.fasta");
+        fs::write(&fasta_path, ">a\nACGTTGCAACGTAAAC\n")?;
+        let output_path = dir.path().join("s_kmer.txt");
+
+        for options in &[
+            CountOptions::new(3),
+            CountOptions {
+                indexed_output: true,
+                ..CountOptions::new(3)
+            },
+        ] {
+            run_checked(&output_path, |path| {
+                run_fasta_kmer_count(&fasta_path, options, path)
+            })?;
+            assert!(output_path.exists());
+            assert!(!check_path(&output_path).exists());
+            assert!(!index_path(&check_path(&output_path)).exists());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_self_check_fails_on_nondeterminism() -> Result<()> {
+        let dir = tempdir()?;
+        let output_path = dir.path().join("s_kmer.txt");
+        let runs = Cell::new(0);
+        let result = run_checked(&output_path, |path| {
+            runs.set(runs.get() + 1);
+            Ok(fs::write(path, format!("run {}\n", runs.get()))?)
+        });
+
+        assert!(result.is_err());
+        assert!(!check_path(&output_path).exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,262 @@
+//! Sequence file helpers
+//!
+//! FASTQ inputs are read through the same records as fasta ones: each read
+//! becomes a `fasta::Record` of its id, description, and sequence, and its
+//! quality line is dropped, so every mode counts reads as it counts contigs.
+//!
+//! `bio::io::fasta::Writer` puts each sequence on a single line; the writer
+//! here wraps sequences so rewritten files keep the layout of their input.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::Result;
+use bio::io::{fasta, fastq};
+
+use crate::open_input;
+
+/// Reader of the records of a fasta or FASTQ input
+pub enum SequenceReader<B: BufRead> {
+    Fasta(fasta::Reader<B>),
+    Fastq(fastq::Reader<B>),
+}
+
+impl<B: BufRead> SequenceReader<B> {
+    /// Reader of the FASTQ records of `reader` if `fastq`, else of its fasta
+    /// records
+    pub fn new(reader: B, fastq: bool) -> Self {
+        if fastq {
+            SequenceReader::Fastq(fastq::Reader::from_bufread(reader))
+        } else {
+            SequenceReader::Fasta(fasta::Reader::from_bufread(reader))
+        }
+    }
+
+    /// Iterate over the records, FASTQ reads as fasta records without their
+    /// qualities
+    pub fn records(self) -> SequenceRecords<B> {
+        match self {
+            SequenceReader::Fasta(reader) => SequenceRecords::Fasta(reader.records()),
+            SequenceReader::Fastq(reader) => SequenceRecords::Fastq(reader.records()),
+        }
+    }
+}
+
+/// Records of a `SequenceReader`
+///
+/// A malformed FASTQ record is an `InvalidData` error, as a fasta one is.
+pub enum SequenceRecords<B: BufRead> {
+    Fasta(fasta::Records<B>),
+    Fastq(fastq::Records<B>),
+}
+
+impl<B: BufRead> Iterator for SequenceRecords<B> {
+    type Item = io::Result<fasta::Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            SequenceRecords::Fasta(records) => records.next(),
+            SequenceRecords::Fastq(records) => records.next().map(|record| {
+                let record =
+                    record.map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                Ok(fasta::Record::with_attrs(
+                    record.id(),
+                    record.desc(),
+                    record.seq(),
+                ))
+            }),
+        }
+    }
+}
+
+/// Buffered reader of a fasta or FASTQ input noting the byte offset at which
+/// the records not yet returned by a `SequenceReader` over it begin
+///
+/// The fasta reader reads the header line of the next record ahead, so for
+/// fasta the offset is that of the last header line begun, and at the end of
+/// the input, its length; FASTQ records are read exactly.
+pub struct OffsetReader<B> {
+    inner: B,
+    fastq: bool,
+    /// bytes consumed so far
+    consumed: u64,
+    /// whether the next byte consumed begins a line
+    line_start: bool,
+    next_record: Arc<AtomicU64>,
+}
+
+impl<B: BufRead> OffsetReader<B> {
+    /// Reader of `inner`, whose first byte is at `offset` in the input
+    pub fn new(inner: B, fastq: bool, offset: u64) -> Self {
+        OffsetReader {
+            inner,
+            fastq,
+            consumed: offset,
+            line_start: true,
+            next_record: Arc::new(AtomicU64::new(offset)),
+        }
+    }
+
+    /// Offset of the next record, kept up to date as the reader is read
+    pub fn next_record(&self) -> Arc<AtomicU64> {
+        Arc::clone(&self.next_record)
+    }
+}
+
+impl<B: BufRead> Read for OffsetReader<B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.fill_buf()?.read(buf)?;
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<B: BufRead> BufRead for OffsetReader<B> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let buf = self.inner.fill_buf()?;
+        if buf.is_empty() {
+            self.next_record.store(self.consumed, Ordering::Relaxed);
+        }
+        Ok(buf)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if !self.fastq {
+            // the bytes to consume are still buffered
+            if let Ok(buf) = self.inner.fill_buf() {
+                for (i, &byte) in buf[..amt].iter().enumerate() {
+                    if self.line_start && byte == b'>' {
+                        self.next_record
+                            .store(self.consumed + i as u64, Ordering::Relaxed);
+                    }
+                    self.line_start = byte == b'\n';
+                }
+            }
+        }
+        self.consumed += amt as u64;
+        if self.fastq {
+            self.next_record.store(self.consumed, Ordering::Relaxed);
+        }
+        self.inner.consume(amt);
+    }
+}
+
+/// Line width used when the input's width cannot be determined
+pub const DEFAULT_LINE_WIDTH: usize = 60;
+
+/// Length of the first sequence line of the fasta file at `fasta_path`
+///
+/// Returns `None` if the file has no sequence lines, or is FASTQ, whose reads
+/// are not wrapped.
+pub fn detect_line_width(fasta_path: &Path) -> Result<Option<usize>> {
+    if crate::is_fastq(fasta_path) {
+        return Ok(None);
+    }
+    for line in BufReader::new(open_input(fasta_path)?).lines() {
+        let line = line?;
+        let line = line.trim_end();
+        if !line.is_empty() && !line.starts_with('>') {
+            return Ok(Some(line.len()));
+        }
+    }
+    Ok(None)
+}
+
+/// Fasta writer wrapping sequences at a fixed line width
+pub struct WrappingFastaWriter<W: Write> {
+    writer: W,
+    line_width: usize,
+}
+
+impl<W: Write> WrappingFastaWriter<W> {
+    pub fn new(writer: W, line_width: usize) -> Self {
+        WrappingFastaWriter {
+            writer,
+            line_width: line_width.max(1),
+        }
+    }
+
+    /// Write a record with the given id, optional description and sequence
+    pub fn write(&mut self, id: &str, desc: Option<&str>, seq: &[u8]) -> Result<()> {
+        match desc {
+            Some(desc) => writeln!(self.writer, ">{} {}", id, desc)?,
+            None => writeln!(self.writer, ">{}", id)?,
+        }
+        for line in seq.chunks(self.line_width) {
+            self.writer.write_all(line)?;
+            self.writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_detect_line_width() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("wrapped.fasta");
+        let mut file = File::create(&path)?;
+        write!(file, ">a desc\nACGTA\nCG\n>b\nAA\n")?;
+        assert_eq!(detect_line_width(&path)?, Some(5));
+
+        let empty = dir.path().join("empty.fasta");
+        File::create(&empty)?;
+        assert_eq!(detect_line_width(&empty)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_offset_reader() -> Result<()> {
+        let fasta = ">a desc\nACGT\nAC\n>b\nGG\n";
+        let reader = OffsetReader::new(fasta.as_bytes(), false, 0);
+        let next_record = reader.next_record();
+        let mut records = SequenceReader::new(reader, false).records();
+        assert_eq!(records.next().unwrap()?.id(), "a");
+        assert_eq!(next_record.load(Ordering::Relaxed), 16);
+        assert_eq!(records.next().unwrap()?.id(), "b");
+        assert_eq!(next_record.load(Ordering::Relaxed), fasta.len() as u64);
+
+        // resuming at a recorded offset counts it from the start of the input
+        let reader = OffsetReader::new(&fasta.as_bytes()[16..], false, 16);
+        let next_record = reader.next_record();
+        let mut records = SequenceReader::new(reader, false).records();
+        assert_eq!(records.next().unwrap()?.seq(), b"GG");
+        assert!(records.next().is_none());
+        assert_eq!(next_record.load(Ordering::Relaxed), fasta.len() as u64);
+
+        let fastq = "@r1\nACGT\n+\nIIII\n@r2\nAC\n+\nII\n";
+        let reader = OffsetReader::new(fastq.as_bytes(), true, 0);
+        let next_record = reader.next_record();
+        let mut records = SequenceReader::new(reader, true).records();
+        assert_eq!(records.next().unwrap()?.id(), "r1");
+        assert_eq!(next_record.load(Ordering::Relaxed), 16);
+        Ok(())
+    }
+
+    #[test]
+    fn test_wrapping_writer() -> Result<()> {
+        let mut out = Vec::new();
+        let mut writer = WrappingFastaWriter::new(&mut out, 4);
+        writer.write("a", Some("some desc"), b"ACGTACGTAC")?;
+        writer.write("b", None, b"ACGT")?;
+        writer.flush()?;
+
+        assert_eq!(
+            String::from_utf8(out)?,
+            ">a some desc\nACGT\nACGT\nAC\n>b\nACGT\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,258 @@
+//! Interactive queries over a loaded count table
+//!
+//! Commands are read one per line. A bare kmer prints its count; `top N`,
+//! `hist`, and `grep PATTERN` summarize the table; `quit` ends the session.
+//! Mistakes print a hint and the session continues.
+
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::histogram::{histogram_from_counts, write_histogram};
+use crate::sketch::canonical;
+use crate::table::read_count_table;
+
+const USAGE: &str = "commands:
+  KMER          count of KMER
+  top [N]       the N most abundant kmers (default 10)
+  hist          abundance histogram
+  grep PATTERN  kmers containing PATTERN; `.` matches any base
+  help          show this message
+  quit          end the session";
+
+/// Count table held in memory for queries
+#[derive(Debug, Clone)]
+pub struct CountTable {
+    /// length of the table's kmers
+    pub k: usize,
+    /// whether every kmer is in canonical form, so queries are canonicalized
+    pub canonical: bool,
+    counts: HashMap<String, u64>,
+}
+
+impl CountTable {
+    pub fn new<I: IntoIterator<Item = (String, u64)>>(rows: I) -> Self {
+        let counts: HashMap<String, u64> = rows.into_iter().collect();
+        let k = counts.keys().next().map(String::len).unwrap_or(0);
+        let canonical = !counts.is_empty()
+            && counts
+                .keys()
+                .all(|kmer| canonical(kmer.as_bytes()) == kmer.as_bytes());
+        CountTable {
+            k,
+            canonical,
+            counts,
+        }
+    }
+
+    /// Load the TSV or varint count table at `path`
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(CountTable::new(read_count_table(path)?))
+    }
+
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// Count of `kmer`, or of its canonical form in a canonical table
+    pub fn count(&self, kmer: &str) -> u64 {
+        let kmer = kmer.to_ascii_uppercase();
+        let key = if self.canonical {
+            String::from_utf8_lossy(&canonical(kmer.as_bytes())).into_owned()
+        } else {
+            kmer
+        };
+        self.counts.get(&key).copied().unwrap_or(0)
+    }
+
+    /// The `n` most abundant kmers, ties in kmer order
+    pub fn top(&self, n: usize) -> Vec<(&str, u64)> {
+        let mut rows: Vec<(&str, u64)> = self
+            .counts
+            .iter()
+            .map(|(kmer, &count)| (kmer.as_str(), count))
+            .collect();
+        rows.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        rows.truncate(n);
+        rows
+    }
+
+    /// Kmers containing `pattern`, where `.` matches any base, in kmer order
+    pub fn grep(&self, pattern: &str) -> Vec<(&str, u64)> {
+        let pattern = pattern.to_ascii_uppercase().into_bytes();
+        let matches = |kmer: &[u8]| {
+            kmer.windows(pattern.len()).any(|window| {
+                window
+                    .iter()
+                    .zip(&pattern)
+                    .all(|(&base, &p)| p == b'.' || p == base)
+            })
+        };
+        let mut rows: Vec<(&str, u64)> = self
+            .counts
+            .iter()
+            .filter(|(kmer, _)| matches(kmer.as_bytes()))
+            .map(|(kmer, &count)| (kmer.as_str(), count))
+            .collect();
+        rows.sort();
+        rows
+    }
+}
+
+/// Whether the session continues after a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Quit,
+}
+
+/// Run one command `line` against `table`, writing its reply to `out`
+pub fn execute<W: Write>(table: &CountTable, line: &str, out: &mut W) -> Result<Flow> {
+    let mut words = line.split_whitespace();
+    let command = match words.next() {
+        Some(command) => command,
+        None => return Ok(Flow::Continue),
+    };
+    let arg = words.next();
+    if words.next().is_some() {
+        writeln!(out, "too many arguments\n{}", USAGE)?;
+        return Ok(Flow::Continue);
+    }
+
+    match (command, arg) {
+        ("quit", None) | ("exit", None) => return Ok(Flow::Quit),
+        ("help", None) => writeln!(out, "{}", USAGE)?,
+        ("top", arg) => match arg.map_or(Ok(10), str::parse::<usize>) {
+            Ok(n) => {
+                for (kmer, count) in table.top(n) {
+                    writeln!(out, "{}\t{}", kmer, count)?;
+                }
+            }
+            Err(_) => writeln!(out, "top takes a number of kmers, e.g. `top 20`")?,
+        },
+        ("hist", None) => write_histogram(
+            &histogram_from_counts(table.counts.values().copied()),
+            &mut *out,
+        )?,
+        ("grep", Some(pattern)) if is_pattern(pattern) => {
+            for (kmer, count) in table.grep(pattern) {
+                writeln!(out, "{}\t{}", kmer, count)?;
+            }
+        }
+        ("grep", _) => writeln!(
+            out,
+            "grep takes one pattern of ACGT and `.`, e.g. `grep CG..T`"
+        )?,
+        (kmer, None) if is_pattern(kmer) && !kmer.contains('.') => {
+            if kmer.len() == table.k {
+                writeln!(out, "{}\t{}", kmer.to_ascii_uppercase(), table.count(kmer))?;
+            } else {
+                writeln!(
+                    out,
+                    "{} has {} bases, but this table has k = {}",
+                    kmer,
+                    kmer.len(),
+                    table.k
+                )?;
+            }
+        }
+        _ => writeln!(out, "unknown command: {}\n{}", line.trim(), USAGE)?,
+    }
+    Ok(Flow::Continue)
+}
+
+fn is_pattern(word: &str) -> bool {
+    word.bytes()
+        .all(|b| matches!(b.to_ascii_uppercase(), b'A' | b'C' | b'G' | b'T' | b'.'))
+}
+
+/// Read commands from `input` until `quit` or end of input
+///
+/// With `prompt`, a prompt is written to `out` before each command.
+pub fn run<R: BufRead, W: Write>(
+    table: &CountTable,
+    input: R,
+    mut out: W,
+    prompt: bool,
+) -> Result<()> {
+    let mut lines = input.lines();
+    loop {
+        if prompt {
+            write!(out, "kmer> ")?;
+            out.flush()?;
+        }
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => return Ok(()),
+        };
+        if execute(table, &line, &mut out)? == Flow::Quit {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> CountTable {
+        CountTable::new(vec![
+            ("ACG".to_owned(), 5),
+            ("CGT".to_owned(), 5),
+            ("GTA".to_owned(), 2),
+            ("TAC".to_owned(), 7),
+        ])
+    }
+
+    fn session(table: &CountTable, script: &str) -> String {
+        let mut out = Vec::new();
+        run(table, script.as_bytes(), &mut out, false).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_queries() {
+        let table = table();
+        assert_eq!(session(&table, "acg\nAAA\n"), "ACG\t5\nAAA\t0\n");
+        assert_eq!(session(&table, "top 3\n"), "TAC\t7\nACG\t5\nCGT\t5\n");
+        assert_eq!(session(&table, "hist\n"), "2\t1\n5\t2\n7\t1\n");
+        assert_eq!(session(&table, "grep C.T\n"), "CGT\t5\n");
+        assert_eq!(session(&table, "grep TA\n"), "GTA\t2\nTAC\t7\n");
+    }
+
+    #[test]
+    fn test_quit_stops_session() {
+        assert_eq!(session(&table(), "ACG\nquit\nACG\n"), "ACG\t5\n");
+    }
+
+    #[test]
+    fn test_forgiving_errors() {
+        let table = table();
+        assert_eq!(
+            session(&table, "ACGT\n"),
+            "ACGT has 4 bases, but this table has k = 3\n"
+        );
+        assert!(
+            session(&table, "frobnicate\n").starts_with("unknown command: frobnicate\ncommands:")
+        );
+        assert!(session(&table, "top many\n").starts_with("top takes a number"));
+        assert!(session(&table, "grep\n").starts_with("grep takes one pattern"));
+        // the session goes on after a mistake
+        assert!(session(&table, "frobnicate\nTAC\n").ends_with("TAC\t7\n"));
+    }
+
+    #[test]
+    fn test_canonical_table() {
+        // every kmer is its own canonical form, so the reverse complement is folded
+        let table = CountTable::new(vec![("AAC".to_owned(), 4), ("ACG".to_owned(), 1)]);
+        assert!(table.canonical);
+        assert_eq!(session(&table, "GTT\n"), "GTT\t4\n");
+        assert!(!self::table().canonical);
+    }
+}
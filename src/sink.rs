@@ -0,0 +1,271 @@
+//! Streaming count tables to an HTTP endpoint
+//!
+//! As each table is saved, its kmers are also POSTed to the sink URL as
+//! NDJSON, one `{"kmer":...,"count":...}` object per line as with
+//! `--format ndjson`, in batches of at most `batch_size` rows. Every request
+//! names its table in an `X-Kmer-Table` header and its place in the table in
+//! `X-Kmer-Batch`, e.g. `1/3`, so a receiver can tell where one input ends.
+//!
+//! Connection failures and `429` or `5xx` responses are retried after a
+//! delay that doubles with every attempt; other responses fail the input at
+//! once. Posting is compiled only with the `http` feature.
+
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::{KmerRecord, NdjsonRow};
+
+/// Rows sent in one request by default
+pub const DEFAULT_BATCH_SIZE: usize = 10_000;
+
+/// Where and how count tables are streamed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sink {
+    pub url: String,
+    /// most rows sent in one request
+    pub batch_size: usize,
+    /// further attempts at a batch after a transient failure
+    pub retries: u32,
+    /// delay before the first retry, doubled before each further one
+    pub backoff: Duration,
+}
+
+impl Sink {
+    /// Sink posting to `url` with the default batch size and 4 retries
+    /// starting 500 ms apart
+    pub fn new(url: &str) -> Self {
+        Sink {
+            url: url.to_owned(),
+            batch_size: DEFAULT_BATCH_SIZE,
+            retries: 4,
+            backoff: Duration::from_millis(500),
+        }
+    }
+
+    /// Post the rows of `kmers` for the table named `table`
+    ///
+    /// An empty table is sent as one empty batch, so every table is heard of.
+    pub(crate) fn send(&self, kmers: &[KmerRecord], table: &str) -> Result<()> {
+        let batches = ndjson_batches(kmers, self.batch_size)?;
+        let total = batches.len();
+        for (index, body) in batches.iter().enumerate() {
+            let batch = format!("{}/{}", index + 1, total);
+            self.post_with_retries(body, table, &batch)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "http")]
+    fn post_with_retries(&self, body: &[u8], table: &str, batch: &str) -> Result<()> {
+        use anyhow::anyhow;
+        use log::warn;
+        use std::thread;
+
+        let mut delay = self.backoff;
+        for attempt in 0.. {
+            let response = ureq::post(&self.url)
+                .set("Content-Type", "application/x-ndjson")
+                .set("X-Kmer-Table", table)
+                .set("X-Kmer-Batch", batch)
+                .send_bytes(body);
+            let err = match response {
+                Ok(_) => return Ok(()),
+                Err(err) => err,
+            };
+            let transient = match &err {
+                ureq::Error::Status(code, _) => *code == 429 || *code >= 500,
+                ureq::Error::Transport(_) => true,
+            };
+            if !transient || attempt >= self.retries {
+                return Err(anyhow!(err).context(format!(
+                    "Could not post batch {} of {} to {}",
+                    batch, table, self.url
+                )));
+            }
+            warn!(
+                "Posting batch {} of {} failed ({}); retrying in {:?}",
+                batch, table, err, delay
+            );
+            thread::sleep(delay);
+            delay *= 2;
+        }
+        unreachable!("attempts are unbounded")
+    }
+
+    #[cfg(not(feature = "http"))]
+    fn post_with_retries(&self, _body: &[u8], _table: &str, _batch: &str) -> Result<()> {
+        anyhow::bail!(
+            "Posting to {} requires building with the `http` feature",
+            self.url
+        )
+    }
+}
+
+/// NDJSON request bodies of at most `batch_size` rows each of `kmers`, in
+/// order; a single empty body if there are no rows
+fn ndjson_batches(kmers: &[KmerRecord], batch_size: usize) -> Result<Vec<Vec<u8>>> {
+    if kmers.is_empty() {
+        return Ok(vec![Vec::new()]);
+    }
+    let mut batches = Vec::new();
+    for chunk in kmers.chunks(batch_size.max(1)) {
+        let mut body = Vec::new();
+        for kmer in chunk {
+            let row = NdjsonRow {
+                kmer: &kmer.seq,
+                count: kmer.count,
+            };
+            serde_json::to_writer(&mut body, &row)?;
+            body.push(b'\n');
+        }
+        batches.push(body);
+    }
+    Ok(batches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    fn records(counts: &[(&'static str, u64)]) -> Vec<KmerRecord<'static>> {
+        counts
+            .iter()
+            .map(|&(seq, count)| KmerRecord {
+                seq: Cow::Borrowed(seq),
+                count,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_ndjson_batches() -> Result<()> {
+        let kmers = records(&[("AAA", 3), ("AAC", 2), ("ACG", 1)]);
+        let batches = ndjson_batches(&kmers, 2)?;
+        assert_eq!(
+            batches,
+            [
+                b"{\"kmer\":\"AAA\",\"count\":3}\n{\"kmer\":\"AAC\",\"count\":2}\n".to_vec(),
+                b"{\"kmer\":\"ACG\",\"count\":1}\n".to_vec(),
+            ]
+        );
+        assert_eq!(ndjson_batches(&[], 2)?, [Vec::<u8>::new()]);
+        Ok(())
+    }
+
+    #[cfg(feature = "http")]
+    mod http {
+        use super::*;
+        use crate::{run_fasta_kmer_count, CountOptions};
+        use std::io::{BufRead, BufReader, Read, Write};
+        use std::net::TcpListener;
+        use std::sync::mpsc::{self, Receiver};
+        use std::thread;
+        use tempfile::tempdir;
+
+        /// A request received by the mock sink
+        #[derive(Debug)]
+        struct Request {
+            batch: String,
+            table: String,
+            body: String,
+        }
+
+        /// Answer one request per status of `statuses` on a local port,
+        /// returning the URL and the requests received
+        fn serve(statuses: Vec<u16>) -> (String, Receiver<Request>) {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap();
+            let (sender, receiver) = mpsc::channel();
+            thread::spawn(move || {
+                for status in statuses {
+                    let (stream, _) = listener.accept().unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let (mut length, mut batch, mut table) = (0, String::new(), String::new());
+                    let mut line = String::new();
+                    while reader.read_line(&mut line).unwrap() > 2 {
+                        if let Some((name, value)) = line.trim_end().split_once(": ") {
+                            match name.to_ascii_lowercase().as_str() {
+                                "content-length" => length = value.parse().unwrap(),
+                                "x-kmer-batch" => batch = value.to_owned(),
+                                "x-kmer-table" => table = value.to_owned(),
+                                _ => {}
+                            }
+                        }
+                        line.clear();
+                    }
+                    let mut body = vec![0; length];
+                    reader.read_exact(&mut body).unwrap();
+                    let body = String::from_utf8(body).unwrap();
+                    sender.send(Request { batch, table, body }).unwrap();
+                    let mut stream = stream;
+                    write!(
+                        stream,
+                        "HTTP/1.1 {} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                        status
+                    )
+                    .unwrap();
+                }
+            });
+            (format!("http://{}/counts", address), receiver)
+        }
+
+        fn sink(url: &str) -> Sink {
+            Sink {
+                batch_size: 2,
+                backoff: Duration::from_millis(1),
+                ..Sink::new(url)
+            }
+        }
+
+        #[test]
+        fn test_counts_are_posted_in_batches() -> Result<()> {
+            let dir = tempdir()?;
+            let fasta_path = dir.path().join("sample.fasta");
+            std::fs::write(&fasta_path, ">a\nAAAACG\n")?;
+            // the second batch is retried after a 503
+            let (url, requests) = serve(vec![200, 503, 200]);
+            let options = CountOptions {
+                sink: Some(sink(&url)),
+                ..CountOptions::new(3)
+            };
+            let output_path = dir.path().join("sample_kmer.txt");
+            run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
+
+            let requests: Vec<Request> = requests.iter().collect();
+            assert_eq!(requests.len(), 3);
+            assert!(requests.iter().all(|r| r.table == "sample_kmer.txt"));
+            assert_eq!(requests[0].batch, "1/2");
+            assert_eq!(
+                requests[0].body,
+                "{\"kmer\":\"AAA\",\"count\":2}\n{\"kmer\":\"AAC\",\"count\":1}\n"
+            );
+            assert_eq!(requests[1].batch, "2/2");
+            assert_eq!(requests[2].batch, "2/2");
+            assert_eq!(requests[1].body, requests[2].body);
+            assert_eq!(requests[2].body, "{\"kmer\":\"ACG\",\"count\":1}\n");
+            assert!(output_path.exists());
+            Ok(())
+        }
+
+        #[test]
+        fn test_sink_gives_up() {
+            let kmers = records(&[("AAA", 1)]);
+            // a client error is not retried
+            let (url, requests) = serve(vec![400]);
+            let err = sink(&url).send(&kmers, "t").unwrap_err();
+            assert!(format!("{:#}", err).contains("Could not post batch 1/1 of t"));
+            assert_eq!(requests.iter().count(), 1);
+
+            // server errors are retried until the retries run out
+            let (url, requests) = serve(vec![500, 502, 503]);
+            let sink = Sink {
+                retries: 2,
+                ..sink(&url)
+            };
+            assert!(sink.send(&kmers, "t").is_err());
+            assert_eq!(requests.iter().count(), 3);
+        }
+    }
+}
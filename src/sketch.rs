@@ -0,0 +1,413 @@
+//! MinHash sketches of canonical kmer sets
+//!
+//! A bottom-`s` sketch keeps the `s` smallest hash values of a sequence's
+//! canonical kmers. Jaccard similarity between two kmer sets is estimated
+//! from their sketches without materializing the sets. The hash is fixed
+//! (FNV-1a followed by a SplitMix64 finalizer) so sketches are reproducible.
+//!
+//! A sketch file records `k`, the sketch size, the seed, and the hash
+//! function in `# key: value` header lines, followed by one hash per line in
+//! ascending order. Sketches are only comparable with equal `k` and seed.
+
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::{open_fasta, reverse_complement};
+
+/// Name of the hash function recorded in sketch files
+pub const HASH_NAME: &str = "fnv1a64-splitmix64";
+
+/// Hash `bytes` with the given `seed`
+pub fn hash_bytes(bytes: &[u8], seed: u64) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325 ^ seed;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    // SplitMix64 finalizer spreads FNV's weak low bits
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+/// The lexicographically smaller of `kmer` and its reverse complement
+pub fn canonical(kmer: &[u8]) -> Vec<u8> {
+    let rc = reverse_complement(kmer);
+    if rc.as_slice() < kmer {
+        rc
+    } else {
+        kmer.to_vec()
+    }
+}
+
+/// Bottom-`size` MinHash sketch over canonical kmers of length `k`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sketch {
+    pub k: usize,
+    pub size: usize,
+    pub seed: u64,
+    /// smallest hash values, ascending
+    hashes: Vec<u64>,
+}
+
+impl Sketch {
+    /// Empty sketch of canonical kmers of length `k`
+    pub fn new(k: usize, size: usize, seed: u64) -> Self {
+        Sketch {
+            k,
+            size,
+            seed,
+            hashes: Vec::new(),
+        }
+    }
+
+    /// Sketch the canonical kmers of `sequence`
+    ///
+    /// Sequences shorter than `k` produce an empty sketch.
+    pub fn from_sequence(sequence: &[u8], k: usize, size: usize, seed: u64) -> Self {
+        let mut sketch = Sketch::new(k, size, seed);
+        sketch.add_sequence(sequence);
+        sketch
+    }
+
+    /// Sketch the canonical kmers of all records of the fasta file at
+    /// `fasta_path`, one record at a time
+    pub fn from_fasta(fasta_path: &Path, k: usize, size: usize, seed: u64) -> Result<Self> {
+        let mut sketch = Sketch::new(k, size, seed);
+        for record in open_fasta(fasta_path)?.records() {
+            sketch.add_sequence(record?.seq());
+        }
+        Ok(sketch)
+    }
+
+    /// Sketch the kmers of the rows of a count table, whose length gives `k`
+    ///
+    /// Rows of canonical and non-canonical tables alike give the sketch of
+    /// the sequence they were counted from. Fails if the table is empty or
+    /// its kmers differ in length.
+    pub fn from_table(rows: &[(String, u64)], size: usize, seed: u64) -> Result<Self> {
+        let k = rows
+            .first()
+            .map(|(kmer, _)| kmer.len())
+            .ok_or_else(|| anyhow!("No kmers to sketch"))?;
+        let mut sketch = Sketch::new(k, size, seed);
+        for (kmer, _) in rows {
+            if kmer.len() != k {
+                bail!("Kmers of lengths {} and {} in one table", k, kmer.len());
+            }
+            sketch.insert(kmer.as_bytes());
+        }
+        Ok(sketch)
+    }
+
+    /// Add the canonical kmers of `sequence`
+    pub fn add_sequence(&mut self, sequence: &[u8]) {
+        if self.k > 0 {
+            for window in sequence.windows(self.k) {
+                self.insert(window);
+            }
+        }
+    }
+
+    /// Add the canonical form of `kmer`
+    pub fn insert(&mut self, kmer: &[u8]) {
+        self.insert_hash(hash_bytes(&canonical(kmer), self.seed));
+    }
+
+    fn insert_hash(&mut self, hash: u64) {
+        if self.hashes.len() == self.size && self.hashes.last().is_none_or(|&last| hash >= last) {
+            return;
+        }
+        if let Err(i) = self.hashes.binary_search(&hash) {
+            self.hashes.insert(i, hash);
+            self.hashes.truncate(self.size);
+        }
+    }
+
+    /// Whether sketches of `self` and `other` can be compared, having the
+    /// same `k` and seed
+    pub fn is_comparable(&self, other: &Sketch) -> bool {
+        self.k == other.k && self.seed == other.seed
+    }
+
+    /// Write the sketch file of `self` to `out`
+    pub fn write<W: Write>(&self, mut out: W) -> Result<()> {
+        writeln!(out, "# k: {}", self.k)?;
+        writeln!(out, "# size: {}", self.size)?;
+        writeln!(out, "# seed: {}", self.seed)?;
+        writeln!(out, "# hash: {}", HASH_NAME)?;
+        for hash in &self.hashes {
+            writeln!(out, "{}", hash)?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Read the sketch file at `path`
+    pub fn read(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Could not open {:?}", path))?;
+        let (mut k, mut size, mut seed, mut hash_name) = (None, None, None, None);
+        let mut hashes: Vec<u64> = Vec::new();
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            let bad_line = || anyhow!("{:?}:{}: malformed sketch line {:?}", path, i + 1, line);
+            if let Some(field) = line.strip_prefix("# ") {
+                let (key, value) = field.split_once(": ").ok_or_else(bad_line)?;
+                match key {
+                    "k" => k = Some(value.parse().map_err(|_| bad_line())?),
+                    "size" => size = Some(value.parse().map_err(|_| bad_line())?),
+                    "seed" => seed = Some(value.parse().map_err(|_| bad_line())?),
+                    "hash" => hash_name = Some(value.to_owned()),
+                    _ => return Err(bad_line()),
+                }
+                continue;
+            }
+            let hash = line.parse().map_err(|_| bad_line())?;
+            if hashes.last().is_some_and(|&last| hash <= last) {
+                bail!("{:?}:{}: hashes are not in ascending order", path, i + 1);
+            }
+            hashes.push(hash);
+        }
+
+        let missing = |key| anyhow!("{:?}: no `# {}:` header line", path, key);
+        let (k, size, seed) = (
+            k.ok_or_else(|| missing("k"))?,
+            size.ok_or_else(|| missing("size"))?,
+            seed.ok_or_else(|| missing("seed"))?,
+        );
+        match hash_name {
+            Some(name) if name == HASH_NAME => {}
+            Some(name) => bail!("{:?}: sketched with hash {}, not {}", path, name, HASH_NAME),
+            None => return Err(missing("hash")),
+        }
+        if hashes.len() > size {
+            bail!(
+                "{:?}: {} hashes in a sketch of size {}",
+                path,
+                hashes.len(),
+                size
+            );
+        }
+        Ok(Sketch {
+            k,
+            size,
+            seed,
+            hashes,
+        })
+    }
+
+    pub fn hashes(&self) -> &[u64] {
+        &self.hashes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    /// Estimate the Jaccard similarity of the underlying kmer sets
+    ///
+    /// Uses the bottom `size` hashes of the union of both sketches. Exact when
+    /// both sequences have at most `size` distinct kmers.
+    pub fn jaccard(&self, other: &Sketch) -> f64 {
+        let union: BTreeSet<u64> = self.hashes.iter().chain(&other.hashes).cloned().collect();
+        let bottom: Vec<u64> = union.into_iter().take(self.size.min(other.size)).collect();
+        if bottom.is_empty() {
+            return 0.0;
+        }
+
+        let shared = bottom
+            .iter()
+            .filter(|h| {
+                self.hashes.binary_search(h).is_ok() && other.hashes.binary_search(h).is_ok()
+            })
+            .count();
+        shared as f64 / bottom.len() as f64
+    }
+
+    /// Mash distance `-ln(2j / (1 + j)) / k` from the estimated Jaccard
+    /// similarity `j`, an estimate of the per-base mutation rate
+    ///
+    /// Sketches sharing no hash are at the largest distance, 1.
+    pub fn mash_distance(&self, other: &Sketch) -> f64 {
+        let j = self.jaccard(other);
+        if j == 0.0 {
+            return 1.0;
+        }
+        // j = 1 gives -0.0
+        (-(2.0 * j / (1.0 + j)).ln() / self.k as f64).max(0.0)
+    }
+
+    /// Estimate the fraction of the smaller kmer set contained in the larger
+    ///
+    /// Both sketches are compared below the smaller of their largest hashes, so
+    /// the estimate is over the same region of hash space.
+    pub fn containment(&self, other: &Sketch) -> f64 {
+        let threshold = match (self.hashes.last(), other.hashes.last()) {
+            (Some(&a), Some(&b)) => a.min(b),
+            _ => return 0.0,
+        };
+        let below = |sketch: &Sketch| -> Vec<u64> {
+            sketch
+                .hashes
+                .iter()
+                .cloned()
+                .take_while(|&h| h <= threshold)
+                .collect()
+        };
+        let (a, b) = (below(self), below(other));
+        let (small, large) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+
+        let shared = small
+            .iter()
+            .filter(|h| large.binary_search(h).is_ok())
+            .count();
+        shared as f64 / small.len() as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::random_sequence;
+    use std::collections::HashSet;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_canonical() {
+        assert_eq!(canonical(b"TTG"), b"CAA");
+        assert_eq!(canonical(b"CAA"), b"CAA");
+        assert_eq!(canonical(b"ACGT"), b"ACGT");
+    }
+
+    #[test]
+    fn test_hash_bytes_stable() {
+        // fixed so sketches are comparable across runs and builds
+        assert_eq!(hash_bytes(b"ACGT", 0), hash_bytes(b"ACGT", 0));
+        assert_ne!(hash_bytes(b"ACGT", 0), hash_bytes(b"ACGT", 1));
+        assert_ne!(hash_bytes(b"ACGT", 0), hash_bytes(b"ACGA", 0));
+    }
+
+    #[test]
+    fn test_sketch_size() {
+        let sketch = Sketch::from_sequence(&random_sequence(1000, 1), 15, 100, 0);
+        assert_eq!(sketch.hashes().len(), 100);
+        assert!(sketch.hashes().windows(2).all(|w| w[0] < w[1]));
+        assert!(Sketch::from_sequence(b"ACG", 15, 100, 0).is_empty());
+    }
+
+    #[test]
+    fn test_jaccard_identical_and_revcomp() {
+        let seq = random_sequence(1000, 1);
+        let a = Sketch::from_sequence(&seq, 15, 200, 0);
+        let rc = Sketch::from_sequence(&reverse_complement(&seq), 15, 200, 0);
+        assert_eq!(a.jaccard(&a), 1.0);
+        assert_eq!(a.jaccard(&rc), 1.0);
+    }
+
+    #[test]
+    fn test_jaccard_unrelated() {
+        let a = Sketch::from_sequence(&random_sequence(1000, 1), 15, 200, 0);
+        let b = Sketch::from_sequence(&random_sequence(1000, 2), 15, 200, 0);
+        assert!(a.jaccard(&b) < 0.05);
+    }
+
+    #[test]
+    fn test_sketch_sources_agree() -> Result<()> {
+        let dir = tempdir()?;
+        let seq = random_sequence(500, 3);
+        let path = dir.path().join("seq.fasta");
+        fs::write(&path, format!(">a\n{}\n", std::str::from_utf8(&seq)?))?;
+        let sketch = Sketch::from_sequence(&seq, 11, 50, 7);
+        assert_eq!(Sketch::from_fasta(&path, 11, 50, 7)?, sketch);
+
+        let rows: Vec<(String, u64)> = seq
+            .windows(11)
+            .map(|kmer| (String::from_utf8(reverse_complement(kmer)).unwrap(), 1))
+            .collect();
+        assert_eq!(Sketch::from_table(&rows, 50, 7)?, sketch);
+        assert!(Sketch::from_table(&[], 50, 7).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sketch_file_roundtrip() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("a.sketch");
+        let sketch = Sketch::from_sequence(&random_sequence(500, 3), 11, 20, 7);
+        let mut file = File::create(&path)?;
+        sketch.write(&mut file)?;
+        assert_eq!(Sketch::read(&path)?, sketch);
+
+        let contents = fs::read_to_string(&path)?;
+        assert!(contents.starts_with(&format!(
+            "# k: 11\n# size: 20\n# seed: 7\n# hash: {}\n",
+            HASH_NAME
+        )));
+        fs::write(&path, contents.replace(HASH_NAME, "murmur3"))?;
+        assert!(Sketch::read(&path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_jaccard_converges_with_size() {
+        // two sequences sharing their middle third
+        let shared = random_sequence(10_000, 4);
+        let mut a = random_sequence(10_000, 5);
+        a.extend_from_slice(&shared);
+        let mut b = shared.clone();
+        b.extend_from_slice(&random_sequence(10_000, 6));
+        let kmers = |seq: &[u8]| -> HashSet<Vec<u8>> { seq.windows(15).map(canonical).collect() };
+        let (set_a, set_b) = (kmers(&a), kmers(&b));
+        let exact = set_a.intersection(&set_b).count() as f64 / set_a.union(&set_b).count() as f64;
+
+        // mean absolute error over several seeds
+        let error = |size: usize| -> f64 {
+            (0..8)
+                .map(|seed| {
+                    let sketch = |seq: &[u8]| Sketch::from_sequence(seq, 15, size, seed);
+                    (sketch(&a).jaccard(&sketch(&b)) - exact).abs()
+                })
+                .sum::<f64>()
+                / 8.0
+        };
+        let errors: Vec<f64> = [20, 200, 2000, 20_000].iter().map(|&s| error(s)).collect();
+        assert!(errors.windows(2).all(|w| w[1] < w[0]), "{:?}", errors);
+        assert!(errors[3] < 0.005, "{:?}", errors);
+    }
+
+    #[test]
+    fn test_mash_distance() {
+        let seq = random_sequence(10_000, 7);
+        // a substitution every 100 bases
+        let mut mutated = seq.clone();
+        for base in mutated.iter_mut().step_by(100) {
+            *base = if *base == b'A' { b'C' } else { b'A' };
+        }
+        let sketch = |seq: &[u8]| Sketch::from_sequence(seq, 21, 1000, 0);
+        let (a, b) = (sketch(&seq), sketch(&mutated));
+        assert_eq!(a.mash_distance(&a), 0.0);
+        let distance = a.mash_distance(&b);
+        assert!((distance - 0.01).abs() < 0.003, "{}", distance);
+        assert_eq!(b.mash_distance(&a), distance);
+
+        let j = a.jaccard(&b);
+        assert_eq!(distance, -(2.0 * j / (1.0 + j)).ln() / 21.0);
+        let unrelated = sketch(&random_sequence(10_000, 8));
+        assert_eq!(a.mash_distance(&unrelated), 1.0);
+    }
+
+    #[test]
+    fn test_containment() {
+        let seq = random_sequence(2000, 1);
+        let whole = Sketch::from_sequence(&seq, 15, 5000, 0);
+        let part = Sketch::from_sequence(&seq[500..1000], 15, 5000, 0);
+        assert_eq!(part.containment(&whole), 1.0);
+        assert!(part.jaccard(&whole) < 0.3);
+    }
+}
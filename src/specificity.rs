@@ -0,0 +1,138 @@
+//! Record specificity: the kmers of each record found in no other record
+//!
+//! A first pass over the file records how many records contain each kmer,
+//! as for `--core-fraction`; a second pass counts each record again and keeps
+//! the kmers present in that record alone. Both numbers are of distinct
+//! kmers, so a kmer repeated within one record is still specific to it.
+
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::metadata::{write_header, RunMetadata};
+use crate::presence::fasta_presence_counts;
+use crate::strand::{self, Mate};
+use crate::timing::PhaseTimings;
+use crate::{add_sequence_counts, create_output, open_fasta, CountOptions};
+
+/// Distinct kmers of one record, and those found in no other record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordSpecificity {
+    pub id: String,
+    /// distinct kmers of the record found in no other record of the file
+    pub specific_kmers: u64,
+    /// distinct kmers of the record
+    pub total_kmers: u64,
+}
+
+/// Specificity of every record of `fasta_path` counted as configured by
+/// `options`, in file order
+///
+/// Records that cannot be counted are skipped, as when counting, and share
+/// no kmers with the rest.
+pub fn fasta_record_specificity(
+    fasta_path: &Path,
+    options: &CountOptions,
+) -> Result<Vec<RecordSpecificity>> {
+    let presence = fasta_presence_counts(fasta_path, options)?;
+    let mut rows = Vec::new();
+    for record in open_fasta(fasta_path)?.records() {
+        let record = record?;
+        let mate = Mate::from_header(record.id(), record.desc());
+        let sequence = strand::orient(record.seq(), mate, options.library_type);
+        let mut counter = HashMap::new();
+        let mut timings = PhaseTimings::disabled();
+        // failures were reported by the first pass
+        if add_sequence_counts(&mut counter, &sequence, options, &mut timings).is_err() {
+            continue;
+        }
+        rows.push(RecordSpecificity {
+            id: record.id().to_owned(),
+            specific_kmers: counter
+                .keys()
+                .filter(|kmer| presence.presence(kmer) == 1)
+                .count() as u64,
+            total_kmers: counter.len() as u64,
+        });
+    }
+    Ok(rows)
+}
+
+/// Write specificity rows as a tab-separated table
+pub fn write_record_specificity<W: Write>(rows: &[RecordSpecificity], mut out: W) -> Result<()> {
+    writeln!(out, "record_id\tspecific_kmers\ttotal_kmers")?;
+    for row in rows {
+        writeln!(
+            out,
+            "{}\t{}\t{}",
+            row.id, row.specific_kmers, row.total_kmers
+        )?;
+    }
+    Ok(())
+}
+
+/// Save the specificity of every record of the fasta file at `fasta_path` at
+/// `output_path`, after the `metadata` header if given
+pub fn run_fasta_record_specificity(
+    fasta_path: &Path,
+    options: &CountOptions,
+    metadata: Option<&RunMetadata>,
+    output_path: &Path,
+) -> Result<()> {
+    let rows = fasta_record_specificity(fasta_path, options)?;
+    let mut out = BufWriter::new(create_output(output_path)?);
+    write_header(metadata, &mut out)?;
+    write_record_specificity(&rows, &mut out)?;
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_run_fasta_record_specificity() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("pair.fasta");
+        // AAC and ACG are shared; a also has AAA, b has CGT and GTT
+        fs::write(&fasta_path, ">a\nAAAACG\n>b\nAACGTT\n>short\nAC\n")?;
+
+        let rows = fasta_record_specificity(&fasta_path, &CountOptions::new(3))?;
+        assert_eq!(
+            rows,
+            [
+                RecordSpecificity {
+                    id: "a".to_owned(),
+                    specific_kmers: 1,
+                    total_kmers: 3,
+                },
+                RecordSpecificity {
+                    id: "b".to_owned(),
+                    specific_kmers: 2,
+                    total_kmers: 4,
+                },
+            ]
+        );
+
+        // canonically, GTT is AAC and CGT is ACG, so b shares them all
+        let options = CountOptions {
+            canonical: true,
+            ..CountOptions::new(3)
+        };
+        let rows = fasta_record_specificity(&fasta_path, &options)?;
+        assert_eq!((rows[1].specific_kmers, rows[1].total_kmers), (0, 2));
+
+        let output_path = dir.path().join("pair_specificity.txt");
+        run_fasta_record_specificity(&fasta_path, &CountOptions::new(3), None, &output_path)?;
+        assert_eq!(
+            fs::read_to_string(&output_path)?,
+            "record_id\tspecific_kmers\ttotal_kmers\na\t1\t3\nb\t2\t4\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+//! Train and test partitions of counted kmers
+//!
+//! Each kmer is assigned to a split by a seeded hash of its sequence alone, so
+//! the assignment is reproducible and the same kmer lands in the same split in
+//! every sample counted with the same seed.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use crate::sketch::hash_bytes;
+use crate::KmerCount;
+
+/// Partition of counted kmers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Split {
+    Train,
+    Test,
+}
+
+impl Split {
+    pub fn name(self) -> &'static str {
+        match self {
+            Split::Train => "train",
+            Split::Test => "test",
+        }
+    }
+}
+
+/// How kmers are split between train and test
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KmerSplit {
+    /// expected fraction of kmers assigned to train, from 0 to 1
+    pub train_fraction: f64,
+    pub seed: u64,
+}
+
+impl KmerSplit {
+    /// Split of `kmer`
+    pub fn assign(&self, kmer: &str) -> Split {
+        // maps the hash to [0, 1)
+        let position = hash_bytes(kmer.as_bytes(), self.seed) as f64 / (u64::MAX as f64 + 1.0);
+        if position < self.train_fraction {
+            Split::Train
+        } else {
+            Split::Test
+        }
+    }
+
+    /// Train and test kmers of `kmer_count`, each in the original order
+    pub(crate) fn partition<'a>(
+        &self,
+        kmer_count: KmerCount<'a>,
+    ) -> (KmerCount<'a>, KmerCount<'a>) {
+        kmer_count
+            .into_iter()
+            .partition(|kmer| self.assign(&kmer.seq) == Split::Train)
+    }
+}
+
+/// Path of the `split` table of the output at `output_path`, with the split
+/// name inserted before the extension, e.g. `a_kmer.train.txt`
+pub fn split_path(output_path: &Path, split: Split) -> PathBuf {
+    let mut name = OsString::from(output_path.file_stem().unwrap_or_default());
+    name.push(".");
+    name.push(split.name());
+    if let Some(extension) = output_path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    output_path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assign_is_stable() {
+        let split = KmerSplit {
+            train_fraction: 0.8,
+            seed: 7,
+        };
+        // fixed so splits are comparable across runs, samples, and builds
+        assert_eq!(split.assign("GGGCC"), Split::Train);
+        assert_eq!(split.assign("TTTTT"), Split::Test);
+        let reseeded = KmerSplit { seed: 8, ..split };
+        assert_eq!(reseeded.assign("TTTTT"), Split::Train);
+
+        let all_train = KmerSplit {
+            train_fraction: 1.0,
+            seed: 7,
+        };
+        let all_test = KmerSplit {
+            train_fraction: 0.0,
+            seed: 7,
+        };
+        for kmer in &["ACGTA", "TTTTT", "GGGCC"] {
+            assert_eq!(all_train.assign(kmer), Split::Train);
+            assert_eq!(all_test.assign(kmer), Split::Test);
+        }
+    }
+
+    #[test]
+    fn test_assign_fraction() {
+        let split = KmerSplit {
+            train_fraction: 0.8,
+            seed: 1,
+        };
+        let train = (0..10_000)
+            .filter(|n| split.assign(&format!("{:08}", n)) == Split::Train)
+            .count();
+        assert!(
+            (7_700..8_300).contains(&train),
+            "{} of 10000 in train",
+            train
+        );
+    }
+
+    #[test]
+    fn test_split_path() {
+        let path = Path::new("out/a_kmer.txt");
+        assert_eq!(
+            split_path(path, Split::Train),
+            Path::new("out/a_kmer.train.txt")
+        );
+        assert_eq!(
+            split_path(Path::new("a_kmer"), Split::Test),
+            Path::new("a_kmer.test")
+        );
+    }
+}
@@ -0,0 +1,110 @@
+//! Strand-aware handling of stranded paired-end libraries
+//!
+//! In a stranded library one mate is sequenced from the transcribed strand
+//! and the other from its complement. Reverse complementing the appropriate
+//! mate before counting puts every kmer on the transcribed strand.
+
+use std::borrow::Cow;
+use std::str::FromStr;
+
+use crate::reverse_complement;
+
+/// Orientation of the mates of a paired-end library relative to the transcript
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LibraryType {
+    /// mate 1 is on the transcribed strand, mate 2 on its complement
+    Fr,
+    /// mate 1 is on the complement (e.g. dUTP protocols), mate 2 on the transcribed strand
+    Rf,
+    /// strand is not preserved; reads are counted as sequenced
+    Unstranded,
+}
+
+impl LibraryType {
+    pub const VARIANTS: &'static [&'static str] = &["fr", "rf", "unstranded"];
+
+    /// Whether reads of mate `mate` must be reverse complemented
+    pub fn reverses(self, mate: Mate) -> bool {
+        matches!(
+            (self, mate),
+            (LibraryType::Fr, Mate::Second) | (LibraryType::Rf, Mate::First)
+        )
+    }
+}
+
+impl FromStr for LibraryType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fr" => Ok(LibraryType::Fr),
+            "rf" => Ok(LibraryType::Rf),
+            "unstranded" => Ok(LibraryType::Unstranded),
+            _ => Err(format!("Unknown library type: {}", s)),
+        }
+    }
+}
+
+/// Which read of a pair a record is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mate {
+    First,
+    Second,
+}
+
+impl Mate {
+    /// Identify the mate from a record's id and description
+    ///
+    /// Recognizes `/1` and `/2` id suffixes and Illumina comments such as
+    /// `2:N:0:ATCACG`. Records without either marker are treated as first mates.
+    pub fn from_header(id: &str, desc: Option<&str>) -> Mate {
+        let illumina = desc.and_then(|d| d.split(':').next());
+        if id.ends_with("/2") || illumina == Some("2") {
+            Mate::Second
+        } else {
+            Mate::First
+        }
+    }
+}
+
+/// Orient `sequence`, read as mate `mate`, onto the transcribed strand
+pub fn orient(sequence: &[u8], mate: Mate, library_type: LibraryType) -> Cow<'_, [u8]> {
+    if library_type.reverses(mate) {
+        Cow::Owned(reverse_complement(sequence))
+    } else {
+        Cow::Borrowed(sequence)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mate_from_header() {
+        assert_eq!(Mate::from_header("read7/1", None), Mate::First);
+        assert_eq!(Mate::from_header("read7/2", None), Mate::Second);
+        assert_eq!(
+            Mate::from_header("M001:1:FC:1:1:5:7", Some("2:N:0:ATCACG")),
+            Mate::Second
+        );
+        assert_eq!(
+            Mate::from_header("M001:1:FC:1:1:5:7", Some("1:N:0:ATCACG")),
+            Mate::First
+        );
+        assert_eq!(Mate::from_header("read7", None), Mate::First);
+    }
+
+    #[test]
+    fn test_orient() {
+        let seq = b"AACG";
+        assert_eq!(&*orient(seq, Mate::First, LibraryType::Fr), b"AACG");
+        assert_eq!(&*orient(seq, Mate::Second, LibraryType::Fr), b"CGTT");
+        assert_eq!(&*orient(seq, Mate::First, LibraryType::Rf), b"CGTT");
+        assert_eq!(&*orient(seq, Mate::Second, LibraryType::Rf), b"AACG");
+        assert_eq!(
+            &*orient(seq, Mate::Second, LibraryType::Unstranded),
+            b"AACG"
+        );
+    }
+}
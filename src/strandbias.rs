@@ -0,0 +1,232 @@
+//! Strand bias of a non-canonical count table
+//!
+//! Each kmer is paired with its reverse complement as for `chargaff`, and the
+//! pair's bias is `log2((count + 1) / (reverse_count + 1))`, the pseudocount
+//! of 1 keeping pairs with one strand uncounted finite. `count` belongs to
+//! the lexicographically smaller kmer of the pair, so the sign only says
+//! which of the two is more abundant. Palindromic kmers, their own reverse
+//! complement, have no bias and are left out of the ratios.
+//!
+//! A canonical table has already merged every pair, so it is refused: when
+//! its metadata header records `canonical: true`, or, without a header, when
+//! no pair has both of its kmers counted and every kmer is the smaller of
+//! its pair.
+
+use std::collections::HashMap;
+use std::io::Write;
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+use crate::chargaff::{strand_pairs, StrandPair};
+use crate::metadata::RunMetadata;
+use crate::{reverse_complement, OutputFormat};
+
+/// Quantiles of the log2 ratios reported in the summary
+pub const QUANTILES: &[f64] = &[0.0, 0.05, 0.25, 0.5, 0.75, 0.95, 1.0];
+
+/// `log2((count + 1) / (reverse_count + 1))` of `pair`
+pub fn log2_ratio(pair: &StrandPair) -> f64 {
+    ((pair.count as f64 + 1.0) / (pair.reverse_count as f64 + 1.0)).log2()
+}
+
+/// Strand bias of the kmer pairs of one table
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StrandBiasSummary {
+    /// pairs with a total count of at least the minimum
+    pub pairs: usize,
+    /// palindromic kmers left out
+    pub palindromes: usize,
+    /// pairs whose absolute log2 ratio is at least the extreme threshold
+    pub extreme: usize,
+    /// mean absolute log2 ratio, 0 without strand bias
+    pub mean_abs_log2_ratio: f64,
+    /// `log2` of the summed counts of the smaller kmers over those of their
+    /// reverse complements
+    pub overall_log2_ratio: f64,
+    /// `(quantile, log2 ratio)` for each of `QUANTILES`
+    pub quantiles: Vec<(f64, f64)>,
+}
+
+/// Pairs of the kmers of `rows` with their reverse complements whose total
+/// count is at least `min_total`, ordered from most to least biased, and
+/// their summary, counting pairs at least `extreme` from 0 in log2 ratio
+///
+/// Fails if `metadata` or the rows show a canonical table, or if no pair
+/// reaches `min_total`.
+pub fn strand_bias(
+    rows: &[(String, u64)],
+    metadata: Option<&RunMetadata>,
+    min_total: u64,
+    extreme: f64,
+) -> Result<(Vec<StrandPair>, StrandBiasSummary)> {
+    if metadata.and_then(|metadata| metadata.get("canonical")) == Some("true") {
+        bail!("The table holds canonical kmers, whose strands are already merged; count without --canonical");
+    }
+    let counter: HashMap<String, u64> = rows.iter().cloned().collect();
+    let palindromes = counter
+        .keys()
+        .filter(|kmer| reverse_complement(kmer.as_bytes()) == kmer.as_bytes())
+        .count();
+    let mut pairs = strand_pairs(&counter);
+    let canonical_looking = !pairs.is_empty()
+        && pairs
+            .iter()
+            .all(|pair| pair.reverse_count == 0 && pair.count > 0);
+    if canonical_looking {
+        bail!("The table looks canonical: no kmer and its reverse complement are both counted; count without --canonical");
+    }
+
+    pairs.retain(|pair| pair.count + pair.reverse_count >= min_total);
+    if pairs.is_empty() {
+        bail!("No kmer pairs with a total count of at least {}", min_total);
+    }
+    pairs.sort_by(|a, b| {
+        log2_ratio(b)
+            .abs()
+            .total_cmp(&log2_ratio(a).abs())
+            .then_with(|| a.kmer.cmp(&b.kmer))
+    });
+
+    let mut ratios: Vec<f64> = pairs.iter().map(log2_ratio).collect();
+    ratios.sort_by(f64::total_cmp);
+    let (forward, reverse) = pairs.iter().fold((0u128, 0u128), |(f, r), pair| {
+        (f + pair.count as u128, r + pair.reverse_count as u128)
+    });
+    let summary = StrandBiasSummary {
+        pairs: pairs.len(),
+        palindromes,
+        extreme: ratios.iter().filter(|ratio| ratio.abs() >= extreme).count(),
+        mean_abs_log2_ratio: ratios.iter().map(|ratio| ratio.abs()).sum::<f64>()
+            / ratios.len() as f64,
+        overall_log2_ratio: ((forward as f64 + 1.0) / (reverse as f64 + 1.0)).log2(),
+        quantiles: QUANTILES
+            .iter()
+            .map(|&q| (q, quantile(&ratios, q)))
+            .collect(),
+    };
+    Ok((pairs, summary))
+}
+
+/// Quantile `q` of the ascending, non-empty `sorted`, interpolating linearly
+/// between the closest ranks
+fn quantile(sorted: &[f64], q: f64) -> f64 {
+    let position = q * (sorted.len() - 1) as f64;
+    let (low, high) = (position.floor() as usize, position.ceil() as usize);
+    sorted[low] + (sorted[high] - sorted[low]) * (position - low as f64)
+}
+
+/// Write `pairs` as a tab-separated table, with an `extreme` column of 1 for
+/// pairs at least `extreme` from 0 in log2 ratio
+pub fn write_bias_pairs<W: Write>(pairs: &[StrandPair], extreme: f64, mut out: W) -> Result<()> {
+    writeln!(
+        out,
+        "kmer\treverse_complement\tcount\treverse_count\tlog2_ratio\textreme"
+    )?;
+    for pair in pairs {
+        let ratio = log2_ratio(pair);
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{:.6}\t{}",
+            pair.kmer,
+            pair.reverse_complement,
+            pair.count,
+            pair.reverse_count,
+            ratio,
+            u8::from(ratio.abs() >= extreme)
+        )?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Write the strand bias summary to `out` in the requested `format`
+pub fn write_bias_summary<W: Write>(
+    summary: &StrandBiasSummary,
+    format: OutputFormat,
+    mut out: W,
+) -> Result<()> {
+    match format {
+        OutputFormat::Tsv => {
+            writeln!(out, "pairs\t{}", summary.pairs)?;
+            writeln!(out, "palindromes\t{}", summary.palindromes)?;
+            writeln!(out, "extreme\t{}", summary.extreme)?;
+            writeln!(
+                out,
+                "mean_abs_log2_ratio\t{:.6}",
+                summary.mean_abs_log2_ratio
+            )?;
+            writeln!(out, "overall_log2_ratio\t{:.6}", summary.overall_log2_ratio)?;
+            for (q, ratio) in &summary.quantiles {
+                writeln!(out, "q{}\t{:.6}", q, ratio)?;
+            }
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, summary)?;
+            writeln!(out)?;
+        }
+        other => return Err(other.unsupported_report()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(counts: &[(&str, u64)]) -> Vec<(String, u64)> {
+        counts.iter().map(|&(k, c)| (k.to_owned(), c)).collect()
+    }
+
+    #[test]
+    fn test_strand_bias() {
+        // AAC/GTT 7:1 and ACA/TGT 1:3 are biased, AAG/CTT balanced; ACGT
+        // and AATT are palindromes
+        let table = rows(&[
+            ("AAC", 7),
+            ("GTT", 1),
+            ("ACA", 1),
+            ("TGT", 3),
+            ("AAG", 5),
+            ("CTT", 5),
+            ("ACGT", 9),
+            ("AATT", 2),
+        ]);
+        let (pairs, summary) = strand_bias(&table, None, 1, 1.0).unwrap();
+        let ratios: Vec<(&str, f64)> = pairs
+            .iter()
+            .map(|pair| (pair.kmer.as_str(), log2_ratio(pair)))
+            .collect();
+        assert_eq!(ratios, [("AAC", 2.0), ("ACA", -1.0), ("AAG", 0.0)]);
+        assert_eq!(summary.pairs, 3);
+        assert_eq!(summary.palindromes, 2);
+        assert_eq!(summary.extreme, 2);
+        assert_eq!(summary.mean_abs_log2_ratio, 1.0);
+        // (7 + 1 + 5 + 1) / (1 + 3 + 5 + 1)
+        assert_eq!(summary.overall_log2_ratio, (14.0f64 / 10.0).log2());
+        assert_eq!(summary.quantiles[0], (0.0, -1.0));
+        assert_eq!(summary.quantiles[2], (0.25, -0.5));
+        assert_eq!(summary.quantiles[3], (0.5, 0.0));
+        assert_eq!(summary.quantiles[6], (1.0, 2.0));
+
+        // the balanced pair alone passes a minimum total of 10
+        let (pairs, _) = strand_bias(&table, None, 10, 1.0).unwrap();
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(pairs[0].kmer, "AAG");
+        assert!(strand_bias(&table, None, 100, 1.0).is_err());
+    }
+
+    #[test]
+    fn test_strand_bias_refuses_canonical_tables() {
+        let canonical = rows(&[("AAC", 8), ("AAG", 10), ("ACA", 4)]);
+        let err = strand_bias(&canonical, None, 1, 1.0).unwrap_err();
+        assert!(err.to_string().contains("looks canonical"), "{}", err);
+
+        let table = rows(&[("AAC", 7), ("GTT", 1)]);
+        let metadata = RunMetadata::new().with("canonical", true);
+        let err = strand_bias(&table, Some(&metadata), 1, 1.0).unwrap_err();
+        assert!(err.to_string().contains("canonical kmers"), "{}", err);
+        let metadata = RunMetadata::new().with("canonical", false);
+        assert!(strand_bias(&table, Some(&metadata), 1, 1.0).is_ok());
+    }
+}
@@ -0,0 +1,87 @@
+//! Reading count tables written by this crate
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+
+/// Read the `kmer\tcount` rows of the count table at `path`, in file order
+///
+/// The `kmer\tcount` header, blank lines, and `#` comments (such as a
+/// metadata header before the column header) are skipped.
+pub fn read_kmer_table(path: &Path) -> Result<Vec<(String, u64)>> {
+    let file = File::open(path).with_context(|| format!("Could not open {:?}", path))?;
+
+    let mut rows = Vec::new();
+    let mut header_allowed = true;
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if std::mem::take(&mut header_allowed) && line.starts_with("kmer\t") {
+            continue;
+        }
+
+        let (kmer, count) = line
+            .split_once('\t')
+            .ok_or_else(|| anyhow!("{:?}:{}: expected kmer and count columns", path, i + 1))?;
+        let count = count
+            .trim_end()
+            .parse()
+            .with_context(|| format!("{:?}:{}: bad count {:?}", path, i + 1, count))?;
+        rows.push((kmer.to_owned(), count));
+    }
+    Ok(rows)
+}
+
+/// Read any count table written by this crate, TSV or varint (see `varint`)
+///
+/// The format is detected from the file's leading bytes.
+pub fn read_count_table(path: &Path) -> Result<Vec<(String, u64)>> {
+    if is_varint_table(path)? {
+        crate::varint::read_varint_table(path)
+    } else {
+        read_kmer_table(path)
+    }
+}
+
+/// Whether the table at `path` begins with the varint table magic bytes
+pub fn is_varint_table(path: &Path) -> Result<bool> {
+    let mut magic = [0u8; 4];
+    let file = File::open(path).with_context(|| format!("Could not open {:?}", path))?;
+    Ok(file.take(4).read(&mut magic)? == 4 && &magic == crate::varint::MAGIC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_read_kmer_table() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("t_kmer.txt");
+        let mut file = File::create(&path)?;
+        write!(file, "kmer\tcount\nATC\t2\n# note\n\nTCG\t1\n")?;
+
+        assert_eq!(
+            read_kmer_table(&path)?,
+            [("ATC".to_owned(), 2), ("TCG".to_owned(), 1)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_kmer_table_bad_count() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("t_kmer.txt");
+        let mut file = File::create(&path)?;
+        write!(file, "kmer\tcount\nATC\tx\n")?;
+
+        assert!(read_kmer_table(&path).is_err());
+        Ok(())
+    }
+}
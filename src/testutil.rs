@@ -0,0 +1,14 @@
+//! Helpers shared by unit tests
+
+/// Deterministic pseudo-random ATCG sequence of length `len`
+pub fn random_sequence(len: usize, seed: u64) -> Vec<u8> {
+    let mut state: u64 = seed ^ 0x2545_f491_4f6c_dd1d;
+    (0..len)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            b"ATCG"[(state >> 62) as usize]
+        })
+        .collect()
+}
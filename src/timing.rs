@@ -0,0 +1,102 @@
+//! Instrumentation hooks for the counting pipeline
+//!
+//! The pipeline reports time spent parsing, counting, sorting, and writing
+//! through a `PhaseTimings`. Disabled timings skip the clock entirely, so the
+//! hooks cost nothing in normal runs.
+
+use std::time::{Duration, Instant};
+
+/// Stage of the counting pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// reading and parsing records
+    Parse,
+    /// building the per-record kmer counter
+    Count,
+    /// ordering counts for output
+    Sort,
+    /// saving count tables
+    Write,
+}
+
+/// Time spent in each phase, and the work done, over one or more pipeline runs
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PhaseTimings {
+    enabled: bool,
+    pub parse: Duration,
+    pub count: Duration,
+    pub sort: Duration,
+    pub write: Duration,
+    pub records: u64,
+    pub bases: u64,
+    /// largest number of distinct kmers held in a counter at once
+    pub peak_counter_size: usize,
+}
+
+impl PhaseTimings {
+    /// Timings that measure each phase
+    pub fn enabled() -> Self {
+        PhaseTimings {
+            enabled: true,
+            ..PhaseTimings::default()
+        }
+    }
+
+    /// Timings that only pass work through
+    pub fn disabled() -> Self {
+        PhaseTimings::default()
+    }
+
+    /// Run `f`, adding its duration to `phase` when enabled
+    pub fn time<T, F: FnOnce() -> T>(&mut self, phase: Phase, f: F) -> T {
+        if !self.enabled {
+            return f();
+        }
+        let start = Instant::now();
+        let result = f();
+        let elapsed = start.elapsed();
+        *match phase {
+            Phase::Parse => &mut self.parse,
+            Phase::Count => &mut self.count,
+            Phase::Sort => &mut self.sort,
+            Phase::Write => &mut self.write,
+        } += elapsed;
+        result
+    }
+
+    /// Record a parsed record of `bases` bases
+    pub fn record(&mut self, bases: usize) {
+        self.records += 1;
+        self.bases += bases as u64;
+    }
+
+    /// Record a counter holding `size` distinct kmers
+    pub fn counter_size(&mut self, size: usize) {
+        self.peak_counter_size = self.peak_counter_size.max(size);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disabled_timings_skip_clock() {
+        let mut timings = PhaseTimings::disabled();
+        assert_eq!(
+            timings.time(Phase::Count, || {
+                std::thread::sleep(Duration::from_millis(1));
+                7
+            }),
+            7
+        );
+        assert_eq!(timings.count, Duration::default());
+
+        let mut timings = PhaseTimings::enabled();
+        timings.time(Phase::Count, || {
+            std::thread::sleep(Duration::from_millis(1))
+        });
+        assert!(timings.count >= Duration::from_millis(1));
+        assert_eq!(timings.sort, Duration::default());
+    }
+}
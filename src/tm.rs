@@ -0,0 +1,87 @@
+//! Melting temperatures of kmers for primer and probe design
+//!
+//! Temperatures follow the Wallace rule, `Tm = 2 * (A + T) + 4 * (G + C)` in
+//! degrees Celsius, counting bases case-insensitively and ignoring any other
+//! character. The rule is meant for oligos of about 14 bases or fewer in
+//! standard salt; it overestimates the Tm of longer kmers and ignores base
+//! stacking, so treat it as a quick ranking rather than a design value.
+
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::metadata::{write_header, RunMetadata};
+use crate::validate::write_trailer;
+use crate::{create_output, KmerCount};
+
+/// Wallace-rule melting temperature of `kmer`, in degrees Celsius
+pub fn wallace_tm(kmer: &str) -> u64 {
+    kmer.bytes()
+        .map(|base| match base.to_ascii_uppercase() {
+            b'A' | b'T' => 2,
+            b'G' | b'C' => 4,
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Save `kmer_count` to `output_path` with a `tm` column of Wallace-rule
+/// melting temperatures, after the `metadata` header if given
+pub(crate) fn save_tm_kmer_count(
+    kmer_count: KmerCount,
+    metadata: Option<&RunMetadata>,
+    output_path: &Path,
+) -> Result<()> {
+    let mut out = BufWriter::new(create_output(output_path)?);
+    write_header(metadata, &mut out)?;
+    writeln!(out, "kmer\tcount\ttm")?;
+    let rows = kmer_count.len();
+    for kmer in kmer_count {
+        writeln!(
+            out,
+            "{}\t{}\t{}",
+            kmer.seq,
+            kmer.count,
+            wallace_tm(&kmer.seq)
+        )?;
+    }
+    write_trailer(metadata, rows, &mut out)?;
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{run_fasta_kmer_count, CountOptions};
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_wallace_tm() {
+        // 8 A/T and 12 G/C
+        assert_eq!(wallace_tm("ATGCGCGTATACGCGCGCAT"), 64);
+        assert_eq!(wallace_tm("acgt"), 12);
+        assert_eq!(wallace_tm("ANNT"), 4);
+        assert_eq!(wallace_tm(""), 0);
+    }
+
+    #[test]
+    fn test_run_fasta_kmer_count_tm() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("primer.fasta");
+        fs::write(&fasta_path, ">p\nGGCATT\n")?;
+        let output_path = dir.path().join("primer_kmer.txt");
+        let options = CountOptions {
+            tm: true,
+            ..CountOptions::new(5)
+        };
+        run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
+        assert_eq!(
+            fs::read_to_string(&output_path)?,
+            "kmer\tcount\ttm\nGCATT\t1\t14\nGGCAT\t1\t16\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+//! Tetranucleotide frequency (TNF) vectors for metagenomic binning
+//!
+//! Each record's tetranucleotides are collapsed with their reverse
+//! complements onto the 136 canonical tetranucleotides: 120 pairs and the 16
+//! palindromes, which are their own reverse complement. The vector lists the
+//! frequency of each canonical tetranucleotide, its count over the record's
+//! total, in lexicographic `ACGT` order of the canonical forms, the
+//! lexicographically smaller of each pair. Windows with bases other than ACGT
+//! are left out, and soft-masked bases count as upper case.
+
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::Result;
+use log::warn;
+
+use crate::metadata::{write_header, RunMetadata};
+use crate::sketch::canonical;
+use crate::{create_output, open_fasta};
+
+/// Length of the words counted
+pub const TNF_K: usize = 4;
+
+/// Number of canonical tetranucleotides, the length of a TNF vector
+pub const TNF_LEN: usize = 136;
+
+/// The canonical tetranucleotides in vector order
+pub fn tnf_kmers() -> Vec<String> {
+    let mut kmers = Vec::with_capacity(TNF_LEN);
+    for index in 0..256usize {
+        let kmer: Vec<u8> = (0..TNF_K)
+            .map(|position| b"ACGT"[index >> (2 * (TNF_K - 1 - position)) & 3])
+            .collect();
+        if canonical(&kmer) == kmer {
+            kmers.push(String::from_utf8(kmer).expect("ACGT is ASCII"));
+        }
+    }
+    kmers
+}
+
+/// TNF vector of `sequence`, or `None` if it has no ACGT tetranucleotide
+pub fn tnf_vector(sequence: &[u8]) -> Option<Vec<f64>> {
+    let kmers = tnf_kmers();
+    let mut counts = vec![0u64; TNF_LEN];
+    let sequence = sequence.to_ascii_uppercase();
+    for window in sequence.windows(TNF_K) {
+        if !window.iter().all(|base| b"ACGT".contains(base)) {
+            continue;
+        }
+        let key = canonical(window);
+        // kmers are sorted, and every canonical window is among them
+        let index = kmers
+            .binary_search_by(|kmer| kmer.as_bytes().cmp(&key))
+            .expect("canonical tetranucleotide");
+        counts[index] += 1;
+    }
+    let total: u64 = counts.iter().sum();
+    if total == 0 {
+        return None;
+    }
+    Some(
+        counts
+            .iter()
+            .map(|&count| count as f64 / total as f64)
+            .collect(),
+    )
+}
+
+/// Save the TNF vector of every record of the fasta file at `fasta_path` at
+/// `output_path`, after the `metadata` header if given, as a tab-separated
+/// table with a `record_id` column and one column per canonical
+/// tetranucleotide
+///
+/// Records without an ACGT tetranucleotide are skipped with a warning.
+pub fn run_fasta_tnf(
+    fasta_path: &Path,
+    metadata: Option<&RunMetadata>,
+    output_path: &Path,
+) -> Result<()> {
+    let mut out = BufWriter::new(create_output(output_path)?);
+    write_header(metadata, &mut out)?;
+    writeln!(out, "record_id\t{}", tnf_kmers().join("\t"))?;
+    for record in open_fasta(fasta_path)?.records() {
+        let record = record?;
+        match tnf_vector(record.seq()) {
+            Some(vector) => {
+                let row: Vec<String> = vector
+                    .iter()
+                    .map(|frequency| format!("{:.6}", frequency))
+                    .collect();
+                writeln!(out, "{}\t{}", record.id(), row.join("\t"))?;
+            }
+            None => warn!("Skipping record {}: no ACGT tetranucleotides", record.id()),
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reverse_complement;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_tnf_kmers() {
+        let kmers = tnf_kmers();
+        assert_eq!(kmers.len(), TNF_LEN);
+        assert_eq!(kmers[0], "AAAA");
+        assert_eq!(kmers[1], "AAAC");
+        assert!(kmers.windows(2).all(|pair| pair[0] < pair[1]));
+        // TTTT folds into AAAA
+        assert!(!kmers.contains(&"TTTT".to_owned()));
+        let palindromes = kmers
+            .iter()
+            .filter(|kmer| reverse_complement(kmer.as_bytes()) == kmer.as_bytes())
+            .count();
+        assert_eq!(palindromes, 16);
+    }
+
+    #[test]
+    fn test_tnf_vector() {
+        // AAAA twice and AAAC once
+        let vector = tnf_vector(b"AAAAAC").unwrap();
+        assert_eq!(vector.len(), TNF_LEN);
+        assert_eq!(vector[0], 2.0 / 3.0);
+        assert_eq!(vector[1], 1.0 / 3.0);
+
+        let sequence = b"aaaacNgttt";
+        let vector = tnf_vector(sequence).unwrap();
+        // AAAA, AAAC, and GTTT folding into AAAC; windows with N are left out
+        assert_eq!(vector[0], 1.0 / 3.0);
+        assert_eq!(vector[1], 2.0 / 3.0);
+        assert_eq!(tnf_vector(&reverse_complement(sequence)), Some(vector));
+        assert_eq!(tnf_vector(b"ACNGT"), None);
+    }
+
+    #[test]
+    fn test_run_fasta_tnf() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("bins.fasta");
+        fs::write(&fasta_path, ">a\nAAAAA\n>short\nACG\n")?;
+        let output_path = dir.path().join("bins_tnf.txt");
+        run_fasta_tnf(&fasta_path, None, &output_path)?;
+
+        let contents = fs::read_to_string(&output_path)?;
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("record_id\tAAAA\tAAAC\tAAAG\t"));
+        let row: Vec<&str> = lines[1].split('\t').collect();
+        assert_eq!(row.len(), TNF_LEN + 1);
+        assert_eq!(row[..3], ["a", "1.000000", "0.000000"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+//! Peptide kmers from six-frame translation
+//!
+//! Each sequence is translated with the standard genetic code in the three
+//! forward frames and the three frames of its reverse complement, and kmers
+//! of amino acids are counted over all six translations.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::{base_index, kmers, ordered_kmer_count, reverse_complement, KmerCount};
+
+/// Standard genetic code, indexed by codon with bases ordered T, C, A, G
+const STANDARD_CODE: &[u8; 64] =
+    b"FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG";
+
+/// Residue for a stop codon
+pub const STOP: u8 = b'*';
+
+/// Residue for a codon with a base other than ACGT
+pub const UNKNOWN: u8 = b'X';
+
+/// How stop codons are treated when counting peptide kmers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopCodons {
+    /// stops end a peptide; no kmer spans a stop
+    Split,
+    /// stops are counted as the residue `*`
+    Include,
+}
+
+impl StopCodons {
+    pub const VARIANTS: &'static [&'static str] = &["split", "include"];
+}
+
+impl FromStr for StopCodons {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "split" => Ok(StopCodons::Split),
+            "include" => Ok(StopCodons::Include),
+            _ => Err(format!("Unknown stop codon handling: {}", s)),
+        }
+    }
+}
+
+/// Index in T, C, A, G order of each base in `base_index` order, A, C, G, T
+const CODE_ORDER: [usize; 4] = [2, 1, 3, 0];
+
+/// Index of `base` in the T, C, A, G order of `STANDARD_CODE`
+fn code_index(base: u8) -> Option<usize> {
+    base_index(base).map(|index| CODE_ORDER[index])
+}
+
+/// Amino acid encoded by `codon`
+pub fn translate_codon(codon: &[u8]) -> u8 {
+    match (
+        code_index(codon[0]),
+        code_index(codon[1]),
+        code_index(codon[2]),
+    ) {
+        (Some(a), Some(b), Some(c)) => STANDARD_CODE[16 * a + 4 * b + c],
+        _ => UNKNOWN,
+    }
+}
+
+/// Translate `sequence` from its first base; a trailing partial codon is dropped
+pub fn translate(sequence: &[u8]) -> Vec<u8> {
+    sequence.chunks_exact(3).map(translate_codon).collect()
+}
+
+/// Translations of forward frames 0, 1, 2, then reverse complement frames 0, 1, 2
+pub fn six_frames(sequence: &[u8]) -> Vec<Vec<u8>> {
+    let rc = reverse_complement(sequence);
+    let mut frames = Vec::with_capacity(6);
+    for strand in &[sequence, rc.as_slice()] {
+        for offset in 0..3 {
+            frames.push(translate(strand.get(offset..).unwrap_or_default()));
+        }
+    }
+    frames
+}
+
+/// Frequency of peptide kmers of length `k` over the six-frame translation of
+/// `sequence`, ordered from most to least abundant
+///
+/// Peptides shorter than `k` contribute no kmers.
+pub(crate) fn count_peptide_kmers(
+    sequence: &[u8],
+    k: usize,
+    stops: StopCodons,
+) -> KmerCount<'static> {
+    let mut counter: HashMap<String, u64> = HashMap::new();
+    for frame in six_frames(sequence) {
+        let peptides: Vec<&[u8]> = match stops {
+            StopCodons::Split => frame.split(|&residue| residue == STOP).collect(),
+            StopCodons::Include => vec![&frame],
+        };
+        for peptide in peptides {
+            if let Ok(kmers) = kmers(peptide, k) {
+                for kmer in kmers {
+                    *counter.entry(kmer.to_owned()).or_insert(0) += 1;
+                }
+            }
+        }
+    }
+    ordered_kmer_count(counter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translate() {
+        assert_eq!(translate(b"ATGGCCTAAtgg"), b"MA*W");
+        assert_eq!(translate(b"ATGNNNGG"), b"MX");
+    }
+
+    #[test]
+    fn test_six_frames() {
+        // reverse complement: TTAGGCCAT
+        let frames = six_frames(b"ATGGCCTAA");
+        assert_eq!(
+            frames,
+            [
+                b"MA*".to_vec(),
+                b"WP".to_vec(),
+                b"GL".to_vec(),
+                b"LGH".to_vec(),
+                b"*A".to_vec(),
+                b"RP".to_vec(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_count_peptide_kmers() {
+        let counts = |stops| -> Vec<(String, u64)> {
+            count_peptide_kmers(b"ATGGCCTAA", 2, stops)
+                .into_iter()
+                .map(|r| (r.seq.into_owned(), r.count))
+                .collect()
+        };
+        let split = counts(StopCodons::Split);
+        assert_eq!(
+            split,
+            [
+                ("GH", 1),
+                ("GL", 1),
+                ("LG", 1),
+                ("MA", 1),
+                ("RP", 1),
+                ("WP", 1)
+            ]
+            .iter()
+            .map(|(k, c)| (k.to_string(), *c))
+            .collect::<Vec<_>>()
+        );
+
+        let include = counts(StopCodons::Include);
+        assert_eq!(include.len(), 8);
+        assert!(include.contains(&("A*".to_owned(), 1)));
+        assert!(include.contains(&("*A".to_owned(), 1)));
+    }
+}
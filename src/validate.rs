@@ -0,0 +1,270 @@
+//! Detection of truncated or malformed count tables
+//!
+//! A count table written with a metadata header ends with a `# rows: N`
+//! trailer after its last row. A run killed while writing leaves a table
+//! whose last line is cut off, whose header is incomplete, or which lacks the
+//! trailer, and `check_table` reports each of these. Tables written with
+//! `--no-metadata-header` have no trailer, so one cut exactly between two rows
+//! cannot be told from a complete table.
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+
+use anyhow::{Context, Result};
+
+use crate::metadata::RunMetadata;
+
+/// Key of the trailer line recording the number of rows of a table
+pub const ROWS_KEY: &str = "rows";
+
+/// File name suffixes of the count tables checked by `find_count_tables`
+pub const TABLE_SUFFIXES: &[&str] = &["_kmer.txt", "_kmer.train.txt", "_kmer.test.txt"];
+
+/// Write the `# rows: N` trailer of a table of `rows` rows, if it has a
+/// `metadata` header
+pub(crate) fn write_trailer<W: Write>(
+    metadata: Option<&RunMetadata>,
+    rows: usize,
+    out: &mut W,
+) -> io::Result<()> {
+    if metadata.is_some() {
+        writeln!(out, "# {}: {}", ROWS_KEY, rows)?;
+    }
+    Ok(())
+}
+
+/// Whether the file name of `path` is that of a count table, including the
+/// `_kmer.shardN.txt` tables of minimizer shards
+pub fn is_count_table(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| {
+            TABLE_SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+                || name
+                    .strip_suffix(".txt")
+                    .and_then(|name| name.rsplit_once("_kmer.shard"))
+                    .is_some_and(|(_, shard)| {
+                        !shard.is_empty() && shard.bytes().all(|b| b.is_ascii_digit())
+                    })
+        })
+}
+
+/// Count tables under `dir`, searched recursively, in path order
+pub fn find_count_tables(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut tables = Vec::new();
+    let mut dirs = vec![dir.to_owned()];
+    while let Some(dir) = dirs.pop() {
+        let entries =
+            fs::read_dir(&dir).with_context(|| format!("Could not read directory {:?}", dir))?;
+        for entry in entries {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+            } else if is_count_table(&path) {
+                tables.push(path);
+            }
+        }
+    }
+    tables.sort();
+    Ok(tables)
+}
+
+/// Why the count table at `path` is truncated or malformed, or `None` if it
+/// is complete
+///
+/// The table must end with a newline and have its column header, after any
+/// `#` lines, and rows with as many columns as the header and numeric values,
+/// but for the kmers of the `kmer` and `revcomp` columns.
+/// A table with a metadata header must end with a `# rows:` trailer matching
+/// its number of rows.
+pub fn check_table(path: &Path) -> Result<Option<String>> {
+    let file = File::open(path).with_context(|| format!("Could not open {:?}", path))?;
+    let mut reader = BufReader::new(file);
+
+    let mut line = Vec::new();
+    let mut line_number = 0;
+    let mut has_metadata = false;
+    let mut columns = None;
+    let mut rows = 0;
+    let mut trailer = None;
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        line_number += 1;
+        if line.pop() != Some(b'\n') {
+            return Ok(Some(format!("line {} is cut off", line_number)));
+        }
+        let text = match str::from_utf8(&line) {
+            Ok(text) => text,
+            Err(_) => return Ok(Some(format!("line {} is not UTF-8", line_number))),
+        };
+        if trailer.is_some() {
+            return Ok(Some(format!(
+                "line {} follows the `# {}:` trailer",
+                line_number, ROWS_KEY
+            )));
+        }
+
+        let columns: &Vec<bool> = match &columns {
+            Some(columns) => columns,
+            None if text.starts_with('#') => {
+                has_metadata = true;
+                continue;
+            }
+            None if text.starts_with("kmer\t") => {
+                // whether each column holds kmers rather than numbers
+                columns = Some(
+                    text.split('\t')
+                        .map(|name| name == "kmer" || name == "revcomp")
+                        .collect(),
+                );
+                continue;
+            }
+            None => {
+                return Ok(Some(format!(
+                    "line {}: expected the column header, found {:?}",
+                    line_number, text
+                )))
+            }
+        };
+        if let Some(comment) = text.strip_prefix('#') {
+            let count = comment
+                .trim()
+                .strip_prefix(ROWS_KEY)
+                .and_then(|rest| rest.strip_prefix(": "))
+                .and_then(|count| count.parse::<u64>().ok());
+            match count {
+                Some(count) => trailer = Some(count),
+                None => {
+                    return Ok(Some(format!(
+                        "line {}: unexpected comment {:?}",
+                        line_number, text
+                    )))
+                }
+            }
+            continue;
+        }
+
+        let fields: Vec<&str> = text.split('\t').collect();
+        let well_formed = fields.len() == columns.len()
+            && fields.iter().zip(columns).all(|(field, &is_kmer)| {
+                if is_kmer {
+                    !field.is_empty()
+                } else {
+                    field.parse::<f64>().is_ok()
+                }
+            });
+        if !well_formed {
+            return Ok(Some(format!(
+                "line {}: malformed row {:?}",
+                line_number, text
+            )));
+        }
+        rows += 1;
+    }
+
+    Ok(if line_number == 0 {
+        Some("empty file".to_owned())
+    } else if columns.is_none() {
+        Some("no column header".to_owned())
+    } else {
+        match trailer {
+            Some(count) if count != rows => {
+                Some(format!("trailer records {} rows, found {}", count, rows))
+            }
+            None if has_metadata => Some(format!("no `# {}:` trailer", ROWS_KEY)),
+            _ => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{run_fasta_kmer_count, CountOptions};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_check_table_detects_truncation() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("seq.fasta");
+        fs::write(&fasta_path, ">a\nATCGATCGGATCC\n")?;
+        let output_path = dir.path().join("seq_kmer.txt");
+        let options = CountOptions {
+            metadata_header: true,
+            ..CountOptions::new(3)
+        };
+        run_fasta_kmer_count(&fasta_path, &options, &output_path)?;
+        assert_eq!(check_table(&output_path)?, None);
+
+        let complete = fs::read(&output_path)?;
+        assert!(complete.ends_with(b"# rows: 7\n"));
+        let truncated_path = dir.path().join("truncated_kmer.txt");
+        for length in 0..complete.len() {
+            fs::write(&truncated_path, &complete[..length])?;
+            assert!(
+                check_table(&truncated_path)?.is_some(),
+                "undetected truncation at byte {}",
+                length
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_table_without_metadata() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("a_kmer.txt");
+        fs::write(&path, "kmer\tlog_tf\nAC\t0.693147\nGT\t1.098612\n")?;
+        assert_eq!(check_table(&path)?, None);
+        fs::write(&path, "kmer\trevcomp\tcount\nAAC\tGTT\t2\n")?;
+        assert_eq!(check_table(&path)?, None);
+        fs::write(&path, "kmer\trevcomp\tcount\nAAC\t\t2\n")?;
+        assert!(check_table(&path)?.is_some());
+
+        fs::write(&path, "kmer\tcount\nAC\t1\nGT\n")?;
+        assert_eq!(
+            check_table(&path)?,
+            Some("line 3: malformed row \"GT\"".to_owned())
+        );
+        fs::write(&path, "kmer\tcount\nAC\t1\n# rows: 2\n")?;
+        assert_eq!(
+            check_table(&path)?,
+            Some("trailer records 2 rows, found 1".to_owned())
+        );
+        fs::write(&path, "# k: 2\n")?;
+        assert_eq!(check_table(&path)?, Some("no column header".to_owned()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_count_tables() -> Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir(dir.path().join("sub"))?;
+        for name in [
+            "b_kmer.txt",
+            "sub/a_kmer.train.txt",
+            "a_kmer.labels.txt",
+            "manifest.tsv",
+            "b_kmer.txt.idx",
+            "c_kmer.shard12.txt",
+            "c_kmer.shard.txt",
+        ] {
+            fs::write(dir.path().join(name), "")?;
+        }
+        assert_eq!(
+            find_count_tables(dir.path())?,
+            [
+                dir.path().join("b_kmer.txt"),
+                dir.path().join("c_kmer.shard12.txt"),
+                dir.path().join("sub/a_kmer.train.txt")
+            ]
+        );
+        Ok(())
+    }
+}
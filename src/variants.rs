@@ -0,0 +1,219 @@
+//! Masking of known variant positions while counting
+//!
+//! A mask lists positions, such as SNPs, per record id; kmer windows
+//! overlapping any of them are not counted, so counts of a reference reflect
+//! only sequence shared by all its haplotypes. Each line of a mask file holds
+//! a record id and a 1-based position separated by whitespace, and further
+//! columns, blank lines, and `#` lines are ignored, so a VCF (`CHROM` then
+//! `POS`) can be used as is. Records the mask does not name are counted
+//! whole.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::strand::{self, Mate};
+use crate::timing::PhaseTimings;
+use crate::{add_sequence_counts_skipping, CountOptions, KmerError};
+
+/// Masked positions of each record
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VariantMask {
+    /// 0-based positions, sorted and without duplicates
+    positions: HashMap<String, Vec<usize>>,
+}
+
+impl VariantMask {
+    /// Read the mask file at `path`
+    pub fn read(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Could not open {:?}", path))?;
+        let mut positions: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let (id, position) = match (fields.next(), fields.next()) {
+                (Some(id), Some(position)) => (id, position),
+                _ => {
+                    return Err(anyhow!(
+                        "{:?}:{}: expected record id and position columns",
+                        path,
+                        i + 1
+                    ))
+                }
+            };
+            let position: usize = position
+                .parse()
+                .ok()
+                .filter(|&position| position > 0)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "{:?}:{}: bad position {:?}, expected a 1-based position",
+                        path,
+                        i + 1,
+                        position
+                    )
+                })?;
+            positions
+                .entry(id.to_owned())
+                .or_default()
+                .push(position - 1);
+        }
+        for record_positions in positions.values_mut() {
+            record_positions.sort_unstable();
+            record_positions.dedup();
+        }
+        Ok(VariantMask { positions })
+    }
+
+    /// Number of masked positions over all records
+    pub fn len(&self) -> usize {
+        self.positions.values().map(Vec::len).sum()
+    }
+
+    /// Whether no position is masked
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Masked 0-based positions of each record, in record id order
+    pub fn by_record(&self) -> BTreeMap<&str, &[usize]> {
+        self.positions
+            .iter()
+            .map(|(id, positions)| (id.as_str(), positions.as_slice()))
+            .collect()
+    }
+
+    /// Pieces of `sequence`, of the record `id`, between its masked
+    /// positions, in order; positions past the end of `sequence` are ignored
+    pub fn segments<'a>(&self, id: &str, sequence: &'a [u8]) -> Vec<&'a [u8]> {
+        let positions = match self.positions.get(id) {
+            Some(positions) => positions,
+            None => return vec![sequence],
+        };
+        let mut segments = Vec::new();
+        let mut start = 0;
+        for &position in positions.iter().take_while(|&&p| p < sequence.len()) {
+            if position > start {
+                segments.push(&sequence[start..position]);
+            }
+            start = position + 1;
+        }
+        if start < sequence.len() {
+            segments.push(&sequence[start..]);
+        }
+        segments
+    }
+}
+
+/// Windows of length `k` in `sequence` that overlap a masked position of
+/// `segments`, its pieces from `VariantMask::segments`
+pub fn masked_windows(sequence: &[u8], segments: &[&[u8]], k: usize) -> u64 {
+    let windows = |len: usize| (len + 1).saturating_sub(k) as u64;
+    windows(sequence.len()) - segments.iter().map(|s| windows(s.len())).sum::<u64>()
+}
+
+/// Add the kmers of `segments`, the unmasked pieces of a record read as mate
+/// `mate`, to `counter` as `add_sequence_counts_skipping` would add those of
+/// the record, returning the number of windows left out as ambiguous
+///
+/// Pieces shorter than `k` are skipped, unless the record is not masked at
+/// all, so a record too short to count is still reported as such.
+pub(crate) fn add_segment_counts(
+    counter: &mut HashMap<String, u64>,
+    segments: &[&[u8]],
+    mate: Mate,
+    options: &CountOptions,
+    timings: &mut PhaseTimings,
+) -> Result<u64, KmerError> {
+    if let [sequence] = segments {
+        let sequence = strand::orient(sequence, mate, options.library_type);
+        return add_sequence_counts_skipping(counter, &sequence, options, timings);
+    }
+    let mut skipped = 0;
+    for segment in segments {
+        let segment = strand::orient(segment, mate, options.library_type);
+        match add_sequence_counts_skipping(counter, &segment, options, timings) {
+            Ok(segment_skipped) => skipped += segment_skipped,
+            Err(KmerError::KmerLengthTooLong { .. }) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn mask(text: &str) -> Result<VariantMask> {
+        let dir = tempdir()?;
+        let path = dir.path().join("variants.vcf");
+        fs::write(&path, text)?;
+        VariantMask::read(&path)
+    }
+
+    #[test]
+    fn test_read_mask() -> Result<()> {
+        let mask = mask(
+            "##fileformat=VCFv4.2\n\
+             #CHROM\tPOS\tID\tREF\tALT\n\
+             chr1\t5\trs1\tA\tG\n\
+             chr1\t2\t.\tC\tT\n\
+             \n\
+             chr2 9\n\
+             chr1\t5\trs2\tA\tC\n",
+        )?;
+        assert_eq!(mask.len(), 3);
+        assert_eq!(mask.positions["chr1"], [1, 4]);
+        assert_eq!(mask.positions["chr2"], [8]);
+
+        let dir = tempdir()?;
+        let path = dir.path().join("bad.txt");
+        fs::write(&path, "chr1\t0\n")?;
+        let err = VariantMask::read(&path).unwrap_err().to_string();
+        assert!(err.contains(":1: bad position \"0\""), "{}", err);
+        fs::write(&path, "chr1\t3\nchr2\n")?;
+        let err = VariantMask::read(&path).unwrap_err().to_string();
+        assert!(err.contains(":2: expected record id"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn test_segments() -> Result<()> {
+        let mask = mask("a\t1\na\t4\na\t5\na\t20\n")?;
+        let sequence = b"ACGTACGTA";
+        let segments = mask.segments("a", sequence);
+        assert_eq!(segments, [&b"CG"[..], b"CGTA"]);
+        // windows of 3 lost: all 7 but the 2 of CGTA
+        assert_eq!(masked_windows(sequence, &segments, 3), 5);
+        assert_eq!(mask.segments("b", sequence), [&sequence[..]]);
+        assert_eq!(masked_windows(sequence, &[sequence], 3), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_segment_counts() -> Result<()> {
+        let options = CountOptions::new(3);
+        let mut timings = PhaseTimings::disabled();
+        let mask = mask("a\t4\n")?;
+        let mut counter = HashMap::new();
+        let segments = mask.segments("a", b"AACGTT");
+        add_segment_counts(&mut counter, &segments, Mate::First, &options, &mut timings)?;
+        // every window of AACGTT overlaps the masked G but AAC
+        assert_eq!(counter, HashMap::from([("AAC".to_owned(), 1)]));
+
+        let segments = mask.segments("b", b"AC");
+        let result =
+            add_segment_counts(&mut counter, &segments, Mate::First, &options, &mut timings);
+        assert!(matches!(result, Err(KmerError::KmerLengthTooLong { .. })));
+        Ok(())
+    }
+}
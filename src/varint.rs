@@ -0,0 +1,269 @@
+//! Compact binary count tables of delta-gapped, variable-byte encoded kmer IDs
+//!
+//! Each kmer of up to `MAX_K` bases is packed into a 2-bit-per-base ID
+//! (`A=0, C=1, G=2, T=3`), so numeric order of IDs is the lexicographic order
+//! of the kmers. Rows are sorted by ID and each ID is stored as the gap from
+//! the previous one, which keeps the gaps of dense tables small. Gaps and
+//! counts are written as LEB128 varints, seven bits per byte.
+//!
+//! Layout: the magic bytes `KMV1`, then varints `k` and the number of rows,
+//! then one `(gap, count)` varint pair per row.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use log::warn;
+use thiserror::Error;
+
+use crate::{create_output, KmerCount};
+
+/// Leading bytes identifying a varint count table
+pub const MAGIC: &[u8; 4] = b"KMV1";
+
+/// Longest kmer whose 2-bit ID fits in a `u64`
+pub const MAX_K: usize = 32;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum VarintError {
+    #[error("kmer length {k:?} exceeds the maximum of {max:?} for 2-bit kmer IDs")]
+    KmerTooLong { k: usize, max: usize },
+
+    #[error("not a varint count table")]
+    BadMagic,
+
+    #[error("table ends in the middle of a varint")]
+    Truncated,
+
+    #[error("varint does not fit in 64 bits")]
+    Overflow,
+}
+
+/// 2-bit ID of `kmer`, or `None` if it contains a base other than ACGT
+///
+/// Bases are read case-insensitively.
+pub fn kmer_id(kmer: &[u8]) -> Option<u64> {
+    if kmer.len() > MAX_K {
+        return None;
+    }
+    kmer.iter().try_fold(0u64, |id, base| {
+        let code = match base.to_ascii_uppercase() {
+            b'A' => 0,
+            b'C' => 1,
+            b'G' => 2,
+            b'T' => 3,
+            _ => return None,
+        };
+        Some(id << 2 | code)
+    })
+}
+
+/// The kmer of length `k` with 2-bit ID `id`
+pub fn kmer_from_id(id: u64, k: usize) -> String {
+    (0..k)
+        .rev()
+        .map(|i| match (id >> (2 * i)) & 3 {
+            0 => 'A',
+            1 => 'C',
+            2 => 'G',
+            _ => 'T',
+        })
+        .collect()
+}
+
+/// Append the LEB128 encoding of `value` to `out`
+pub fn write_varint<W: Write>(mut value: u64, out: &mut W) -> std::io::Result<()> {
+    let mut buf = [0u8; 10];
+    let mut n = 0;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf[n] = byte;
+            n += 1;
+            break;
+        }
+        buf[n] = byte | 0x80;
+        n += 1;
+    }
+    out.write_all(&buf[..n])
+}
+
+/// Read one LEB128 varint from `input`
+pub fn read_varint<R: Read>(input: &mut R) -> Result<u64> {
+    let mut value = 0u64;
+    let mut byte = [0u8; 1];
+    for shift in (0..64).step_by(7) {
+        if input.read(&mut byte)? == 0 {
+            return Err(VarintError::Truncated.into());
+        }
+        let bits = (byte[0] & 0x7f) as u64;
+        if shift == 63 && bits > 1 {
+            return Err(VarintError::Overflow.into());
+        }
+        value |= bits << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(VarintError::Overflow.into())
+}
+
+/// Save `kmer_count` of kmers of length `k` as a varint table at `output_path`
+///
+/// Kmers containing bases other than ACGT have no 2-bit ID and are skipped
+/// with a warning; kmers differing only in case are merged.
+pub(crate) fn save_varint_kmer_count(
+    kmer_count: KmerCount,
+    k: usize,
+    output_path: &Path,
+) -> Result<()> {
+    if k > MAX_K {
+        return Err(VarintError::KmerTooLong { k, max: MAX_K }.into());
+    }
+
+    let mut rows: Vec<(u64, u64)> = Vec::with_capacity(kmer_count.len());
+    let mut skipped = 0;
+    for kmer in &kmer_count {
+        match kmer_id(kmer.seq.as_bytes()) {
+            Some(id) => rows.push((id, kmer.count)),
+            None => skipped += 1,
+        }
+    }
+    if skipped > 0 {
+        warn!(
+            "Skipped {} kmers with non-ACGT bases in {:?}",
+            skipped, output_path
+        );
+    }
+    rows.sort_unstable();
+    rows.dedup_by(|next, kept| {
+        let same = next.0 == kept.0;
+        if same {
+            kept.1 += next.1;
+        }
+        same
+    });
+
+    let mut out = BufWriter::new(create_output(output_path)?);
+    out.write_all(MAGIC)?;
+    write_varint(k as u64, &mut out)?;
+    write_varint(rows.len() as u64, &mut out)?;
+    let mut previous = 0;
+    for (id, count) in rows {
+        write_varint(id - previous, &mut out)?;
+        write_varint(count, &mut out)?;
+        previous = id;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Read the varint table at `path` as `(kmer, count)` rows sorted by kmer
+pub fn read_varint_table(path: &Path) -> Result<Vec<(String, u64)>> {
+    let file = File::open(path).with_context(|| format!("Could not open {:?}", path))?;
+    let mut input = BufReader::new(file);
+
+    let mut magic = [0u8; 4];
+    if input.read_exact(&mut magic).is_err() || &magic != MAGIC {
+        return Err(VarintError::BadMagic).with_context(|| format!("{:?}", path));
+    }
+    let k = read_varint(&mut input)? as usize;
+    if k > MAX_K {
+        return Err(VarintError::KmerTooLong { k, max: MAX_K }.into());
+    }
+    let len = read_varint(&mut input)?;
+
+    let mut rows = Vec::new();
+    let mut id = 0u64;
+    for _ in 0..len {
+        id += read_varint(&mut input).with_context(|| format!("{:?}", path))?;
+        let count = read_varint(&mut input).with_context(|| format!("{:?}", path))?;
+        rows.push((kmer_from_id(id, k), count));
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::count_kmers;
+    use crate::table::read_kmer_table;
+    use crate::testutil::random_sequence;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_varint_roundtrip() -> Result<()> {
+        for &value in &[0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut buf = Vec::new();
+            write_varint(value, &mut buf)?;
+            assert_eq!(read_varint(&mut buf.as_slice())?, value);
+        }
+
+        let mut buf = Vec::new();
+        write_varint(300, &mut buf)?;
+        assert_eq!(buf, [0xac, 0x02]);
+        assert!(read_varint(&mut &buf[..1]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_kmer_id() {
+        assert_eq!(kmer_id(b"AAA"), Some(0));
+        assert_eq!(kmer_id(b"ACGT"), Some(0b00_01_10_11));
+        assert_eq!(kmer_id(b"acgt"), kmer_id(b"ACGT"));
+        assert_eq!(kmer_id(b"ANT"), None);
+        assert_eq!(kmer_from_id(0b00_01_10_11, 4), "ACGT");
+        assert_eq!(
+            kmer_from_id(kmer_id(&[b'T'; 32]).unwrap(), 32),
+            "T".repeat(32)
+        );
+    }
+
+    #[test]
+    fn test_save_varint_roundtrip() -> Result<()> {
+        let dir = tempdir()?;
+        let sequence = random_sequence(5000, 7);
+
+        let varint_path = dir.path().join("t_kmer.kmv");
+        save_varint_kmer_count(count_kmers(&sequence, 6, false)?, 6, &varint_path)?;
+        let tsv_path = dir.path().join("t_kmer.txt");
+        crate::save_kmer_count(count_kmers(&sequence, 6, false)?, None, &tsv_path)?;
+
+        let mut expected = read_kmer_table(&tsv_path)?;
+        expected.sort();
+        assert_eq!(read_varint_table(&varint_path)?, expected);
+
+        // one byte per gap and count for this dense table, against ~9 per TSV row
+        let varint_size = std::fs::metadata(&varint_path)?.len();
+        let tsv_size = std::fs::metadata(&tsv_path)?.len();
+        assert!(varint_size * 3 < tsv_size);
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_varint_skips_non_acgt() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("t_kmer.kmv");
+        save_varint_kmer_count(count_kmers(b"ACNACgAC", 2, false)?, 2, &path)?;
+        assert_eq!(
+            read_varint_table(&path)?,
+            [
+                ("AC".to_owned(), 3),
+                ("CG".to_owned(), 1),
+                ("GA".to_owned(), 1)
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_varint_bad_magic() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("t_kmer.txt");
+        std::fs::write(&path, "kmer\tcount\n")?;
+        assert!(read_varint_table(&path).is_err());
+        Ok(())
+    }
+}
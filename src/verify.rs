@@ -0,0 +1,184 @@
+//! Comparing a count table against an expected one
+//!
+//! Meant for regression tests of pipelines: after an upgrade, the new table
+//! should hold the same kmers with the same counts. With a tolerance, a count
+//! may differ from the expected one by up to that fraction of the larger of
+//! the two; a kmer found in only one table always differs.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use anyhow::Result;
+
+/// A kmer whose counts differ between the expected and actual tables, `None`
+/// where a table lacks it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    pub kmer: String,
+    pub expected: Option<u64>,
+    pub actual: Option<u64>,
+}
+
+/// Outcome of comparing two count tables
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verification {
+    /// distinct kmers of the two tables together
+    pub kmers: usize,
+    /// differing kmers, in lexicographic order
+    pub differences: Vec<Difference>,
+}
+
+impl Verification {
+    /// Whether the tables match
+    pub fn matches(&self) -> bool {
+        self.differences.is_empty()
+    }
+}
+
+/// Compare the `actual` rows with the `expected` ones, letting counts differ
+/// by up to `tolerance` times the larger of the two
+///
+/// Row order does not matter. A kmer listed more than once in a table counts
+/// with the sum of its rows.
+pub fn compare_tables(
+    expected: &[(String, u64)],
+    actual: &[(String, u64)],
+    tolerance: f64,
+) -> Verification {
+    let mut counts: BTreeMap<&str, (Option<u64>, Option<u64>)> = BTreeMap::new();
+    for (kmer, count) in expected {
+        let entry = &mut counts.entry(kmer).or_default().0;
+        *entry = Some(entry.unwrap_or(0).saturating_add(*count));
+    }
+    for (kmer, count) in actual {
+        let entry = &mut counts.entry(kmer).or_default().1;
+        *entry = Some(entry.unwrap_or(0).saturating_add(*count));
+    }
+    let kmers = counts.len();
+    let differences = counts
+        .into_iter()
+        .filter(|&(_, counts)| match counts {
+            (Some(expected), Some(actual)) => {
+                expected.abs_diff(actual) as f64 > tolerance * expected.max(actual) as f64
+            }
+            _ => true,
+        })
+        .map(|(kmer, (expected, actual))| Difference {
+            kmer: kmer.to_owned(),
+            expected,
+            actual,
+        })
+        .collect();
+    Verification { kmers, differences }
+}
+
+/// Write the verdict of `verification`, then the first `show` differences,
+/// if any, as a tab-separated table with `-` for a missing count
+pub fn write_report<W: Write>(verification: &Verification, show: usize, mut out: W) -> Result<()> {
+    if verification.matches() {
+        writeln!(out, "match: {} kmers", verification.kmers)?;
+        return Ok(());
+    }
+    writeln!(
+        out,
+        "mismatch: {} of {} kmers differ",
+        verification.differences.len(),
+        verification.kmers
+    )?;
+    writeln!(out, "kmer\texpected\tactual")?;
+    let count = |count: Option<u64>| count.map_or_else(|| "-".to_owned(), |c| c.to_string());
+    for difference in verification.differences.iter().take(show) {
+        writeln!(
+            out,
+            "{}\t{}\t{}",
+            difference.kmer,
+            count(difference.expected),
+            count(difference.actual)
+        )?;
+    }
+    if verification.differences.len() > show {
+        writeln!(
+            out,
+            "... and {} more",
+            verification.differences.len() - show
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(counts: &[(&str, u64)]) -> Vec<(String, u64)> {
+        counts.iter().map(|&(k, c)| (k.to_owned(), c)).collect()
+    }
+
+    #[test]
+    fn test_compare_tables() {
+        let expected = rows(&[("AAC", 100), ("ACG", 10), ("CGT", 1)]);
+        // same rows in another order
+        let reordered = rows(&[("CGT", 1), ("AAC", 100), ("ACG", 10)]);
+        let verification = compare_tables(&expected, &reordered, 0.0);
+        assert!(verification.matches());
+        assert_eq!(verification.kmers, 3);
+
+        let actual = rows(&[("AAC", 98), ("ACG", 10), ("GTT", 4)]);
+        let verification = compare_tables(&expected, &actual, 0.0);
+        assert_eq!(
+            verification.differences,
+            [
+                Difference {
+                    kmer: "AAC".to_owned(),
+                    expected: Some(100),
+                    actual: Some(98),
+                },
+                Difference {
+                    kmer: "CGT".to_owned(),
+                    expected: Some(1),
+                    actual: None,
+                },
+                Difference {
+                    kmer: "GTT".to_owned(),
+                    expected: None,
+                    actual: Some(4),
+                },
+            ]
+        );
+        assert_eq!(verification.kmers, 4);
+
+        // 98 is within 5% of 100, but missing kmers still differ
+        let verification = compare_tables(&expected, &actual, 0.05);
+        let kmers: Vec<&str> = verification
+            .differences
+            .iter()
+            .map(|difference| difference.kmer.as_str())
+            .collect();
+        assert_eq!(kmers, ["CGT", "GTT"]);
+        assert_eq!(
+            compare_tables(&expected, &actual, 0.01).differences.len(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_write_report() -> Result<()> {
+        let expected = rows(&[("AAC", 2), ("ACG", 1), ("CGT", 3)]);
+        let mut out = Vec::new();
+        write_report(&compare_tables(&expected, &expected, 0.0), 10, &mut out)?;
+        assert_eq!(String::from_utf8(out)?, "match: 3 kmers\n");
+
+        let actual = rows(&[("AAC", 1), ("GGG", 1)]);
+        let mut out = Vec::new();
+        write_report(&compare_tables(&expected, &actual, 0.0), 2, &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "mismatch: 4 of 4 kmers differ\n\
+             kmer\texpected\tactual\n\
+             AAC\t2\t1\n\
+             ACG\t1\t-\n\
+             ... and 2 more\n"
+        );
+        Ok(())
+    }
+}
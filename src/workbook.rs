@@ -0,0 +1,203 @@
+//! Per-record counts bundled into one spreadsheet workbook
+//!
+//! Each record gets its own worksheet holding its most abundant kmers. Sheet
+//! names are derived from record ids, made valid for spreadsheet programs
+//! (at most 31 characters, none of `[]:*?/\`) and unique. The number of
+//! sheets is capped so large inputs do not produce unwieldy files.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use log::warn;
+use rust_xlsxwriter::Workbook;
+
+use crate::strand::{self, Mate};
+use crate::timing::PhaseTimings;
+use crate::{count_sequence, open_fasta, CountOptions};
+
+/// Longest worksheet name spreadsheet programs accept
+pub const MAX_SHEET_NAME_LEN: usize = 31;
+
+/// Default cap on the number of worksheets
+pub const DEFAULT_MAX_SHEETS: usize = 100;
+
+/// Valid worksheet name for record `id` not already in `taken`
+///
+/// `taken` holds lowercased names, since sheet names are compared without case.
+pub fn sheet_name(id: &str, taken: &HashSet<String>) -> String {
+    let base: String = id
+        .chars()
+        .map(|c| match c {
+            '[' | ']' | ':' | '*' | '?' | '/' | '\\' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let base = base.trim_matches('\'');
+    let base = if base.is_empty() { "record" } else { base };
+
+    let truncate = |s: &str, len: usize| -> String { s.chars().take(len).collect() };
+    let mut name = truncate(base, MAX_SHEET_NAME_LEN);
+    let mut n = 2;
+    while taken.contains(&name.to_lowercase()) {
+        let suffix = format!("~{}", n);
+        name = format!(
+            "{}{}",
+            truncate(base, MAX_SHEET_NAME_LEN - suffix.len()),
+            suffix
+        );
+        n += 1;
+    }
+    name
+}
+
+/// Workbook of per-record kmer counts, one worksheet per record
+pub struct CountWorkbook {
+    workbook: Workbook,
+    names: HashSet<String>,
+    max_sheets: usize,
+    top: usize,
+    skipped: usize,
+}
+
+impl CountWorkbook {
+    /// Workbook of at most `max_sheets` sheets of the `top` most abundant kmers
+    pub fn new(max_sheets: usize, top: usize) -> Self {
+        CountWorkbook {
+            workbook: Workbook::new(),
+            names: HashSet::new(),
+            max_sheets,
+            top,
+            skipped: 0,
+        }
+    }
+
+    pub fn sheet_count(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Add a sheet for every record of the fasta file at `fasta_path`
+    ///
+    /// Records beyond the sheet cap and records shorter than `k` are skipped
+    /// with a warning.
+    pub fn add_fasta(&mut self, fasta_path: &Path, options: &CountOptions) -> Result<()> {
+        for record in open_fasta(fasta_path)?.records() {
+            let record = record?;
+            if self.names.len() == self.max_sheets {
+                if self.skipped == 0 {
+                    warn!(
+                        "Workbook is limited to {} sheets; further records are skipped",
+                        self.max_sheets
+                    );
+                }
+                self.skipped += 1;
+                continue;
+            }
+
+            let mate = Mate::from_header(record.id(), record.desc());
+            let sequence = strand::orient(record.seq(), mate, options.library_type);
+            let kmer_count = match count_sequence(&sequence, options, &mut PhaseTimings::disabled())
+            {
+                Ok(kmer_count) => kmer_count,
+                Err(err) => {
+                    warn!("Skipping record {}: {}", record.id(), err);
+                    continue;
+                }
+            };
+
+            let name = sheet_name(record.id(), &self.names);
+            let sheet = self.workbook.add_worksheet();
+            sheet.set_name(&name)?;
+            sheet.write_string(0, 0, "kmer")?;
+            sheet.write_string(0, 1, "count")?;
+            for (row, kmer) in (1..).zip(kmer_count.iter().take(self.top)) {
+                sheet.write_string(row, 0, kmer.seq.as_ref())?;
+                sheet.write_number(row, 1, kmer.count as f64)?;
+            }
+            self.names.insert(name.to_lowercase());
+        }
+        Ok(())
+    }
+
+    /// Save the workbook to `path`
+    pub fn save(mut self, path: &Path) -> Result<()> {
+        if self.skipped > 0 {
+            warn!(
+                "{} records did not fit in the workbook's {} sheets",
+                self.skipped, self.max_sheets
+            );
+        }
+        if self.names.is_empty() {
+            // a workbook needs at least one sheet
+            self.workbook.add_worksheet();
+        }
+        self.workbook
+            .save(path)
+            .with_context(|| format!("Could not save workbook {:?}", path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Read;
+    use tempfile::tempdir;
+
+    fn zipped_file(path: &Path, name: &str) -> Result<String> {
+        let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+        let mut contents = String::new();
+        archive.by_name(name)?.read_to_string(&mut contents)?;
+        Ok(contents)
+    }
+
+    #[test]
+    fn test_sheet_name() {
+        let mut taken = HashSet::new();
+        assert_eq!(
+            sheet_name("gi|123|ref|NC_1.1|", &taken),
+            "gi|123|ref|NC_1.1|"
+        );
+        assert_eq!(sheet_name("a/b:c*d?[e]\\f", &taken), "a_b_c_d__e__f");
+        assert_eq!(sheet_name("''", &taken), "record");
+        assert_eq!(sheet_name(&"x".repeat(40), &taken), "x".repeat(31));
+
+        taken.insert("x".repeat(31));
+        assert_eq!(
+            sheet_name(&"X".repeat(40), &taken),
+            format!("{}~2", "X".repeat(29))
+        );
+        taken.insert("chr1".to_owned());
+        taken.insert("chr1~2".to_owned());
+        assert_eq!(sheet_name("chr1", &taken), "chr1~3");
+    }
+
+    #[test]
+    fn test_workbook_sheets() -> Result<()> {
+        let dir = tempdir()?;
+        let fasta_path = dir.path().join("w.fasta");
+        std::fs::write(
+            &fasta_path,
+            ">chr1 first\nACGTACGT\n>chr1 again\nTTTTGGGG\n>too/many\nACGT\n",
+        )?;
+
+        let mut workbook = CountWorkbook::new(2, 3);
+        workbook.add_fasta(&fasta_path, &CountOptions::new(3))?;
+        assert_eq!(workbook.sheet_count(), 2);
+        let path = dir.path().join("counts.xlsx");
+        workbook.save(&path)?;
+
+        let sheets = zipped_file(&path, "xl/workbook.xml")?;
+        assert!(sheets.contains(r#"<sheet name="chr1" sheetId="1""#));
+        assert!(sheets.contains(r#"<sheet name="chr1~2" sheetId="2""#));
+        assert!(!sheets.contains("too_many"));
+
+        let strings = zipped_file(&path, "xl/sharedStrings.xml")?;
+        assert!(strings.contains("<t>kmer</t>") && strings.contains("<t>count</t>"));
+        // the top 3 kmers of chr1: ACG and CGT twice, then GTA
+        let sheet = zipped_file(&path, "xl/worksheets/sheet1.xml")?;
+        assert!(sheet.contains(r#"<dimension ref="A1:B4"/>"#));
+        Ok(())
+    }
+}
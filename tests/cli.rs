@@ -0,0 +1,970 @@
+//! End-to-end tests of the `kmer` binary
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::time::{Duration, SystemTime};
+
+use tempfile::tempdir;
+
+fn kmer(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_kmer"))
+        .args(args)
+        .output()
+        .expect("failed to run kmer")
+}
+
+/// Run the binary with `input` on its stdin
+fn kmer_with_stdin(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_kmer"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run kmer");
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(input.as_bytes()).unwrap();
+    drop(stdin);
+    child.wait_with_output().expect("failed to run kmer")
+}
+
+fn path_str(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+#[test]
+fn test_dedupe_inputs() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::create_dir(&input).unwrap();
+    fs::write(input.join("a.fasta"), ">x\nACGTACGT\n").unwrap();
+    fs::write(input.join("b.fasta"), ">x\nACGTACGT\n").unwrap();
+    // same size as a.fasta, one base different
+    fs::write(input.join("c.fasta"), ">x\nACGTACGA\n").unwrap();
+
+    let result = kmer(&[
+        "-k",
+        "3",
+        "--dedupe-inputs",
+        path_str(&input),
+        path_str(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+
+    assert!(output.join("a_kmer.txt").exists());
+    assert!(!output.join("b_kmer.txt").exists());
+    assert!(output.join("c_kmer.txt").exists());
+
+    let input = input.canonicalize().unwrap();
+    let manifest = fs::read_to_string(output.join("manifest.tsv")).unwrap();
+    let a = input.join("a.fasta");
+    let b = input.join("b.fasta");
+    let a_output = output.join("a_kmer.txt");
+    assert!(manifest
+        .lines()
+        .any(|line| line == format!("{}\t{}\t{}\t\t", b.display(), a_output.display(), a.display())));
+}
+
+#[test]
+fn test_self_check() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::create_dir(&input).unwrap();
+    fs::write(input.join("a.fasta"), ">x\nACGTACGTTTGACCA\n").unwrap();
+
+    let result = kmer(&[
+        "-k",
+        "3",
+        "--self-check",
+        path_str(&input),
+        path_str(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(output.join("a_kmer.txt").exists());
+    assert!(!output.join("a_kmer.txt.check").exists());
+}
+
+#[test]
+fn test_in_place_rerun_ignores_own_outputs() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("a.fasta"), ">x\nACGTACGT\n").unwrap();
+    let args = [
+        path_str(dir.path()),
+        "-k",
+        "3",
+        "--in-place",
+        "-e",
+        "fasta",
+        "-e",
+        "txt",
+        "-e",
+        "tsv",
+    ];
+
+    for _ in 0..2 {
+        let result = kmer(&args);
+        assert!(result.status.success(), "{:?}", result);
+    }
+    assert!(dir.path().join("a_kmer.txt").exists());
+    assert!(!dir.path().join("a_kmer_kmer.txt").exists());
+    assert!(!dir.path().join("manifest_kmer.txt").exists());
+    assert!(!dir.path().join("output").exists());
+}
+
+#[test]
+fn test_in_place_refuses_to_overwrite_input() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("a.fasta"), ">x\nACGTACGT\n").unwrap();
+    // a fasta file that happens to be named like a.fasta's output
+    fs::write(dir.path().join("a_kmer.txt"), ">y\nTTTT\n").unwrap();
+
+    let result = kmer(&[
+        path_str(dir.path()),
+        "-k",
+        "3",
+        "--in-place",
+        "-e",
+        "fasta",
+        "-e",
+        "txt",
+    ]);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("would overwrite input"));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("a_kmer.txt")).unwrap(),
+        ">y\nTTTT\n"
+    );
+}
+
+#[test]
+fn test_label_from_path() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("proj").join("s1");
+    let output = dir.path().join("output");
+    fs::create_dir_all(&input).unwrap();
+    fs::write(input.join("rep1.fasta"), ">x\nACGT\n").unwrap();
+    fs::write(input.join("rep2.fasta"), ">x\nACGA\n").unwrap();
+
+    let result = kmer(&[path_str(&input), path_str(&output), "-k", "2", "--label-from-path", "{-3}/{-2}"]);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("Labels are not unique"));
+
+    let result = kmer(&[
+        path_str(&input),
+        path_str(&output),
+        "-k",
+        "2",
+        "--label-from-path",
+        "{-3}/{-2}/{-1}",
+    ]);
+    assert!(result.status.success());
+    let manifest = fs::read_to_string(output.join("manifest.tsv")).unwrap();
+    let labels: Vec<&str> = manifest
+        .lines()
+        .skip(1)
+        .map(|line| line.split('\t').nth(3).unwrap())
+        .collect();
+    assert_eq!(labels, ["proj/s1/rep1.fasta", "proj/s1/rep2.fasta"]);
+    let table = fs::read_to_string(output.join("rep1_kmer.txt")).unwrap();
+    assert!(table.contains("# label: proj/s1/rep1.fasta\n"));
+}
+
+#[test]
+fn test_skip_existing() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::create_dir_all(&output).unwrap();
+    fs::create_dir(&input).unwrap();
+    fs::write(input.join("old.fasta"), ">x\nACGT\n").unwrap();
+    fs::write(input.join("new.fasta"), ">x\nACGT\n").unwrap();
+
+    // an up-to-date output for old.fasta, and a stale one for new.fasta
+    let sentinel = "kmer\tcount\nTT\t7\n";
+    let hour = Duration::from_secs(3600);
+    let now = SystemTime::now();
+    for (name, modified) in &[("old", now - 2 * hour), ("new", now)] {
+        fs::write(output.join(format!("{}_kmer.txt", name)), sentinel).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(input.join(format!("{}.fasta", name)))
+            .unwrap()
+            .set_modified(*modified - hour)
+            .unwrap();
+    }
+    fs::File::options()
+        .write(true)
+        .open(output.join("new_kmer.txt"))
+        .unwrap()
+        .set_modified(now - 3 * hour)
+        .unwrap();
+
+    let result = kmer(&[path_str(&input), path_str(&output), "-k", "2", "--skip-existing"]);
+    assert!(result.status.success());
+    assert_eq!(fs::read_to_string(output.join("old_kmer.txt")).unwrap(), sentinel);
+    assert_ne!(fs::read_to_string(output.join("new_kmer.txt")).unwrap(), sentinel);
+    assert!(fs::read_to_string(output.join("manifest.tsv")).unwrap().contains("old.fasta"));
+
+    // an up-to-date but truncated output is counted again
+    fs::write(output.join("old_kmer.txt"), "kmer\tcount\nTT\t").unwrap();
+    let result = kmer(&[
+        path_str(&input),
+        path_str(&output),
+        "-k",
+        "2",
+        "--skip-existing",
+    ]);
+    assert!(result.status.success());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains("Recounting") && stderr.contains("cut off"),
+        "{}",
+        stderr
+    );
+    let table = fs::read_to_string(output.join("old_kmer.txt")).unwrap();
+    assert!(table.ends_with("# rows: 3\n"), "{}", table);
+}
+
+#[test]
+fn test_validate_outputs() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::create_dir(&input).unwrap();
+    fs::write(input.join("a.fasta"), ">a\nACGTAC\n").unwrap();
+    fs::write(input.join("b.fasta"), ">b\nGGATCC\n").unwrap();
+    let result = kmer(&["-k", "3", path_str(&input), path_str(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+
+    let result = kmer(&["validate-outputs", path_str(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(result.stdout.is_empty());
+
+    let table = fs::read(output.join("b_kmer.txt")).unwrap();
+    fs::write(output.join("b_kmer.txt"), &table[..table.len() - 3]).unwrap();
+    let result = kmer(&["validate-outputs", path_str(&output)]);
+    assert!(!result.status.success());
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    assert!(
+        stdout.contains("b_kmer.txt") && !stdout.contains("a_kmer.txt"),
+        "{}",
+        stdout
+    );
+
+    let result = kmer(&["validate-outputs", "--delete", path_str(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(!output.join("b_kmer.txt").exists());
+    assert!(output.join("a_kmer.txt").exists());
+}
+
+#[test]
+fn test_empty_inputs() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::create_dir(&input).unwrap();
+    fs::write(input.join("empty.fasta"), "").unwrap();
+    fs::write(input.join("header.fasta"), ">chr1\n").unwrap();
+    fs::write(input.join("gaps.fasta"), ">a\n\n>b\nACGT\n>c\n\n").unwrap();
+
+    let result = kmer(&["-k", "3", path_str(&input), path_str(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("No records counted in") && stderr.contains("empty.fasta"));
+    assert!(stderr.contains("Skipped 2 record(s)") && stderr.contains("gaps.fasta"));
+    let table = fs::read_to_string(output.join("header_kmer.txt")).unwrap();
+    assert!(table.contains("# records_processed: 0\n"));
+    assert!(table.ends_with("kmer\tcount\n# rows: 0\n"), "{}", table);
+
+    fs::remove_dir_all(&output).unwrap();
+    let result = kmer(&[
+        "-k",
+        "3",
+        "--empty-inputs",
+        "skip",
+        path_str(&input),
+        path_str(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(!output.join("empty_kmer.txt").exists());
+    assert!(!output.join("header_kmer.txt").exists());
+    assert!(output.join("gaps_kmer.txt").exists());
+}
+
+#[test]
+fn test_short_records_are_summarized() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::create_dir(&input).unwrap();
+    let mut reads = String::from(">long\nACGTACGTAC\n");
+    for n in 0..50 {
+        reads.push_str(&format!(">short{}\nACG\n", n));
+    }
+    fs::write(input.join("reads.fasta"), reads).unwrap();
+
+    // the plain count runs last, leaving its table with the record counts
+    for mode in &[&["--core-fraction", "0.01"][..], &[][..]] {
+        let mut args = vec!["-k", "5", "-vvv"];
+        args.extend_from_slice(mode);
+        args.extend_from_slice(&[path_str(&input), path_str(&output)]);
+        let result = kmer(&args);
+        assert!(result.status.success(), "{:?}", result);
+
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        assert!(!stderr.contains("short7"), "{}", stderr);
+        let warnings: Vec<&str> = stderr
+            .lines()
+            .filter(|line| line.contains("shorter than k"))
+            .collect();
+        assert_eq!(warnings.len(), 1, "{}", stderr);
+        assert!(warnings[0].contains("Skipped 50 record(s)"), "{}", stderr);
+    }
+    let table = fs::read_to_string(output.join("reads_kmer.txt")).unwrap();
+    assert!(table.contains("# records_skipped_too_short: 50\n"), "{}", table);
+}
+
+#[test]
+fn test_provenance_header() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::create_dir(&input).unwrap();
+    fs::write(input.join("a.fasta"), ">a\nACGTACGA\n").unwrap();
+
+    let result = kmer(&[
+        "-k",
+        "3",
+        "--provenance",
+        path_str(&input),
+        path_str(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    let table = fs::read_to_string(output.join("a_kmer.txt")).unwrap();
+    assert!(table.contains("# k: 3\n"), "{}", table);
+    assert!(table.contains("# version: kmer "), "{}", table);
+    let command = format!(
+        "# command: {} -k 3 --provenance {} {}\n",
+        env!("CARGO_BIN_EXE_kmer"),
+        path_str(&input),
+        path_str(&output)
+    );
+    assert!(table.contains(&command), "{}", table);
+    assert!(table.contains("# working_directory: "), "{}", table);
+}
+
+#[test]
+fn test_input_order() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::create_dir(&input).unwrap();
+    // created out of name order, with sizes in neither order
+    fs::write(input.join("m.fasta"), ">x\nACGTACGTACGT\n").unwrap();
+    fs::write(input.join("z.fasta"), ">x\nACGTACGT\n").unwrap();
+    fs::write(input.join("a.fasta"), ">x\nACGT\n").unwrap();
+
+    for (order, expected) in &[("name", ["a", "m", "z"]), ("size", ["m", "z", "a"])] {
+        let result = kmer(&[
+            "-k",
+            "3",
+            "-vv",
+            "--sort-inputs",
+            order,
+            path_str(&input),
+            path_str(&output),
+        ]);
+        assert!(result.status.success(), "{:?}", result);
+
+        let stem = |path: &str| {
+            Path::new(path.trim_matches('"'))
+                .file_stem()
+                .unwrap()
+                .to_string_lossy()
+                .into_owned()
+        };
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        let counted: Vec<String> = stderr
+            .lines()
+            .filter_map(|line| line.split("Counting kmers in ").nth(1))
+            .map(|rest| stem(rest.split(". Output to").next().unwrap()))
+            .collect();
+        assert_eq!(counted, expected, "{}", stderr);
+
+        let manifest = fs::read_to_string(output.join("manifest.tsv")).unwrap();
+        let listed: Vec<String> = manifest
+            .lines()
+            .skip(1)
+            .map(|line| stem(line.split('\t').next().unwrap()))
+            .collect();
+        assert_eq!(listed, expected, "{}", manifest);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_dangling_symlink_is_skipped() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::create_dir(&input).unwrap();
+    fs::write(input.join("a.fasta"), ">x\nACGT\n").unwrap();
+    fs::write(input.join("b.fasta"), ">x\nACGA\n").unwrap();
+    std::os::unix::fs::symlink(input.join("gone.fasta"), input.join("c.fasta")).unwrap();
+
+    let result = kmer(&["-k", "2", path_str(&input), path_str(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(output.join("a_kmer.txt").exists());
+    assert!(output.join("b_kmer.txt").exists());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("Skipped 1 input(s)") && stderr.contains("c.fasta"));
+
+    let strict_output = dir.path().join("strict");
+    let result = kmer(&[
+        "-k",
+        "2",
+        "--strict-discovery",
+        path_str(&input),
+        path_str(&strict_output),
+    ]);
+    assert!(!result.status.success());
+    assert!(!strict_output.join("a_kmer.txt").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_external_symlinks() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input");
+    let elsewhere = dir.path().join("elsewhere");
+    fs::create_dir(&input).unwrap();
+    fs::create_dir(&elsewhere).unwrap();
+    fs::write(input.join("a.fasta"), ">x\nACGT\n").unwrap();
+    fs::write(elsewhere.join("ref.fasta"), ">x\nACGA\n").unwrap();
+    std::os::unix::fs::symlink(elsewhere.join("ref.fasta"), input.join("linked.fasta")).unwrap();
+
+    let output = dir.path().join("output");
+    let result = kmer(&["-k", "2", path_str(&input), path_str(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(output.join("a_kmer.txt").exists());
+    let table = fs::read_to_string(output.join("linked_kmer.txt")).unwrap();
+    assert!(table.contains("GA\t1\n"), "{}", table);
+
+    let skipped = dir.path().join("skipped");
+    let result = kmer(&[
+        "-k",
+        "2",
+        "--external-symlinks",
+        "skip",
+        path_str(&input),
+        path_str(&skipped),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(skipped.join("a_kmer.txt").exists());
+    assert!(!skipped.join("linked_kmer.txt").exists());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains("linked.fasta") && stderr.contains("elsewhere/ref.fasta"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_default_extensions() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::create_dir(&input).unwrap();
+    fs::write(input.join("a.fa"), ">x\nACGT\n").unwrap();
+    fs::write(input.join("b.fasta"), ">x\nACGA\n").unwrap();
+    fs::write(input.join("c.fna"), ">x\nACGG\n").unwrap();
+    fs::write(input.join("d.txt"), ">x\nACGC\n").unwrap();
+
+    let result = kmer(&["-k", "2", path_str(&input), path_str(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(output.join("a_kmer.txt").exists());
+    assert!(output.join("b_kmer.txt").exists());
+    assert!(output.join("c_kmer.txt").exists());
+    assert!(!output.join("d_kmer.txt").exists());
+
+    let custom = dir.path().join("custom");
+    let result = kmer(&[
+        path_str(&input),
+        path_str(&custom),
+        "-k",
+        "2",
+        "-e",
+        ".fa, txt",
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(custom.join("a_kmer.txt").exists());
+    assert!(!custom.join("b_kmer.txt").exists());
+    assert!(custom.join("d_kmer.txt").exists());
+}
+
+#[test]
+fn test_failing_input_does_not_stop_run() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::create_dir(&input).unwrap();
+    fs::write(input.join("a.fasta"), ">x\nACGT\n").unwrap();
+    // not UTF-8, so the fasta reader fails
+    fs::write(input.join("b.fasta"), b">x\nAC\xffGT\n").unwrap();
+    fs::write(input.join("c.fasta"), ">x\nACGA\n").unwrap();
+
+    let result = kmer(&["-k", "2", path_str(&input), path_str(&output)]);
+    assert!(!result.status.success());
+    assert!(output.join("a_kmer.txt").exists());
+    assert!(!output.join("b_kmer.txt").exists());
+    assert!(output.join("c_kmer.txt").exists());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("1 input(s) failed"), "{}", stderr);
+
+    let manifest = fs::read_to_string(output.join("manifest.tsv")).unwrap();
+    let errors: Vec<&str> = manifest
+        .lines()
+        .skip(1)
+        .map(|line| line.split('\t').nth(4).unwrap())
+        .collect();
+    assert_eq!(errors[0], "");
+    assert!(errors[1].contains("Could not count") && errors[1].contains("b.fasta"));
+    assert_eq!(errors[2], "");
+
+    let fail_fast = dir.path().join("fail_fast");
+    let result = kmer(&[
+        "-k",
+        "2",
+        "--fail-fast",
+        path_str(&input),
+        path_str(&fail_fast),
+    ]);
+    assert!(!result.status.success());
+    assert!(!fail_fast.join("manifest.tsv").exists());
+}
+
+#[test]
+fn test_threads_count_inputs_in_parallel() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input");
+    fs::create_dir(&input).unwrap();
+    for i in 0..8 {
+        let sequence = "ACGTTGCA".repeat(i + 1);
+        let path = input.join(format!("s{}.fasta", i));
+        fs::write(path, format!(">x\n{}\n", sequence)).unwrap();
+    }
+    fs::write(input.join("s3.fasta"), b">x\nAC\xffGT\n").unwrap();
+    fs::write(input.join("s6.fasta"), b">x\nAC\xffGT\n").unwrap();
+
+    let mut outputs = Vec::new();
+    for threads in &["1", "4"] {
+        let output = dir.path().join(format!("output{}", threads));
+        let result = kmer(&[
+            "-k",
+            "3",
+            "--no-metadata-header",
+            "--threads",
+            threads,
+            path_str(&input),
+            path_str(&output),
+        ]);
+        assert!(!result.status.success());
+        // every failure is reported, not only the first
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        assert!(stderr.contains("2 input(s) failed"), "{}", stderr);
+        assert!(stderr.contains("s3.fasta") && stderr.contains("s6.fasta"));
+        outputs.push(output);
+    }
+    for i in [0, 1, 2, 4, 5, 7] {
+        let name = format!("s{}_kmer.txt", i);
+        assert_eq!(
+            fs::read_to_string(outputs[0].join(&name)).unwrap(),
+            fs::read_to_string(outputs[1].join(&name)).unwrap()
+        );
+    }
+    // the manifest lists inputs in order, whichever finished first
+    let manifest = fs::read_to_string(outputs[1].join("manifest.tsv")).unwrap();
+    let inputs: Vec<&str> = manifest
+        .lines()
+        .skip(1)
+        .map(|line| line.split('\t').next().unwrap())
+        .collect();
+    let mut sorted = inputs.clone();
+    sorted.sort_unstable();
+    assert_eq!(inputs.len(), 8);
+    assert_eq!(inputs, sorted);
+
+    let result = kmer(&[
+        "-k",
+        "3",
+        "--threads",
+        "0",
+        path_str(&input),
+        path_str(&outputs[0]),
+    ]);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("--threads must be at least 1"));
+}
+
+#[test]
+fn test_group_by_pools_lanes() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::create_dir(&input).unwrap();
+    fs::write(input.join("sampleA_lane1.fasta"), ">r1\nACGT\n").unwrap();
+    fs::write(input.join("sampleA_lane2.fasta"), ">r1\nACGA\n").unwrap();
+    fs::write(input.join("control.fasta"), ">r1\nTTTT\n").unwrap();
+
+    let result = kmer(&[
+        "-k",
+        "3",
+        "--no-metadata-header",
+        "--group-by",
+        r"^(.+)_lane\d+",
+        path_str(&input),
+        path_str(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(
+        fs::read_to_string(output.join("sampleA_kmer.txt")).unwrap(),
+        "kmer\tcount\nACG\t2\nCGA\t1\nCGT\t1\n"
+    );
+    let mut outputs: Vec<String> = fs::read_dir(&output)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    outputs.sort();
+    assert_eq!(outputs, ["manifest.tsv", "sampleA_kmer.txt"]);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("control.fasta"), "{}", stderr);
+
+    let manifest = fs::read_to_string(output.join("manifest.tsv")).unwrap();
+    assert_eq!(manifest.matches("sampleA_kmer.txt").count(), 2);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_nested_output_root_is_not_discovered() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input");
+    let output = input.join("output");
+    fs::create_dir(&input).unwrap();
+    fs::write(input.join("a.fasta"), ">x\nACGT\n").unwrap();
+    // a link into the output root, as left by tools that gather results
+    std::os::unix::fs::symlink(output.join("a_kmer.txt"), input.join("latest.txt")).unwrap();
+
+    for _ in 0..2 {
+        let result = kmer(&[
+            "-k",
+            "2",
+            "-v",
+            path_str(&input),
+            path_str(&output),
+            "-e",
+            "fasta,txt",
+        ]);
+        assert!(result.status.success(), "{:?}", result);
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        assert!(stderr.contains("is inside the input directory"), "{}", stderr);
+    }
+    let mut outputs: Vec<String> = fs::read_dir(&output)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    outputs.sort();
+    assert_eq!(outputs, ["a_kmer.txt", "manifest.tsv"]);
+}
+
+#[test]
+fn test_large_k() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::create_dir(&input).unwrap();
+    fs::write(input.join("a.fasta"), ">x\nACGTACGT\n").unwrap();
+
+    let result = kmer(&["-k", "0", path_str(&input), path_str(&output)]);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("must be 1 or greater"));
+
+    let result = kmer(&[
+        "-k",
+        "300",
+        "--large-k",
+        "error",
+        path_str(&input),
+        path_str(&output),
+    ]);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("300 exceeds 256"));
+    assert!(!output.exists());
+
+    let result = kmer(&["-k", "300", path_str(&input), path_str(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains("WARNING: kmer length 300 exceeds 256"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_suspect_base_warnings_are_capped() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::create_dir(&input).unwrap();
+    // soft-masked, so with case significant every base of every scaffold is
+    // suspect
+    let mut genome = String::new();
+    for n in 0..500 {
+        genome.push_str(&format!(">scaffold{}\n{}\n", n, "acgtn".repeat(200)));
+    }
+    fs::write(input.join("genome.fasta"), genome).unwrap();
+
+    let result = kmer(&[
+        "-k",
+        "5",
+        "--case",
+        "sensitive",
+        path_str(&input),
+        path_str(&output),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    let warnings: Vec<&str> = stderr
+        .lines()
+        .filter(|line| line.contains("Suspect base(s) found"))
+        .collect();
+    assert_eq!(warnings.len(), 5, "{}", stderr);
+    assert!(warnings[0].contains("a (200), c (200), g (200), n (200), t (200)"));
+    assert!(stderr.contains("and 495 more record(s)"), "{}", stderr);
+    assert!(stderr.contains("a (100000)"), "{}", stderr);
+
+    let table = fs::read_to_string(output.join("genome_kmer.txt")).unwrap();
+    assert!(
+        table.contains("# records_with_suspect_bases: 500\n"),
+        "{}",
+        table
+    );
+}
+
+#[test]
+fn test_sketch_and_cluster_sketches() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    let sketches = dir.path().join("sketches");
+    fs::create_dir(&input).unwrap();
+    fs::create_dir(&sketches).unwrap();
+    fs::write(input.join("a.fasta"), ">a\nACGTACGGTACCAGTTAGCA\n").unwrap();
+    fs::write(input.join("b.fasta"), ">b\nTGCTAACTGGTACCGTACGT\n").unwrap();
+    let result = kmer(&["-k", "5", path_str(&input), path_str(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+
+    let a_sketch = sketches.join("a.sketch");
+    let b_sketch = sketches.join("b.sketch");
+    let a_fasta = input.join("a.fasta");
+    let b_table = output.join("b_kmer.txt");
+    let result = kmer(&[
+        "sketch",
+        "-k",
+        "5",
+        path_str(&a_fasta),
+        "-o",
+        path_str(&a_sketch),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    // b is the reverse complement of a, so its table has the same sketch
+    let result = kmer(&[
+        "sketch",
+        "--table",
+        path_str(&b_table),
+        "-o",
+        path_str(&b_sketch),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    let sketch = fs::read_to_string(&a_sketch).unwrap();
+    assert!(
+        sketch.starts_with("# k: 5\n# size: 1000\n# seed: 42\n"),
+        "{}",
+        sketch
+    );
+    assert_eq!(sketch, fs::read_to_string(&b_sketch).unwrap());
+
+    let result = kmer(&["cluster", "--sketches", path_str(&sketches)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(
+        String::from_utf8_lossy(&result.stdout),
+        "sample\ta\tb\na\t0.000000\t0.000000\nb\t0.000000\t0.000000\n"
+    );
+
+    let result = kmer(&[
+        "sketch",
+        "-k",
+        "5",
+        "--seed",
+        "7",
+        path_str(&a_fasta),
+        "-o",
+        path_str(&b_sketch),
+    ]);
+    assert!(result.status.success(), "{:?}", result);
+    let result = kmer(&["cluster", "--sketches", path_str(&sketches)]);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("Cannot compare sketches"));
+}
+
+#[test]
+fn test_verify() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::create_dir(&input).unwrap();
+    fs::write(input.join("a.fasta"), ">a\nACGTACGGTACC\n").unwrap();
+    let result = kmer(&["-k", "3", path_str(&input), path_str(&output)]);
+    assert!(result.status.success(), "{:?}", result);
+    let actual = output.join("a_kmer.txt");
+
+    // the same counts, without a metadata header and in another order
+    let expected = dir.path().join("expected.tsv");
+    let mut rows: Vec<String> = fs::read_to_string(&actual)
+        .unwrap()
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.starts_with("kmer\t"))
+        .map(str::to_owned)
+        .collect();
+    rows.reverse();
+    fs::write(&expected, rows.join("\n") + "\n").unwrap();
+    let result = kmer(&["verify", path_str(&expected), path_str(&actual)]);
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(String::from_utf8_lossy(&result.stdout), "match: 7 kmers\n");
+
+    // one count changed and one kmer missing
+    let differing = dir.path().join("differing.tsv");
+    fs::write(&differing, "kmer\tcount\nACG\t3\nCGT\t1\nTTT\t1\n").unwrap();
+    let result = kmer(&["verify", path_str(&differing), path_str(&actual)]);
+    assert!(!result.status.success(), "{:?}", result);
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    assert!(
+        stdout.starts_with("mismatch: 7 of 8 kmers differ\n"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("\nACG\t3\t2\n"), "{}", stdout);
+    assert!(stdout.contains("\nTTT\t1\t-\n"), "{}", stdout);
+
+    // ACG is within a tolerance of 50%, but the other kmers still differ
+    let result = kmer(&[
+        "verify",
+        "--tolerance",
+        "0.5",
+        "--show",
+        "1",
+        path_str(&differing),
+        path_str(&actual),
+    ]);
+    assert!(!result.status.success(), "{:?}", result);
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    assert!(
+        stdout.starts_with("mismatch: 6 of 8 kmers differ\n"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("... and 5 more"), "{}", stdout);
+}
+
+#[test]
+fn test_stdin_to_stdout() {
+    let fasta = ">a\nAAAACG\n>b\nACG\n";
+    let result = kmer_with_stdin(&["-k", "3", "-vv", "--no-metadata-header", "-"], fasta);
+    assert!(result.status.success(), "{:?}", result);
+    // log lines go to stderr, leaving only the table on stdout
+    assert_eq!(
+        String::from_utf8_lossy(&result.stdout),
+        "kmer\tcount\nAAA\t2\nACG\t2\nAAC\t1\n"
+    );
+    assert!(!result.stderr.is_empty());
+
+    let dir = tempdir().unwrap();
+    let output = dir.path().join("counts.txt");
+    let args = [
+        "-k",
+        "3",
+        "--no-metadata-header",
+        "-o",
+        path_str(&output),
+        "-",
+    ];
+    let result = kmer_with_stdin(&args, fasta);
+    assert!(result.status.success(), "{:?}", result);
+    assert!(result.stdout.is_empty());
+    assert_eq!(
+        fs::read_to_string(&output).unwrap(),
+        "kmer\tcount\nAAA\t2\nACG\t2\nAAC\t1\n"
+    );
+
+    let result = kmer_with_stdin(&["-k", "3", "--per-record", "-"], fasta);
+    assert!(!result.status.success());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains("cannot be used with --per-record"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_screen_rejects_min_fraction_out_of_range() {
+    for fraction in &["--min-fraction=1.5", "--min-fraction=-0.1"] {
+        let result = kmer(&["screen", "reads.fasta", "--ref", "a:a_kmer.txt", fraction]);
+        assert!(!result.status.success());
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        assert!(
+            stderr.contains("--min-fraction must be between 0 and 1"),
+            "{}",
+            stderr
+        );
+    }
+}
+
+#[test]
+fn test_output_modes_conflict_only_with_their_own_options() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input");
+    fs::create_dir(&input).unwrap();
+    fs::write(input.join("a.fasta"), ">a\nACGTACGT\n").unwrap();
+
+    let output = dir.path().join("output");
+    let run = |args: &[&str]| {
+        let mut args = args.to_vec();
+        args.extend([path_str(&input), path_str(&output)]);
+        kmer(&args)
+    };
+    // options conflicting with one output mode leave the others alone
+    for args in [
+        &["-k", "3", "--canonical", "--log-tf"][..],
+        &["-k", "3", "--translate", "--log-tf"],
+        &["-k", "3", "--mismatch", "1", "--skip-ambiguous", "--tm"],
+    ] {
+        let result = run(args);
+        assert!(result.status.success(), "{:?}: {:?}", args, result);
+    }
+    for args in [
+        &["--dinucleotide-signature", "--canonical"][..],
+        &["-k", "3", "--translate", "--tm"],
+        &["-k", "3", "--log-tf", "--tm"],
+    ] {
+        let result = run(args);
+        assert!(!result.status.success(), "{:?}", args);
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        assert!(stderr.contains("cannot be used with"), "{}", stderr);
+    }
+}